@@ -1,18 +1,25 @@
-use crate::config::Config;
+use crate::{config::Config, onion::is_onion_address, peer_filter::PeerFilter};
 use bitcoin::{
     network::{constants::ServiceFlags, Address},
     Network,
 };
-use logger::{info, ReplicaLogger};
+use logger::{info, warn, ReplicaLogger};
+use parking_lot::Mutex;
 use rand::{
     prelude::{IteratorRandom, SliceRandom, StdRng},
     SeedableRng,
 };
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::{HashSet, VecDeque},
-    net::{SocketAddr, ToSocketAddrs},
+    collections::{HashMap, HashSet, VecDeque},
+    fs, io,
+    net::{Ipv6Addr, SocketAddr, ToSocketAddrs},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use thiserror::Error;
+use tokio::task::JoinHandle;
 
 /// Starting with version 31402, addresses are prefixed with a timestamp.
 /// If no timestamp is present, the addresses should not be relayed to other peers,
@@ -23,6 +30,10 @@ pub type AddressTimestamp = u32;
 /// `addr` message.
 pub const MAX_ADDR_MESSAGE_SIZE: usize = 1000;
 
+/// The minimum time between two saves of the peers file. Addresses seen in between are
+/// written with the next save.
+const PEERS_FILE_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
 /// This enum is used to address errors found while working with the AddressBook.
 #[derive(Debug, Error)]
 pub enum AddressBookError {
@@ -49,6 +60,15 @@ pub enum AddressBookError {
 /// A convenience wrapper type for addressing results from the AddressBook.
 pub type AddressBookResult<T> = Result<T, AddressBookError>;
 
+/// This struct represents an address entry in the peers file.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+struct PersistedAddress {
+    /// The address of the peer.
+    address: SocketAddr,
+    /// When the peer was last seen, in seconds since the Unix epoch.
+    last_seen: u64,
+}
+
 /// This enum is used to indicate the type of address that is being retrieved from
 /// the address book.
 #[derive(Debug)]
@@ -79,6 +99,9 @@ pub struct AddressBook {
     dns_seeds: Vec<String>,
     /// This field controls whether or not the address book should accept IPv4 addresses.
     ipv6_only: bool,
+    /// This field controls whether or not the address book should only accept onion addresses.
+    /// It is set in proxy-only mode, where all connections go through the SOCKS proxy.
+    proxy_only: bool,
     /// The port that should be targeted based on the provided configuration.
     port: u16,
     /// This field contains the addresses that are already in use.
@@ -94,6 +117,26 @@ pub struct AddressBook {
     /// This field contains the addresses that will be used for the address discovery
     /// on initial startup and when the adapter is running low on addresses.
     seed_queue: VecDeque<SocketAddr>,
+    /// This field contains when each discovered address was last seen. These are the
+    /// addresses that are written to the peers file.
+    last_seen: HashMap<SocketAddr, SystemTime>,
+    /// This field contains the addresses reloaded from the peers file that have not been
+    /// tried yet. They are tried before falling back to the DNS seeds.
+    reloaded_addresses: HashSet<SocketAddr>,
+    /// The file the discovered addresses are persisted to.
+    peers_file: Option<PathBuf>,
+    /// This field is set when addresses have been seen since the peers file was last saved.
+    unsaved_changes: bool,
+    /// The time the peers file was last saved.
+    last_saved_at: Instant,
+    /// This field serializes the writes of the peers file, which run on the blocking thread pool.
+    save_lock: Arc<Mutex<()>>,
+    /// Addresses that have not been seen within this duration are considered stale and are
+    /// not shared with other nodes.
+    max_address_age: Duration,
+    /// This field contains the allowlist and denylist of peers. Addresses of peers that may
+    /// not be connected to are not added.
+    peer_filter: PeerFilter,
 }
 
 impl AddressBook {
@@ -101,12 +144,46 @@ impl AddressBook {
     /// config provided. If no addresses found, a panic will be issued as a connection
     /// cannot be made without an address. If not enough addresses are found to
     /// meet the minimum number of connections, a panic will be issued.
+    /// If a peers file is configured, the addresses that have been seen within the configured
+    /// maximum age are reloaded from it.
+    /// In proxy-only mode, only onion addresses are reloaded and discovered. The configured
+    /// nodes are always used.
     pub fn new(config: &Config, logger: ReplicaLogger) -> Self {
         let (min_addresses, max_addresses) = address_limits(config.network);
-        let known_addresses: HashSet<SocketAddr> = config.nodes.iter().cloned().collect();
+        let mut known_addresses: HashSet<SocketAddr> = config.nodes.iter().cloned().collect();
+        let max_age = Duration::from_secs(config.peers_file_max_age_secs);
+        let peer_filter = PeerFilter::new(config);
+        let last_seen = match &config.peers_file {
+            Some(path) => load_peers_file(path, max_age).unwrap_or_else(|err| {
+                warn!(
+                    logger,
+                    "Failed to load peer addresses from {}: {}",
+                    path.display(),
+                    err
+                );
+                HashMap::new()
+            }),
+            None => HashMap::new(),
+        };
+        let reloaded_addresses: HashSet<SocketAddr> = last_seen
+            .keys()
+            .filter(|addr| !config.ipv6_only || addr.is_ipv6())
+            .filter(|addr| !config.proxy_only || is_onion_address(addr))
+            .filter(|addr| peer_filter.is_allowed(addr))
+            .copied()
+            .collect();
+        if !reloaded_addresses.is_empty() {
+            info!(
+                logger,
+                "Reloaded {} peer address(es) from the peers file.",
+                reloaded_addresses.len()
+            );
+        }
+        known_addresses.extend(&reloaded_addresses);
         Self {
             dns_seeds: config.dns_seeds.clone(),
             ipv6_only: config.ipv6_only,
+            proxy_only: config.proxy_only,
             port: config.network_port(),
             active_addresses: HashSet::new(),
             known_addresses,
@@ -114,6 +191,14 @@ impl AddressBook {
             min_addresses,
             max_addresses,
             seed_queue: VecDeque::new(),
+            last_seen,
+            reloaded_addresses,
+            peers_file: config.peers_file.clone(),
+            unsaved_changes: false,
+            last_saved_at: Instant::now(),
+            save_lock: Arc::new(Mutex::new(())),
+            max_address_age: max_age,
+            peer_filter,
         }
     }
 
@@ -128,7 +213,9 @@ impl AddressBook {
         let mut addresses = dns_seeds
             .flat_map(|seed| {
                 seed.to_socket_addrs().map_or(vec![], |v| {
-                    v.filter(|addr| !self.ipv6_only || addr.is_ipv6()).collect()
+                    v.filter(|addr| !self.ipv6_only || addr.is_ipv6())
+                        .filter(|addr| !self.proxy_only || is_onion_address(addr))
+                        .collect()
                 })
             })
             .collect::<Vec<SocketAddr>>();
@@ -151,6 +238,9 @@ impl AddressBook {
         }
         self.active_addresses = HashSet::new();
         self.seed_queue = VecDeque::new();
+        if self.unsaved_changes {
+            self.save();
+        }
     }
 
     /// This function is used to determine how many entries are in the address book.
@@ -164,6 +254,12 @@ impl AddressBook {
         self.size() >= self.min_addresses
     }
 
+    /// This function is used to determine if there are addresses reloaded from the peers file
+    /// that have not been tried yet.
+    pub fn has_reloaded_addresses(&self) -> bool {
+        !self.reloaded_addresses.is_empty()
+    }
+
     /// This function is used to determine if the address book has been filled with the maximum
     /// number of addresses.
     pub fn has_max_address(&self) -> bool {
@@ -183,7 +279,7 @@ impl AddressBook {
             });
         }
         let mut added_addresses = 0u32;
-        for (_, address) in addresses {
+        for (timestamp, address) in addresses {
             if self.has_max_address() {
                 break;
             }
@@ -198,10 +294,18 @@ impl AddressBook {
                     continue;
                 }
 
-                if *sender == addr {
+                // if the adapter is in proxy-only mode, skip addresses that are not onion services.
+                if self.proxy_only && !is_onion_address(&addr) {
+                    continue;
+                }
+
+                if *sender == addr || !self.peer_filter.is_allowed(&addr) {
                     continue;
                 }
                 self.add(addr);
+                let last_seen = UNIX_EPOCH + Duration::from_secs(u64::from(*timestamp));
+                self.last_seen
+                    .insert(addr, last_seen.min(SystemTime::now()));
                 added_addresses = added_addresses.saturating_add(1);
             }
         }
@@ -211,11 +315,93 @@ impl AddressBook {
                 self.logger,
                 "Added {} address(es) from {:?}.", added_addresses, sender
             );
+            self.unsaved_changes = true;
         }
 
         Ok(())
     }
 
+    /// This function returns a random sample of the addresses that have been seen recently,
+    /// which is sent in response to a `getaddr` message. Stale addresses are skipped and at most
+    /// [MAX_ADDR_MESSAGE_SIZE](MAX_ADDR_MESSAGE_SIZE) addresses are returned.
+    pub fn sample_recently_seen(&self) -> Vec<(AddressTimestamp, Address)> {
+        let mut rng = StdRng::from_entropy();
+        let now = SystemTime::now();
+        self.last_seen
+            .iter()
+            .filter(|(addr, _)| !self.ipv6_only || addr.is_ipv6())
+            .filter(|(_, last_seen)| {
+                now.duration_since(**last_seen)
+                    .map_or(true, |age| age <= self.max_address_age)
+            })
+            .choose_multiple(&mut rng, MAX_ADDR_MESSAGE_SIZE)
+            .into_iter()
+            .map(|(addr, last_seen)| {
+                let timestamp = last_seen
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or(Duration::ZERO)
+                    .as_secs();
+                // Only addresses advertising `NETWORK` are added to the address book.
+                (
+                    AddressTimestamp::try_from(timestamp).unwrap_or(AddressTimestamp::MAX),
+                    Address::new(addr, ServiceFlags::NETWORK),
+                )
+            })
+            .collect()
+    }
+
+    /// This function records that the node at the given address has been seen, i.e., the
+    /// version handshake with it has been completed.
+    pub fn mark_as_seen(&mut self, addr: &SocketAddr) {
+        if self.active_addresses.contains(addr) {
+            self.last_seen.insert(*addr, SystemTime::now());
+            self.unsaved_changes = true;
+        }
+    }
+
+    /// This function saves the peers file if addresses have been seen since it was last saved
+    /// and the save interval has passed. It is called on every tick of the connection manager.
+    pub fn save_if_due(&mut self) -> Option<JoinHandle<()>> {
+        if self.unsaved_changes && self.last_saved_at.elapsed() >= PEERS_FILE_SAVE_INTERVAL {
+            self.save()
+        } else {
+            None
+        }
+    }
+
+    /// This function writes the most recently seen addresses to the peers file, if one is
+    /// configured. The file is written on the blocking thread pool, so the returned handle
+    /// completes once the file has been written.
+    pub fn save(&mut self) -> Option<JoinHandle<()>> {
+        let path = self.peers_file.clone()?;
+        let mut entries: Vec<_> = self
+            .last_seen
+            .iter()
+            .map(|(address, last_seen)| (*address, *last_seen))
+            .collect();
+        entries.sort_by(|a, b| b.1.cmp(&a.1));
+        entries.truncate(self.max_addresses);
+        self.unsaved_changes = false;
+        self.last_saved_at = Instant::now();
+
+        let logger = self.logger.clone();
+        let save_lock = self.save_lock.clone();
+        Some(tokio::task::spawn_blocking(move || {
+            let _guard = save_lock.lock();
+            let entries = entries
+                .iter()
+                .map(|(address, last_seen)| (address, last_seen));
+            if let Err(err) = save_peers_file(&path, entries) {
+                warn!(
+                    logger,
+                    "Failed to save peer addresses to {}: {}",
+                    path.display(),
+                    err
+                );
+            }
+        }))
+    }
+
     /// This adds a new address to the possible sets.
     fn add(&mut self, addr: SocketAddr) {
         if self.active_addresses.contains(&addr) {
@@ -265,6 +451,7 @@ impl AddressBook {
     /// the address is used again.
     fn mark_as_active(&mut self, addr: &SocketAddr) {
         self.known_addresses.remove(addr);
+        self.reloaded_addresses.remove(addr);
         self.active_addresses.insert(*addr);
     }
 
@@ -286,6 +473,7 @@ impl AddressBook {
             if self.has_seeds() {
                 self.active_addresses.remove(addr);
                 self.known_addresses.remove(addr);
+                self.last_seen.remove(addr);
             } else {
                 self.remove_from_active(address);
             }
@@ -301,10 +489,83 @@ pub fn validate_services(services: &ServiceFlags) -> bool {
     services.has(ServiceFlags::NETWORK)
 }
 
+/// This function reads the addresses from the peers file. Addresses that have not been seen
+/// within `max_age` are pruned. A missing file is treated as an empty file.
+fn load_peers_file(path: &Path, max_age: Duration) -> io::Result<HashMap<SocketAddr, SystemTime>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(err) => return Err(err),
+    };
+    let entries: Vec<PersistedAddress> = serde_json::from_str(&contents)?;
+    let now = SystemTime::now();
+    Ok(entries
+        .into_iter()
+        .map(|entry| {
+            (
+                entry.address,
+                UNIX_EPOCH + Duration::from_secs(entry.last_seen),
+            )
+        })
+        .filter(|(_, last_seen)| {
+            now.duration_since(*last_seen)
+                .map_or(true, |age| age <= max_age)
+        })
+        .collect())
+}
+
+/// This function writes the addresses to the peers file. The file is replaced atomically
+/// so a crash while writing does not leave a truncated file behind.
+fn save_peers_file<'a>(
+    path: &Path,
+    entries: impl IntoIterator<Item = (&'a SocketAddr, &'a SystemTime)>,
+) -> io::Result<()> {
+    let entries: Vec<PersistedAddress> = entries
+        .into_iter()
+        .map(|(address, last_seen)| PersistedAddress {
+            address: *address,
+            last_seen: last_seen
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or(Duration::ZERO)
+                .as_secs(),
+        })
+        .collect();
+    let contents = serde_json::to_string(&entries)?;
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, contents)?;
+    fs::rename(&tmp_path, path)
+}
+
 /// This is a simple utility function for creating a string that is a valid string
-/// for ToSocketAddrs.
+/// for ToSocketAddrs. IPv6 addresses are enclosed in brackets. The port of the seed is
+/// used if it has one, otherwise the provided port is appended.
 fn format_addr(seed: &str, port: u16) -> String {
-    format!("{}:{}", seed, port)
+    let (host, seed_port) = split_dns_seed_port(seed);
+    let port = seed_port.unwrap_or(port);
+    if host.parse::<Ipv6Addr>().is_ok() {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// This function splits the optional `:port` suffix off a DNS seed. The suffix is only split
+/// off if it is a valid port. An IPv6 address must be enclosed in brackets to carry a port.
+pub(crate) fn split_dns_seed_port(seed: &str) -> (&str, Option<u16>) {
+    if seed.parse::<Ipv6Addr>().is_ok() {
+        return (seed, None);
+    }
+    match seed.rsplit_once(':') {
+        Some((host, port))
+            if !host.contains(':') || (host.starts_with('[') && host.ends_with(']')) =>
+        {
+            match port.parse() {
+                Ok(port) => (host, Some(port)),
+                Err(_) => (seed, None),
+            }
+        }
+        _ => (seed, None),
+    }
 }
 
 /// This function is used to get the address limits for the `AddressBook`
@@ -424,6 +685,60 @@ mod test {
         assert!(book.known_addresses.contains(&socket_2));
     }
 
+    /// This function tests the `AddressBook::add_many(...)` function to ensure
+    /// addresses that are not onion services are skipped when in proxy-only mode.
+    /// Test Steps:
+    /// 1. Create an address book in proxy-only mode.
+    /// 2. Add an IPv4, an IPv6 and an onion address.
+    /// 3. Check that only the onion address is known.
+    #[test]
+    fn test_address_book_add_many_proxy_only() {
+        let config = ConfigBuilder::new()
+            .with_dns_seeds(vec![String::from("127.0.0.1")])
+            .with_proxy_only(true)
+            .build();
+        let mut book = AddressBook::new(&config, no_op_logger());
+        let sender = SocketAddr::from_str("127.0.0.1:8333").expect("bad address format");
+
+        let socket_1 = SocketAddr::from_str("127.0.0.1:8444").expect("bad address format");
+        let socket_2 = SocketAddr::from_str("[2401:3f00:1000:23:5000:7bff:fe3d:b81d]:8444")
+            .expect("bad address format");
+        let socket_3 = SocketAddr::from_str("[fd87:d87e:eb43:25df:8a67:3cb4:2188:1d2d]:8333")
+            .expect("bad address format");
+        let addresses = vec![
+            (0, Address::new(&socket_1, ServiceFlags::NETWORK)),
+            (0, Address::new(&socket_2, ServiceFlags::NETWORK)),
+            (0, Address::new(&socket_3, ServiceFlags::NETWORK)),
+        ];
+        book.add_many(&sender, &addresses)
+            .expect("should not cause an error");
+        assert_eq!(book.known_addresses.len(), 1);
+        assert!(book.known_addresses.contains(&socket_3));
+    }
+
+    /// This function tests the `AddressBook::add_many(...)` function to ensure
+    /// addresses of peers that are not allowed are skipped.
+    #[test]
+    fn test_address_book_add_many_skips_denied_peers() {
+        let config = ConfigBuilder::new()
+            .with_dns_seeds(vec![String::from("127.0.0.1")])
+            .with_denied_peers(vec![String::from("10.0.0.0/8")])
+            .build();
+        let mut book = AddressBook::new(&config, no_op_logger());
+
+        let seed = book.pop_seed().expect("there should be 1 seed");
+        let socket_1 = SocketAddr::from_str("10.0.0.1:8444").expect("bad address format");
+        let address_1 = Address::new(&socket_1, ServiceFlags::NETWORK);
+        let socket_2 = SocketAddr::from_str("11.0.0.1:8444").expect("bad address format");
+        let address_2 = Address::new(&socket_2, ServiceFlags::NETWORK);
+        let addresses = vec![(0, address_1), (0, address_2)];
+        book.add_many(seed.addr(), &addresses)
+            .expect("should not cause an error");
+        assert_eq!(book.known_addresses.len(), 1);
+        assert!(!book.known_addresses.contains(&socket_1));
+        assert!(book.known_addresses.contains(&socket_2));
+    }
+
     /// This function tests the `AddressBook::discard(...)` function to ensure
     /// the addresses are removed from the pool.
     #[test]
@@ -504,6 +819,37 @@ mod test {
         ));
     }
 
+    /// This function tests that IPv6 seeds are formatted as valid socket addresses, with or
+    /// without brackets.
+    #[test]
+    fn test_format_addr_ipv6() {
+        assert_eq!(format_addr("127.0.0.1", 8333), "127.0.0.1:8333");
+        assert_eq!(
+            format_addr("seed.bitcoin.sipa.be", 8333),
+            "seed.bitcoin.sipa.be:8333"
+        );
+        assert_eq!(format_addr("2001:db8::1", 8333), "[2001:db8::1]:8333");
+        assert_eq!(format_addr("[2001:db8::1]", 8333), "[2001:db8::1]:8333");
+        assert!(format_addr("2001:db8::1", 8333)
+            .parse::<SocketAddr>()
+            .is_ok());
+    }
+
+    /// This function tests that the port of a seed is used instead of the network port.
+    #[test]
+    fn test_format_addr_with_port() {
+        assert_eq!(format_addr("127.0.0.1:18444", 8333), "127.0.0.1:18444");
+        assert_eq!(
+            format_addr("seed.bitcoin.sipa.be:8334", 8333),
+            "seed.bitcoin.sipa.be:8334"
+        );
+        assert_eq!(
+            format_addr("[2001:db8::1]:18444", 8333),
+            "[2001:db8::1]:18444"
+        );
+        assert_eq!(format_addr("2001:db8::1", 8333), "[2001:db8::1]:8333");
+    }
+
     /// This function tests to ensure that when the seed queue is built and IPv6 only is enabled,
     /// IPv4 seeds are filtered out.
     #[test]
@@ -564,4 +910,148 @@ mod test {
         assert_eq!(book.known_addresses.len(), 1);
         assert_eq!(book.active_addresses.len(), 0);
     }
+
+    /// This function tests that discovered addresses are persisted to the peers file and
+    /// reloaded by a new address book.
+    /// Test Steps:
+    /// 1. Add addresses from an `addr` message to an address book with a peers file.
+    /// 2. Create a new address book with the same config.
+    /// 3. Check that the addresses have been reloaded and are tried before the seeds.
+    #[tokio::test]
+    async fn test_peers_file_round_trip() {
+        let dir = tempfile::tempdir().expect("failed to create a temporary directory");
+        let config = ConfigBuilder::new()
+            .with_dns_seeds(vec![String::from("127.0.0.1")])
+            .with_peers_file(Some(dir.path().join("peers.json")))
+            .build();
+        let mut book = AddressBook::new(&config, no_op_logger());
+        assert!(!book.has_reloaded_addresses());
+
+        let sender = SocketAddr::from_str("127.0.0.1:8333").expect("bad address format");
+        let socket_1 = SocketAddr::from_str("127.0.0.1:8444").expect("bad address format");
+        let socket_2 = SocketAddr::from_str("[::1]:8555").expect("bad address format");
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time is behind")
+            .as_secs() as AddressTimestamp;
+        let addresses = vec![
+            (now, Address::new(&socket_1, ServiceFlags::NETWORK)),
+            (now, Address::new(&socket_2, ServiceFlags::NETWORK)),
+        ];
+        book.add_many(&sender, &addresses)
+            .expect("should not cause an error");
+        book.save()
+            .expect("a peers file is configured")
+            .await
+            .expect("failed to save the peers file");
+
+        let mut book = AddressBook::new(&config, no_op_logger());
+        assert!(book.has_reloaded_addresses());
+        assert_eq!(
+            book.known_addresses,
+            vec![socket_1, socket_2].into_iter().collect()
+        );
+        assert_eq!(
+            book.last_seen[&socket_1],
+            UNIX_EPOCH + Duration::from_secs(u64::from(now))
+        );
+
+        book.pop().expect("there should be a reloaded address");
+        book.pop().expect("there should be a reloaded address");
+        assert!(!book.has_reloaded_addresses());
+    }
+
+    /// This function tests that the peers file is not written for every `addr` message, but
+    /// once the save interval has passed.
+    /// Test Steps:
+    /// 1. Add addresses from an `addr` message to an address book with a peers file.
+    /// 2. Check that the peers file is not saved before the save interval has passed.
+    /// 3. Let the save interval pass and check that the peers file is saved.
+    #[tokio::test]
+    async fn test_peers_file_save_is_debounced() {
+        let dir = tempfile::tempdir().expect("failed to create a temporary directory");
+        let path = dir.path().join("peers.json");
+        let config = ConfigBuilder::new()
+            .with_dns_seeds(vec![String::from("127.0.0.1")])
+            .with_peers_file(Some(path.clone()))
+            .build();
+        let mut book = AddressBook::new(&config, no_op_logger());
+
+        let sender = SocketAddr::from_str("127.0.0.1:8333").expect("bad address format");
+        let socket = SocketAddr::from_str("127.0.0.1:8444").expect("bad address format");
+        let addresses = vec![(0, Address::new(&socket, ServiceFlags::NETWORK))];
+        book.add_many(&sender, &addresses)
+            .expect("should not cause an error");
+        assert!(book.save_if_due().is_none());
+        assert!(!path.exists());
+
+        book.last_saved_at = Instant::now()
+            .checked_sub(PEERS_FILE_SAVE_INTERVAL)
+            .expect("instant underflow");
+        book.save_if_due()
+            .expect("the save should be due")
+            .await
+            .expect("failed to save the peers file");
+        assert!(path.exists());
+        assert!(book.save_if_due().is_none());
+    }
+
+    /// This function tests that the sample sent in response to `getaddr` is capped and skips
+    /// stale addresses.
+    #[test]
+    fn test_sample_recently_seen() {
+        let config = ConfigBuilder::new()
+            .with_dns_seeds(vec![String::from("127.0.0.1")])
+            .with_peers_file_max_age_secs(60 * 60)
+            .build();
+        let mut book = AddressBook::new(&config, no_op_logger());
+        let now = SystemTime::now();
+        for port in 0..1500 {
+            let addr = SocketAddr::from(([10, 0, (port / 256) as u8, (port % 256) as u8], 8333));
+            book.last_seen.insert(addr, now);
+        }
+        let stale = SocketAddr::from_str("127.0.0.1:8555").expect("bad address format");
+        book.last_seen
+            .insert(stale, now - Duration::from_secs(2 * 60 * 60));
+
+        let sample = book.sample_recently_seen();
+        assert_eq!(sample.len(), MAX_ADDR_MESSAGE_SIZE);
+        assert!(sample.iter().all(|(_, address)| {
+            let addr = address.socket_addr().expect("should be an IP address");
+            addr != stale && validate_services(&address.services)
+        }));
+
+        book.last_seen.clear();
+        book.last_seen.insert(stale, now);
+        let sample = book.sample_recently_seen();
+        assert_eq!(sample.len(), 1);
+    }
+
+    /// This function tests that addresses that have not been seen within the maximum age are
+    /// pruned when the peers file is loaded.
+    #[test]
+    fn test_peers_file_prunes_stale_entries() {
+        let dir = tempfile::tempdir().expect("failed to create a temporary directory");
+        let path = dir.path().join("peers.json");
+        let fresh = SocketAddr::from_str("127.0.0.1:8444").expect("bad address format");
+        let stale = SocketAddr::from_str("127.0.0.1:8555").expect("bad address format");
+        let now = SystemTime::now();
+        let long_ago = now - Duration::from_secs(2 * 60 * 60);
+        save_peers_file(&path, vec![(&fresh, &now), (&stale, &long_ago)])
+            .expect("failed to save the peers file");
+
+        let config = ConfigBuilder::new()
+            .with_dns_seeds(vec![String::from("127.0.0.1")])
+            .with_peers_file(Some(path.clone()))
+            .with_peers_file_max_age_secs(60 * 60)
+            .build();
+        let book = AddressBook::new(&config, no_op_logger());
+        assert!(book.known_addresses.contains(&fresh));
+        assert!(!book.known_addresses.contains(&stale));
+
+        // A missing peers file is treated as empty.
+        let entries = load_peers_file(&dir.path().join("missing.json"), Duration::from_secs(60))
+            .expect("a missing file should not cause an error");
+        assert!(entries.is_empty());
+    }
 }
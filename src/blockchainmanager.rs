@@ -1,13 +1,15 @@
 use crate::{
-    blockchainstate::{AddHeaderError, BlockchainState},
-    common::{BlockHeight, MINIMUM_VERSION_NUMBER},
+    blockchainstate::{AddFilterHeadersError, AddHeaderError, BlockchainState, BASIC_FILTER_TYPE},
+    common::{BlockHeight, MAX_HEADERS_SIZE, MINIMUM_VERSION_NUMBER},
     metrics::RouterMetrics,
     Channel, Command, ProcessBitcoinNetworkMessageError,
 };
 use bitcoin::{
     network::{
+        constants::ServiceFlags,
         message::{NetworkMessage, MAX_INV_SIZE},
         message_blockdata::{GetHeadersMessage, Inventory},
+        message_filter::{CFHeaders, GetCFHeaders},
     },
     Block, BlockHash, BlockHeader,
 };
@@ -29,10 +31,6 @@ const GETDATA_REQUEST_TIMEOUT_SECS: u64 = 30;
 /// This constant is the maximum number of seconds to wait until we get response to the getdata request sent by us.
 const GETHEADERS_REQUEST_TIMEOUT_SECS: u64 = 30;
 
-/// This constant represents the maximum size of `headers` messages.
-/// https://developer.bitcoin.org/reference/p2p_networking.html#headers
-const MAX_HEADERS_SIZE: usize = 2_000;
-
 /// This constant stores the maximum number of headers allowed in an unsolicited `headers` message
 /// (`headers message for which a `getheaders` request was not sent before.)
 const MAX_UNSOLICITED_HEADERS: usize = 20;
@@ -161,6 +159,16 @@ pub struct BlockchainManager {
     /// peer and request headers till the end of the chain.
     catchup_headers: HashSet<SocketAddr>,
 
+    /// This field contains the peers that sent a `sendheaders` message (BIP-130). These peers
+    /// announce new blocks with `headers` messages and only fall back to `inv` announcements
+    /// when a reorganization is too deep to announce with headers.
+    sendheaders_peers: HashSet<SocketAddr>,
+
+    /// This field contains the peers that advertised serving compact block filters (BIP-157).
+    /// The filter headers of new headers are requested from these peers if filter headers
+    /// are tracked.
+    compact_filter_peers: HashSet<SocketAddr>,
+
     /// This queue stores the set of block hashes belonging to blocks that have yet to be synced by the BlockChainManager
     /// and stored into the block cache.
     ///
@@ -194,6 +202,8 @@ impl BlockchainManager {
             getdata_request_info,
             getheaders_requests: HashMap::new(),
             catchup_headers: HashSet::new(),
+            sendheaders_peers: HashSet::new(),
+            compact_filter_peers: HashSet::new(),
             block_sync_queue: LinkedHashSet::new(),
             logger,
             metrics,
@@ -243,8 +253,38 @@ impl BlockchainManager {
         self.getheaders_requests.insert(*addr, request);
     }
 
+    /// This method sends a `getcfheaders` command for the basic filter headers of the blocks
+    /// from `start_height` up to `stop_hash` to the adapter.
+    /// https://github.com/bitcoin/bips/blob/master/bip-0157.mediawiki#getcfheaders
+    fn send_getcfheaders(
+        &mut self,
+        channel: &mut impl Channel,
+        addr: &SocketAddr,
+        start_height: BlockHeight,
+        stop_hash: BlockHash,
+    ) {
+        trace!(
+            self.logger,
+            "Sending getcfheaders";
+            "peer" => %addr,
+            "start_height" => start_height,
+            "stop_hash" => %stop_hash,
+        );
+        let command = Command {
+            address: Some(*addr),
+            message: NetworkMessage::GetCFHeaders(GetCFHeaders {
+                filter_type: BASIC_FILTER_TYPE,
+                start_height,
+                stop_hash,
+            }),
+        };
+        channel.send(command).ok();
+    }
+
     /// This function processes "inv" messages received from Bitcoin nodes.
     /// Given a block_hash, this method sends the corresponding "getheaders" message to the Bitcoin node.
+    /// A peer that sent `sendheaders` only announces blocks with an `inv` message after a deep
+    /// reorganization, so the headers are requested up to the end of its chain in a single request.
     async fn received_inv_message(
         &mut self,
         channel: &mut impl Channel,
@@ -284,7 +324,15 @@ impl BlockchainManager {
                 }
             }
 
-            last_block.map(|stop_hash| (blockchain_state.locator_hashes(), *stop_hash))
+            let sends_headers = self.sendheaders_peers.contains(addr);
+            last_block.map(|stop_hash| {
+                let stop_hash = if sends_headers {
+                    BlockHash::default()
+                } else {
+                    *stop_hash
+                };
+                (blockchain_state.locator_hashes(), stop_hash)
+            })
         };
 
         if let Some(locators) = maybe_locators {
@@ -313,9 +361,9 @@ impl BlockchainManager {
             .ok_or(ReceivedHeadersMessageError::UnknownPeer)?;
         trace!(
             self.logger,
-            "Received headers from {}: {}",
-            addr,
-            headers.len()
+            "Received headers";
+            "peer" => %addr,
+            "count" => headers.len(),
         );
         // If no `getheaders` request was sent to the peer, the `headers` message is unsolicited.
         // Don't accept more than a few headers in that case.
@@ -338,18 +386,25 @@ impl BlockchainManager {
             }
         };
 
+        let mut maybe_getcfheaders = None;
         let maybe_locators = {
             let mut blockchain_state = self.blockchain.lock().await;
             let prev_tip_height = blockchain_state.get_active_chain_tip().height;
 
             let (added_headers, maybe_err) = blockchain_state.add_headers(headers);
+            if blockchain_state.tracks_filter_headers() && self.compact_filter_peers.contains(addr)
+            {
+                if let (Some(first), Some(last)) = (added_headers.first(), added_headers.last()) {
+                    maybe_getcfheaders = Some((first.height, last.header.block_hash()));
+                }
+            }
             let active_tip = blockchain_state.get_active_chain_tip();
             if prev_tip_height < active_tip.height {
                 info!(
                     self.logger,
-                    "Added headers: Height = {}, Active chain's tip = {}",
-                    active_tip.height,
-                    active_tip.header.block_hash()
+                    "Added headers, active tip advanced";
+                    "height" => active_tip.height,
+                    "block_hash" => %active_tip.header.block_hash(),
                 );
             }
 
@@ -371,19 +426,27 @@ impl BlockchainManager {
                     peer.height = last.height;
                     trace!(
                         self.logger,
-                        "Peer {}'s height = {}, tip = {}",
-                        addr,
-                        peer.height,
-                        peer.tip
+                        "Updated peer tip";
+                        "peer" => %addr,
+                        "height" => peer.height,
+                        "block_hash" => %peer.tip,
                     );
                 }
             }
 
             match maybe_err {
-                Some(AddHeaderError::InvalidHeader(_, _)) => {
+                Some(AddHeaderError::InvalidHeader(_, _))
+                | Some(AddHeaderError::UnexpectedDifficulty { .. })
+                | Some(AddHeaderError::UnexpectedGenesis(_)) => {
                     return Err(ReceivedHeadersMessageError::ReceivedInvalidHeader)
                 }
                 Some(AddHeaderError::PrevHeaderNotCached(stop_hash)) => {
+                    debug!(
+                        self.logger,
+                        "Received headers out of order, requesting the gap";
+                        "peer" => %addr,
+                        "missing_block_hash" => %stop_hash,
+                    );
                     Some((blockchain_state.locator_hashes(), stop_hash))
                 }
                 None => {
@@ -408,9 +471,38 @@ impl BlockchainManager {
             self.getheaders_requests.remove(addr);
         }
 
+        if let Some((start_height, stop_hash)) = maybe_getcfheaders {
+            self.send_getcfheaders(channel, addr, start_height, stop_hash);
+        }
+
         Ok(())
     }
 
+    /// This function processes "cfheaders" messages received from Bitcoin nodes.
+    async fn received_cfheaders_message(
+        &mut self,
+        addr: &SocketAddr,
+        message: &CFHeaders,
+    ) -> Result<(), AddFilterHeadersError> {
+        let result = self.blockchain.lock().await.add_filter_headers(message);
+        match &result {
+            Ok(count) => trace!(
+                self.logger,
+                "Added filter headers";
+                "peer" => %addr,
+                "count" => count,
+                "stop_hash" => %message.stop_hash,
+            ),
+            Err(err) => debug!(
+                self.logger,
+                "Failed to add filter headers";
+                "peer" => %addr,
+                "error" => %err,
+            ),
+        }
+        result.map(|_| ())
+    }
+
     /// This function processes "block" messages received from Bitcoin nodes
     async fn received_block_message(
         &mut self,
@@ -434,25 +526,34 @@ impl BlockchainManager {
         let time_taken = request.sent_at.map(|i| i.elapsed()).unwrap_or_default();
         trace!(
             self.logger,
-            "Received block message from {} : Took {:?}sec. Block {:?}",
-            addr,
-            time_taken,
-            block_hash
+            "Received block";
+            "peer" => %addr,
+            "block_hash" => %block_hash,
+            "time_taken" => ?time_taken,
         );
 
         match self.blockchain.lock().await.add_block(block.clone()) {
             Ok(block_height) => {
+                if request.sent_at.is_some() {
+                    self.metrics
+                        .block_download_latency
+                        .observe(time_taken.as_secs_f64());
+                }
                 trace!(
                     self.logger,
-                    "Block added to the cache successfully at height = {}",
-                    block_height
+                    "Block added to the cache";
+                    "block_hash" => %block_hash,
+                    "height" => block_height,
                 );
                 Ok(())
             }
             Err(err) => {
                 warn!(
                     self.logger,
-                    "Unable to add the received block in blockchain. Error: {:?}", err
+                    "Unable to add the received block";
+                    "peer" => %addr,
+                    "block_hash" => %block_hash,
+                    "error" => ?err,
                 );
                 Err(ReceivedBlockMessageError::BlockNotAdded)
             }
@@ -468,10 +569,7 @@ impl BlockchainManager {
 
         let (initial_hash, locator_hashes) = {
             let blockchain = self.blockchain.lock().await;
-            (
-                blockchain.genesis().header.block_hash(),
-                blockchain.locator_hashes(),
-            )
+            (blockchain.genesis_hash(), blockchain.locator_hashes())
         };
 
         trace!(self.logger, "Adding peer_info with addr : {} ", addr);
@@ -492,6 +590,8 @@ impl BlockchainManager {
     fn remove_peer(&mut self, addr: &SocketAddr) {
         trace!(self.logger, "Removing peer_info with addr : {} ", addr);
         self.peer_info.remove(addr);
+        self.sendheaders_peers.remove(addr);
+        self.compact_filter_peers.remove(addr);
         // Removing all the `getdata` requests that have been sent to the peer before.
         for request in self.getdata_request_info.values_mut() {
             if request.socket == *addr {
@@ -654,12 +754,12 @@ impl BlockchainManager {
                 }
             }
             NetworkMessage::Headers(headers) => {
-                if self
-                    .received_headers_message(channel, &addr, headers)
-                    .await
-                    .is_err()
-                {
-                    return Err(ProcessBitcoinNetworkMessageError::InvalidMessage);
+                match self.received_headers_message(channel, &addr, headers).await {
+                    Err(ReceivedHeadersMessageError::ReceivedInvalidHeader) => {
+                        return Err(ProcessBitcoinNetworkMessageError::InvalidHeader);
+                    }
+                    Err(_) => return Err(ProcessBitcoinNetworkMessageError::InvalidMessage),
+                    Ok(()) => {}
                 }
             }
             NetworkMessage::Block(block) => {
@@ -667,6 +767,27 @@ impl BlockchainManager {
                     return Err(ProcessBitcoinNetworkMessageError::InvalidMessage);
                 }
             }
+            // The negotiated features are reset when the peer reconnects.
+            NetworkMessage::Version(version) => {
+                self.sendheaders_peers.remove(&addr);
+                if version.services.has(ServiceFlags::COMPACT_FILTERS) {
+                    self.compact_filter_peers.insert(addr);
+                } else {
+                    self.compact_filter_peers.remove(&addr);
+                }
+            }
+            NetworkMessage::CFHeaders(cfheaders) => {
+                // Filter headers that do not connect to the stored filter headers are invalid.
+                if let Err(AddFilterHeadersError::PrevFilterHeaderMismatch(_)) =
+                    self.received_cfheaders_message(&addr, cfheaders).await
+                {
+                    return Err(ProcessBitcoinNetworkMessageError::InvalidMessage);
+                }
+            }
+            NetworkMessage::SendHeaders => {
+                trace!(self.logger, "Peer prefers headers announcements"; "peer" => %addr);
+                self.sendheaders_peers.insert(addr);
+            }
             _ => {}
         };
         Ok(())
@@ -706,6 +827,10 @@ impl BlockchainManager {
 
         self.sync_blocks(channel).await;
         self.handle_getheaders_timeouts(channel);
+        self.blockchain
+            .lock()
+            .await
+            .update_seconds_since_last_block();
     }
 
     /// Add block hashes to the sync queue that are not already being synced, planned to be synced,
@@ -714,7 +839,7 @@ impl BlockchainManager {
         let state = self.blockchain.lock().await;
         for header in next_headers {
             let hash = header.block_hash();
-            if state.get_block(&hash).is_none()
+            if !state.contains_block(&hash)
                 && !self.block_sync_queue.contains(&hash)
                 && !self.getdata_request_info.contains_key(&hash)
             {
@@ -759,7 +884,7 @@ impl BlockchainManager {
 
     /// Retrieves the height of the active tip.
     pub async fn get_height(&self) -> BlockHeight {
-        self.blockchain.lock().await.get_active_chain_tip().height
+        self.blockchain.lock().await.active_tip_height()
     }
 }
 
@@ -783,13 +908,16 @@ fn get_next_block_hash_to_sync(
 pub mod test {
     use super::*;
     use crate::common::test_common::{
-        generate_headers, generate_large_block_blockchain, TestChannel, TestState, BLOCK_1_ENCODED,
-        BLOCK_2_ENCODED,
+        generate_header, generate_headers, generate_large_block_blockchain, TestChannel, TestState,
+        BLOCK_1_ENCODED, BLOCK_2_ENCODED,
     };
     use crate::config::test::ConfigBuilder;
     use crate::config::Config;
     use bitcoin::blockdata::constants::genesis_block;
     use bitcoin::consensus::deserialize;
+    use bitcoin::network::{
+        address::Address, constants::ServiceFlags, message_network::VersionMessage,
+    };
     use bitcoin::Network;
     use bitcoin::{
         network::message::NetworkMessage, network::message_blockdata::Inventory, BlockHash,
@@ -1050,6 +1178,78 @@ pub mod test {
         }
     }
 
+    /// This function tests that the `sendheaders` preference of a peer is recorded and changes
+    /// how headers are requested after an `inv` announcement.
+    /// Test Steps:
+    /// 1. Add two peers, only the first of which sends a `sendheaders` message.
+    /// 2. Both peers announce an unknown block with an `inv` message.
+    /// 3. Check that headers are requested up to the end of the chain from the first peer and up to
+    ///    the announced block from the second peer.
+    /// 4. Check that the preference is reset when the first peer reconnects.
+    #[tokio::test]
+    async fn test_received_inv_from_sendheaders_peer() {
+        let sockets = vec![
+            SocketAddr::from_str("127.0.0.1:8333").expect("bad address format"),
+            SocketAddr::from_str("127.0.0.2:8333").expect("bad address format"),
+        ];
+        let mut channel = TestChannel::new(sockets.clone());
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let (genesis, mut blockchain_manager) = create_blockchain_manager(&config);
+        for socket in &sockets {
+            blockchain_manager.add_peer(&mut channel, socket).await;
+        }
+        // Remove the `getheaders` requests generated by `add_peer`.
+        while channel.pop_front().is_some() {}
+        blockchain_manager.getheaders_requests.clear();
+
+        assert!(blockchain_manager
+            .process_bitcoin_network_message(&mut channel, sockets[0], &NetworkMessage::SendHeaders)
+            .await
+            .is_ok());
+        assert!(blockchain_manager.sendheaders_peers.contains(&sockets[0]));
+        assert!(!blockchain_manager.sendheaders_peers.contains(&sockets[1]));
+
+        let announced = generate_header(genesis.block_hash(), genesis.time, 0);
+        let message = NetworkMessage::Inv(vec![Inventory::Block(announced.block_hash())]);
+        for socket in &sockets {
+            assert!(blockchain_manager
+                .process_bitcoin_network_message(&mut channel, *socket, &message)
+                .await
+                .is_ok());
+        }
+
+        let stop_hashes: Vec<_> = std::iter::from_fn(|| channel.pop_front())
+            .map(|command| match command.message {
+                NetworkMessage::GetHeaders(get_headers_message) => {
+                    (command.address, get_headers_message.stop_hash)
+                }
+                message => panic!("Unexpected message: {:?}", message),
+            })
+            .collect();
+        assert_eq!(
+            stop_hashes,
+            vec![
+                (Some(sockets[0]), BlockHash::default()),
+                (Some(sockets[1]), announced.block_hash()),
+            ]
+        );
+
+        let version = NetworkMessage::Version(VersionMessage::new(
+            ServiceFlags::NETWORK,
+            0,
+            Address::new(&sockets[0], ServiceFlags::NETWORK),
+            Address::new(&sockets[0], ServiceFlags::NONE),
+            1,
+            String::from("test"),
+            0,
+        ));
+        assert!(blockchain_manager
+            .process_bitcoin_network_message(&mut channel, sockets[0], &version)
+            .await
+            .is_ok());
+        assert!(!blockchain_manager.sendheaders_peers.contains(&sockets[0]));
+    }
+
     /// This test performs a surface level check to make ensure the `sync_blocks` and `received_block_message`
     /// adds to and removes from `BlockchainManager.getdata_request_info` correctly.
     #[tokio::test]
@@ -1230,6 +1430,45 @@ pub mod test {
         );
     }
 
+    /// This function tests that the time between sending a `getdata` request and adding the
+    /// received block is observed as the block download latency.
+    /// Test Steps:
+    /// 1. Record a `getdata` request for block 1 that was sent a known time ago.
+    /// 2. Receive block 1 from the peer.
+    /// 3. Check that the latency has been observed.
+    #[tokio::test]
+    async fn test_received_block_message_observes_download_latency() {
+        let addr = SocketAddr::from_str("127.0.0.1:8333").expect("bad address format");
+        let mut channel = TestChannel::new(vec![addr]);
+        let config = ConfigBuilder::new().build();
+        let (_, mut blockchain_manager) = create_blockchain_manager(&config);
+        let encoded_block_1 = Vec::from_hex(BLOCK_1_ENCODED).expect("unable to make vec from hex");
+        let block_1: Block = deserialize(&encoded_block_1).expect("failed to decoded block 1");
+        let delay = std::time::Duration::from_millis(250);
+        blockchain_manager.add_peer(&mut channel, &addr).await;
+
+        blockchain_manager.getdata_request_info.insert(
+            block_1.block_hash(),
+            GetDataRequestInfo {
+                socket: addr,
+                sent_at: Some(
+                    Instant::now()
+                        .checked_sub(delay)
+                        .expect("instant underflow"),
+                ),
+            },
+        );
+        let result = blockchain_manager
+            .received_block_message(&addr, &block_1)
+            .await;
+        assert!(result.is_ok());
+
+        let latency = &blockchain_manager.metrics.block_download_latency;
+        assert_eq!(latency.get_sample_count(), 1);
+        assert!(latency.get_sample_sum() >= delay.as_secs_f64());
+        assert!(latency.get_sample_sum() < 5.0);
+    }
+
     /// This function tests to ensure that the BlockchainManager retries `getdata` requests
     /// that were sent to peers that have disconnected when calling `sync_blocks`.
     #[tokio::test]
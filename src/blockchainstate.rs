@@ -1,12 +1,42 @@
-use crate::{common::BlockHeight, config::Config, metrics::BlockchainStateMetrics};
-use bitcoin::{blockdata::constants::genesis_block, Block, BlockHash, BlockHeader, Network};
+use crate::{
+    common::{BlockHeight, MAX_HEADERS_SIZE},
+    config::Config,
+    metrics::{BlockchainStateMetrics, BlockchainStateMetricsSnapshot},
+};
+use bitcoin::{
+    blockdata::{
+        constants::{genesis_block, max_target},
+        script::Builder,
+    },
+    hash_types::FilterHeader,
+    network::message_filter::CFHeaders,
+    Block, BlockHash, BlockHeader, Network,
+};
 use btc_validation::{validate_header, HeaderStore, ValidateHeaderError};
+use hashlink::LinkedHashMap;
 use metrics::MetricsRegistry;
 use parking_lot::Mutex;
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    mem::size_of,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 use std::sync::Mutex;
 use thiserror::Error;
 
+/// The number of single-block steps taken when building locators before the step starts doubling.
+const DEFAULT_LOCATOR_DENSE_HASHES: u32 = 8;
+
+/// The maximum number of locator hashes collected before the genesis hash is appended.
+const DEFAULT_LOCATOR_MAX_STEPS: u32 = 22;
+
+/// The filter type of the basic compact block filter (BIP-158).
+pub const BASIC_FILTER_TYPE: u8 = 0;
+
+/// The number of headers `revalidate_headers` validates per acquisition of the state lock.
+const REVALIDATION_BATCH_SIZE: usize = 2_000;
+
 /// This field contains the datatype used to store "work" of a Bitcoin blockchain
 pub type Work = bitcoin::util::uint::Uint256;
 
@@ -22,6 +52,61 @@ pub struct Tip {
     /// This field stores the work of the Blockchain leading up to this tip.
     /// That is, this field is the sum of work of the above header and all its ancestors.
     pub work: Work,
+    /// This field stores the order in which the tip's header was first seen.
+    /// It breaks ties between tips with equal work in favor of the earlier one.
+    pub sequence: u64,
+}
+
+/// Describes a non-active tip relative to the active chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ForkSummary {
+    /// The hash of the fork's tip.
+    pub tip_hash: BlockHash,
+    /// The height of the fork's tip.
+    pub tip_height: BlockHeight,
+    /// The hash of the last header the fork shares with the active chain.
+    pub fork_point_hash: BlockHash,
+    /// The height of the last header the fork shares with the active chain.
+    pub fork_point_height: BlockHeight,
+    /// The number of active chain blocks above the fork point.
+    pub blocks_behind: BlockHeight,
+}
+
+/// A snapshot of the sync health of the blockchain state.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyncStatus {
+    /// The height of the active tip.
+    pub tip_height: BlockHeight,
+    /// The number of known tips.
+    pub tip_count: usize,
+    /// The number of blocks in the block cache.
+    pub block_cache_entries: usize,
+    /// The total size of the blocks in the block cache in bytes.
+    pub block_cache_bytes: usize,
+    /// The number of seconds since a block was last added, if any block has been added.
+    pub seconds_since_last_block: Option<u64>,
+}
+
+/// The progress of a revalidation of the cached headers. The revalidation runs in batches, so
+/// the lock on the state can be released in between.
+#[derive(Debug, Default)]
+pub struct HeaderRevalidation {
+    /// The hashes of the headers left to validate, parents before their children.
+    queue: VecDeque<BlockHash>,
+    /// The headers that failed the validation so far.
+    failures: Vec<(BlockHash, ValidateHeaderError)>,
+}
+
+impl HeaderRevalidation {
+    /// Returns whether every header has been validated.
+    pub fn is_done(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Returns the headers that failed the validation.
+    pub fn into_failures(self) -> Vec<(BlockHash, ValidateHeaderError)> {
+        self.failures
+    }
 }
 
 /// A possible error that the header cache may raise.
@@ -35,6 +120,16 @@ enum HeaderCacheError {
     AlreadyExists,
 }
 
+/// The estimated memory used by a single entry of the header cache: the key and the pointer
+/// stored in the map, and the reference counts and the `HeaderNode` stored on the heap.
+const HEADER_CACHE_ENTRY_BYTES: usize = size_of::<BlockHash>()
+    + size_of::<CachedHeader>()
+    + 2 * size_of::<usize>()
+    + size_of::<HeaderNode>();
+
+/// The estimated memory used by a reference to a header in its parent's children.
+const HEADER_CACHE_CHILD_BYTES: usize = size_of::<CachedHeader>();
+
 /// Used to maintain the discovered headers from peers.
 #[derive(Debug)]
 struct HeaderCache {
@@ -42,13 +137,13 @@ struct HeaderCache {
     genesis: CachedHeader,
     /// The tree of headers that are stored in-memory.
     headers: HashMap<BlockHash, CachedHeader>,
+    /// The estimated memory used by the headers in bytes.
+    bytes: usize,
 }
 
 impl HeaderCache {
-    /// Creates a new `HeaderCache` with a set genesis header determined by the
-    /// provided network.
-    fn new(network: Network) -> Self {
-        let header = genesis_block(network).header;
+    /// Creates a new `HeaderCache` with the provided genesis header.
+    fn new(header: BlockHeader) -> Self {
         let mut headers = HashMap::new();
         let work = header.work();
         let block_hash = header.block_hash();
@@ -61,7 +156,11 @@ impl HeaderCache {
         let genesis = cached_header.clone();
         headers.insert(block_hash, cached_header);
 
-        Self { genesis, headers }
+        Self {
+            genesis,
+            headers,
+            bytes: HEADER_CACHE_ENTRY_BYTES,
+        }
     }
 
     /// Retrieves a cached header entry from internal HashMap. If not found,
@@ -70,6 +169,19 @@ impl HeaderCache {
         self.headers.get(hash)
     }
 
+    /// Removes a header from the internal HashMap and from its parent's children.
+    fn remove(&mut self, hash: &BlockHash) -> Option<CachedHeader> {
+        let cached_header = self.headers.remove(hash)?;
+        if let Some(parent) = self.get(&cached_header.header.prev_blockhash) {
+            parent
+                .children
+                .lock()
+                .retain(|child| child.header.block_hash() != *hash);
+        }
+        self.bytes -= HEADER_CACHE_ENTRY_BYTES + HEADER_CACHE_CHILD_BYTES;
+        Some(cached_header)
+    }
+
     /// Checks if the internal HashMap contains a header with the provided hash.
     fn contains(&self, hash: &BlockHash) -> bool {
         self.headers.contains_key(hash)
@@ -100,6 +212,7 @@ impl HeaderCache {
         };
 
         self.headers.insert(block_hash, cached_header);
+        self.bytes += HEADER_CACHE_ENTRY_BYTES + HEADER_CACHE_CHILD_BYTES;
 
         Ok(())
     }
@@ -137,6 +250,81 @@ pub enum AddHeaderError {
     /// This variant is used when the predecessor of the input header is not part of header_cache.
     #[error("Received a block header where we do not have the previous header in the cache: {0}")]
     PrevHeaderNotCached(BlockHash),
+    /// This variant is used when the difficulty of a header at a retarget boundary does not match
+    /// the difficulty recomputed from the previous retarget interval.
+    #[error("Received a block header with an unexpected difficulty: {block_hash} (bits: {bits:#x}, expected: {expected_bits:#x})")]
+    UnexpectedDifficulty {
+        /// The hash of the header.
+        block_hash: BlockHash,
+        /// The difficulty bits of the header.
+        bits: u32,
+        /// The recomputed difficulty bits.
+        expected_bits: u32,
+    },
+    /// This variant is used when the input header claims to be a genesis header, i.e., has no
+    /// predecessor, but differs from the configured genesis header.
+    #[error("Received a block header claiming to be a genesis header: {0}")]
+    UnexpectedGenesis(BlockHash),
+}
+
+/// The possible errors the `BlockchainState::add_filter_headers(...)` may produce.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum AddFilterHeadersError {
+    /// This variant is used when compact block filter headers are not tracked.
+    #[error("Compact block filter headers are not tracked")]
+    Disabled,
+    /// This variant is used when the filter type is not the basic filter type.
+    #[error("Received filter headers of an unsupported filter type: {0}")]
+    UnsupportedFilterType(u8),
+    /// This variant is used when the stop hash of the message is not part of the header cache.
+    #[error("Received filter headers for an unknown block: {0}")]
+    UnknownStopHash(BlockHash),
+    /// This variant is used when the message contains more filter hashes than there are
+    /// blocks up to the stop hash.
+    #[error("Received more filter hashes than blocks up to {0}")]
+    TooManyFilterHashes(BlockHash),
+    /// This variant is used when the previous filter header of the message does not match
+    /// the filter header stored for the block preceding the first block of the message.
+    #[error("Received filter headers that do not connect to the filter header of {0}")]
+    PrevFilterHeaderMismatch(BlockHash),
+}
+
+/// The parameters of the difficulty adjustment that happens at retarget boundaries.
+#[derive(Debug)]
+struct DifficultyAdjustment {
+    /// The number of blocks between retargets.
+    interval: BlockHeight,
+    /// The expected duration of a retarget interval in seconds.
+    target_timespan: u32,
+    /// The maximum target, i.e., the minimum difficulty.
+    pow_limit: Work,
+}
+
+impl DifficultyAdjustment {
+    /// Returns the difficulty adjustment of the network or `None` if the network does not
+    /// retarget.
+    fn new(network: Network) -> Option<Self> {
+        match network {
+            Network::Regtest => None,
+            _ => Some(Self {
+                interval: 2016,
+                target_timespan: 14 * 24 * 60 * 60,
+                pow_limit: max_target(network),
+            }),
+        }
+    }
+
+    /// Computes the difficulty bits following the retarget interval from the interval's first
+    /// header to its last header, as Bitcoin Core does.
+    fn next_bits(&self, first: &BlockHeader, last: &BlockHeader) -> u32 {
+        let actual_timespan = last
+            .time
+            .saturating_sub(first.time)
+            .clamp(self.target_timespan / 4, self.target_timespan * 4);
+        let target = BlockHeader::u256_from_compact_target(last.bits).mul_u32(actual_timespan)
+            / Work::from_u64(self.target_timespan as u64).expect("u64 fits into Uint256");
+        BlockHeader::compact_target_from_u256(&target.min(self.pow_limit))
+    }
 }
 
 #[derive(Debug, Error)]
@@ -147,6 +335,29 @@ pub enum AddBlockError {
     // Used to indicate when the header causes an error while adding a block to the state.
     #[error("Block's header caused an error: {0}")]
     Header(AddHeaderError),
+    /// Used to indicate that the coinbase transaction does not start with the block's height
+    /// (BIP-34).
+    #[error("Received a block whose coinbase does not encode its height {1}: {0}")]
+    InvalidCoinbaseHeight(BlockHash, BlockHeight),
+}
+
+/// The inconsistencies `BlockchainState::validate_cache_invariants(...)` may report.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum CacheInconsistency {
+    /// This variant is used when a block is cached but its header is not.
+    #[error("The block {0} is cached without its header")]
+    BlockWithoutHeader(BlockHash),
+    /// This variant is used when the header of a tip is not cached.
+    #[error("The header of the tip {0} is not cached")]
+    TipWithoutHeader(BlockHash),
+    /// This variant is used when a header or tip is stored with a height that does not follow
+    /// from its parent.
+    #[error("The header {block_hash} is stored at height {actual} instead of {expected}")]
+    HeightMismatch {
+        block_hash: BlockHash,
+        expected: BlockHeight,
+        actual: BlockHeight,
+    },
 }
 
 /// This struct is a cache of Bitcoin blockchain.
@@ -157,14 +368,60 @@ pub struct BlockchainState {
     /// This field stores all the Bitcoin headers using a HashMap containining BlockHash and the corresponding header.
     header_cache: HeaderCache,
 
+    /// The hash of the genesis header, computed once as it is needed frequently.
+    genesis_hash: BlockHash,
+
     /// This field stores a hashmap containing BlockHash and the corresponding Block.
-    block_cache: HashMap<BlockHash, Block>,
+    /// The blocks are kept in insertion order, so the oldest block can be evicted first.
+    block_cache: LinkedHashMap<BlockHash, Block>,
+
+    /// This field contains the total size of the blocks in the `block_cache` in bytes.
+    block_cache_bytes: usize,
 
     /// This field contains the known tips of the header cache.
     tips: Vec<Tip>,
 
+    /// This field contains the sequence number assigned to the next added tip.
+    next_tip_sequence: u64,
+
+    /// This field contains the height of the tip with the most work among the tips being
+    /// updated by `add_header`, so header validation observes the headers accepted so far.
+    /// It is reset once the tips are replaced by `update_tips`.
+    pending_tip_height: Option<BlockHeight>,
+
+    /// The maximum number of tips. Tips with the least work are dropped when it is exceeded.
+    max_tips: usize,
+
+    /// The number of blocks below the active tip that are kept when a block is added.
+    block_cache_depth: Option<BlockHeight>,
+
+    /// The maximum number of blocks in the block cache. The lowest blocks below the active tip
+    /// are evicted when a block is added and it is exceeded.
+    max_block_cache_entries: Option<usize>,
+
+    /// The number of headers kept behind the active tip. Older headers, except for the genesis
+    /// header, are pruned when headers are added.
+    max_header_chain_length: Option<BlockHeight>,
+
+    /// The height of the anchor of the last `GetSuccessors` request. Headers at and above it
+    /// are never pruned. Headers are not pruned before the first request is received.
+    last_anchor_height: Option<BlockHeight>,
+
+    /// The number of blocks of work a competing tip needs above the active tip to replace it.
+    tip_switch_margin_blocks: Option<u32>,
+
+    /// This field stores the compact block filter headers (BIP-157) by block hash. It is `None`
+    /// if filter headers are not tracked.
+    filter_headers: Option<HashMap<BlockHash, FilterHeader>>,
+
+    /// This field contains the time the last block was successfully added to the `block_cache`.
+    last_block_added_at: Option<SystemTime>,
+
     /// Used to determine how validation should be handled with `validate_header`.
     network: Network,
+
+    /// The difficulty adjustment checked at retarget boundaries, if the network retargets.
+    difficulty_adjustment: Option<DifficultyAdjustment>,
     metrics: BlockchainStateMetrics,
 }
 
@@ -172,19 +429,38 @@ impl BlockchainState {
     /// This function is used to create a new BlockChainState object.  
     pub fn new(config: &Config, metrics_registry: &MetricsRegistry) -> Self {
         // Create a header cache and inserting dummy header corresponding the `adapter_genesis_hash`.
-        let header_cache = HeaderCache::new(config.network);
-        let block_cache = HashMap::new();
+        // A custom regtest network may override the genesis header.
+        let genesis = config
+            .custom_genesis_header()
+            .unwrap_or_else(|| genesis_block(config.network).header);
+        let genesis_hash = genesis.block_hash();
+        let header_cache = HeaderCache::new(genesis);
+        let block_cache = LinkedHashMap::new();
         let tips = vec![Tip {
             header: header_cache.genesis.header,
             height: 0,
             work: header_cache.genesis.work,
+            sequence: 0,
         }];
 
         BlockchainState {
             header_cache,
+            genesis_hash,
             block_cache,
+            block_cache_bytes: 0,
             tips,
+            next_tip_sequence: 1,
+            pending_tip_height: None,
+            max_tips: config.max_tips,
+            block_cache_depth: config.block_cache_depth,
+            max_block_cache_entries: config.max_block_cache_entries,
+            max_header_chain_length: config.max_header_chain_length,
+            last_anchor_height: None,
+            tip_switch_margin_blocks: config.tip_switch_margin_blocks,
+            filter_headers: config.compact_filters.then(HashMap::new),
+            last_block_added_at: None,
             network: config.network,
+            difficulty_adjustment: DifficultyAdjustment::new(config.network),
             metrics: BlockchainStateMetrics::new(metrics_registry),
         }
     }
@@ -194,11 +470,108 @@ impl BlockchainState {
         &self.header_cache.genesis
     }
 
+    /// Returns the hash of the genesis header that the store is initialized with.
+    pub fn genesis_hash(&self) -> BlockHash {
+        self.genesis_hash
+    }
+
     /// Returns the header for the given block hash.
     pub fn get_cached_header(&self, hash: &BlockHash) -> Option<&CachedHeader> {
         self.header_cache.get(hash)
     }
 
+    /// Returns the header and height for each of the given block hashes. The results are
+    /// positionally aligned with `hashes`, with `None` for hashes that are not cached.
+    pub fn get_headers(&self, hashes: &[BlockHash]) -> Vec<Option<(BlockHeader, BlockHeight)>> {
+        hashes
+            .iter()
+            .map(|hash| {
+                self.header_cache
+                    .get(hash)
+                    .map(|cached| (cached.header, cached.height))
+            })
+            .collect()
+    }
+
+    /// Returns true if compact block filter headers are tracked.
+    pub fn tracks_filter_headers(&self) -> bool {
+        self.filter_headers.is_some()
+    }
+
+    /// Returns the compact block filter header of the block with the given hash.
+    pub fn get_filter_header(&self, hash: &BlockHash) -> Option<&FilterHeader> {
+        self.filter_headers.as_ref()?.get(hash)
+    }
+
+    /// Processes the `cfheaders` message received from Bitcoin nodes by deriving the filter
+    /// header of each block ending at the message's stop hash. The previous filter header is
+    /// checked against the stored filter header of the preceding block, if there is one.
+    /// Returns the number of filter headers added.
+    pub fn add_filter_headers(
+        &mut self,
+        message: &CFHeaders,
+    ) -> Result<usize, AddFilterHeadersError> {
+        if message.filter_type != BASIC_FILTER_TYPE {
+            return Err(AddFilterHeadersError::UnsupportedFilterType(
+                message.filter_type,
+            ));
+        }
+        let filter_headers = self
+            .filter_headers
+            .as_ref()
+            .ok_or(AddFilterHeadersError::Disabled)?;
+
+        // Collect the block hashes from the stop hash back to the first block of the message.
+        let mut block_hashes = Vec::with_capacity(message.filter_hashes.len());
+        let mut current = self
+            .header_cache
+            .get(&message.stop_hash)
+            .ok_or(AddFilterHeadersError::UnknownStopHash(message.stop_hash))?;
+        for _ in 0..message.filter_hashes.len() {
+            block_hashes.push(current.header.block_hash());
+            current = match self.header_cache.get(&current.header.prev_blockhash) {
+                Some(prev) => prev,
+                None if block_hashes.len() == message.filter_hashes.len() => break,
+                None => {
+                    return Err(AddFilterHeadersError::TooManyFilterHashes(
+                        message.stop_hash,
+                    ))
+                }
+            };
+        }
+        block_hashes.reverse();
+
+        // The first block of the message is the genesis block if it has no predecessor.
+        let expected_prev = match block_hashes.first() {
+            Some(first)
+                if self
+                    .header_cache
+                    .get(first)
+                    .map_or(false, |c| c.height == 0) =>
+            {
+                Some(FilterHeader::default())
+            }
+            Some(_) => filter_headers.get(&current.header.block_hash()).copied(),
+            None => None,
+        };
+        if matches!(expected_prev, Some(prev) if prev != message.previous_filter_header) {
+            return Err(AddFilterHeadersError::PrevFilterHeaderMismatch(
+                current.header.block_hash(),
+            ));
+        }
+
+        let filter_headers = self
+            .filter_headers
+            .as_mut()
+            .ok_or(AddFilterHeadersError::Disabled)?;
+        let mut prev = message.previous_filter_header;
+        for (block_hash, filter_hash) in block_hashes.iter().zip(message.filter_hashes.iter()) {
+            prev = filter_hash.filter_header(&prev);
+            filter_headers.insert(*block_hash, prev);
+        }
+        Ok(block_hashes.len())
+    }
+
     /// Processes the `headers` message received from Bitcoin nodes by adding them to the state.
     /// Headers are expected to be sorted. If they are not, the headers will be likely be rejected
     /// with a [AddHeaderError::PrevHeaderNotCached](AddHeaderError::PrevHeaderNotCached) error.
@@ -209,10 +582,14 @@ impl BlockchainState {
         headers: &[BlockHeader],
     ) -> (Vec<CachedHeader>, Option<AddHeaderError>) {
         let mut added_headers = vec![];
+        // The tips are updated on a copy that replaces the tips once all headers are added,
+        // so the active tip moves from its pre-call to its post-call state in a single step.
+        // Validation observes the height of the copy through `pending_tip_height`.
+        let mut tips = self.tips.clone();
 
         let err = headers
             .iter()
-            .try_for_each(|header| match self.add_header(*header) {
+            .try_for_each(|header| match self.add_header(*header, &mut tips) {
                 Ok(AddHeaderResult::HeaderAdded(cached_header)) => {
                     added_headers.push(cached_header);
                     Ok(())
@@ -222,19 +599,36 @@ impl BlockchainState {
             })
             .err();
 
-        // Sort the tips by the total work
-        self.tips.sort_unstable_by(|a, b| b.work.cmp(&a.work));
-        self.metrics.tips.set(self.tips.len() as i64);
+        self.update_tips(tips);
+        // The headers the canister's next request starts from must be kept, so headers are
+        // only pruned below the anchor of the last request.
+        if let (Some(length), Some(anchor_height)) =
+            (self.max_header_chain_length, self.last_anchor_height)
+        {
+            let height = self
+                .active_tip_height()
+                .saturating_sub(length)
+                .min(anchor_height);
+            self.prune_headers_below_height(height);
+        }
+        self.set_tips_metrics();
         self.metrics
             .tip_height
             .set(self.get_active_chain_tip().height.into());
+        self.metrics
+            .tip_work_log2
+            .set(work_log2(&self.get_active_chain_work()));
 
         (added_headers, err)
     }
 
-    /// This method adds the input header to the `header_cache`.
+    /// This method adds the input header to the `header_cache` and updates the provided tips.
     #[allow(clippy::indexing_slicing)]
-    fn add_header(&mut self, header: BlockHeader) -> Result<AddHeaderResult, AddHeaderError> {
+    fn add_header(
+        &mut self,
+        header: BlockHeader,
+        tips: &mut Vec<Tip>,
+    ) -> Result<AddHeaderResult, AddHeaderError> {
         let block_hash = header.block_hash();
 
         // If the header already exists in the cache,
@@ -243,6 +637,19 @@ impl BlockchainState {
             return Ok(AddHeaderResult::HeaderAlreadyExists(cached_header.clone()));
         }
 
+        // Only the configured genesis header may be a root of the header cache.
+        if header.prev_blockhash == BlockHash::default() {
+            return Err(AddHeaderError::UnexpectedGenesis(block_hash));
+        }
+
+        // The header cannot be connected yet. This is usually caused by headers delivered out
+        // of order rather than an invalid header.
+        if self.get_cached_header(&header.prev_blockhash).is_none() {
+            self.metrics.headers_out_of_order.inc();
+            return Err(AddHeaderError::PrevHeaderNotCached(header.prev_blockhash));
+        }
+
+        self.validate_retarget(&header)?;
         if let Err(err) = validate_header(&self.network, self, &header) {
             return Err(AddHeaderError::InvalidHeader(block_hash, err));
         }
@@ -270,422 +677,3094 @@ impl BlockchainState {
 
         // Update the tip headers.
         // If the previous header already exists in `tips`, then update it with the new tip.
-        let maybe_cached_header_idx = self
-            .tips
+        let maybe_cached_header_idx = tips
             .iter()
             .position(|tip| tip.header.block_hash() == prev_hash);
         let tip = Tip {
             header,
             height: cached_header.height,
             work: cached_header.work,
+            sequence: self.next_tip_sequence,
         };
+        self.next_tip_sequence += 1;
 
         match maybe_cached_header_idx {
             Some(idx) => {
-                self.tips[idx] = tip;
+                tips[idx] = tip;
             }
             None => {
                 // If the previous header is not a tip, then add the `cached_header` as a tip.
-                self.tips.push(tip);
+                tips.push(tip);
             }
         };
+        // Ties in work are resolved in favor of the first-seen tip, as in `sort_tips`.
+        self.pending_tip_height = tips
+            .iter()
+            .max_by(|a, b| a.work.cmp(&b.work).then(b.sequence.cmp(&a.sequence)))
+            .map(|tip| tip.height);
 
         self.metrics.header_cache_size.inc();
+        self.metrics
+            .header_cache_bytes
+            .set(self.get_header_cache_bytes() as i64);
         Ok(AddHeaderResult::HeaderAdded(cached_header.clone()))
     }
 
+    /// This method checks that a header at a retarget boundary carries the difficulty
+    /// recomputed from the timespan of the previous retarget interval.
+    /// Headers that are not at a boundary or whose interval is not cached are not checked.
+    fn validate_retarget(&self, header: &BlockHeader) -> Result<(), AddHeaderError> {
+        let adjustment = match &self.difficulty_adjustment {
+            Some(adjustment) => adjustment,
+            None => return Ok(()),
+        };
+        let last = match self.get_cached_header(&header.prev_blockhash) {
+            Some(last) => last,
+            None => return Ok(()),
+        };
+        let height = last.height + 1;
+        if height % adjustment.interval != 0 {
+            return Ok(());
+        }
+
+        // Walk back to the first header of the retarget interval.
+        let mut first = last;
+        for _ in 1..adjustment.interval {
+            first = match self.get_cached_header(&first.header.prev_blockhash) {
+                Some(prev) => prev,
+                None => return Ok(()),
+            };
+        }
+
+        let expected_bits = adjustment.next_bits(&first.header, &last.header);
+        if header.bits != expected_bits {
+            return Err(AddHeaderError::UnexpectedDifficulty {
+                block_hash: header.block_hash(),
+                bits: header.bits,
+                expected_bits,
+            });
+        }
+        Ok(())
+    }
+
     /// This method adds a new block to the `block_cache`
     pub fn add_block(&mut self, block: Block) -> Result<BlockHeight, AddBlockError> {
-        let block_hash = block.block_hash();
-
         if block.compute_merkle_root().is_some() && !block.check_merkle_root() {
-            return Err(AddBlockError::InvalidMerkleRoot(block_hash));
+            return Err(AddBlockError::InvalidMerkleRoot(block.block_hash()));
+        }
+        self.check_coinbase_height(&block)?;
+        self.insert_block(block)
+    }
+
+    /// Checks that the coinbase transaction's script starts with the block's height once BIP-34
+    /// is active. Blocks without transactions or whose previous header is unknown are left to
+    /// the merkle root and header checks.
+    fn check_coinbase_height(&self, block: &Block) -> Result<(), AddBlockError> {
+        let height = match self.get_cached_header(&block.header.prev_blockhash) {
+            Some(prev) => prev.height + 1,
+            None => return Ok(()),
+        };
+        if height < bip34_activation_height(self.network) {
+            return Ok(());
+        }
+        let coinbase = match block.txdata.first() {
+            Some(coinbase) => coinbase,
+            None => return Ok(()),
+        };
+
+        let expected = Builder::new().push_int(height.into()).into_script();
+        let encodes_height = coinbase.input.first().map_or(false, |input| {
+            input.script_sig.as_bytes().starts_with(expected.as_bytes())
+        });
+        if !encodes_height {
+            return Err(AddBlockError::InvalidCoinbaseHeight(
+                block.block_hash(),
+                height,
+            ));
         }
+        Ok(())
+    }
+
+    /// This method adds a new block to the `block_cache` without verifying its merkle root.
+    /// The header is still validated.
+    ///
+    /// Skipping the merkle root check is only safe for blocks from a trusted source that has
+    /// already validated them. A block from a peer must be added with `add_block(...)`, as the
+    /// peer could otherwise pair a valid header with arbitrary transactions.
+    pub fn add_block_trusted(&mut self, block: Block) -> Result<BlockHeight, AddBlockError> {
+        self.insert_block(block)
+    }
+
+    /// Adds the block's header and then the block to the caches.
+    fn insert_block(&mut self, block: Block) -> Result<BlockHeight, AddBlockError> {
+        let block_hash = block.block_hash();
 
         // If the block's header is not added before, then add the header into the `header_cache` first.
+        let mut tips = self.tips.clone();
         let result = self
-            .add_header(block.header)
+            .add_header(block.header, &mut tips)
             .map_err(AddBlockError::Header)?;
-        self.tips.sort_unstable_by(|a, b| b.work.cmp(&a.work));
-        self.block_cache.insert(block_hash, block);
+        self.block_cache_bytes += block.size();
+        if let Some(replaced) = self.block_cache.insert(block_hash, block) {
+            self.block_cache_bytes -= replaced.size();
+        }
+        self.update_tips(tips);
+        self.set_tips_metrics();
+        if let Some(depth) = self.block_cache_depth {
+            let tip_height = self.active_tip_height();
+            if tip_height > depth {
+                self.prune_blocks_below_height(tip_height - depth);
+            }
+        }
+        if let Some(max_entries) = self.max_block_cache_entries {
+            while self.block_cache.len() > max_entries {
+                match self.lowest_block_below_tip() {
+                    Some(block_hash) => self.prune_blocks(&[block_hash]),
+                    None => break,
+                }
+            }
+        }
         self.metrics
             .block_cache_size
             .set(self.get_block_cache_size() as i64);
+        self.last_block_added_at = Some(SystemTime::now());
+        self.metrics.seconds_since_last_block.set(0);
         Ok(match result {
             AddHeaderResult::HeaderAdded(cached) => cached.height,
             AddHeaderResult::HeaderAlreadyExists(cached) => cached.height,
         })
     }
 
+    /// Updates the metrics holding the number of tips and the number of side forks.
+    fn set_tips_metrics(&self) {
+        self.metrics.tips.set(self.tips.len() as i64);
+        self.metrics
+            .active_forks
+            .set(self.tips.len().saturating_sub(1) as i64);
+    }
+
+    /// Sorts the tips and drops the tips with the least work if there are more than `max_tips`.
+    /// The updated tips replace the current tips in a single step.
+    fn update_tips(&mut self, mut tips: Vec<Tip>) {
+        self.pending_tip_height = None;
+        sort_tips(&mut tips);
+        if let Some(margin_blocks) = self.tip_switch_margin_blocks {
+            self.keep_incumbent_tip(&mut tips, margin_blocks);
+        }
+        if tips.len() > self.max_tips {
+            self.drop_excess_tips(&mut tips);
+            // The front tip is never dropped. The tips left behind by dropped branches have
+            // less work than the dropped tips, so they do not overtake a kept incumbent tip.
+            if let Some(rest) = tips.get_mut(1..) {
+                sort_tips(rest);
+            }
+        }
+        self.tips = tips;
+    }
+
+    /// Moves the tip extending the current active tip back to the front of the sorted tips
+    /// unless the tip with the most work exceeds its work by more than `margin_blocks` blocks
+    /// of work at the incumbent's difficulty.
+    #[allow(clippy::indexing_slicing)]
+    fn keep_incumbent_tip(&self, tips: &mut [Tip], margin_blocks: u32) {
+        let active = self.get_active_chain_tip();
+        let active_hash = active.header.block_hash();
+        let incumbent_idx = match tips.iter().position(|tip| {
+            self.get_ancestor_at_height(&tip.header.block_hash(), active.height)
+                .map_or(false, |ancestor| {
+                    ancestor.header.block_hash() == active_hash
+                })
+        }) {
+            Some(0) | None => return,
+            Some(idx) => idx,
+        };
+
+        let incumbent = &tips[incumbent_idx];
+        let margin = incumbent.header.work().mul_u32(margin_blocks);
+        if tips[0].work <= incumbent.work + margin {
+            tips[..=incumbent_idx].rotate_right(1);
+        }
+    }
+
+    /// Drops the tips with the least work until at most `max_tips` tips remain. The tips after
+    /// the first one are expected to be sorted. The first tip, which becomes the active tip,
+    /// and tips with a cached block are never dropped.
+    /// The headers that only belong to a dropped tip's branch are removed as well. The new tips
+    /// left behind by removed branches count towards `max_tips`, so more tips are dropped
+    /// in their place.
+    fn drop_excess_tips(&mut self, tips: &mut Vec<Tip>) {
+        let mut new_tips = vec![];
+        let mut idx = tips.len();
+        while tips.len() + new_tips.len() > self.max_tips && idx > 1 {
+            idx -= 1;
+            let tip_hash = match tips.get(idx) {
+                Some(tip) => tip.header.block_hash(),
+                None => break,
+            };
+            if self.block_cache.contains_key(&tip_hash) {
+                continue;
+            }
+            tips.remove(idx);
+            new_tips.extend(self.remove_branch(tip_hash));
+        }
+        tips.extend(new_tips);
+    }
+
+    /// Removes the header of a dropped tip and its ancestors until reaching a header that has
+    /// other children, has a cached block or is the genesis header. If that header has no
+    /// children left, it is returned as a new tip.
+    fn remove_branch(&mut self, tip_hash: BlockHash) -> Option<Tip> {
+        let genesis_hash = self.genesis_hash();
+        let mut hash = tip_hash;
+        loop {
+            let cached = self.header_cache.remove(&hash)?;
+            self.metrics.header_cache_size.dec();
+            self.metrics
+                .header_cache_bytes
+                .set(self.get_header_cache_bytes() as i64);
+            let parent = self
+                .header_cache
+                .get(&cached.header.prev_blockhash)?
+                .clone();
+            let parent_hash = parent.header.block_hash();
+            if !parent.children.lock().is_empty() {
+                return None;
+            }
+
+            if parent_hash == genesis_hash || self.block_cache.contains_key(&parent_hash) {
+                let tip = Tip {
+                    header: parent.header,
+                    height: parent.height,
+                    work: parent.work,
+                    sequence: self.next_tip_sequence,
+                };
+                self.next_tip_sequence += 1;
+                return Some(tip);
+            }
+            hash = parent_hash;
+        }
+    }
+
     /// This method returns the tip header with the highest cumulative work.
+    /// If several tips have the same work, the first seen tip is returned.
     #[allow(clippy::indexing_slicing)]
     pub fn get_active_chain_tip(&self) -> &Tip {
         // `self.tips` is initialized in the new() method with the initial header.
-        // `add_headers` sorts the tips by total work. The zero index will always be
-        // the active tip.
+        // `add_headers` sorts the tips by total work and first-seen order. The zero index
+        // will always be the active tip.
         &self.tips[0]
     }
 
-    /// This method is used to remove blocks in the `header_cache` that are found in the given
-    /// block hashes.
-    pub fn prune_blocks(&mut self, block_hashes: &[BlockHash]) {
-        for block_hash in block_hashes {
-            self.block_cache.remove(block_hash);
-        }
+    /// Returns the hash of the active chain's tip.
+    pub fn active_tip_hash(&self) -> BlockHash {
+        self.get_active_chain_tip().header.block_hash()
     }
 
-    /// Removes blocks that are below a given height from the block cache.
-    pub fn prune_blocks_below_height(&mut self, height: BlockHeight) {
-        let hashes_below_height = self
-            .block_cache
-            .keys()
-            .filter(|b| height > self.get_cached_header(b).map_or(0, |c| c.height))
-            .copied()
-            .collect::<Vec<_>>();
-        self.prune_blocks(&hashes_below_height);
+    /// Returns the height of the active chain's tip.
+    pub fn active_tip_height(&self) -> BlockHeight {
+        self.get_active_chain_tip().height
     }
 
-    /// Get the locator hashes for the active chain (the chain with the highest amount of work).
-    /// Returns the block hashes corresponding to  tip, tip - 1, tip - 2, tip - 3, tip - 4, tip - 5, tip - 6, tip - 7, tip - 8,
-    /// tip - (8 + 2), tip - (8 + 2 + 4), tip - (8 + 2 + 4 + 8), tip - (8 + 2 + 4 + 8 + 16) ..., tip - (8 + 2 + 4 + 8 + ... + 4096), adapter_gensis_hash
-    pub fn locator_hashes(&self) -> Vec<BlockHash> {
-        let mut hashes = Vec::new();
+    /// Returns whether the state is caught up with the network: the active tip's timestamp is
+    /// at most `max_tip_age` old and, if a reference height is provided, the active tip is at
+    /// least at that height. The network's tip is not known, so this is only a heuristic.
+    pub fn is_synced(&self, reference_height: Option<BlockHeight>, max_tip_age: Duration) -> bool {
         let tip = self.get_active_chain_tip();
-        let mut current_header = tip.header;
-        let mut current_hash = current_header.block_hash();
-        let mut step: u32 = 1;
-        let mut last_hash = current_hash;
-        let genesis_hash = self.genesis().header.block_hash();
-        // Push the most recent 8 block hashes start from the tip of the active chain.
-        for i in 0..22 {
-            current_hash = current_header.block_hash();
-            last_hash = current_hash;
-            hashes.push(current_hash);
-            for _j in 0..step {
-                let prev_hash = current_header.prev_blockhash;
-                //If the prev header does not exist, then simply return the `hashes` vector.
-                if let Some(cached) = self.header_cache.get(&prev_hash) {
-                    current_header = cached.header;
-                } else {
-                    if last_hash != genesis_hash {
-                        hashes.push(genesis_hash);
-                    }
-                    return hashes;
-                }
-            }
-            if i >= 7 {
-                step = step.saturating_mul(2);
-            }
+        if reference_height.map_or(false, |reference_height| tip.height < reference_height) {
+            return false;
         }
+        let tip_time = UNIX_EPOCH + Duration::from_secs(u64::from(tip.header.time));
+        // A tip with a timestamp in the future is treated as fresh.
+        SystemTime::now()
+            .duration_since(tip_time)
+            .map_or(true, |age| age <= max_tip_age)
+    }
 
-        if last_hash != genesis_hash {
-            hashes.push(genesis_hash);
-        }
-        hashes
+    /// Returns the cumulative work of the active chain's tip.
+    pub fn get_active_chain_work(&self) -> Work {
+        self.get_active_chain_tip().work
     }
 
-    /// Check whether a block hash is known i.e., stored in the `header_cache`.
-    pub fn is_block_hash_known(&self, block_hash: &BlockHash) -> bool {
-        self.header_cache.contains(block_hash)
+    /// Returns the cumulative work up to the cached header with the given hash. Returns `None`
+    /// if the header is not cached.
+    pub fn get_header_work(&self, hash: &BlockHash) -> Option<Work> {
+        self.get_cached_header(hash).map(|cached| cached.work)
     }
 
-    /// This method takes a list of block hashes as input.
-    /// For each block hash, if the corresponding block is stored in the `block_cache`, the cached block is returned.
-    pub fn get_block(&self, block_hash: &BlockHash) -> Option<&Block> {
-        self.block_cache.get(block_hash)
+    /// Walks back from the header with the given hash and returns its ancestor at the given
+    /// height. Returns `None` if the header is unknown or the height is above the header's height.
+    fn get_ancestor_at_height(
+        &self,
+        hash: &BlockHash,
+        height: BlockHeight,
+    ) -> Option<&CachedHeader> {
+        let mut current = self.get_cached_header(hash)?;
+        if current.height < height {
+            return None;
+        }
+
+        while current.height > height {
+            current = self.get_cached_header(&current.header.prev_blockhash)?;
+        }
+        Some(current)
     }
 
-    /// Used when the adapter is shutdown and no longer requires holding on to blocks.
-    pub fn clear_blocks(&mut self) {
-        self.block_cache = HashMap::new();
+    /// Returns the last common ancestor of the headers with the given hashes. Returns `None` if
+    /// either header is unknown.
+    fn find_common_ancestor(&self, a: &BlockHash, b: &BlockHash) -> Option<&CachedHeader> {
+        let height = self
+            .get_cached_header(a)?
+            .height
+            .min(self.get_cached_header(b)?.height);
+        let mut a = self.get_ancestor_at_height(a, height)?;
+        let mut b = self.get_ancestor_at_height(b, height)?;
+        while a.header.block_hash() != b.header.block_hash() {
+            a = self.get_cached_header(&a.header.prev_blockhash)?;
+            b = self.get_cached_header(&b.header.prev_blockhash)?;
+        }
+        Some(a)
     }
 
-    /// Returns the current size of the block cache.
-    pub fn get_block_cache_size(&self) -> usize {
-        self.block_cache.values().fold(0, |sum, b| b.size() + sum)
+    /// Returns a summary of each non-active tip with the point where it forks off the active
+    /// chain.
+    pub fn fork_summaries(&self) -> Vec<ForkSummary> {
+        let active_tip = self.get_active_chain_tip();
+        let active_tip_hash = active_tip.header.block_hash();
+        self.tips
+            .iter()
+            .skip(1)
+            .filter_map(|tip| {
+                let tip_hash = tip.header.block_hash();
+                let fork_point = self.find_common_ancestor(&tip_hash, &active_tip_hash)?;
+                Some(ForkSummary {
+                    tip_hash,
+                    tip_height: tip.height,
+                    fork_point_hash: fork_point.header.block_hash(),
+                    fork_point_height: fork_point.height,
+                    blocks_behind: active_tip.height.saturating_sub(fork_point.height),
+                })
+            })
+            .collect()
     }
-}
 
-impl HeaderStore for BlockchainState {
-    fn get_header(&self, hash: &BlockHash) -> Option<(BlockHeader, BlockHeight)> {
-        self.get_cached_header(hash)
-            .map(|cached| (cached.header, cached.height))
+    /// Records the height of the anchor of a `GetSuccessors` request. Headers at and above the
+    /// anchor of the last request are not pruned by `max_header_chain_length`.
+    pub fn set_last_anchor_height(&mut self, height: BlockHeight) {
+        self.last_anchor_height = Some(height);
     }
 
-    fn get_height(&self) -> BlockHeight {
-        self.get_active_chain_tip().height
+    /// Starts a revalidation of the cached headers. The walk starts from the roots of the
+    /// retained header tree, i.e., the headers whose parent is not cached: the genesis header
+    /// and, after pruning, the lowest retained headers. The roots themselves are not validated
+    /// as their parents are not available.
+    pub fn start_header_revalidation(&self) -> HeaderRevalidation {
+        let queue = self
+            .header_cache
+            .headers
+            .values()
+            .filter(|cached| !self.header_cache.contains(&cached.header.prev_blockhash))
+            .flat_map(|root| {
+                root.children
+                    .lock()
+                    .iter()
+                    .map(|child| child.header.block_hash())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        HeaderRevalidation {
+            queue,
+            failures: vec![],
+        }
     }
 
-    fn get_initial_hash(&self) -> BlockHash {
-        self.genesis().header.block_hash()
+    /// Runs the header validation against up to `max` of the headers left in the revalidation,
+    /// parents before their children. The state is not changed. Headers removed since the
+    /// revalidation started are skipped along with their descendants.
+    pub fn revalidate_headers_batch(&self, revalidation: &mut HeaderRevalidation, max: usize) {
+        for _ in 0..max {
+            let hash = match revalidation.queue.pop_front() {
+                Some(hash) => hash,
+                None => return,
+            };
+            let cached = match self.get_cached_header(&hash) {
+                Some(cached) => cached,
+                None => continue,
+            };
+            if let Err(err) = validate_header(&self.network, self, &cached.header) {
+                revalidation.failures.push((hash, err));
+            }
+            revalidation.queue.extend(
+                cached
+                    .children
+                    .lock()
+                    .iter()
+                    .map(|child| child.header.block_hash()),
+            );
+        }
     }
-}
 
-#[cfg(test)]
-mod test {
-    use bitcoin::TxMerkleNode;
-    use metrics::MetricsRegistry;
+    /// Returns the cached block at the given height on the active chain. Returns `None` if the
+    /// height is above the active tip or the block is not cached.
+    pub fn get_active_chain_block_at_height(&self, height: BlockHeight) -> Option<&Block> {
+        let cached = self.get_ancestor_at_height(&self.active_tip_hash(), height)?;
+        self.block_cache.get(&cached.header.block_hash())
+    }
 
-    use super::*;
-    use crate::{
-        common::test_common::{block_1, block_2, generate_header, generate_headers, TestState},
-        config::test::ConfigBuilder,
-    };
-    use std::collections::HashSet;
+    /// Returns the number of confirmations the block with the given hash has relative to the
+    /// active chain's tip. A block on a side fork has `0` confirmations. Returns `None` if
+    /// the block hash is unknown.
+    pub fn get_confirmations(&self, hash: &BlockHash) -> Option<i64> {
+        let cached = self.get_cached_header(hash)?;
+        let tip = self.get_active_chain_tip();
+        let tip_hash = tip.header.block_hash();
+        let is_on_active_chain = self
+            .get_ancestor_at_height(&tip_hash, cached.height)
+            .map_or(false, |ancestor| ancestor.header.block_hash() == *hash);
+
+        if is_on_active_chain {
+            Some(i64::from(tip.height) - i64::from(cached.height) + 1)
+        } else {
+            Some(0)
+        }
+    }
 
-    #[test]
-    fn test_get_block() {
-        let test_state = TestState::setup();
-        let config = ConfigBuilder::new().build();
-        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+    /// This method is used to remove blocks in the `header_cache` that are found in the given
+    /// block hashes.
+    pub fn prune_blocks(&mut self, block_hashes: &[BlockHash]) {
+        for block_hash in block_hashes {
+            if let Some(block) = self.block_cache.remove(block_hash) {
+                self.block_cache_bytes -= block.size();
+            }
+        }
+    }
 
-        state
-            .add_block(test_state.block_1.clone())
-            .expect("should be able to add block 1");
-        let block_1_hash = test_state.block_1.block_hash();
-        let block_2_hash = test_state.block_2.block_hash();
-        let block_hashes: HashSet<_> = vec![block_1_hash, block_2_hash].into_iter().collect();
+    /// Removes the headers below the given height from the header cache, together with their
+    /// blocks and the tips among them. The genesis header is kept, so the locators still
+    /// terminate at it. The remaining header with the lowest height on each chain is left without
+    /// a cached parent.
+    fn prune_headers_below_height(&mut self, height: BlockHeight) {
+        let mut stale_headers: Vec<(BlockHeight, BlockHash)> = self
+            .header_cache
+            .headers
+            .iter()
+            .filter(|(_, cached)| cached.height > 0 && cached.height < height)
+            .map(|(hash, cached)| (cached.height, *hash))
+            .collect();
+        if stale_headers.is_empty() {
+            return;
+        }
 
-        let mut cached_blocks = vec![];
-        for hash in &block_hashes {
-            if let Some(block) = state.get_block(hash) {
-                cached_blocks.push(block);
+        // Remove parents before their children, so the children lists of removed headers are
+        // not updated needlessly.
+        stale_headers.sort();
+        let stale_hashes: Vec<BlockHash> = stale_headers.iter().map(|(_, hash)| *hash).collect();
+        for hash in &stale_hashes {
+            self.header_cache.remove(hash);
+            if let Some(filter_headers) = self.filter_headers.as_mut() {
+                filter_headers.remove(hash);
             }
         }
+        self.prune_blocks(&stale_hashes);
+        self.tips.retain(|tip| tip.height >= height);
 
-        assert_eq!(cached_blocks.len(), 1);
-        let block = cached_blocks.get(0).expect("there should be 1");
-        assert_eq!(block.block_hash(), block_1_hash);
-    }
-    /// Tests whether or not the `BlockchainState::add_headers(...)` function can add headers to the cache
+        self.metrics
+            .header_cache_size
+            .sub(stale_hashes.len() as i64);
+        self.metrics
+            .header_cache_bytes
+            .set(self.get_header_cache_bytes() as i64);
+        self.metrics
+            .block_cache_size
+            .set(self.get_block_cache_size() as i64);
+    }
+
+    /// Removes every header and block that is not on the active chain, leaving the active tip
+    /// as the only tip. The active chain itself is never touched.
+    pub fn prune_to_active_chain(&mut self) {
+        let mut active_chain: HashSet<BlockHash> = self
+            .active_chain_iter()
+            .map(|cached| cached.header.block_hash())
+            .collect();
+        // The active chain may not reach the genesis header if old headers have been pruned.
+        active_chain.insert(self.genesis_hash());
+
+        let stale_headers: Vec<BlockHash> = self
+            .header_cache
+            .headers
+            .keys()
+            .filter(|hash| !active_chain.contains(hash))
+            .copied()
+            .collect();
+        for hash in &stale_headers {
+            self.header_cache.remove(hash);
+            if let Some(filter_headers) = self.filter_headers.as_mut() {
+                filter_headers.remove(hash);
+            }
+        }
+
+        let stale_blocks: Vec<BlockHash> = self
+            .block_cache
+            .keys()
+            .filter(|hash| !active_chain.contains(hash))
+            .copied()
+            .collect();
+        self.prune_blocks(&stale_blocks);
+
+        self.tips.truncate(1);
+        self.set_tips_metrics();
+        self.metrics
+            .header_cache_size
+            .sub(stale_headers.len() as i64);
+        self.metrics
+            .header_cache_bytes
+            .set(self.get_header_cache_bytes() as i64);
+        self.metrics
+            .block_cache_size
+            .set(self.get_block_cache_size() as i64);
+    }
+
+    /// Evicts the oldest inserted block that is below the active tip from the block cache.
+    /// Blocks whose headers are unknown are evicted as well. Returns the hash of the evicted block.
+    pub fn evict_oldest_block(&mut self) -> Option<BlockHash> {
+        let tip_height = self.get_active_chain_tip().height;
+        let block_hash = self
+            .block_cache
+            .keys()
+            .find(|hash| {
+                self.get_cached_header(hash)
+                    .map_or(true, |cached| cached.height < tip_height)
+            })
+            .copied()?;
+        self.prune_blocks(&[block_hash]);
+        self.metrics
+            .block_cache_size
+            .set(self.get_block_cache_size() as i64);
+        Some(block_hash)
+    }
+
+    /// Returns the hash of the cached block with the lowest height below the active tip. Blocks
+    /// whose headers are unknown are considered the lowest.
+    fn lowest_block_below_tip(&self) -> Option<BlockHash> {
+        let tip_height = self.active_tip_height();
+        self.block_cache
+            .keys()
+            .map(|hash| (hash, self.get_cached_header(hash).map_or(0, |c| c.height)))
+            .filter(|(_, height)| *height < tip_height)
+            .min_by_key(|(_, height)| *height)
+            .map(|(hash, _)| *hash)
+    }
+
+    /// Removes blocks that are below a given height from the block cache.
+    pub fn prune_blocks_below_height(&mut self, height: BlockHeight) {
+        let hashes_below_height = self
+            .block_cache
+            .keys()
+            .filter(|b| height > self.get_cached_header(b).map_or(0, |c| c.height))
+            .copied()
+            .collect::<Vec<_>>();
+        self.prune_blocks(&hashes_below_height);
+    }
+
+    /// Removes blocks from the block cache while always retaining the `keep_last` highest
+    /// blocks on the active chain. Blocks below the lowest retained block are removed.
+    /// If `keep_last` is zero, all blocks up to the active tip are removed.
+    pub fn prune_blocks_keeping_recent(&mut self, keep_last: usize) {
+        let lowest_cached_height = match self
+            .block_cache
+            .keys()
+            .filter_map(|hash| self.get_cached_header(hash))
+            .map(|cached| cached.height)
+            .min()
+        {
+            Some(height) => height,
+            None => return,
+        };
+
+        let threshold = match keep_last.checked_sub(1) {
+            None => self.get_active_chain_tip().height.saturating_add(1),
+            Some(idx) => {
+                match self
+                    .active_chain_iter()
+                    .take_while(|cached| cached.height >= lowest_cached_height)
+                    .filter(|cached| self.contains_block(&cached.header.block_hash()))
+                    .nth(idx)
+                {
+                    Some(cached) => cached.height,
+                    None => return,
+                }
+            }
+        };
+
+        self.prune_blocks_below_height(threshold);
+        self.metrics
+            .block_cache_size
+            .set(self.get_block_cache_size() as i64);
+    }
+
+    /// Removes blocks that are above a given height from the block cache.
+    /// Blocks whose headers are unknown are left untouched.
+    pub fn prune_blocks_above_height(&mut self, height: BlockHeight) {
+        let hashes_above_height = self
+            .block_cache
+            .keys()
+            .filter(|b| {
+                self.get_cached_header(b)
+                    .map_or(false, |c| c.height > height)
+            })
+            .copied()
+            .collect::<Vec<_>>();
+        self.prune_blocks(&hashes_above_height);
+        self.metrics
+            .block_cache_size
+            .set(self.get_block_cache_size() as i64);
+    }
+
+    /// Get the locator hashes for the active chain (the chain with the highest amount of work).
+    /// Returns the block hashes corresponding to  tip, tip - 1, tip - 2, tip - 3, tip - 4, tip - 5, tip - 6, tip - 7, tip - 8,
+    /// tip - (8 + 2), tip - (8 + 2 + 4), tip - (8 + 2 + 4 + 8), tip - (8 + 2 + 4 + 8 + 16) ..., tip - (8 + 2 + 4 + 8 + ... + 4096), adapter_gensis_hash
+    pub fn locator_hashes(&self) -> Vec<BlockHash> {
+        let hashes = self.locator_hashes_from(&self.active_tip_hash());
+        self.metrics.locator_hashes.set(hashes.len() as i64);
+        hashes
+    }
+
+    /// Get the locator hashes starting at the header with the given hash instead of the active tip.
+    /// The same step schedule as `locator_hashes` is used. Returns an empty vector if the hash is unknown.
+    pub fn locator_hashes_from(&self, start: &BlockHash) -> Vec<BlockHash> {
+        match self.header_cache.get(start) {
+            Some(cached) => self.collect_locator_hashes(
+                cached.header,
+                DEFAULT_LOCATOR_DENSE_HASHES,
+                DEFAULT_LOCATOR_MAX_STEPS,
+            ),
+            None => vec![],
+        }
+    }
+
+    /// Get the locator hashes for the active chain using a custom step schedule.
+    /// The first `dense` steps walk back a single block, after which the step doubles on
+    /// every iteration. At most `max_steps` hashes are collected before the genesis hash is
+    /// appended.
+    pub fn locator_hashes_with(&self, dense: u32, max_steps: u32) -> Vec<BlockHash> {
+        self.collect_locator_hashes(self.get_active_chain_tip().header, dense, max_steps)
+    }
+
+    /// Collects the locator hashes walking back from the `start` header with the given step schedule.
+    fn collect_locator_hashes(
+        &self,
+        start: BlockHeader,
+        dense: u32,
+        max_steps: u32,
+    ) -> Vec<BlockHash> {
+        let mut hashes = Vec::new();
+        let mut current_header = start;
+        let mut current_hash = current_header.block_hash();
+        let mut step: u32 = 1;
+        let mut last_hash = current_hash;
+        let genesis_hash = self.genesis_hash();
+        // Push the most recent `dense` block hashes start from the `start` header.
+        for i in 0..max_steps {
+            current_hash = current_header.block_hash();
+            last_hash = current_hash;
+            hashes.push(current_hash);
+            for _j in 0..step {
+                let prev_hash = current_header.prev_blockhash;
+                //If the prev header does not exist, then simply return the `hashes` vector.
+                if let Some(cached) = self.header_cache.get(&prev_hash) {
+                    current_header = cached.header;
+                } else {
+                    if last_hash != genesis_hash {
+                        hashes.push(genesis_hash);
+                    }
+                    return hashes;
+                }
+            }
+            if i.saturating_add(1) >= dense {
+                step = step.saturating_mul(2);
+            }
+        }
+
+        if last_hash != genesis_hash {
+            hashes.push(genesis_hash);
+        }
+        hashes
+    }
+
+    /// Returns an iterator over the headers of the active chain, from the active tip back to
+    /// the genesis header.
+    pub fn active_chain_iter(&self) -> ActiveChainIter<'_> {
+        ActiveChainIter {
+            state: self,
+            next: self.get_cached_header(&self.get_active_chain_tip().header.block_hash()),
+        }
+    }
+
+    /// Returns up to `max` headers following the header with the given hash on the active
+    /// chain, in chain order. At most `MAX_HEADERS_SIZE` headers are returned, so they fit into
+    /// a single `headers` message. Returns no headers if the hash is not on the active chain.
+    pub fn active_chain_headers_batch(&self, after: &BlockHash, max: usize) -> Vec<BlockHeader> {
+        let after_height = match self.get_cached_header(after) {
+            Some(cached) if self.is_on_active_chain(cached) => cached.height,
+            _ => return vec![],
+        };
+
+        let max = max.min(MAX_HEADERS_SIZE) as BlockHeight;
+        let last_height = self
+            .active_tip_height()
+            .min(after_height.saturating_add(max));
+        let mut headers: Vec<BlockHeader> = self
+            .active_chain_iter()
+            .skip_while(|cached| cached.height > last_height)
+            .take_while(|cached| cached.height > after_height)
+            .map(|cached| cached.header)
+            .collect();
+        headers.reverse();
+        headers
+    }
+
+    /// Returns the headers of the active chain following a locator as a response to a
+    /// `getheaders` request. The first locator hash that is on the active chain is the starting
+    /// point. If no locator hash is on the active chain, the genesis header is the starting point.
+    /// At most `max` headers after the starting point are returned, stopping at the `stop` hash
+    /// (inclusive) or at the active tip. If the headers following the genesis header have been
+    /// pruned, a starting point below the retained headers yields no headers.
+    pub fn headers_following_locator(
+        &self,
+        locator: &[BlockHash],
+        stop: BlockHash,
+        max: usize,
+    ) -> Vec<BlockHeader> {
+        let start = locator
+            .iter()
+            .filter_map(|hash| self.get_cached_header(hash))
+            .find(|cached| self.is_on_active_chain(cached))
+            .map_or(self.genesis_hash(), |cached| cached.header.block_hash());
+
+        let mut headers = self.active_chain_headers_batch(&start, max);
+        if let Some(stop_index) = headers
+            .iter()
+            .position(|header| header.block_hash() == stop)
+        {
+            headers.truncate(stop_index + 1);
+        }
+        headers
+    }
+
+    /// Checks whether the cached header is on the active chain. The ancestor of the active tip
+    /// at the header's height is looked up, so the active chain does not need to reach the
+    /// genesis header.
+    fn is_on_active_chain(&self, cached: &CachedHeader) -> bool {
+        self.get_ancestor_at_height(&self.active_tip_hash(), cached.height)
+            .map_or(false, |ancestor| {
+                ancestor.header.block_hash() == cached.header.block_hash()
+            })
+    }
+
+    /// Check whether a block hash is known i.e., its header is stored in the `header_cache`.
+    /// This does not imply that the block itself is available, see `contains_block`.
+    pub fn is_block_hash_known(&self, block_hash: &BlockHash) -> bool {
+        self.header_cache.contains(block_hash)
+    }
+
+    /// Returns the time the last block was successfully added, if any block has been added.
+    pub fn last_block_added_at(&self) -> Option<SystemTime> {
+        self.last_block_added_at
+    }
+
+    /// Returns a snapshot of the sync health of the state.
+    pub fn sync_status(&self) -> SyncStatus {
+        SyncStatus {
+            tip_height: self.get_active_chain_tip().height,
+            tip_count: self.tips.len(),
+            block_cache_entries: self.block_cache.len(),
+            block_cache_bytes: self.get_block_cache_size(),
+            seconds_since_last_block: self.last_block_added_at.map(|last_block_added_at| {
+                last_block_added_at.elapsed().unwrap_or_default().as_secs()
+            }),
+        }
+    }
+
+    /// Checks that the caches agree with each other: every cached block and every tip has a
+    /// cached header, and every header and tip is stored one above its parent. Returns the
+    /// first inconsistency found.
+    pub fn validate_cache_invariants(&self) -> Result<(), CacheInconsistency> {
+        if let Some(block_hash) = self
+            .block_cache
+            .keys()
+            .find(|block_hash| !self.header_cache.contains(block_hash))
+        {
+            return Err(CacheInconsistency::BlockWithoutHeader(*block_hash));
+        }
+
+        for tip in &self.tips {
+            let block_hash = tip.header.block_hash();
+            let cached = self
+                .get_cached_header(&block_hash)
+                .ok_or(CacheInconsistency::TipWithoutHeader(block_hash))?;
+            if cached.height != tip.height {
+                return Err(CacheInconsistency::HeightMismatch {
+                    block_hash,
+                    expected: cached.height,
+                    actual: tip.height,
+                });
+            }
+        }
+
+        let genesis_hash = self.genesis_hash();
+        for (block_hash, cached) in &self.header_cache.headers {
+            let expected = match self.get_cached_header(&cached.header.prev_blockhash) {
+                Some(parent) => parent.height + 1,
+                // Only the genesis header or headers whose ancestors have been pruned lack a
+                // cached parent.
+                None if *block_hash == genesis_hash => 0,
+                None => continue,
+            };
+            if cached.height != expected {
+                return Err(CacheInconsistency::HeightMismatch {
+                    block_hash: *block_hash,
+                    expected,
+                    actual: cached.height,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Updates the metric holding the seconds since the last block was added.
+    /// The metric is left unchanged if no block has been added yet.
+    pub fn update_seconds_since_last_block(&self) {
+        if let Some(last_block_added_at) = self.last_block_added_at {
+            let elapsed = last_block_added_at.elapsed().unwrap_or_default();
+            self.metrics
+                .seconds_since_last_block
+                .set(elapsed.as_secs() as i64);
+        }
+    }
+
+    /// Check whether the full block is stored in the `block_cache`.
+    pub fn contains_block(&self, block_hash: &BlockHash) -> bool {
+        self.block_cache.contains_key(block_hash)
+    }
+
+    /// Returns the cached blocks sorted by the height of their headers. Blocks at the same
+    /// height keep their insertion order, and blocks whose headers are not cached sort last.
+    pub fn cached_blocks_sorted_by_height(&self) -> Vec<(&BlockHash, &Block)> {
+        let mut blocks: Vec<_> = self.block_cache.iter().collect();
+        blocks.sort_by_key(|(block_hash, _)| match self.get_cached_header(block_hash) {
+            Some(cached) => (false, cached.height),
+            None => (true, 0),
+        });
+        blocks
+    }
+
+    /// Returns the current values of the blockchain state metrics.
+    pub fn metrics_snapshot(&self) -> BlockchainStateMetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    /// This method takes a list of block hashes as input.
+    /// For each block hash, if the corresponding block is stored in the `block_cache`, the cached block is returned.
+    /// Each lookup is counted as a block cache hit or miss.
+    pub fn get_block(&self, block_hash: &BlockHash) -> Option<&Block> {
+        let block = self.block_cache.get(block_hash);
+        match block {
+            Some(_) => self.metrics.block_cache_hits.inc(),
+            None => self.metrics.block_cache_misses.inc(),
+        }
+        block
+    }
+
+    /// Used when the adapter is shutdown and no longer requires holding on to blocks.
+    pub fn clear_blocks(&mut self) {
+        self.block_cache = LinkedHashMap::new();
+        self.block_cache_bytes = 0;
+    }
+
+    /// Returns an estimate of the memory used by the header cache in bytes. The estimate
+    /// scales with the number of headers and the references to their children.
+    pub fn get_header_cache_bytes(&self) -> usize {
+        self.header_cache.bytes
+    }
+
+    /// Returns the current size of the block cache.
+    pub fn get_block_cache_size(&self) -> usize {
+        self.block_cache_bytes
+    }
+}
+
+/// An iterator over the headers of the active chain, starting at the active tip and walking
+/// back to the genesis header. The iteration stops early if a parent header is missing.
+#[derive(Debug)]
+pub struct ActiveChainIter<'a> {
+    state: &'a BlockchainState,
+    next: Option<&'a CachedHeader>,
+}
+
+impl<'a> Iterator for ActiveChainIter<'a> {
+    type Item = &'a CachedHeader;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next.take()?;
+        if current.height > 0 {
+            self.next = self.state.get_cached_header(&current.header.prev_blockhash);
+        }
+        Some(current)
+    }
+}
+
+/// Returns the height from which the coinbase transaction must start with the block's height
+/// (BIP-34), as used by Bitcoin Core.
+fn bip34_activation_height(network: Network) -> BlockHeight {
+    match network {
+        Network::Bitcoin => 227_931,
+        Network::Testnet => 21_111,
+        Network::Signet | Network::Regtest => 1,
+    }
+}
+
+/// Sorts the tips by the total work. Tips with equal work are ordered by when they were
+/// first seen, so the earliest tip wins the tie.
+fn sort_tips(tips: &mut [Tip]) {
+    tips.sort_unstable_by(|a, b| b.work.cmp(&a.work).then(a.sequence.cmp(&b.sequence)));
+}
+
+/// Approximates `log2(work)`. The work is accumulated into an `f64` word by word, which
+/// loses precision below the 53 most significant bits but is sufficient for monitoring.
+fn work_log2(work: &Work) -> f64 {
+    let value = work
+        .0
+        .iter()
+        .rev()
+        .fold(0f64, |acc, word| acc * 2f64.powi(64) + *word as f64);
+    value.log2()
+}
+
+/// Runs the header validation against every cached header again and returns the headers that
+/// fail it. The state is not changed. This is a diagnostic to confirm the cached headers still
+/// pass after the validation rules changed. The lock on the state is only held for batches of
+/// `REVALIDATION_BATCH_SIZE` headers, so the adapter keeps serving requests during the walk.
+pub async fn revalidate_headers(
+    state: &tokio::sync::Mutex<BlockchainState>,
+) -> Vec<(BlockHash, ValidateHeaderError)> {
+    let mut revalidation = state.lock().await.start_header_revalidation();
+    while !revalidation.is_done() {
+        state
+            .lock()
+            .await
+            .revalidate_headers_batch(&mut revalidation, REVALIDATION_BATCH_SIZE);
+        tokio::task::yield_now().await;
+    }
+    revalidation.into_failures()
+}
+
+impl HeaderStore for BlockchainState {
+    fn get_header(&self, hash: &BlockHash) -> Option<(BlockHeader, BlockHeight)> {
+        self.get_cached_header(hash)
+            .map(|cached| (cached.header, cached.height))
+    }
+
+    fn get_height(&self) -> BlockHeight {
+        self.pending_tip_height
+            .unwrap_or_else(|| self.get_active_chain_tip().height)
+    }
+
+    fn get_initial_hash(&self) -> BlockHash {
+        self.genesis_hash()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::{
+        hash_types::FilterHash, hashes::Hash, OutPoint, Script, Transaction, TxIn, TxMerkleNode,
+        TxOut, Witness,
+    };
+    use metrics::MetricsRegistry;
+
+    use super::*;
+    use crate::{
+        common::test_common::{
+            block_1, block_2, generate_header, generate_headers, headers_to_hashes, TestState,
+        },
+        config::test::ConfigBuilder,
+    };
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_get_block() {
+        let test_state = TestState::setup();
+        let config = ConfigBuilder::new().build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+
+        state
+            .add_block(test_state.block_1.clone())
+            .expect("should be able to add block 1");
+        let block_1_hash = test_state.block_1.block_hash();
+        let block_2_hash = test_state.block_2.block_hash();
+        let block_hashes: HashSet<_> = vec![block_1_hash, block_2_hash].into_iter().collect();
+
+        let mut cached_blocks = vec![];
+        for hash in &block_hashes {
+            if let Some(block) = state.get_block(hash) {
+                cached_blocks.push(block);
+            }
+        }
+
+        assert_eq!(cached_blocks.len(), 1);
+        let block = cached_blocks.get(0).expect("there should be 1");
+        assert_eq!(block.block_hash(), block_1_hash);
+    }
+
+    /// Tests that `BlockchainState::genesis_hash(...)` returns the hash of the library's genesis
+    /// block for each network.
+    #[test]
+    fn test_genesis_hash() {
+        for network in [
+            Network::Bitcoin,
+            Network::Testnet,
+            Network::Signet,
+            Network::Regtest,
+        ] {
+            let config = ConfigBuilder::new().with_network(network).build();
+            let state = BlockchainState::new(&config, &MetricsRegistry::default());
+            assert_eq!(state.genesis_hash(), genesis_block(network).block_hash());
+            assert_eq!(state.genesis_hash(), state.genesis().header.block_hash());
+        }
+    }
+
+    /// Tests that headers below the `max_header_chain_length` window are pruned when headers
+    /// are added, while the genesis header is kept and still terminates the locators.
+    #[test]
+    fn test_max_header_chain_length_prunes_old_headers() {
+        let config = ConfigBuilder::new()
+            .with_network(Network::Regtest)
+            .with_max_header_chain_length(Some(10))
+            .build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        state.set_last_anchor_height(30);
+        let genesis = state.genesis().header;
+        let chain = generate_headers(genesis.block_hash(), genesis.time, 30, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+
+        // Headers at heights 20 to 30 and the genesis header are kept.
+        assert_eq!(state.active_tip_height(), 30);
+        assert_eq!(state.header_cache.headers.len(), 12);
+        assert!(state.get_cached_header(&chain[18].block_hash()).is_none());
+        assert!(state.get_cached_header(&chain[19].block_hash()).is_some());
+        assert!(state.get_cached_header(&state.genesis_hash()).is_some());
+        assert!(state.genesis().children.lock().is_empty());
+
+        let locator_hashes = state.locator_hashes();
+        assert_eq!(locator_hashes[0], chain[29].block_hash());
+        assert_eq!(locator_hashes.last(), Some(&state.genesis_hash()));
+        assert!(locator_hashes
+            .iter()
+            .all(|hash| state.get_cached_header(hash).is_some()));
+
+        // The window keeps moving with the active tip.
+        let more = generate_headers(chain[29].block_hash(), chain[29].time, 5, &[]);
+        let (_, maybe_err) = state.add_headers(&more);
+        assert!(maybe_err.is_none());
+        assert_eq!(state.header_cache.headers.len(), 12);
+        assert!(state.get_cached_header(&chain[23].block_hash()).is_none());
+        assert_eq!(state.validate_cache_invariants(), Ok(()));
+    }
+
+    /// Tests that headers at and above the anchor of the last `GetSuccessors` request are not
+    /// pruned by the `max_header_chain_length` window.
+    /// Test Steps:
+    /// 1. Add headers before any anchor is recorded and check that nothing is pruned.
+    /// 2. Record an anchor below the window and add more headers.
+    /// 3. Check that only the headers below the anchor are pruned.
+    #[test]
+    fn test_max_header_chain_length_keeps_headers_above_anchor() {
+        let config = ConfigBuilder::new()
+            .with_network(Network::Regtest)
+            .with_max_header_chain_length(Some(10))
+            .build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis = state.genesis().header;
+        let chain = generate_headers(genesis.block_hash(), genesis.time, 30, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        assert_eq!(state.header_cache.headers.len(), 31);
+
+        state.set_last_anchor_height(15);
+        let more = generate_headers(chain[29].block_hash(), chain[29].time, 5, &[]);
+        let (_, maybe_err) = state.add_headers(&more);
+        assert!(maybe_err.is_none());
+
+        // Headers at heights 15 to 35 and the genesis header are kept.
+        assert_eq!(state.active_tip_height(), 35);
+        assert_eq!(state.header_cache.headers.len(), 22);
+        assert!(state.get_cached_header(&chain[13].block_hash()).is_none());
+        assert!(state.get_cached_header(&chain[14].block_hash()).is_some());
+        assert_eq!(state.validate_cache_invariants(), Ok(()));
+    }
+
+    /// Tests that `BlockchainState::get_header_work(...)` returns the cumulative work of any
+    /// cached header, which increases along a chain.
+    #[test]
+    fn test_get_header_work() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis = state.genesis().header;
+        let genesis_hash = genesis.block_hash();
+        let chain = generate_headers(genesis_hash, genesis.time, 5, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+
+        let mut previous_work = state
+            .get_header_work(&genesis_hash)
+            .expect("the genesis header should be cached");
+        assert_eq!(previous_work, genesis.work());
+        for header in &chain {
+            let work = state
+                .get_header_work(&header.block_hash())
+                .expect("the header should be cached");
+            assert_eq!(work, previous_work + header.work());
+            previous_work = work;
+        }
+        assert_eq!(previous_work, state.get_active_chain_work());
+
+        let unknown = generate_header(chain[4].block_hash(), chain[4].time, 0);
+        assert_eq!(state.get_header_work(&unknown.block_hash()), None);
+    }
+
+    /// Tests that `BlockchainState::get_headers(...)` returns the cached headers aligned with the
+    /// requested hashes.
+    #[test]
+    fn test_get_headers() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis = state.genesis().header;
+        let chain = generate_headers(genesis.block_hash(), genesis.time, 3, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        let unknown = generate_header(chain[2].block_hash(), chain[2].time, 0);
+
+        let headers = state.get_headers(&[
+            chain[1].block_hash(),
+            unknown.block_hash(),
+            genesis.block_hash(),
+            chain[2].block_hash(),
+            unknown.block_hash(),
+        ]);
+        assert_eq!(
+            headers,
+            vec![
+                Some((chain[1], 2)),
+                None,
+                Some((genesis, 0)),
+                Some((chain[2], 3)),
+                None
+            ]
+        );
+        assert!(state.get_headers(&[]).is_empty());
+    }
+
+    /// Tests that a header claiming to be a genesis header that differs from the configured
+    /// genesis header is rejected, while the configured genesis header is reported as known.
+    #[test]
+    fn test_adding_a_fake_genesis_header() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis = state.genesis().header;
+
+        let fake_genesis = generate_header(BlockHash::default(), genesis.time, 0);
+        let (added_headers, maybe_err) = state.add_headers(&[fake_genesis]);
+        assert!(added_headers.is_empty());
+        assert!(matches!(
+            maybe_err,
+            Some(AddHeaderError::UnexpectedGenesis(hash)) if hash == fake_genesis.block_hash()
+        ));
+        assert!(state
+            .get_cached_header(&fake_genesis.block_hash())
+            .is_none());
+        assert_eq!(state.tips.len(), 1);
+
+        let (added_headers, maybe_err) = state.add_headers(&[genesis]);
+        assert!(added_headers.is_empty());
+        assert!(maybe_err.is_none());
+    }
+
+    /// Tests that the metrics snapshot reflects the state after adding headers and blocks.
+    #[test]
+    fn test_metrics_snapshot() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+        let chain = generate_headers(genesis_hash, genesis_time, 4, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        let block = Block {
+            header: chain[0],
+            txdata: vec![],
+        };
+        state.add_block(block.clone()).unwrap();
+        state.get_block(&block.block_hash());
+        state.get_block(&chain[1].block_hash());
+
+        let snapshot = state.metrics_snapshot();
+        assert_eq!(snapshot.tip_height, 4);
+        assert_eq!(snapshot.tips, 1);
+        assert_eq!(snapshot.block_cache_size_bytes, block.size() as i64);
+        assert_eq!(
+            snapshot.header_cache_size_bytes,
+            state.get_header_cache_bytes() as i64
+        );
+        assert_eq!(snapshot.seconds_since_last_block, 0);
+        assert_eq!(snapshot.block_cache_hits, 1);
+        assert_eq!(snapshot.block_cache_misses, 1);
+        let json = serde_json::to_value(&snapshot).unwrap();
+        assert_eq!(json["tip_height"], 4);
+    }
+
+    /// Tests that headers received before their previous header are counted as out of order.
+    #[test]
+    fn test_adding_unsorted_headers_counts_out_of_order_headers() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+        let mut chain = generate_headers(genesis_hash, genesis_time, 4, &[]);
+        chain.swap(1, 2);
+
+        let (added_headers, maybe_err) = state.add_headers(&chain);
+        assert_eq!(added_headers.len(), 1);
+        assert!(
+            matches!(maybe_err, Some(AddHeaderError::PrevHeaderNotCached(prev_hash)) if prev_hash == chain[2].block_hash())
+        );
+        assert_eq!(state.metrics.headers_out_of_order.get(), 1);
+
+        chain.swap(1, 2);
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        assert_eq!(state.metrics_snapshot().headers_out_of_order, 1);
+    }
+
+    /// Tests that `BlockchainState::get_block(...)` counts block cache hits and misses.
+    #[test]
+    fn test_get_block_counts_hits_and_misses() {
+        let test_state = TestState::setup();
+        let config = ConfigBuilder::new().build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        state
+            .add_block(test_state.block_1.clone())
+            .expect("should be able to add block 1");
+
+        assert!(state.get_block(&test_state.block_1.block_hash()).is_some());
+        assert_eq!(state.metrics.block_cache_hits.get(), 1);
+        assert_eq!(state.metrics.block_cache_misses.get(), 0);
+
+        assert!(state.get_block(&test_state.block_2.block_hash()).is_none());
+        assert_eq!(state.metrics.block_cache_hits.get(), 1);
+        assert_eq!(state.metrics.block_cache_misses.get(), 1);
+    }
+
+    /// Tests that `BlockchainState::contains_block(...)` only reports blocks stored in the block
+    /// cache while `BlockchainState::is_block_hash_known(...)` also reports header-only entries.
+    #[test]
+    fn test_contains_block() {
+        let test_state = TestState::setup();
+        let config = ConfigBuilder::new().build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let block_1_hash = test_state.block_1.block_hash();
+        let block_2_hash = test_state.block_2.block_hash();
+
+        state
+            .add_block(test_state.block_1.clone())
+            .expect("should be able to add block 1");
+        let (_, maybe_err) = state.add_headers(&[test_state.block_2.header]);
+        assert!(maybe_err.is_none());
+
+        assert!(state.is_block_hash_known(&block_1_hash));
+        assert!(state.contains_block(&block_1_hash));
+        assert!(state.is_block_hash_known(&block_2_hash));
+        assert!(!state.contains_block(&block_2_hash));
+    }
+    /// Tests whether or not the `BlockchainState::add_headers(...)` function can add headers to the cache
     /// successfully.
     #[test]
-    fn test_adding_headers_successfully() {
+    fn test_adding_headers_successfully() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+
+        let initial_header = state.genesis();
+        let chain = generate_headers(
+            initial_header.header.block_hash(),
+            initial_header.header.time,
+            16,
+            &[],
+        );
+        let chain_hashes: Vec<BlockHash> = chain.iter().map(|header| header.block_hash()).collect();
+        let last_hash = *chain_hashes.last().unwrap();
+
+        let (added_headers, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+
+        let last_cached = added_headers.last().unwrap();
+        assert_eq!(last_cached.header.block_hash(), last_hash);
+        assert_eq!(last_cached.height, 16);
+        let tip = state.get_active_chain_tip();
+        assert_eq!(tip.height, 16);
+        assert_eq!(tip.header.block_hash(), last_hash);
+    }
+
+    #[test]
+    /// Tests whether or not the `BlockchainState::add_headers(...)` function can add headers that
+    /// cause 2 forks in the chain. The state should be able to determine what is the active tip.
+    fn test_forks_when_adding_headers() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let initial_header = state.genesis();
+
+        // Create an arbitrary chain and adding to the BlockchainState
+        let chain = generate_headers(
+            initial_header.header.block_hash(),
+            initial_header.header.time,
+            16,
+            &[],
+        );
+        let chain_hashes: Vec<BlockHash> = chain.iter().map(|header| header.block_hash()).collect();
+        let last_chain_hash = chain_hashes.last().expect("missing last hash");
+
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(
+            maybe_err.is_none(),
+            "unsuccessfully added first chain: {:?}",
+            maybe_err
+        );
+
+        // Create a fork chain forking from chain_hashes[10] and adding to the BlockchainState.
+        let fork_chain = generate_headers(chain_hashes[10], chain[10].time, 16, &chain_hashes);
+        let fork_hashes: Vec<BlockHash> = fork_chain
+            .iter()
+            .map(|header| header.block_hash())
+            .collect();
+        let last_fork_hash = fork_hashes.last().expect("missing last hash");
+
+        let (_, maybe_err) = state.add_headers(&fork_chain);
+        assert!(
+            maybe_err.is_none(),
+            "unsuccessfully added fork chain: {:?}",
+            maybe_err
+        );
+
+        assert_eq!(state.tips.len(), 2);
+        assert_eq!(state.tips[0].header.block_hash(), *last_fork_hash);
+        assert_eq!(state.tips[1].header.block_hash(), *last_chain_hash);
+        assert_eq!(state.get_active_chain_tip().height, 27);
+    }
+
+    /// Tests that ties between tips with equal work are broken in favor of the tip seen first.
+    /// Test Steps:
+    /// 1. Add a chain and then a fork of the same length, so both tips have equal work.
+    /// 2. Check that the first added tip stays the active tip.
+    /// 3. Add the same headers in the opposite order to a new state and check that the fork wins.
+    #[test]
+    fn test_equal_work_tips_prefer_first_seen() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+        let chain = generate_headers(genesis_hash, genesis_time, 4, &[]);
+        let chain_hashes = headers_to_hashes(&chain);
+        let fork = generate_headers(genesis_hash, genesis_time, 4, &chain_hashes);
+
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        let (_, maybe_err) = state.add_headers(&fork);
+        assert!(maybe_err.is_none());
+        assert_eq!(state.tips.len(), 2);
+        assert_eq!(state.tips[0].work, state.tips[1].work);
+        for _ in 0..10 {
+            assert_eq!(
+                state.get_active_chain_tip().header.block_hash(),
+                chain[3].block_hash()
+            );
+        }
+
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let (_, maybe_err) = state.add_headers(&fork);
+        assert!(maybe_err.is_none());
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        assert_eq!(
+            state.get_active_chain_tip().header.block_hash(),
+            fork[3].block_hash()
+        );
+    }
+
+    /// Tests that the number of tips is capped when the state is flooded with junk headers.
+    /// Test Steps:
+    /// 1. Add a main chain and a junk block forking from the genesis header.
+    /// 2. Add many junk headers that each create a new tip.
+    /// 3. Check that the tip count is capped, the active tip and the tip with a cached block
+    ///    are kept, and the headers of the dropped tips are removed.
+    #[test]
+    fn test_max_tips_is_enforced() {
+        let config = ConfigBuilder::new()
+            .with_network(Network::Regtest)
+            .with_max_tips(5)
+            .build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+        let chain = generate_headers(genesis_hash, genesis_time, 4, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+
+        let junk_block = Block {
+            header: generate_header(genesis_hash, genesis_time, 1000),
+            txdata: vec![],
+        };
+        let junk_block_hash = junk_block.block_hash();
+        state.add_block(junk_block).unwrap();
+
+        let junk_headers: Vec<BlockHeader> = (0..20)
+            .map(|nonce| {
+                let parent = &chain[nonce as usize % 3];
+                generate_header(parent.block_hash(), parent.time, nonce)
+            })
+            .collect();
+        for header in &junk_headers {
+            let (_, maybe_err) = state.add_headers(&[*header]);
+            assert!(maybe_err.is_none());
+            assert!(state.tips.len() <= 5);
+        }
+
+        assert_eq!(state.tips.len(), 5);
+        assert_eq!(
+            state.get_active_chain_tip().header.block_hash(),
+            chain[3].block_hash()
+        );
+        assert!(state
+            .tips
+            .iter()
+            .any(|tip| tip.header.block_hash() == junk_block_hash));
+        let kept_junk_headers = junk_headers
+            .iter()
+            .filter(|header| state.is_block_hash_known(&header.block_hash()))
+            .count();
+        assert_eq!(kept_junk_headers, 3);
+        // The genesis header, the main chain, the junk block and the kept junk headers.
+        assert_eq!(state.header_cache.headers.len(), 1 + 4 + 1 + 3);
+    }
+
+    /// Tests that the tip left behind by a dropped branch counts towards the tip limit.
+    /// Test Steps:
+    /// 1. Add a main chain and a fork whose first header has a cached block.
+    /// 2. Add a second fork with more work than the first one, exceeding the tip limit.
+    /// 3. Check that the first fork is cut back to its cached block, which becomes a tip, and
+    ///    that the second fork is dropped to stay within the limit.
+    #[test]
+    fn test_max_tips_counts_tips_left_by_dropped_branches() {
+        let config = ConfigBuilder::new()
+            .with_network(Network::Regtest)
+            .with_max_tips(2)
+            .build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+        let chain = generate_headers(genesis_hash, genesis_time, 5, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        let chain_hashes = headers_to_hashes(&chain);
+
+        let cached_block = Block {
+            header: generate_header(chain_hashes[0], chain[0].time, 1000),
+            txdata: vec![],
+        };
+        let cached_block_hash = cached_block.block_hash();
+        state.add_block(cached_block).unwrap();
+        let fork_tip = generate_header(cached_block_hash, chain[0].time, 1001);
+        let (_, maybe_err) = state.add_headers(&[fork_tip]);
+        assert!(maybe_err.is_none());
+        assert_eq!(state.tips.len(), 2);
+
+        let other_fork = generate_headers(chain_hashes[0], chain[0].time, 3, &chain_hashes);
+        let (_, maybe_err) = state.add_headers(&other_fork);
+        assert!(maybe_err.is_none());
+
+        assert_eq!(state.tips.len(), 2);
+        assert_eq!(state.active_tip_hash(), chain_hashes[4]);
+        assert!(state
+            .tips
+            .iter()
+            .any(|tip| tip.header.block_hash() == cached_block_hash));
+        assert!(!state.is_block_hash_known(&fork_tip.block_hash()));
+        assert!(other_fork
+            .iter()
+            .all(|header| !state.is_block_hash_known(&header.block_hash())));
+    }
+
+    /// Tests `BlockchainState::add_headers(...)` with an empty set of headers.
+    #[test]
+    fn test_adding_an_empty_headers_vector() {
+        let config = ConfigBuilder::new().build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let chain = vec![];
+        let (added_headers, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        assert!(added_headers.is_empty());
+        assert_eq!(state.get_active_chain_tip().height, 0);
+    }
+
+    /// Tests whether or not the `BlockchainState::add_headers(...)` function can handle adding already known
+    /// headers in the state.
+    #[test]
+    fn test_adding_headers_that_already_exist() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+
+        let initial_header = state.genesis();
+        let chain = generate_headers(
+            initial_header.header.block_hash(),
+            initial_header.header.time,
+            16,
+            &[],
+        );
+        let chain_hashes: Vec<BlockHash> = chain.iter().map(|header| header.block_hash()).collect();
+        let last_hash = *chain_hashes.last().unwrap();
+
+        let (added_headers, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        assert_eq!(added_headers.len(), 16);
+
+        let last_cached = added_headers.last().unwrap();
+        assert_eq!(last_cached.header.block_hash(), last_hash);
+        assert_eq!(last_cached.height, 16);
+
+        let (added_headers, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        assert!(added_headers.is_empty());
+    }
+
+    /// Tests whether or not the `BlockchainState::add_headers(...)` function can add headers while avoiding
+    /// adding a header that is invalid.
+    #[test]
+    fn test_adding_headers_with_an_invalid_header() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+
+        let initial_header = state.genesis();
+        let mut chain = generate_headers(
+            initial_header.header.block_hash(),
+            initial_header.header.time,
+            16,
+            &[],
+        );
+        // The header still connects to its cached predecessor, but its target is easier than
+        // the maximum target of the network.
+        let last_header = chain.get_mut(10).unwrap();
+        last_header.bits = 0x2100ffff;
+
+        let chain_hashes: Vec<BlockHash> = chain.iter().map(|header| header.block_hash()).collect();
+        let last_hash = chain_hashes[10];
+
+        let (added_headers, maybe_err) = state.add_headers(&chain);
+
+        assert_eq!(added_headers.len(), 10);
+        assert!(
+            matches!(maybe_err, Some(AddHeaderError::InvalidHeader(block_hash, err)) if block_hash == last_hash && matches!(err, ValidateHeaderError::TargetDifficultyAboveMax))
+        );
+
+        let tip = state.get_active_chain_tip();
+        assert_eq!(tip.height, 10);
+    }
+
+    /// Tests that `BlockchainState::add_headers(...)` stops at a header that claims to be a
+    /// genesis header, while the headers before it are added.
+    #[test]
+    fn test_adding_headers_with_an_unexpected_genesis_header() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+
+        let initial_header = state.genesis();
+        let mut chain = generate_headers(
+            initial_header.header.block_hash(),
+            initial_header.header.time,
+            16,
+            &[],
+        );
+        let last_header = chain.get_mut(10).unwrap();
+        last_header.prev_blockhash = BlockHash::default();
+
+        let chain_hashes: Vec<BlockHash> = chain.iter().map(|header| header.block_hash()).collect();
+        let last_hash = chain_hashes[10];
+
+        let (added_headers, maybe_err) = state.add_headers(&chain);
+
+        assert_eq!(added_headers.len(), 10);
+        assert!(
+            matches!(maybe_err, Some(AddHeaderError::UnexpectedGenesis(block_hash)) if block_hash == last_hash)
+        );
+
+        let tip = state.get_active_chain_tip();
+        assert_eq!(tip.height, 10);
+    }
+
+    /// Tests the functionality of `BlockchainState::add_block(...)` to push it through the add_header
+    /// validation and adding the block to the cache.
+    #[test]
+    fn test_adding_blocks_to_the_cache() {
+        let block_1 = block_1();
+        let mut block_2 = block_2();
+
+        let config = ConfigBuilder::new().build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+
+        // Attempt to add block 2 to the cache before block 1's header has been added.
+        let block_2_hash = block_2.header.block_hash();
+        let result = state.add_block(block_2.clone());
+        assert!(
+            matches!(result, Err(AddBlockError::Header(AddHeaderError::PrevHeaderNotCached(prev_hash))) if prev_hash == block_2.header.prev_blockhash),
+        );
+
+        let result = state.add_block(block_1);
+        assert!(matches!(result, Ok(height) if height == 1));
+
+        // Make a block 2's merkle root invalid and try to add the block to the cache.
+        block_2.header.merkle_root = TxMerkleNode::default();
+        // Block 2's hash will now be changed because of the merkle root change.
+        let block_2_hash = block_2.block_hash();
+        let result = state.add_block(block_2);
+        assert!(
+            matches!(result, Err(AddBlockError::InvalidMerkleRoot(stop_hash)) if stop_hash == block_2_hash),
+        );
+    }
+
+    /// Creates a block on top of the given header with a coinbase transaction using the given
+    /// script.
+    fn block_with_coinbase_script(prev: &BlockHeader, script_sig: Script) -> Block {
+        let coinbase = Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig,
+                sequence: 0xFFFFFFFF,
+                witness: Witness::default(),
+            }],
+            output: vec![TxOut {
+                value: 50_0000_0000,
+                script_pubkey: Script::new(),
+            }],
+        };
+        let mut header = generate_header(prev.block_hash(), prev.time, 0);
+        header.merkle_root = TxMerkleNode::from_hash(coinbase.txid().as_hash());
+        while header.validate_pow(&header.target()).is_err() {
+            header.nonce += 1;
+        }
+        Block {
+            header,
+            txdata: vec![coinbase],
+        }
+    }
+
+    /// Tests that `BlockchainState::add_block(...)` rejects a block whose coinbase does not
+    /// encode the block's height once BIP-34 is active.
+    #[test]
+    fn test_add_block_with_invalid_coinbase_height() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis = state.genesis().header;
+
+        let block = block_with_coinbase_script(&genesis, Builder::new().push_int(2).into_script());
+        let block_hash = block.block_hash();
+        let result = state.add_block(block);
+        assert!(matches!(
+            result,
+            Err(AddBlockError::InvalidCoinbaseHeight(hash, 1)) if hash == block_hash
+        ));
+        assert!(!state.contains_block(&block_hash));
+        assert!(state.get_cached_header(&block_hash).is_none());
+
+        let block = block_with_coinbase_script(
+            &genesis,
+            Builder::new().push_int(1).push_int(42).into_script(),
+        );
+        assert!(matches!(state.add_block(block), Ok(1)));
+    }
+
+    /// Tests that `BlockchainState::add_block_trusted(...)` accepts a block whose merkle root
+    /// does not match its transactions while `BlockchainState::add_block(...)` rejects it.
+    #[test]
+    fn test_add_block_trusted_skips_merkle_root_check() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis = state.genesis().header;
+        let block = Block {
+            header: generate_header(genesis.block_hash(), genesis.time, 0),
+            txdata: block_1().txdata,
+        };
+        let block_hash = block.block_hash();
+        assert!(!block.check_merkle_root());
+
+        let result = state.add_block(block.clone());
+        assert!(
+            matches!(result, Err(AddBlockError::InvalidMerkleRoot(hash)) if hash == block_hash)
+        );
+        assert!(!state.contains_block(&block_hash));
+
+        let result = state.add_block_trusted(block);
+        assert!(matches!(result, Ok(1)));
+        assert!(state.contains_block(&block_hash));
+        assert_eq!(state.active_tip_hash(), block_hash);
+    }
+
+    /// Tests the functionality of `BlockchainState::prune_blocks(...)` to ensure
+    /// blocks are removed from the cache.
+    #[test]
+    fn test_pruning_blocks_from_the_cache() {
+        let test_state = TestState::setup();
+        let config = ConfigBuilder::new().build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let block_1_hash = test_state.block_1.block_hash();
+        let block_2_hash = test_state.block_2.block_hash();
+        state.add_block(test_state.block_1).unwrap();
+        state.add_block(test_state.block_2).unwrap();
+
+        state.prune_blocks(&[block_2_hash]);
+        assert!(state.block_cache.contains_key(&block_1_hash));
+        assert!(!state.block_cache.contains_key(&block_2_hash));
+    }
+
+    /// Tests the functionality of `BlockchainState::prune_blocks_below_height(...)` to ensure
+    /// blocks are removed from the cache that are below a given height.
+    #[test]
+    fn test_pruning_blocks_below_a_given_height_from_the_cache() {
+        let test_state = TestState::setup();
+        let config = ConfigBuilder::new().build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let block_1_hash = test_state.block_1.block_hash();
+        let block_2_hash = test_state.block_2.block_hash();
+        state.add_block(test_state.block_1).unwrap();
+        state.add_block(test_state.block_2).unwrap();
+
+        state.prune_blocks_below_height(2);
+        assert!(!state.block_cache.contains_key(&block_1_hash));
+        assert!(state.block_cache.contains_key(&block_2_hash));
+    }
+
+    /// Tests that `BlockchainState::last_block_added_at(...)` is only populated once a block
+    /// has been added.
+    #[test]
+    fn test_last_block_added_at() {
+        let test_state = TestState::setup();
+        let config = ConfigBuilder::new().build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        assert_eq!(state.last_block_added_at(), None);
+
+        let before = SystemTime::now();
+        state.add_block(test_state.block_1).unwrap();
+        let last_block_added_at = state
+            .last_block_added_at()
+            .expect("the time should be recorded");
+        assert!(last_block_added_at >= before);
+
+        state.last_block_added_at = before.checked_sub(std::time::Duration::from_secs(30));
+        state.update_seconds_since_last_block();
+        assert!(state.metrics.seconds_since_last_block.get() >= 30);
+    }
+
+    /// Tests that `BlockchainState::sync_status(...)` reflects the state.
+    /// Test Steps:
+    /// 1. Check the status of a new state.
+    /// 2. Add headers and a block.
+    /// 3. Check that the status reports the tip, the tips and the block cache.
+    #[test]
+    fn test_sync_status() {
         let config = ConfigBuilder::new().with_network(Network::Regtest).build();
         let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        assert_eq!(
+            state.sync_status(),
+            SyncStatus {
+                tip_height: 0,
+                tip_count: 1,
+                block_cache_entries: 0,
+                block_cache_bytes: 0,
+                seconds_since_last_block: None,
+            }
+        );
 
-        let initial_header = state.genesis();
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+        let chain = generate_headers(genesis_hash, genesis_time, 8, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        let fork = generate_headers(
+            chain[3].block_hash(),
+            chain[3].time,
+            2,
+            &headers_to_hashes(&chain),
+        );
+        let (_, maybe_err) = state.add_headers(&fork);
+        assert!(maybe_err.is_none());
+        let block = Block {
+            header: chain[0],
+            txdata: vec![],
+        };
+        let block_size = block.size();
+        state.add_block(block).unwrap();
+
+        let status = state.sync_status();
+        assert_eq!(status.tip_height, 8);
+        assert_eq!(status.tip_count, 2);
+        assert_eq!(status.block_cache_entries, 1);
+        assert_eq!(status.block_cache_bytes, block_size);
+        assert!(matches!(status.seconds_since_last_block, Some(seconds) if seconds < 5));
+    }
+
+    /// Tests that `BlockchainState::prune_blocks_keeping_recent(...)` retains the most recent
+    /// blocks on the active chain.
+    /// Test Steps:
+    /// 1. Add blocks at heights 1 to 6.
+    /// 2. Prune while keeping more blocks than cached and check that nothing is removed.
+    /// 3. Prune while keeping 2 blocks and check that only the blocks at heights 5 and 6 survive.
+    #[test]
+    fn test_prune_blocks_keeping_recent() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+        let chain = generate_headers(genesis_hash, genesis_time, 6, &[]);
+        for header in &chain {
+            state
+                .add_block(Block {
+                    header: *header,
+                    txdata: vec![],
+                })
+                .unwrap();
+        }
+
+        state.prune_blocks_keeping_recent(10);
+        assert_eq!(state.block_cache.len(), 6);
+
+        state.prune_blocks_keeping_recent(2);
+        assert_eq!(state.block_cache.len(), 2);
+        assert!(state.contains_block(&chain[4].block_hash()));
+        assert!(state.contains_block(&chain[5].block_hash()));
+        assert_eq!(
+            state.metrics.block_cache_size.get(),
+            state.get_block_cache_size() as i64
+        );
+
+        state.prune_blocks_keeping_recent(0);
+        assert!(state.block_cache.is_empty());
+    }
+
+    /// Tests the functionality of `BlockchainState::prune_blocks_above_height(...)` to ensure
+    /// blocks are removed from the cache that are above a given height.
+    /// Test Steps:
+    /// 1. Add blocks at heights 1 to 4 and a block with an unknown header.
+    /// 2. Prune the blocks above height 2.
+    /// 3. Check that only the blocks at heights 3 and 4 are removed and the metric is updated.
+    #[test]
+    fn test_pruning_blocks_above_a_given_height_from_the_cache() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+        let chain = generate_headers(genesis_hash, genesis_time, 4, &[]);
+        for header in &chain {
+            state
+                .add_block(Block {
+                    header: *header,
+                    txdata: vec![],
+                })
+                .unwrap();
+        }
+        let orphan = Block {
+            header: generate_header(BlockHash::default(), genesis_time, 0),
+            txdata: vec![],
+        };
+        let orphan_hash = orphan.block_hash();
+        state.block_cache_bytes += orphan.size();
+        state.block_cache.insert(orphan_hash, orphan);
+
+        state.prune_blocks_above_height(2);
+        assert!(state.contains_block(&chain[0].block_hash()));
+        assert!(state.contains_block(&chain[1].block_hash()));
+        assert!(!state.contains_block(&chain[2].block_hash()));
+        assert!(!state.contains_block(&chain[3].block_hash()));
+        assert!(state.contains_block(&orphan_hash));
+        assert_eq!(
+            state.metrics.block_cache_size.get(),
+            state.get_block_cache_size() as i64
+        );
+    }
+
+    /// Tests that `BlockchainState::cached_blocks_sorted_by_height(...)` returns the cached blocks
+    /// in height order regardless of the order they were added in.
+    #[test]
+    fn test_cached_blocks_sorted_by_height() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+        let chain = generate_headers(genesis_hash, genesis_time, 4, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        let orphan = Block {
+            header: generate_header(BlockHash::default(), genesis_time, 0),
+            txdata: vec![],
+        };
+        let orphan_hash = orphan.block_hash();
+        state.block_cache_bytes += orphan.size();
+        state.block_cache.insert(orphan_hash, orphan);
+        for idx in [2, 0, 3, 1] {
+            state
+                .add_block(Block {
+                    header: chain[idx],
+                    txdata: vec![],
+                })
+                .unwrap();
+        }
+
+        let sorted_hashes: Vec<BlockHash> = state
+            .cached_blocks_sorted_by_height()
+            .into_iter()
+            .map(|(block_hash, block)| {
+                assert_eq!(*block_hash, block.block_hash());
+                *block_hash
+            })
+            .collect();
+        let mut expected_hashes = headers_to_hashes(&chain);
+        expected_hashes.push(orphan_hash);
+        assert_eq!(sorted_hashes, expected_hashes);
+    }
+
+    /// Tests that `BlockchainState::get_active_chain_block_at_height(...)` only returns cached
+    /// blocks on the active chain.
+    #[test]
+    fn test_get_active_chain_block_at_height() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+        let chain = generate_headers(genesis_hash, genesis_time, 4, &[]);
+        let fork = generate_headers(genesis_hash, genesis_time, 2, &headers_to_hashes(&chain));
+        for header in chain.iter().take(3).chain(fork.iter()) {
+            state
+                .add_block(Block {
+                    header: *header,
+                    txdata: vec![],
+                })
+                .unwrap();
+        }
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+
+        for height in 1..=3 {
+            let block = state
+                .get_active_chain_block_at_height(height)
+                .expect("block should be cached");
+            assert_eq!(block.header, chain[height as usize - 1]);
+        }
+        // The block at the active tip is not cached.
+        assert!(state.get_active_chain_block_at_height(4).is_none());
+        assert!(state.get_active_chain_block_at_height(5).is_none());
+        // The genesis block is never cached.
+        assert!(state.get_active_chain_block_at_height(0).is_none());
+    }
+
+    /// Tests that `BlockchainState::validate_cache_invariants(...)` detects blocks and tips whose
+    /// headers are missing from the header cache.
+    #[test]
+    fn test_validate_cache_invariants() {
+        let config = ConfigBuilder::new().build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let block = block_1();
+        let block_hash = block.block_hash();
+        state
+            .add_block(block)
+            .expect("should be able to add block 1");
+        assert_eq!(state.validate_cache_invariants(), Ok(()));
+
+        // Remove the header of the cached block that is also the active tip.
+        state.header_cache.remove(&block_hash);
+        assert_eq!(
+            state.validate_cache_invariants(),
+            Err(CacheInconsistency::BlockWithoutHeader(block_hash))
+        );
+
+        state.block_cache.remove(&block_hash);
+        assert_eq!(
+            state.validate_cache_invariants(),
+            Err(CacheInconsistency::TipWithoutHeader(block_hash))
+        );
+
+        state.tips[0].header = state.genesis().header;
+        let genesis_hash = state.genesis().header.block_hash();
+        assert_eq!(
+            state.validate_cache_invariants(),
+            Err(CacheInconsistency::HeightMismatch {
+                block_hash: genesis_hash,
+                expected: 0,
+                actual: 1,
+            })
+        );
+    }
+
+    /// Tests that `BlockchainState::active_chain_headers_batch(...)` returns at most the number
+    /// of headers fitting into a `headers` message.
+    #[test]
+    fn test_active_chain_headers_batch_is_capped() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis = state.genesis().header;
         let chain = generate_headers(
-            initial_header.header.block_hash(),
-            initial_header.header.time,
-            16,
+            genesis.block_hash(),
+            genesis.time,
+            MAX_HEADERS_SIZE as u32 + 100,
             &[],
         );
-        let chain_hashes: Vec<BlockHash> = chain.iter().map(|header| header.block_hash()).collect();
-        let last_hash = *chain_hashes.last().unwrap();
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+
+        let batch = state.active_chain_headers_batch(&genesis.block_hash(), usize::MAX);
+        assert_eq!(batch, chain[..MAX_HEADERS_SIZE]);
+
+        let batch = state.active_chain_headers_batch(&chain[9].block_hash(), 10);
+        assert_eq!(batch, chain[10..20]);
+    }
+
+    /// Tests that `BlockchainState::active_chain_headers_batch(...)` stops at the active tip and
+    /// returns no headers for hashes that are not on the active chain.
+    #[test]
+    fn test_active_chain_headers_batch_near_tip() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis = state.genesis().header;
+        let chain = generate_headers(genesis.block_hash(), genesis.time, 10, &[]);
+        let fork = generate_headers(
+            chain[4].block_hash(),
+            chain[4].time,
+            2,
+            &headers_to_hashes(&chain),
+        );
+        for headers in [&chain, &fork] {
+            let (_, maybe_err) = state.add_headers(headers);
+            assert!(maybe_err.is_none());
+        }
+
+        let batch = state.active_chain_headers_batch(&chain[7].block_hash(), MAX_HEADERS_SIZE);
+        assert_eq!(batch, chain[8..]);
+        assert!(state
+            .active_chain_headers_batch(&chain[9].block_hash(), MAX_HEADERS_SIZE)
+            .is_empty());
+        assert!(state
+            .active_chain_headers_batch(&fork[0].block_hash(), MAX_HEADERS_SIZE)
+            .is_empty());
+        assert!(state
+            .active_chain_headers_batch(&BlockHash::from_inner([0; 32]), MAX_HEADERS_SIZE)
+            .is_empty());
+    }
+
+    /// Tests that `BlockchainState::is_synced(...)` reports a state with a recent active tip as
+    /// synced, unless it is below the reference height.
+    #[test]
+    fn test_is_synced_with_fresh_tip() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let max_tip_age = Duration::from_secs(60 * 60);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time went backwards")
+            .as_secs() as u32;
+        // The header's timestamp is 1 to 10 minutes after the given time.
+        let header = generate_header(state.genesis().header.block_hash(), now - 700, 0);
+        let (_, maybe_err) = state.add_headers(&[header]);
+        assert!(maybe_err.is_none());
+
+        assert!(state.is_synced(None, max_tip_age));
+        assert!(state.is_synced(Some(1), max_tip_age));
+        assert!(!state.is_synced(Some(2), max_tip_age));
+        assert!(!state.is_synced(None, Duration::from_secs(60)));
+    }
+
+    /// Tests that `BlockchainState::is_synced(...)` reports a state with an old active tip as
+    /// not synced.
+    #[test]
+    fn test_is_synced_with_stale_tip() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        assert!(!state.is_synced(None, Duration::from_secs(60 * 60)));
+
+        let genesis = state.genesis().header;
+        let chain = generate_headers(genesis.block_hash(), genesis.time, 5, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        assert!(!state.is_synced(Some(5), Duration::from_secs(60 * 60)));
+    }
+
+    /// Tests that `revalidate_headers(...)` reports no failures for a valid header tree.
+    #[tokio::test]
+    async fn test_revalidate_headers() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let state =
+            tokio::sync::Mutex::new(BlockchainState::new(&config, &MetricsRegistry::default()));
+        let genesis_hash = state.lock().await.genesis().header.block_hash();
+        let genesis_time = state.lock().await.genesis().header.time;
+        assert!(revalidate_headers(&state).await.is_empty());
+
+        let chain = generate_headers(genesis_hash, genesis_time, 20, &[]);
+        let fork = generate_headers(
+            chain[4].block_hash(),
+            chain[4].time,
+            5,
+            &headers_to_hashes(&chain),
+        );
+        for headers in [&chain, &fork] {
+            let (_, maybe_err) = state.lock().await.add_headers(headers);
+            assert!(maybe_err.is_none());
+        }
+
+        assert!(revalidate_headers(&state).await.is_empty());
+        assert_eq!(state.lock().await.header_cache.headers.len(), 26);
+    }
+
+    /// Tests that the revalidation walks the retained headers after the headers following the
+    /// genesis header have been pruned.
+    /// Test Steps:
+    /// 1. Add a chain of 20 headers and prune the headers below height 10.
+    /// 2. Revalidate the headers in batches of 5 headers.
+    /// 3. Check that the 10 headers above the lowest retained header are validated in 2 batches.
+    #[test]
+    fn test_revalidate_headers_after_pruning() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+        let chain = generate_headers(genesis_hash, genesis_time, 20, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        state.prune_headers_below_height(10);
+        assert!(state.genesis().children.lock().is_empty());
+
+        let mut revalidation = state.start_header_revalidation();
+        let mut batches = 0;
+        while !revalidation.is_done() {
+            state.revalidate_headers_batch(&mut revalidation, 5);
+            batches += 1;
+        }
+        assert_eq!(batches, 2);
+        assert!(revalidation.into_failures().is_empty());
+    }
+
+    /// Tests that the `active_forks` metric counts the tips besides the active tip.
+    #[test]
+    fn test_active_forks_metric() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+
+        let chain = generate_headers(genesis_hash, genesis_time, 3, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        assert_eq!(state.metrics.active_forks.get(), 0);
+
+        let fork_1 = generate_headers(genesis_hash, genesis_time, 2, &headers_to_hashes(&chain));
+        let (_, maybe_err) = state.add_headers(&fork_1);
+        assert!(maybe_err.is_none());
+        assert_eq!(state.metrics.active_forks.get(), 1);
+
+        let block = Block {
+            header: generate_header(chain[0].block_hash(), chain[0].time, 1),
+            txdata: vec![],
+        };
+        state
+            .add_block(block)
+            .expect("should be able to add the block");
+        assert_eq!(state.metrics.tips.get(), 3);
+        assert_eq!(state.metrics.active_forks.get(), 2);
+
+        state.prune_to_active_chain();
+        assert_eq!(state.metrics.active_forks.get(), 0);
+    }
+
+    /// Tests that `BlockchainState::fork_summaries(...)` reports the fork point and depth of
+    /// each non-active tip.
+    #[test]
+    fn test_fork_summaries() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+        assert!(state.fork_summaries().is_empty());
+
+        // 0 -> 1 -> 2 -> 3 -> 4 -> 5
+        //      |         |--> 4'
+        //      |--> 2'' -> 3''
+        let chain = generate_headers(genesis_hash, genesis_time, 5, &[]);
+        let fork_1 = generate_headers(
+            chain[2].block_hash(),
+            chain[2].time,
+            1,
+            &headers_to_hashes(&chain),
+        );
+        let fork_2 = generate_headers(
+            chain[0].block_hash(),
+            chain[0].time,
+            2,
+            &headers_to_hashes(&chain),
+        );
+        for headers in [&chain, &fork_1, &fork_2] {
+            let (_, maybe_err) = state.add_headers(headers);
+            assert!(maybe_err.is_none());
+        }
+
+        let mut summaries = state.fork_summaries();
+        summaries.sort_by_key(|summary| summary.fork_point_height);
+        assert_eq!(
+            summaries,
+            vec![
+                ForkSummary {
+                    tip_hash: fork_2[1].block_hash(),
+                    tip_height: 3,
+                    fork_point_hash: chain[0].block_hash(),
+                    fork_point_height: 1,
+                    blocks_behind: 4,
+                },
+                ForkSummary {
+                    tip_hash: fork_1[0].block_hash(),
+                    tip_height: 4,
+                    fork_point_hash: chain[2].block_hash(),
+                    fork_point_height: 3,
+                    blocks_behind: 2,
+                },
+            ]
+        );
+    }
+
+    /// Tests that a competing tip only becomes the active tip once its work exceeds the work of
+    /// the active tip by more than the configured margin.
+    #[test]
+    fn test_tip_switch_margin_keeps_incumbent_tip() {
+        let config = ConfigBuilder::new()
+            .with_network(Network::Regtest)
+            .with_tip_switch_margin_blocks(Some(1))
+            .build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+
+        let chain = generate_headers(genesis_hash, genesis_time, 2, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        assert_eq!(state.active_tip_hash(), chain[1].block_hash());
+
+        // The fork exceeds the active tip's work by one block, which is within the margin.
+        let fork = generate_headers(genesis_hash, genesis_time, 3, &headers_to_hashes(&chain));
+        let (_, maybe_err) = state.add_headers(&fork);
+        assert!(maybe_err.is_none());
+        assert_eq!(state.tips.len(), 2);
+        assert_eq!(state.active_tip_hash(), chain[1].block_hash());
+
+        // Extending the active chain keeps it active.
+        let extension = generate_headers(chain[1].block_hash(), chain[1].time, 1, &[]);
+        let (_, maybe_err) = state.add_headers(&extension);
+        assert!(maybe_err.is_none());
+        assert_eq!(state.active_tip_hash(), extension[0].block_hash());
+
+        // The fork exceeds the active tip's work by two blocks, which is beyond the margin.
+        let fork_extension = generate_headers(fork[2].block_hash(), fork[2].time, 2, &[]);
+        let (_, maybe_err) = state.add_headers(&fork_extension);
+        assert!(maybe_err.is_none());
+        assert_eq!(state.active_tip_hash(), fork_extension[1].block_hash());
+    }
+
+    /// Tests that the incumbent tip kept by the tip switch margin is not dropped when the
+    /// number of tips exceeds `max_tips`.
+    /// Test Steps:
+    /// 1. Add an active chain and a fork that exceeds its work within the margin.
+    /// 2. Add a second fork with the same work as the first fork, exceeding `max_tips`.
+    /// 3. Check that the incumbent tip remains active and the second fork is dropped.
+    #[test]
+    fn test_tip_switch_margin_keeps_incumbent_tip_at_max_tips() {
+        let config = ConfigBuilder::new()
+            .with_network(Network::Regtest)
+            .with_tip_switch_margin_blocks(Some(1))
+            .with_max_tips(2)
+            .build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+
+        let chain = generate_headers(genesis_hash, genesis_time, 2, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        let fork_1 = generate_headers(genesis_hash, genesis_time, 3, &headers_to_hashes(&chain));
+        let (_, maybe_err) = state.add_headers(&fork_1);
+        assert!(maybe_err.is_none());
+        assert_eq!(state.active_tip_hash(), chain[1].block_hash());
+
+        let mut known_hashes = headers_to_hashes(&chain);
+        known_hashes.extend(headers_to_hashes(&fork_1));
+        let fork_2 = generate_headers(genesis_hash, genesis_time, 3, &known_hashes);
+        let (_, maybe_err) = state.add_headers(&fork_2);
+        assert!(maybe_err.is_none());
+
+        assert_eq!(state.tips.len(), 2);
+        assert_eq!(state.active_tip_hash(), chain[1].block_hash());
+        assert!(state.get_cached_header(&chain[1].block_hash()).is_some());
+        assert_eq!(state.tips[1].header.block_hash(), fork_1[2].block_hash());
+        assert!(state.get_cached_header(&fork_2[2].block_hash()).is_none());
+    }
+
+    /// Tests that `BlockchainState::add_headers(...)` replaces the tips in a single step: while
+    /// the headers of a reorganization are validated, the active tip remains the pre-call tip,
+    /// and afterwards the tips are sorted with the new active tip first. Validation observes
+    /// the height of the headers accepted so far.
+    #[test]
+    fn test_add_headers_swaps_tips_atomically() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+        let chain = generate_headers(genesis_hash, genesis_time, 2, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        let pre_call_tip = state.active_tip_hash();
+
+        // The fork overtakes the active chain with its third header. The last header of the
+        // batch is invalid, so the call fails after the reorganization.
+        let fork = generate_headers(genesis_hash, genesis_time, 3, &headers_to_hashes(&chain));
+        let mut invalid = generate_header(fork[2].block_hash(), fork[2].time, 42);
+        invalid.prev_blockhash = BlockHash::default();
+        let mut batch = fork.clone();
+        batch.push(invalid);
+
+        // The tips are only replaced once all headers have been processed, but validation
+        // observes the height of the best tip accepted so far. The fork only takes over the
+        // height once it exceeds the work of the first-seen active chain.
+        let mut tips = state.tips.clone();
+        for (header, height) in fork.iter().zip([2, 2, 3]) {
+            assert!(state.add_header(*header, &mut tips).is_ok());
+            assert_eq!(state.active_tip_hash(), pre_call_tip);
+            assert_eq!(HeaderStore::get_height(&state), height);
+        }
+        state.update_tips(tips);
+        assert_eq!(state.active_tip_hash(), fork[2].block_hash());
+
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        let (added_headers, maybe_err) = state.add_headers(&batch);
+        assert_eq!(added_headers.len(), 3);
+        assert!(matches!(
+            maybe_err,
+            Some(AddHeaderError::UnexpectedGenesis(_))
+        ));
+        assert_eq!(state.active_tip_hash(), fork[2].block_hash());
+        assert_eq!(state.tips.len(), 2);
+        assert!(state
+            .tips
+            .windows(2)
+            .all(|pair| pair[0].work >= pair[1].work));
+    }
+
+    /// Tests that compact block filter headers derived from `cfheaders` messages can be
+    /// queried by block hash.
+    #[test]
+    fn test_add_filter_headers() {
+        let config = ConfigBuilder::new()
+            .with_network(Network::Regtest)
+            .with_compact_filters(true)
+            .build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+        let chain = generate_headers(genesis_hash, genesis_time, 4, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        let filter_hashes: Vec<FilterHash> = (0..5u8).map(|i| FilterHash::hash(&[i])).collect();
+
+        // The filter headers of the genesis block up to height 2.
+        let message = CFHeaders {
+            filter_type: BASIC_FILTER_TYPE,
+            stop_hash: chain[1].block_hash(),
+            previous_filter_header: FilterHeader::default(),
+            filter_hashes: filter_hashes[..3].to_vec(),
+        };
+        assert_eq!(state.add_filter_headers(&message), Ok(3));
+        let genesis_filter_header = filter_hashes[0].filter_header(&FilterHeader::default());
+        let filter_header_1 = filter_hashes[1].filter_header(&genesis_filter_header);
+        let filter_header_2 = filter_hashes[2].filter_header(&filter_header_1);
+        assert_eq!(
+            state.get_filter_header(&genesis_hash),
+            Some(&genesis_filter_header)
+        );
+        assert_eq!(
+            state.get_filter_header(&chain[0].block_hash()),
+            Some(&filter_header_1)
+        );
+        assert_eq!(
+            state.get_filter_header(&chain[1].block_hash()),
+            Some(&filter_header_2)
+        );
+        assert_eq!(state.get_filter_header(&chain[2].block_hash()), None);
+
+        // The next message has to connect to the stored filter header of height 2.
+        let mut message = CFHeaders {
+            filter_type: BASIC_FILTER_TYPE,
+            stop_hash: chain[3].block_hash(),
+            previous_filter_header: filter_header_1,
+            filter_hashes: filter_hashes[3..].to_vec(),
+        };
+        assert_eq!(
+            state.add_filter_headers(&message),
+            Err(AddFilterHeadersError::PrevFilterHeaderMismatch(
+                chain[1].block_hash()
+            ))
+        );
+        message.previous_filter_header = filter_header_2;
+        assert_eq!(state.add_filter_headers(&message), Ok(2));
+        assert_eq!(
+            state.get_filter_header(&chain[3].block_hash()),
+            Some(
+                &filter_hashes[4].filter_header(&filter_hashes[3].filter_header(&filter_header_2))
+            )
+        );
+
+        message.filter_hashes = vec![filter_hashes[0]; 6];
+        assert_eq!(
+            state.add_filter_headers(&message),
+            Err(AddFilterHeadersError::TooManyFilterHashes(
+                chain[3].block_hash()
+            ))
+        );
+
+        // Filter headers are only tracked if enabled.
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        assert!(!state.tracks_filter_headers());
+        assert_eq!(
+            state.add_filter_headers(&message),
+            Err(AddFilterHeadersError::Disabled)
+        );
+        assert_eq!(state.get_filter_header(&genesis_hash), None);
+    }
+
+    /// Tests that `BlockchainState::add_block(...)` prunes blocks that are more than the
+    /// configured depth below the active tip.
+    #[test]
+    fn test_add_block_prunes_below_block_cache_depth() {
+        let config = ConfigBuilder::new()
+            .with_network(Network::Regtest)
+            .with_block_cache_depth(Some(3))
+            .build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+        let chain = generate_headers(genesis_hash, genesis_time, 10, &[]);
+
+        for header in &chain {
+            state
+                .add_block(Block {
+                    header: *header,
+                    txdata: vec![],
+                })
+                .unwrap();
+        }
+
+        // Heights 7 to 10 are within 3 blocks of the tip at height 10.
+        for (idx, header) in chain.iter().enumerate() {
+            assert_eq!(state.contains_block(&header.block_hash()), idx >= 6);
+        }
+        assert_eq!(state.block_cache.len(), 4);
+
+        // Without a depth, nothing is pruned.
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        for header in &chain {
+            state
+                .add_block(Block {
+                    header: *header,
+                    txdata: vec![],
+                })
+                .unwrap();
+        }
+        assert_eq!(state.block_cache.len(), 10);
+    }
+
+    /// Tests that `BlockchainState::prune_to_active_chain(...)` removes the headers and blocks of
+    /// every fork while keeping the active chain.
+    /// Test Steps:
+    /// 1. Add a main chain and two forks, with blocks on the main chain and a fork.
+    /// 2. Prune to the active chain.
+    /// 3. Check that only the active chain's headers and blocks remain and that there is a single tip.
+    /// 4. Check that pruning again does not change the state.
+    #[test]
+    fn test_prune_to_active_chain() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let initial_bytes = state.get_header_cache_bytes();
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+        let chain = generate_headers(genesis_hash, genesis_time, 6, &[]);
+        let chain_hashes = headers_to_hashes(&chain);
+        let fork_1 = generate_headers(chain_hashes[1], chain[1].time, 2, &chain_hashes);
+        let fork_2 = generate_headers(chain_hashes[2], chain[2].time, 1, &chain_hashes);
+        for headers in [&chain, &fork_1, &fork_2] {
+            let (_, maybe_err) = state.add_headers(headers);
+            assert!(maybe_err.is_none());
+        }
+        for header in [chain[0], chain[3], fork_1[0]] {
+            state
+                .add_block(Block {
+                    header,
+                    txdata: vec![],
+                })
+                .unwrap();
+        }
+        assert_eq!(state.tips.len(), 3);
+
+        for _ in 0..2 {
+            state.prune_to_active_chain();
+            assert_eq!(state.tips.len(), 1);
+            assert_eq!(state.get_active_chain_tip().header, chain[5]);
+            for header in &chain {
+                assert!(state.get_cached_header(&header.block_hash()).is_some());
+            }
+            for header in fork_1.iter().chain(fork_2.iter()) {
+                assert!(state.get_cached_header(&header.block_hash()).is_none());
+            }
+            assert!(state.get_cached_header(&genesis_hash).is_some());
+            assert_eq!(
+                state.get_header_cache_bytes(),
+                initial_bytes + 6 * (HEADER_CACHE_ENTRY_BYTES + HEADER_CACHE_CHILD_BYTES)
+            );
+            let block_hashes: Vec<BlockHash> = state.block_cache.keys().copied().collect();
+            assert_eq!(block_hashes, vec![chain_hashes[0], chain_hashes[3]]);
+            assert_eq!(
+                state.metrics.block_cache_size.get(),
+                state.get_block_cache_size() as i64
+            );
+        }
+    }
 
-        let (added_headers, maybe_err) = state.add_headers(&chain);
+    /// Tests that `BlockchainState::add_block(...)` evicts the lowest blocks below the active tip
+    /// once the cache holds more than `max_block_cache_entries` blocks.
+    #[test]
+    fn test_add_block_enforces_max_block_cache_entries() {
+        let config = ConfigBuilder::new()
+            .with_network(Network::Regtest)
+            .with_max_block_cache_entries(Some(3))
+            .build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis = state.genesis().header;
+        let chain = generate_headers(genesis.block_hash(), genesis.time, 5, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
         assert!(maybe_err.is_none());
 
-        let last_cached = added_headers.last().unwrap();
-        assert_eq!(last_cached.header.block_hash(), last_hash);
-        assert_eq!(last_cached.height, 16);
-        let tip = state.get_active_chain_tip();
-        assert_eq!(tip.height, 16);
-        assert_eq!(tip.header.block_hash(), last_hash);
+        for idx in [4, 0, 2] {
+            state
+                .add_block(Block {
+                    header: chain[idx],
+                    txdata: vec![],
+                })
+                .expect("should be able to add the block");
+        }
+        assert_eq!(state.block_cache.len(), 3);
+
+        // The block at height 1 is the lowest, although the tip's block was added first.
+        state
+            .add_block(Block {
+                header: chain[1],
+                txdata: vec![],
+            })
+            .expect("should be able to add the block");
+        assert_eq!(state.block_cache.len(), 3);
+        assert!(!state.contains_block(&chain[0].block_hash()));
+
+        state
+            .add_block(Block {
+                header: chain[3],
+                txdata: vec![],
+            })
+            .expect("should be able to add the block");
+        let mut cached: Vec<_> = state.block_cache.keys().copied().collect();
+        cached.sort();
+        let mut expected = vec![
+            chain[2].block_hash(),
+            chain[3].block_hash(),
+            chain[4].block_hash(),
+        ];
+        expected.sort();
+        assert_eq!(cached, expected);
     }
 
+    /// Tests that `BlockchainState::evict_oldest_block(...)` evicts blocks in insertion order.
+    /// Test Steps:
+    /// 1. Add blocks at heights 1 to 4 out of order.
+    /// 2. Evict blocks and check that they are evicted in insertion order.
+    /// 3. Check that the block at the active tip is never evicted.
     #[test]
-    /// Tests whether or not the `BlockchainState::add_headers(...)` function can add headers that
-    /// cause 2 forks in the chain. The state should be able to determine what is the active tip.
-    fn test_forks_when_adding_headers() {
+    fn test_evict_oldest_block() {
         let config = ConfigBuilder::new().with_network(Network::Regtest).build();
         let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
-        let initial_header = state.genesis();
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+        let chain = generate_headers(genesis_hash, genesis_time, 4, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        for idx in [2, 0, 3, 1] {
+            state
+                .add_block(Block {
+                    header: chain[idx],
+                    txdata: vec![],
+                })
+                .unwrap();
+        }
 
-        // Create an arbitrary chain and adding to the BlockchainState
-        let chain = generate_headers(
-            initial_header.header.block_hash(),
-            initial_header.header.time,
-            16,
-            &[],
+        assert_eq!(state.evict_oldest_block(), Some(chain[2].block_hash()));
+        assert_eq!(state.evict_oldest_block(), Some(chain[0].block_hash()));
+        // The block at the active tip is skipped.
+        assert_eq!(state.evict_oldest_block(), Some(chain[1].block_hash()));
+        assert_eq!(state.evict_oldest_block(), None);
+        assert!(state.contains_block(&chain[3].block_hash()));
+        assert_eq!(
+            state.get_block_cache_size(),
+            Block {
+                header: chain[3],
+                txdata: vec![]
+            }
+            .size()
         );
-        let chain_hashes: Vec<BlockHash> = chain.iter().map(|header| header.block_hash()).collect();
-        let last_chain_hash = chain_hashes.last().expect("missing last hash");
+    }
+
+    /// Tests that `BlockchainState::get_header_cache_bytes(...)` grows as headers are added.
+    /// Test Steps:
+    /// 1. Check the estimate of a state containing only the genesis header.
+    /// 2. Add a chain and a fork and check that the estimate grows with each added header.
+    #[test]
+    fn test_header_cache_bytes() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let initial_bytes = state.get_header_cache_bytes();
+        assert_eq!(initial_bytes, HEADER_CACHE_ENTRY_BYTES);
+        assert!(initial_bytes > 80);
 
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+        let chain = generate_headers(genesis_hash, genesis_time, 8, &[]);
         let (_, maybe_err) = state.add_headers(&chain);
-        assert!(
-            maybe_err.is_none(),
-            "unsuccessfully added first chain: {:?}",
-            maybe_err
+        assert!(maybe_err.is_none());
+        let chain_bytes = state.get_header_cache_bytes();
+        assert_eq!(
+            chain_bytes,
+            initial_bytes + 8 * (HEADER_CACHE_ENTRY_BYTES + HEADER_CACHE_CHILD_BYTES)
         );
 
-        // Create a fork chain forking from chain_hashes[10] and adding to the BlockchainState.
-        let fork_chain = generate_headers(chain_hashes[10], chain[10].time, 16, &chain_hashes);
-        let fork_hashes: Vec<BlockHash> = fork_chain
-            .iter()
-            .map(|header| header.block_hash())
-            .collect();
-        let last_fork_hash = fork_hashes.last().expect("missing last hash");
-
-        let (_, maybe_err) = state.add_headers(&fork_chain);
-        assert!(
-            maybe_err.is_none(),
-            "unsuccessfully added fork chain: {:?}",
-            maybe_err
+        let fork = generate_headers(
+            chain[3].block_hash(),
+            chain[3].time,
+            2,
+            &headers_to_hashes(&chain),
+        );
+        let (_, maybe_err) = state.add_headers(&fork);
+        assert!(maybe_err.is_none());
+        assert_eq!(
+            state.get_header_cache_bytes(),
+            chain_bytes + 2 * (HEADER_CACHE_ENTRY_BYTES + HEADER_CACHE_CHILD_BYTES)
+        );
+        assert_eq!(
+            state.metrics.header_cache_bytes.get(),
+            state.get_header_cache_bytes() as i64
         );
-
-        assert_eq!(state.tips.len(), 2);
-        assert_eq!(state.tips[0].header.block_hash(), *last_fork_hash);
-        assert_eq!(state.tips[1].header.block_hash(), *last_chain_hash);
-        assert_eq!(state.get_active_chain_tip().height, 27);
     }
 
-    /// Tests `BlockchainState::add_headers(...)` with an empty set of headers.
+    /// Tests that a custom regtest genesis header is used as the starting point of the header
+    /// cache and as the initial tip, and that headers can be added on top of it.
     #[test]
-    fn test_adding_an_empty_headers_vector() {
-        let config = ConfigBuilder::new().build();
+    fn test_custom_genesis() {
+        let genesis = generate_header(BlockHash::default(), 1_600_000_000, 0);
+        let config = ConfigBuilder::new()
+            .with_network(Network::Regtest)
+            .with_custom_genesis(Some(hex::encode(bitcoin::consensus::serialize(&genesis))))
+            .build();
         let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
-        let chain = vec![];
-        let (added_headers, maybe_err) = state.add_headers(&chain);
+        assert_eq!(state.genesis().header, genesis);
+        assert_eq!(state.get_active_chain_tip().header, genesis);
+        assert_ne!(
+            genesis.block_hash(),
+            genesis_block(Network::Regtest).header.block_hash()
+        );
+
+        let chain = generate_headers(genesis.block_hash(), genesis.time, 4, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
         assert!(maybe_err.is_none());
-        assert!(added_headers.is_empty());
-        assert_eq!(state.get_active_chain_tip().height, 0);
+        assert_eq!(state.get_active_chain_tip().height, 4);
+        assert_eq!(state.locator_hashes().last(), Some(&genesis.block_hash()));
     }
 
-    /// Tests whether or not the `BlockchainState::add_headers(...)` function can handle adding already known
-    /// headers in the state.
+    /// Tests that headers at a retarget boundary must carry the difficulty recomputed from the
+    /// previous retarget interval.
     #[test]
-    fn test_adding_headers_that_already_exist() {
+    fn test_unexpected_difficulty_at_retarget_boundary() {
         let config = ConfigBuilder::new().with_network(Network::Regtest).build();
         let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        state.difficulty_adjustment = Some(DifficultyAdjustment {
+            interval: 4,
+            target_timespan: 2400,
+            pow_limit: max_target(Network::Regtest),
+        });
 
-        let initial_header = state.genesis();
-        let chain = generate_headers(
-            initial_header.header.block_hash(),
-            initial_header.header.time,
-            16,
-            &[],
-        );
-        let chain_hashes: Vec<BlockHash> = chain.iter().map(|header| header.block_hash()).collect();
-        let last_hash = *chain_hashes.last().unwrap();
+        let next_header = |prev: &BlockHeader, bits: u32| {
+            let mut header = BlockHeader {
+                version: 1,
+                prev_blockhash: prev.block_hash(),
+                merkle_root: TxMerkleNode::default(),
+                time: prev.time + 800,
+                bits,
+                nonce: 0,
+            };
+            while header.validate_pow(&header.target()).is_err() {
+                header.nonce += 1;
+            }
+            header
+        };
 
-        let (added_headers, maybe_err) = state.add_headers(&chain);
+        let genesis = state.genesis().header;
+        let mut chain = vec![next_header(&genesis, genesis.bits)];
+        for _ in 0..2 {
+            let header = next_header(chain.last().unwrap(), genesis.bits);
+            chain.push(header);
+        }
+        let (_, maybe_err) = state.add_headers(&chain);
         assert!(maybe_err.is_none());
-        assert_eq!(added_headers.len(), 16);
-
-        let last_cached = added_headers.last().unwrap();
-        assert_eq!(last_cached.header.block_hash(), last_hash);
-        assert_eq!(last_cached.height, 16);
 
-        let (added_headers, maybe_err) = state.add_headers(&chain);
+        // The interval took exactly the target timespan, so the difficulty must not change.
+        let unexpected = BlockHeader {
+            bits: 0x1f7fffff,
+            ..next_header(chain.last().unwrap(), genesis.bits)
+        };
+        let (_, maybe_err) = state.add_headers(&[unexpected]);
+        assert!(matches!(
+            maybe_err,
+            Some(AddHeaderError::UnexpectedDifficulty { bits, expected_bits, .. })
+                if bits == 0x1f7fffff && expected_bits == genesis.bits
+        ));
+
+        let expected = next_header(chain.last().unwrap(), genesis.bits);
+        let (_, maybe_err) = state.add_headers(&[expected]);
         assert!(maybe_err.is_none());
-        assert!(added_headers.is_empty());
+        assert_eq!(state.get_active_chain_tip().height, 4);
     }
 
-    /// Tests whether or not the `BlockchainState::add_headers(...)` function can add headers while avoiding
-    /// adding a header that is invalid.
+    /// Simple test to verify that `BlockchainState::block_cache_size()` returns the total
+    /// number of bytes in the block cache.
     #[test]
-    fn test_adding_headers_with_an_invalid_header() {
+    fn test_block_cache_size() {
+        let test_state = TestState::setup();
+        let config = ConfigBuilder::new().build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+
+        let block_cache_size = state.get_block_cache_size();
+        assert_eq!(block_cache_size, 0);
+
+        state.add_block(test_state.block_1.clone()).unwrap();
+        state.add_block(test_state.block_2.clone()).unwrap();
+
+        let expected_cache_size = test_state.block_1.size() + test_state.block_2.size();
+        let block_cache_size = state.get_block_cache_size();
+
+        assert_eq!(expected_cache_size, block_cache_size);
+    }
+
+    /// Tests that `BlockchainState::locator_hashes_with(...)` honors a custom dense prefix and
+    /// maximum step count while still appending the genesis hash.
+    #[test]
+    fn test_locator_hashes_with_custom_schedule() {
         let config = ConfigBuilder::new().with_network(Network::Regtest).build();
         let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+        let chain = generate_headers(genesis_hash, genesis_time, 16, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
 
-        let initial_header = state.genesis();
-        let mut chain = generate_headers(
-            initial_header.header.block_hash(),
-            initial_header.header.time,
-            16,
-            &[],
+        // The default schedule must be unchanged.
+        assert_eq!(
+            state.locator_hashes(),
+            state.locator_hashes_with(DEFAULT_LOCATOR_DENSE_HASHES, DEFAULT_LOCATOR_MAX_STEPS)
         );
-        let last_header = chain.get_mut(10).unwrap();
-        last_header.prev_blockhash = BlockHash::default();
 
-        let chain_hashes: Vec<BlockHash> = chain.iter().map(|header| header.block_hash()).collect();
-        let last_hash = chain_hashes[10];
+        // Two dense steps, then doubling: tip, tip - 1, tip - 2, tip - 4, genesis.
+        let hashes = state.locator_hashes_with(2, 4);
+        assert_eq!(
+            hashes,
+            vec![
+                chain[15].block_hash(),
+                chain[14].block_hash(),
+                chain[13].block_hash(),
+                chain[11].block_hash(),
+                genesis_hash,
+            ]
+        );
 
-        let (added_headers, maybe_err) = state.add_headers(&chain);
+        // Every header is dense until the genesis header is reached.
+        let hashes = state.locator_hashes_with(u32::MAX, 100);
+        assert_eq!(hashes.len(), 17);
+        assert_eq!(hashes.last(), Some(&genesis_hash));
+    }
 
-        assert_eq!(added_headers.len(), 10);
-        assert!(
-            matches!(maybe_err, Some(AddHeaderError::InvalidHeader(block_hash, err)) if block_hash == last_hash && matches!(err, ValidateHeaderError::PrevHeaderNotFound))
+    /// Tests that `BlockchainState::locator_hashes_from(...)` matches `locator_hashes` for the
+    /// active tip and can start at any known header.
+    /// Test Steps:
+    /// 1. Add a main chain and a shorter fork.
+    /// 2. Check that the locators from the active tip's hash match `locator_hashes`.
+    /// 3. Check that the locators from the fork's tip start at the fork's tip.
+    /// 4. Check that an unknown hash returns no locators.
+    #[test]
+    fn test_locator_hashes_from() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+        let chain = generate_headers(genesis_hash, genesis_time, 16, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        let chain_hashes = headers_to_hashes(&chain);
+        let fork = generate_headers(chain_hashes[4], chain[4].time, 3, &chain_hashes);
+        let (_, maybe_err) = state.add_headers(&fork);
+        assert!(maybe_err.is_none());
+
+        let tip_hash = state.get_active_chain_tip().header.block_hash();
+        assert_eq!(tip_hash, chain[15].block_hash());
+        assert_eq!(state.locator_hashes_from(&tip_hash), state.locator_hashes());
+
+        let fork_tip_hash = fork[2].block_hash();
+        let hashes = state.locator_hashes_from(&fork_tip_hash);
+        assert_eq!(
+            hashes,
+            vec![
+                fork[2].block_hash(),
+                fork[1].block_hash(),
+                fork[0].block_hash(),
+                chain[4].block_hash(),
+                chain[3].block_hash(),
+                chain[2].block_hash(),
+                chain[1].block_hash(),
+                chain[0].block_hash(),
+                genesis_hash,
+            ]
         );
 
-        let tip = state.get_active_chain_tip();
-        assert_eq!(tip.height, 10);
+        assert!(state.locator_hashes_from(&BlockHash::default()).is_empty());
     }
 
-    /// Tests the functionality of `BlockchainState::add_block(...)` to push it through the add_header
-    /// validation and adding the block to the cache.
+    /// Tests that `BlockchainState::active_chain_iter(...)` walks the active chain from the tip
+    /// back to the genesis header.
+    /// Test Steps:
+    /// 1. Add a main chain and a shorter fork.
+    /// 2. Check that the iterator yields tip height + 1 headers with decreasing heights.
+    /// 3. Check that the headers are the main chain's headers followed by the genesis header.
     #[test]
-    fn test_adding_blocks_to_the_cache() {
-        let block_1 = block_1();
-        let mut block_2 = block_2();
-
-        let config = ConfigBuilder::new().build();
+    fn test_active_chain_iter() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
         let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        assert_eq!(state.active_chain_iter().count(), 1);
 
-        // Attempt to add block 2 to the cache before block 1's header has been added.
-        let block_2_hash = block_2.header.block_hash();
-        let result = state.add_block(block_2.clone());
-        assert!(
-            matches!(result, Err(AddBlockError::Header(AddHeaderError::InvalidHeader(stop_hash, err))) if stop_hash == block_2_hash && matches!(err, ValidateHeaderError::PrevHeaderNotFound)),
-        );
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+        let chain = generate_headers(genesis_hash, genesis_time, 16, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        let chain_hashes = headers_to_hashes(&chain);
+        let fork = generate_headers(chain_hashes[4], chain[4].time, 3, &chain_hashes);
+        let (_, maybe_err) = state.add_headers(&fork);
+        assert!(maybe_err.is_none());
 
-        let result = state.add_block(block_1);
-        assert!(matches!(result, Ok(height) if height == 1));
+        let tip_height = state.get_active_chain_tip().height;
+        let headers: Vec<_> = state.active_chain_iter().collect();
+        assert_eq!(headers.len(), tip_height as usize + 1);
+        for (cached, expected_height) in headers.iter().zip((0..=tip_height).rev()) {
+            assert_eq!(cached.height, expected_height);
+        }
 
-        // Make a block 2's merkle root invalid and try to add the block to the cache.
-        block_2.header.merkle_root = TxMerkleNode::default();
-        // Block 2's hash will now be changed because of the merkle root change.
-        let block_2_hash = block_2.block_hash();
-        let result = state.add_block(block_2);
-        assert!(
-            matches!(result, Err(AddBlockError::InvalidMerkleRoot(stop_hash)) if stop_hash == block_2_hash),
-        );
+        let hashes: Vec<_> = headers
+            .iter()
+            .map(|cached| cached.header.block_hash())
+            .collect();
+        let mut expected_hashes: Vec<_> = chain_hashes.into_iter().rev().collect();
+        expected_hashes.push(genesis_hash);
+        assert_eq!(hashes, expected_hashes);
     }
 
-    /// Tests the functionality of `BlockchainState::prune_blocks(...)` to ensure
-    /// blocks are removed from the cache.
+    /// Tests that `BlockchainState::headers_following_locator(...)` follows the `getheaders`
+    /// semantics.
+    /// Test Steps:
+    /// 1. Add a main chain and a shorter fork.
+    /// 2. Check that an empty or unknown locator starts after the genesis header.
+    /// 3. Check that the first locator hash on the active chain is the starting point.
+    /// 4. Check that the headers stop at the stop hash or the tip.
     #[test]
-    fn test_pruning_blocks_from_the_cache() {
-        let test_state = TestState::setup();
-        let config = ConfigBuilder::new().build();
+    fn test_headers_following_locator() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
         let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
-        let block_1_hash = test_state.block_1.block_hash();
-        let block_2_hash = test_state.block_2.block_hash();
-        state.add_block(test_state.block_1).unwrap();
-        state.add_block(test_state.block_2).unwrap();
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+        let chain = generate_headers(genesis_hash, genesis_time, 16, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        let chain_hashes = headers_to_hashes(&chain);
+        let fork = generate_headers(chain_hashes[4], chain[4].time, 3, &chain_hashes);
+        let (_, maybe_err) = state.add_headers(&fork);
+        assert!(maybe_err.is_none());
 
-        state.prune_blocks(&[block_2_hash]);
-        assert!(state.block_cache.contains_key(&block_1_hash));
-        assert!(!state.block_cache.contains_key(&block_2_hash));
+        // An empty locator starts after the genesis header.
+        let headers = state.headers_following_locator(&[], BlockHash::default(), 5);
+        assert_eq!(headers, chain[..5].to_vec());
+
+        // An unknown locator starts after the genesis header.
+        let headers =
+            state.headers_following_locator(&[BlockHash::default()], BlockHash::default(), 5);
+        assert_eq!(headers, chain[..5].to_vec());
+
+        // The fork's tip is not on the active chain, so the next locator hash is used.
+        let locator = [fork[2].block_hash(), chain_hashes[2]];
+        let headers = state.headers_following_locator(&locator, BlockHash::default(), 100);
+        assert_eq!(headers, chain[3..].to_vec());
+
+        // The stop hash is in the middle of the range.
+        let headers = state.headers_following_locator(&[chain_hashes[3]], chain_hashes[7], 100);
+        assert_eq!(headers, chain[4..=7].to_vec());
+
+        // The tip has no following headers.
+        let headers = state.headers_following_locator(&[chain_hashes[15]], chain_hashes[7], 100);
+        assert!(headers.is_empty());
     }
 
-    /// Tests the functionality of `BlockchainState::prune_blocks_below_height(...)` to ensure
-    /// blocks are removed from the cache that are below a given height.
+    /// Tests that `BlockchainState::headers_following_locator(...)` resolves the starting point
+    /// by height once old headers have been pruned.
+    /// Test Steps:
+    /// 1. Add a chain of 16 headers and prune the headers below height 8.
+    /// 2. Check that a locator on the retained part of the chain is served.
+    /// 3. Check that a locator below the retained headers yields no headers.
     #[test]
-    fn test_pruning_blocks_below_a_given_height_from_the_cache() {
-        let test_state = TestState::setup();
-        let config = ConfigBuilder::new().build();
+    fn test_headers_following_locator_after_pruning() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
         let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
-        let block_1_hash = test_state.block_1.block_hash();
-        let block_2_hash = test_state.block_2.block_hash();
-        state.add_block(test_state.block_1).unwrap();
-        state.add_block(test_state.block_2).unwrap();
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+        let chain = generate_headers(genesis_hash, genesis_time, 16, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        let chain_hashes = headers_to_hashes(&chain);
+        state.prune_headers_below_height(8);
+        assert!(state.get_cached_header(&chain_hashes[5]).is_none());
+
+        // The header at height 10 is retained, so the headers above it are returned.
+        let locator = [chain_hashes[9], genesis_hash];
+        let headers = state.headers_following_locator(&locator, BlockHash::default(), 100);
+        assert_eq!(headers, chain[10..].to_vec());
+
+        let headers = state.headers_following_locator(&locator, chain_hashes[12], 100);
+        assert_eq!(headers, chain[10..=12].to_vec());
+
+        // The headers following the genesis header have been pruned.
+        let locator = [chain_hashes[5], genesis_hash];
+        let headers = state.headers_following_locator(&locator, BlockHash::default(), 100);
+        assert!(headers.is_empty());
+    }
 
-        state.prune_blocks_below_height(2);
-        assert!(!state.block_cache.contains_key(&block_1_hash));
-        assert!(state.block_cache.contains_key(&block_2_hash));
+    /// Tests that `BlockchainState::locator_hashes(...)` records the number of returned hashes.
+    /// Test Steps:
+    /// 1. Check the gauge for a state containing only the genesis header.
+    /// 2. Add headers and check that the gauge reflects the length of the new locator.
+    #[test]
+    fn test_locator_hashes_metric() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let hashes = state.locator_hashes();
+        assert_eq!(hashes.len(), 1);
+        assert_eq!(state.metrics.locator_hashes.get(), 1);
+
+        let genesis_hash = state.genesis().header.block_hash();
+        let genesis_time = state.genesis().header.time;
+        let chain = generate_headers(genesis_hash, genesis_time, 32, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        let hashes = state.locator_hashes();
+        // tip, tip - 1, ..., tip - 8, tip - 10, tip - 14, tip - 22, genesis.
+        assert_eq!(hashes.len(), 13);
+        assert_eq!(state.metrics.locator_hashes.get(), hashes.len() as i64);
     }
 
-    /// Simple test to verify that `BlockchainState::block_cache_size()` returns the total
-    /// number of bytes in the block cache.
+    /// Tests that `BlockchainState::get_active_chain_work(...)` follows the active tip and that
+    /// the work approximation is consistent with the actual value.
     #[test]
-    fn test_block_cache_size() {
-        let test_state = TestState::setup();
-        let config = ConfigBuilder::new().build();
+    fn test_get_active_chain_work() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
         let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis = state.genesis().clone();
+        assert_eq!(state.get_active_chain_work(), genesis.work);
 
-        let block_cache_size = state.get_block_cache_size();
-        assert_eq!(block_cache_size, 0);
+        let chain = generate_headers(genesis.header.block_hash(), genesis.header.time, 4, &[]);
+        let (added_headers, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        let last = added_headers.last().expect("there should be 4 headers");
+        assert_eq!(state.get_active_chain_work(), last.work);
+        assert!(state.get_active_chain_work() > genesis.work);
 
-        state.add_block(test_state.block_1.clone()).unwrap();
-        state.add_block(test_state.block_2.clone()).unwrap();
+        assert_eq!(work_log2(&Work::from_u64(1).unwrap()), 0.0);
+        assert_eq!(work_log2(&Work::from_u64(1 << 40).unwrap()), 40.0);
+        assert_eq!(work_log2(&(Work::from_u64(1).unwrap() << 100)), 100.0);
+    }
 
-        let expected_cache_size = test_state.block_1.size() + test_state.block_2.size();
-        let block_cache_size = state.get_block_cache_size();
+    /// Tests that `BlockchainState::active_tip_hash(...)` and
+    /// `BlockchainState::active_tip_height(...)` agree with the active chain's tip.
+    #[test]
+    fn test_active_tip_hash_and_height() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis = state.genesis().clone();
+        assert_eq!(state.active_tip_hash(), genesis.header.block_hash());
+        assert_eq!(state.active_tip_height(), 0);
 
-        assert_eq!(expected_cache_size, block_cache_size);
+        let chain = generate_headers(genesis.header.block_hash(), genesis.header.time, 4, &[]);
+        let (_, maybe_err) = state.add_headers(&chain);
+        assert!(maybe_err.is_none());
+        let tip = state.get_active_chain_tip();
+        assert_eq!(state.active_tip_hash(), tip.header.block_hash());
+        assert_eq!(state.active_tip_height(), tip.height);
+        assert_eq!(state.active_tip_hash(), chain[3].block_hash());
+        assert_eq!(state.active_tip_height(), 4);
+    }
+
+    /// Tests `BlockchainState::get_confirmations(...)` for a block on the active chain, a block
+    /// on a stale fork, and an unknown block hash.
+    #[test]
+    fn test_get_confirmations() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let mut state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis = state.genesis().clone();
+        let genesis_hash = genesis.header.block_hash();
+
+        // 0 -> 1 -> 2 -> 3 -> 4
+        // |--> 1'
+        let chain = generate_headers(genesis_hash, genesis.header.time, 4, &[]);
+        let fork = generate_headers(
+            genesis_hash,
+            genesis.header.time,
+            1,
+            &headers_to_hashes(&chain),
+        );
+        state.add_headers(&chain);
+        state.add_headers(&fork);
+
+        assert_eq!(state.get_confirmations(&chain[3].block_hash()), Some(1));
+        assert_eq!(state.get_confirmations(&chain[1].block_hash()), Some(3));
+        assert_eq!(state.get_confirmations(&genesis_hash), Some(5));
+        assert_eq!(state.get_confirmations(&fork[0].block_hash()), Some(0));
+        assert_eq!(state.get_confirmations(&BlockHash::default()), None);
     }
 
     /// Test that verifies that the tip is always correctly sorted in case of forks and blocks
@@ -1,10 +1,39 @@
 //! A parser for the command line flags and configuration file.
-use crate::config::Config;
+use crate::{
+    addressbook::split_dns_seed_port,
+    common::{MINIMUM_VERSION_NUMBER, MIN_HEADER_CHAIN_LENGTH},
+    config::{Config, SocksProxy},
+    peer_filter::PeerMatcher,
+};
+use bitcoin::Network;
 use clap::Parser;
 use http::Uri;
-use std::{fs::File, io, path::PathBuf};
+use std::{
+    env,
+    fs::File,
+    io::{self, Read},
+    net::{Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 use thiserror::Error;
 
+/// The maximum length of the user agent in `version` messages (BIP-14).
+const MAX_USER_AGENT_LENGTH: usize = 256;
+
+/// Environment variable overriding the `network` field of the config file.
+pub const ENV_NETWORK: &str = "BITCOIN_ADAPTER_NETWORK";
+/// Environment variable overriding the `socks_proxy` field of the config file.
+pub const ENV_SOCKS_PROXY: &str = "BITCOIN_ADAPTER_SOCKS_PROXY";
+/// Environment variable overriding the `idle_seconds` field of the config file.
+pub const ENV_IDLE_SECONDS: &str = "BITCOIN_ADAPTER_IDLE_SECONDS";
+
+/// The `config` argument value that makes the adapter read the config from stdin.
+const STDIN_CONFIG: &str = "-";
+
+/// The accepted values of the `network` field.
+const ACCEPTED_NETWORKS: [&str; 4] = ["bitcoin", "testnet", "signet", "regtest"];
+
 #[allow(missing_docs)]
 #[derive(Debug, Error)]
 pub enum CliError {
@@ -19,31 +48,933 @@ pub enum CliError {
 #[derive(Parser)]
 #[clap(version = "0.0.0", author = "BitBolt Team")]
 pub struct Cli {
-    /// This field contains the path to the config file.
-    pub config: PathBuf,
+    /// This field contains the path to the config file. If the path is `-`, the config is read
+    /// from stdin.
+    #[clap(required_unless_present = "generate_config")]
+    pub config: Option<PathBuf>,
+    /// If this field is set, the default config of the given network is printed instead of
+    /// starting the adapter, e.g., `--generate-config testnet`.
+    #[clap(long, value_name = "NETWORK")]
+    pub generate_config: Option<Network>,
 }
 
 impl Cli {
     /// Loads the config from the provided `config` argument.
+    /// Values from the config file are overridden by the following environment variables:
+    /// - `BITCOIN_ADAPTER_NETWORK`: the Bitcoin network (e.g. `bitcoin`, `testnet`).
+    /// - `BITCOIN_ADAPTER_SOCKS_PROXY`: the SOCKS proxy url.
+    /// - `BITCOIN_ADAPTER_IDLE_SECONDS`: the number of seconds before the adapter becomes idle.
     pub fn get_config(&self) -> Result<Config, CliError> {
+        self.get_config_with_stdin(io::stdin())
+    }
+
+    /// Returns true if the config is read from stdin, in which case it cannot be read again.
+    pub fn reads_config_from_stdin(&self) -> bool {
+        self.config.as_deref() == Some(Path::new(STDIN_CONFIG))
+    }
+
+    /// Loads the config from the provided `config` argument, reading it from the provided
+    /// `stdin` reader if the argument is `-`.
+    fn get_config_with_stdin<R: Read>(&self, stdin: R) -> Result<Config, CliError> {
+        let path = self.config.as_deref().ok_or_else(|| {
+            CliError::Validation("the path to the config file is missing".to_string())
+        })?;
+        if path == Path::new(STDIN_CONFIG) {
+            Self::get_config_from_reader(stdin)
+        } else {
+            let file = File::open(path).map_err(CliError::Io)?;
+            Self::get_config_from_reader(file)
+        }
+    }
+
+    /// Loads and validates the config from the provided reader.
+    fn get_config_from_reader<R: Read>(reader: R) -> Result<Config, CliError> {
         // The expected JSON config.
-        let file = File::open(&self.config).map_err(CliError::Io)?;
-        let config: Config =
-            serde_json::from_reader(file).map_err(|err| CliError::Deserialize(err.to_string()))?;
+        let value: serde_json::Value = serde_json::from_reader(reader)
+            .map_err(|err| CliError::Deserialize(err.to_string()))?;
+        validate_network(&value)?;
+        let mut config: Config =
+            serde_json::from_value(value).map_err(|err| CliError::Deserialize(err.to_string()))?;
+
+        apply_env_overrides(&mut config)?;
 
-        // Validate proxy URL.
+        // Validate proxy URLs.
         // Check for general validation errors.
         if let Some(socks_proxy) = &config.socks_proxy {
-            let uri = socks_proxy
-                .parse::<Uri>()
-                .map_err(|_| CliError::Validation("Failed to parse socks_proxy url".to_string()))?;
-            // scheme, host, port should be present. 'socks5://someproxy.com:80'
-            if uri.scheme().is_none() || uri.host().is_none() || uri.port().is_none() {
+            if socks_proxy.urls().is_empty() {
+                return Err(CliError::Validation(
+                    "socks_proxy list must not be empty".to_string(),
+                ));
+            }
+            for url in socks_proxy.urls() {
+                let uri = url.parse::<Uri>().map_err(|_| {
+                    CliError::Validation(format!("Failed to parse socks_proxy url: {}", url))
+                })?;
+                // scheme, host, port should be present. 'socks5://someproxy.com:80'
+                if uri.scheme().is_none() || uri.host().is_none() || uri.port().is_none() {
+                    return Err(CliError::Validation(format!(
+                        "Make sure socks proxy url contains (scheme,host,port): {}",
+                        url
+                    )));
+                }
+            }
+        }
+
+        if config.proxy_only && config.socks_proxy.is_none() {
+            return Err(CliError::Validation(
+                "proxy_only requires a socks_proxy to be configured".to_string(),
+            ));
+        }
+
+        if let Some(signet_challenge) = &config.signet_challenge {
+            if config.network != Network::Signet {
                 return Err(CliError::Validation(
-                    "Make sure socks proxy url contains (scheme,host,port)".to_string(),
+                    "signet_challenge is only valid for the signet network".to_string(),
                 ));
             }
+            if !matches!(hex::decode(signet_challenge), Ok(challenge) if !challenge.is_empty()) {
+                return Err(CliError::Validation(format!(
+                    "signet_challenge must be a non-empty hex-encoded script: {}",
+                    signet_challenge
+                )));
+            }
+        }
+
+        if config.rpc_systemd_socket_index < 1 || config.metrics_systemd_socket_index < 1 {
+            return Err(CliError::Validation(
+                "systemd socket indices must be greater than 0".to_string(),
+            ));
+        }
+
+        if config.rpc_systemd_socket_index == config.metrics_systemd_socket_index {
+            return Err(CliError::Validation(format!(
+                "rpc_systemd_socket_index and metrics_systemd_socket_index must be distinct: {}",
+                config.rpc_systemd_socket_index
+            )));
+        }
+
+        if let Some((module, level)) = config
+            .module_log_levels
+            .iter()
+            .find(|(_, level)| slog::Level::from_str(level).is_err())
+        {
+            return Err(CliError::Validation(format!(
+                "module_log_levels contains an invalid level for {}: {}",
+                module, level
+            )));
+        }
+
+        if !config.is_network_allowed() {
+            return Err(CliError::Validation(format!(
+                "network {} is not one of the allowed_networks",
+                config.network
+            )));
+        }
+
+        if let Some(custom_genesis) = &config.custom_genesis {
+            if config.network != Network::Regtest {
+                return Err(CliError::Validation(
+                    "custom_genesis is only valid for the regtest network".to_string(),
+                ));
+            }
+            if config.custom_genesis_header().is_none() {
+                return Err(CliError::Validation(format!(
+                    "custom_genesis must be a hex-encoded serialized block header: {}",
+                    custom_genesis
+                )));
+            }
+        }
+
+        // Validate DNS seeds. Explicit node addresses are socket addresses and always carry a port.
+        if let Some(seed) = config
+            .dns_seeds
+            .iter()
+            .find(|seed| !is_valid_dns_seed(seed))
+        {
+            return Err(CliError::Validation(format!("Invalid DNS seed: {}", seed)));
+        }
+
+        for (field, entries) in [
+            ("allowed_peers", &config.allowed_peers),
+            ("denied_peers", &config.denied_peers),
+        ] {
+            if let Some(err) = entries
+                .iter()
+                .find_map(|entry| PeerMatcher::from_str(entry).err())
+            {
+                return Err(CliError::Validation(format!(
+                    "{} is invalid: {}",
+                    field, err
+                )));
+            }
+        }
+
+        if config.user_agent.len() > MAX_USER_AGENT_LENGTH {
+            return Err(CliError::Validation(format!(
+                "user_agent must be at most {} bytes long",
+                MAX_USER_AGENT_LENGTH
+            )));
+        }
+
+        if config.protocol_version < MINIMUM_VERSION_NUMBER {
+            return Err(CliError::Validation(format!(
+                "protocol_version must be at least {}",
+                MINIMUM_VERSION_NUMBER
+            )));
+        }
+
+        if config.tx_cache_max_entries == 0 {
+            return Err(CliError::Validation(
+                "tx_cache_max_entries must be greater than 0".to_string(),
+            ));
+        }
+
+        if config.tx_timeout_secs == 0 {
+            return Err(CliError::Validation(
+                "tx_timeout_secs must be greater than 0".to_string(),
+            ));
+        }
+
+        if config.tx_rebroadcast_interval_secs == 0 {
+            return Err(CliError::Validation(
+                "tx_rebroadcast_interval_secs must be greater than 0".to_string(),
+            ));
+        }
+
+        if config.tx_advertisements_per_peer_per_tick == 0 {
+            return Err(CliError::Validation(
+                "tx_advertisements_per_peer_per_tick must be greater than 0".to_string(),
+            ));
+        }
+
+        if config.max_tx_size_bytes == 0 {
+            return Err(CliError::Validation(
+                "max_tx_size_bytes must be greater than 0".to_string(),
+            ));
+        }
+
+        if config.max_get_successors_response_bytes == 0 {
+            return Err(CliError::Validation(
+                "max_get_successors_response_bytes must be greater than 0".to_string(),
+            ));
+        }
+
+        if config.max_tips == 0 {
+            return Err(CliError::Validation(
+                "max_tips must be greater than 0".to_string(),
+            ));
+        }
+
+        if config.blockchain_manager_channel_size == 0 {
+            return Err(CliError::Validation(
+                "blockchain_manager_channel_size must be greater than 0".to_string(),
+            ));
+        }
+
+        if config.transaction_manager_channel_size == 0 {
+            return Err(CliError::Validation(
+                "transaction_manager_channel_size must be greater than 0".to_string(),
+            ));
+        }
+
+        if config.max_peer_connections == 0 {
+            return Err(CliError::Validation(
+                "max_peer_connections must be greater than 0".to_string(),
+            ));
+        }
+
+        if config.get_successors_debug_history == Some(0) {
+            return Err(CliError::Validation(
+                "get_successors_debug_history must be greater than 0".to_string(),
+            ));
+        }
+
+        if config.block_cache_depth == Some(0) {
+            return Err(CliError::Validation(
+                "block_cache_depth must be greater than 0".to_string(),
+            ));
+        }
+
+        if config.max_block_cache_entries == Some(0) {
+            return Err(CliError::Validation(
+                "max_block_cache_entries must be greater than 0".to_string(),
+            ));
+        }
+
+        if matches!(config.max_header_chain_length, Some(length) if length < MIN_HEADER_CHAIN_LENGTH)
+        {
+            return Err(CliError::Validation(format!(
+                "max_header_chain_length must be at least {}",
+                MIN_HEADER_CHAIN_LENGTH
+            )));
+        }
+
+        if config.socks_connect_timeout_secs == 0 {
+            return Err(CliError::Validation(
+                "socks_connect_timeout_secs must be greater than 0".to_string(),
+            ));
+        }
+
+        if config.peer_backoff_base_secs == 0 {
+            return Err(CliError::Validation(
+                "peer_backoff_base_secs must be greater than 0".to_string(),
+            ));
+        }
+
+        if config.peer_backoff_max_secs < config.peer_backoff_base_secs {
+            return Err(CliError::Validation(
+                "peer_backoff_max_secs must be greater than or equal to peer_backoff_base_secs"
+                    .to_string(),
+            ));
+        }
+
+        if config.getdata_rate_per_sec == 0 {
+            return Err(CliError::Validation(
+                "getdata_rate_per_sec must be greater than 0".to_string(),
+            ));
+        }
+
+        if config.getdata_burst == 0 {
+            return Err(CliError::Validation(
+                "getdata_burst must be greater than 0".to_string(),
+            ));
+        }
+
+        if config.peer_ban_threshold == 0 {
+            return Err(CliError::Validation(
+                "peer_ban_threshold must be greater than 0".to_string(),
+            ));
         }
         Ok(config)
     }
-}
\ No newline at end of file
+}
+
+/// Checks that a DNS seed is either a valid hostname, an IPv4 address or an IPv6 address.
+/// IPv6 addresses may be enclosed in brackets.
+/// A seed may carry a `:port` suffix, otherwise the network port is appended to the seed when it
+/// is resolved. IPv6 addresses must be enclosed in brackets to carry a port.
+fn is_valid_dns_seed(seed: &str) -> bool {
+    let (seed, _) = split_dns_seed_port(seed);
+    if seed.parse::<Ipv4Addr>().is_ok() {
+        return true;
+    }
+
+    let unbracketed = seed
+        .strip_prefix('[')
+        .and_then(|seed| seed.strip_suffix(']'))
+        .unwrap_or(seed);
+    if unbracketed.parse::<Ipv6Addr>().is_ok() {
+        return true;
+    }
+
+    let hostname = seed.strip_suffix('.').unwrap_or(seed);
+    !hostname.is_empty()
+        && hostname.len() <= 253
+        && hostname.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Parses a network name, producing an error listing the accepted values on failure.
+fn parse_network(network: &str) -> Result<Network, CliError> {
+    Network::from_str(network).map_err(|_| {
+        CliError::Validation(format!(
+            "Invalid network '{}', expected one of: {}",
+            network,
+            ACCEPTED_NETWORKS.join(", ")
+        ))
+    })
+}
+
+/// Checks that the `network` field of the raw config, if present, is a known network.
+/// A missing field is reported when the config is deserialized.
+fn validate_network(value: &serde_json::Value) -> Result<(), CliError> {
+    match value.get("network") {
+        None => Ok(()),
+        Some(serde_json::Value::String(network)) => parse_network(network).map(|_| ()),
+        Some(network) => Err(CliError::Validation(format!(
+            "Invalid network {}, expected one of: {}",
+            network,
+            ACCEPTED_NETWORKS.join(", ")
+        ))),
+    }
+}
+
+/// Overrides config fields with the values of the corresponding environment variables, if set.
+/// The overridden values are validated together with the rest of the config.
+fn apply_env_overrides(config: &mut Config) -> Result<(), CliError> {
+    if let Ok(network) = env::var(ENV_NETWORK) {
+        config.network = parse_network(&network)?;
+    }
+
+    if let Ok(socks_proxy) = env::var(ENV_SOCKS_PROXY) {
+        config.socks_proxy = Some(SocksProxy::Single(socks_proxy));
+    }
+
+    if let Ok(idle_seconds) = env::var(ENV_IDLE_SECONDS) {
+        config.idle_seconds = idle_seconds.parse().map_err(|_| {
+            CliError::Validation(format!(
+                "Failed to parse {}: {}",
+                ENV_IDLE_SECONDS, idle_seconds
+            ))
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        collections::BTreeMap,
+        io::Write,
+        net::SocketAddr,
+        sync::{Mutex, MutexGuard},
+    };
+    use tempfile::NamedTempFile;
+
+    /// Serializes tests that read or modify the process environment.
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    /// This struct sets environment variables for the duration of a test and restores
+    /// their previous values when dropped.
+    struct ScopedEnv {
+        previous: Vec<(&'static str, Option<String>)>,
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl ScopedEnv {
+        fn new() -> Self {
+            Self {
+                previous: vec![],
+                _lock: ENV_LOCK.lock().unwrap_or_else(|err| err.into_inner()),
+            }
+        }
+
+        fn set(&mut self, key: &'static str, value: &str) {
+            self.previous.push((key, env::var(key).ok()));
+            env::set_var(key, value);
+        }
+
+        fn remove(&mut self, key: &'static str) {
+            self.previous.push((key, env::var(key).ok()));
+            env::remove_var(key);
+        }
+    }
+
+    impl Drop for ScopedEnv {
+        fn drop(&mut self) {
+            for (key, value) in self.previous.drain(..).rev() {
+                match value {
+                    Some(value) => env::set_var(key, value),
+                    None => env::remove_var(key),
+                }
+            }
+        }
+    }
+
+    /// This function writes the provided JSON to a temporary file and loads it through the `Cli`.
+    fn get_config(json: &str) -> Result<Config, CliError> {
+        let mut file = NamedTempFile::new().expect("Failed to create config file");
+        file.write_all(json.as_bytes())
+            .expect("Failed to write config file");
+        Cli {
+            config: Some(file.path().to_path_buf()),
+            generate_config: None,
+        }
+        .get_config()
+    }
+
+    /// This function tests that the config is read from the provided stdin reader when the
+    /// `config` argument is `-`, and that it is validated.
+    #[test]
+    fn test_get_config_from_stdin() {
+        let _env = ScopedEnv::new();
+        let cli = Cli {
+            config: Some(PathBuf::from("-")),
+            generate_config: None,
+        };
+        assert!(cli.reads_config_from_stdin());
+        let stdin = io::Cursor::new(r#"{"network": "regtest", "idle_seconds": 5}"#);
+        let config = cli.get_config_with_stdin(stdin).unwrap();
+        assert_eq!(config.network, Network::Regtest);
+        assert_eq!(config.idle_seconds, 5);
+
+        let stdin = io::Cursor::new(r#"{"network": "regtest", "tx_cache_max_entries": 0}"#);
+        assert!(matches!(
+            cli.get_config_with_stdin(stdin),
+            Err(CliError::Validation(_))
+        ));
+    }
+
+    /// This function tests that a `max_tx_size_bytes` of 0 is rejected.
+    #[test]
+    fn test_get_config_max_tx_size_bytes() {
+        let _env = ScopedEnv::new();
+        let config = get_config(r#"{"network": "regtest", "max_tx_size_bytes": 1000}"#).unwrap();
+        assert_eq!(config.max_tx_size_bytes, 1000);
+
+        let err = get_config(r#"{"network": "regtest", "max_tx_size_bytes": 0}"#).unwrap_err();
+        assert!(
+            matches!(err, CliError::Validation(message) if message.contains("max_tx_size_bytes"))
+        );
+    }
+
+    /// This function tests that an unknown network produces an error listing the accepted values.
+    #[test]
+    fn test_get_config_invalid_network() {
+        let _env = ScopedEnv::new();
+        let err = get_config(r#"{"network": "moonnet"}"#).unwrap_err();
+        assert!(matches!(err, CliError::Validation(_)));
+        assert_eq!(
+            err.to_string(),
+            "An error occurred while validating the provided configuration: Invalid network 'moonnet', expected one of: bitcoin, testnet, signet, regtest"
+        );
+
+        let err = get_config(r#"{"network": 1}"#).unwrap_err();
+        assert!(
+            matches!(err, CliError::Validation(message) if message.contains("bitcoin, testnet, signet, regtest"))
+        );
+    }
+
+    /// This function tests that both a single proxy url and a list of proxy urls are accepted.
+    #[test]
+    fn test_get_config_socks_proxies() {
+        let _env = ScopedEnv::new();
+        let config =
+            get_config(r#"{"network": "testnet", "socks_proxy": "socks5://proxy1.com:1080"}"#)
+                .unwrap();
+        assert_eq!(
+            config.socks_proxy,
+            Some(SocksProxy::Single("socks5://proxy1.com:1080".to_string()))
+        );
+
+        let config = get_config(
+            r#"{"network": "testnet", "socks_proxy": ["socks5://proxy1.com:1080", "socks5://proxy2.com:1080"]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.socks_proxy,
+            Some(SocksProxy::Multiple(vec![
+                "socks5://proxy1.com:1080".to_string(),
+                "socks5://proxy2.com:1080".to_string()
+            ]))
+        );
+    }
+
+    /// This function tests that a list of proxy urls with a malformed entry is rejected.
+    #[test]
+    fn test_get_config_socks_proxies_malformed_entry() {
+        let _env = ScopedEnv::new();
+        let err = get_config(
+            r#"{"network": "testnet", "socks_proxy": ["socks5://proxy1.com:1080", "proxy2.com"]}"#,
+        )
+        .unwrap_err();
+        assert!(matches!(err, CliError::Validation(message) if message.contains("proxy2.com")));
+
+        let err = get_config(r#"{"network": "testnet", "socks_proxy": []}"#).unwrap_err();
+        assert!(matches!(err, CliError::Validation(_)));
+    }
+
+    /// This function tests that `proxy_only` is rejected unless a SOCKS proxy is configured.
+    #[test]
+    fn test_get_config_proxy_only_requires_proxy() {
+        let mut env = ScopedEnv::new();
+        env.remove(ENV_SOCKS_PROXY);
+        let err = get_config(r#"{"network": "bitcoin", "proxy_only": true}"#).unwrap_err();
+        assert!(matches!(err, CliError::Validation(message) if message.contains("proxy_only")));
+
+        let config = get_config(
+            r#"{"network": "bitcoin", "proxy_only": true, "socks_proxy": "socks5://someproxy.com:1080"}"#,
+        )
+        .unwrap();
+        assert!(config.proxy_only);
+    }
+
+    /// This function tests that the systemd socket indices round-trip through the config and
+    /// must be distinct.
+    #[test]
+    fn test_get_config_systemd_socket_indices() {
+        let _env = ScopedEnv::new();
+        let config = get_config(r#"{"network": "bitcoin"}"#).unwrap();
+        assert_eq!(config.rpc_systemd_socket_index, 1);
+        assert_eq!(config.metrics_systemd_socket_index, 2);
+
+        let config = get_config(
+            r#"{"network": "bitcoin", "rpc_systemd_socket_index": 3, "metrics_systemd_socket_index": 1}"#,
+        )
+        .unwrap();
+        assert_eq!(config.rpc_systemd_socket_index, 3);
+        assert_eq!(config.metrics_systemd_socket_index, 1);
+        let serialized = serde_json::to_string(&config).unwrap();
+        let deserialized: Config = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized, config);
+
+        let err = get_config(
+            r#"{"network": "bitcoin", "rpc_systemd_socket_index": 2, "metrics_systemd_socket_index": 2}"#,
+        )
+        .unwrap_err();
+        assert!(matches!(err, CliError::Validation(message) if message.contains("distinct")));
+
+        let err =
+            get_config(r#"{"network": "bitcoin", "rpc_systemd_socket_index": 0}"#).unwrap_err();
+        assert!(matches!(err, CliError::Validation(_)));
+    }
+
+    /// This function tests that the default config of every network passes the validation and
+    /// has peers to connect to.
+    /// Test Steps:
+    /// 1. Round-trip the default config of every network through the config parsing.
+    /// 2. Check that the DNS seeds and nodes are preserved.
+    /// 3. Check that every network but regtest has DNS seeds and regtest has a local node.
+    #[test]
+    fn test_default_for_network_is_valid() {
+        let _env = ScopedEnv::new();
+        for network in [
+            Network::Bitcoin,
+            Network::Testnet,
+            Network::Signet,
+            Network::Regtest,
+        ] {
+            let default_config = Config::default_for(network);
+            let json = serde_json::to_string(&default_config).unwrap();
+            let config = get_config(&json).unwrap();
+            assert_eq!(config.network, network);
+            assert_eq!(config.dns_seeds, default_config.dns_seeds);
+            assert_eq!(config.nodes, default_config.nodes);
+        }
+
+        assert!(!Config::default_for(Network::Bitcoin).dns_seeds.is_empty());
+        assert!(!Config::default_for(Network::Testnet).dns_seeds.is_empty());
+        assert!(!Config::default_for(Network::Signet).dns_seeds.is_empty());
+        assert_eq!(
+            Config::default_for(Network::Regtest).nodes,
+            vec![SocketAddr::from_str("127.0.0.1:18444").unwrap()]
+        );
+    }
+
+    /// This function tests that the header chain length is unlimited by default and that a
+    /// length below the minimum is rejected.
+    /// Test Steps:
+    /// 1. Check that the header chain length is not set by default.
+    /// 2. Check that a length above the minimum is accepted.
+    /// 3. Check that a length below the minimum is rejected.
+    #[test]
+    fn test_get_config_max_header_chain_length() {
+        let _env = ScopedEnv::new();
+        let config = get_config(r#"{"network": "bitcoin"}"#).unwrap();
+        assert_eq!(config.max_header_chain_length, None);
+
+        let config =
+            get_config(r#"{"network": "bitcoin", "max_header_chain_length": 100000}"#).unwrap();
+        assert_eq!(config.max_header_chain_length, Some(100_000));
+
+        let err =
+            get_config(r#"{"network": "bitcoin", "max_header_chain_length": 2015}"#).unwrap_err();
+        assert!(
+            matches!(err, CliError::Validation(message) if message.contains("max_header_chain_length"))
+        );
+    }
+
+    /// This function tests that the advertised user agent, protocol version and services
+    /// round-trip through the config and that the user agent respects the BIP-14 length limit.
+    #[test]
+    fn test_get_config_version_message_fields() {
+        let _env = ScopedEnv::new();
+        let config = get_config(r#"{"network": "bitcoin"}"#).unwrap();
+        assert_eq!(config.user_agent, "bitbolt-btc-adapter");
+        assert_eq!(config.protocol_version, 70016);
+        assert_eq!(config.services, 0);
+
+        let config = get_config(
+            r#"{"network": "bitcoin", "user_agent": "/operator:1.0/", "protocol_version": 70015, "services": 1024}"#,
+        )
+        .unwrap();
+        let serialized = serde_json::to_string(&config).unwrap();
+        let deserialized: Config = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.user_agent, "/operator:1.0/");
+        assert_eq!(deserialized.protocol_version, 70015);
+        assert_eq!(deserialized.services, 1024);
+
+        let user_agent = "a".repeat(MAX_USER_AGENT_LENGTH + 1);
+        let err = get_config(&format!(
+            r#"{{"network": "bitcoin", "user_agent": "{}"}}"#,
+            user_agent
+        ))
+        .unwrap_err();
+        assert!(matches!(err, CliError::Validation(message) if message.contains("user_agent")));
+
+        let err = get_config(r#"{"network": "bitcoin", "protocol_version": 60000}"#).unwrap_err();
+        assert!(
+            matches!(err, CliError::Validation(message) if message.contains("protocol_version"))
+        );
+    }
+
+    /// This function tests that per-module log levels are parsed and invalid levels are rejected.
+    #[test]
+    fn test_get_config_module_log_levels() {
+        let _env = ScopedEnv::new();
+        let config = get_config(
+            r#"{"network": "bitcoin", "module_log_levels": {"transaction_manager": "debug", "blockchainstate": "info"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.module_log_levels(),
+            BTreeMap::from([
+                ("blockchainstate".to_string(), slog::Level::Info),
+                ("transaction_manager".to_string(), slog::Level::Debug),
+            ])
+        );
+        let serialized = serde_json::to_string(&config).unwrap();
+        let deserialized: Config = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized, config);
+
+        let err = get_config(r#"{"network": "bitcoin", "module_log_levels": {"router": "loud"}}"#)
+            .unwrap_err();
+        assert!(
+            matches!(err, CliError::Validation(message) if message.contains("module_log_levels"))
+        );
+    }
+
+    /// This function tests that a custom genesis header is accepted for the regtest network only.
+    #[test]
+    fn test_get_config_custom_genesis() {
+        let _env = ScopedEnv::new();
+        let genesis = bitcoin::blockdata::constants::genesis_block(Network::Regtest).header;
+        let custom_genesis = hex::encode(bitcoin::consensus::serialize(&genesis));
+
+        let config = get_config(&format!(
+            r#"{{"network": "regtest", "custom_genesis": "{}"}}"#,
+            custom_genesis
+        ))
+        .unwrap();
+        assert_eq!(config.custom_genesis_header(), Some(genesis));
+
+        let err = get_config(&format!(
+            r#"{{"network": "testnet", "custom_genesis": "{}"}}"#,
+            custom_genesis
+        ))
+        .unwrap_err();
+        assert!(matches!(err, CliError::Validation(message) if message.contains("custom_genesis")));
+
+        let err = get_config(r#"{"network": "regtest", "custom_genesis": "0100"}"#).unwrap_err();
+        assert!(matches!(err, CliError::Validation(message) if message.contains("custom_genesis")));
+    }
+
+    /// This function tests that the adapter refuses to start against a network that is not
+    /// one of the allowed networks.
+    #[test]
+    fn test_get_config_allowed_networks() {
+        let _env = ScopedEnv::new();
+        let config =
+            get_config(r#"{"network": "bitcoin", "allowed_networks": ["bitcoin"]}"#).unwrap();
+        assert_eq!(config.allowed_networks, Some(vec![Network::Bitcoin]));
+
+        let config = get_config(r#"{"network": "regtest"}"#).unwrap();
+        assert_eq!(config.allowed_networks, None);
+
+        let err =
+            get_config(r#"{"network": "regtest", "allowed_networks": ["bitcoin"]}"#).unwrap_err();
+        assert!(
+            matches!(err, CliError::Validation(message) if message.contains("allowed_networks"))
+        );
+
+        let err = get_config(r#"{"network": "testnet", "allowed_networks": []}"#).unwrap_err();
+        assert!(
+            matches!(err, CliError::Validation(message) if message.contains("allowed_networks"))
+        );
+    }
+
+    /// This function tests that the entries of the peer allowlist and denylist must be socket
+    /// addresses or CIDR ranges.
+    #[test]
+    fn test_get_config_allowed_and_denied_peers() {
+        let _env = ScopedEnv::new();
+        let config = get_config(
+            r#"{"network": "bitcoin", "allowed_peers": ["10.0.0.0/8", "[2001:db8::1]:8333"], "denied_peers": ["10.0.0.1:8333"]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.allowed_peers,
+            vec!["10.0.0.0/8", "[2001:db8::1]:8333"]
+        );
+        assert_eq!(config.denied_peers, vec!["10.0.0.1:8333"]);
+
+        let err =
+            get_config(r#"{"network": "bitcoin", "allowed_peers": ["10.0.0.0/40"]}"#).unwrap_err();
+        assert!(matches!(err, CliError::Validation(message) if message.contains("allowed_peers")));
+
+        let err =
+            get_config(r#"{"network": "bitcoin", "denied_peers": ["localhost"]}"#).unwrap_err();
+        assert!(matches!(err, CliError::Validation(message) if message.contains("denied_peers")));
+    }
+
+    /// This function tests that a custom signet challenge is accepted for the signet network only.
+    /// Test Steps:
+    /// 1. Check that the default signet challenge results in the default signet magic value.
+    /// 2. Check that a custom challenge results in a different magic value.
+    /// 3. Check that a challenge is rejected for other networks and if it is not valid hex.
+    #[test]
+    fn test_get_config_signet_challenge() {
+        let _env = ScopedEnv::new();
+        let config = get_config(
+            r#"{"network": "signet", "signet_challenge": "512103ad5e0edad18cb1f0fc0d28a3d4f1f3e445640337489abb10404f2d1e086be430210359ef5021964fe22d6f8e05b2463c9540ce96883fe3b278760f048f5189f2e6c452ae"}"#,
+        )
+        .unwrap();
+        assert!(config.signet_challenge_script().is_some());
+        assert_eq!(config.network_magic(), Network::Signet.magic());
+
+        // A 1-of-1 multisig challenge of a custom signet.
+        let config = get_config(
+            r#"{"network": "signet", "signet_challenge": "5121027be9dc6a3da9a7b5fe2e1e1e2ec1d2a3a1d4ccb6a1e9a34d6e86e5e0d2a0e5a951ae"}"#,
+        )
+        .unwrap();
+        assert_ne!(config.network_magic(), Network::Signet.magic());
+
+        let config = get_config(r#"{"network": "signet"}"#).unwrap();
+        assert_eq!(config.signet_challenge_script(), None);
+        assert_eq!(config.network_magic(), Network::Signet.magic());
+
+        let err = get_config(r#"{"network": "testnet", "signet_challenge": "51"}"#).unwrap_err();
+        assert!(
+            matches!(err, CliError::Validation(message) if message.contains("signet_challenge"))
+        );
+
+        let err = get_config(r#"{"network": "signet", "signet_challenge": "xyz"}"#).unwrap_err();
+        assert!(
+            matches!(err, CliError::Validation(message) if message.contains("signet_challenge"))
+        );
+
+        let err = get_config(r#"{"network": "signet", "signet_challenge": ""}"#).unwrap_err();
+        assert!(
+            matches!(err, CliError::Validation(message) if message.contains("signet_challenge"))
+        );
+    }
+
+    /// This function tests that valid DNS seeds, with or without a port, and an empty list of DNS
+    /// seeds are accepted.
+    #[test]
+    fn test_get_config_dns_seeds() {
+        let _env = ScopedEnv::new();
+        let config = get_config(
+            r#"{"network": "bitcoin", "dns_seeds": ["seed.bitcoin.sipa.be", "dnsseed.bluematt.me.", "127.0.0.1"]}"#,
+        )
+        .unwrap();
+        assert_eq!(config.dns_seeds.len(), 3);
+
+        let config = get_config(
+            r#"{"network": "bitcoin", "dns_seeds": ["seed.bitcoin.sipa.be:8333", "127.0.0.1:18444", "[2001:db8::1]:8333"]}"#,
+        )
+        .unwrap();
+        assert_eq!(config.dns_seeds.len(), 3);
+
+        let config = get_config(r#"{"network": "bitcoin", "dns_seeds": []}"#).unwrap();
+        assert!(config.dns_seeds.is_empty());
+    }
+
+    /// This function tests that IPv6 addresses are accepted for SOCKS proxies, nodes and DNS seeds.
+    #[test]
+    fn test_get_config_ipv6_addresses() {
+        let mut env = ScopedEnv::new();
+        env.remove(ENV_SOCKS_PROXY);
+        let config = get_config(
+            r#"{"network": "bitcoin", "socks_proxy": "socks5://[::1]:1080", "nodes": ["[2001:db8::1]:8333"], "dns_seeds": ["2001:db8::2", "[2001:db8::3]"]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.socks_proxy,
+            Some(SocksProxy::Single("socks5://[::1]:1080".to_string()))
+        );
+        assert_eq!(
+            config.nodes,
+            vec!["[2001:db8::1]:8333".parse::<SocketAddr>().unwrap()]
+        );
+        assert_eq!(config.dns_seeds.len(), 2);
+
+        // A seed must not carry a port, which requires brackets for IPv6 addresses.
+        let err = get_config(r#"{"network": "bitcoin", "dns_seeds": ["[2001:db8::2]:8333"]}"#)
+            .unwrap_err();
+        assert!(matches!(err, CliError::Validation(_)));
+
+        let err =
+            get_config(r#"{"network": "bitcoin", "socks_proxy": "socks5://[::1]"}"#).unwrap_err();
+        assert!(matches!(err, CliError::Validation(_)));
+    }
+
+    /// This function tests that a list of DNS seeds with a malformed entry or port is rejected.
+    #[test]
+    fn test_get_config_dns_seeds_malformed_entry() {
+        let _env = ScopedEnv::new();
+        let err = get_config(
+            r#"{"network": "bitcoin", "dns_seeds": ["seed.bitcoin.sipa.be", "seed..bitcoin.sprovoost.nl"]}"#,
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, CliError::Validation(message) if message.contains("seed..bitcoin.sprovoost.nl"))
+        );
+
+        for seed in [
+            "127.0.0.1:",
+            "127.0.0.1:65536",
+            "seed.bitcoin.sipa.be:port",
+            ":8333",
+        ] {
+            let err = get_config(&format!(
+                r#"{{"network": "bitcoin", "dns_seeds": ["{}"]}}"#,
+                seed
+            ))
+            .unwrap_err();
+            assert!(matches!(err, CliError::Validation(_)));
+        }
+    }
+
+    /// This function tests that environment variables override the values from the config file.
+    #[test]
+    fn test_get_config_env_overrides() {
+        let json = r#"{"network": "testnet", "idle_seconds": 10}"#;
+        let mut env = ScopedEnv::new();
+        env.remove(ENV_NETWORK);
+        env.remove(ENV_SOCKS_PROXY);
+        env.remove(ENV_IDLE_SECONDS);
+        let config = get_config(json).unwrap();
+        assert_eq!(config.network, Network::Testnet);
+        assert_eq!(config.socks_proxy, None);
+        assert_eq!(config.idle_seconds, 10);
+
+        env.set(ENV_NETWORK, "regtest");
+        env.set(ENV_SOCKS_PROXY, "socks5://someproxy.com:1080");
+        env.set(ENV_IDLE_SECONDS, "20");
+        let config = get_config(json).unwrap();
+        assert_eq!(config.network, Network::Regtest);
+        assert_eq!(
+            config.socks_proxy,
+            Some(SocksProxy::Single(
+                "socks5://someproxy.com:1080".to_string()
+            ))
+        );
+        assert_eq!(config.idle_seconds, 20);
+    }
+
+    /// This function tests that overridden values are validated.
+    #[test]
+    fn test_get_config_env_overrides_invalid() {
+        let json = r#"{"network": "testnet"}"#;
+        let mut env = ScopedEnv::new();
+        env.remove(ENV_NETWORK);
+        env.remove(ENV_IDLE_SECONDS);
+        env.set(ENV_SOCKS_PROXY, "someproxy.com");
+        assert!(matches!(get_config(json), Err(CliError::Validation(_))));
+
+        env.remove(ENV_SOCKS_PROXY);
+        env.set(ENV_IDLE_SECONDS, "soon");
+        assert!(matches!(get_config(json), Err(CliError::Validation(_))));
+
+        env.remove(ENV_IDLE_SECONDS);
+        env.set(ENV_NETWORK, "moonnet");
+        assert!(matches!(get_config(json), Err(CliError::Validation(_))));
+    }
+}
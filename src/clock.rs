@@ -0,0 +1,51 @@
+use std::time::SystemTime;
+
+/// This trait provides the current time, so time-dependent behavior can be driven by a
+/// different clock in tests.
+pub trait Clock: Send + Sync {
+    /// This function returns the current time.
+    fn now(&self) -> SystemTime;
+}
+
+/// This struct is the clock that reads the system time.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use parking_lot::Mutex;
+    use std::time::Duration;
+
+    /// This struct is a clock that only moves when it is advanced.
+    #[derive(Debug)]
+    pub struct MockClock {
+        now: Mutex<SystemTime>,
+    }
+
+    impl MockClock {
+        /// This function creates a new clock starting at the given time.
+        pub fn new(now: SystemTime) -> Self {
+            Self {
+                now: Mutex::new(now),
+            }
+        }
+
+        /// This function moves the clock forward by the given duration.
+        pub fn advance(&self, duration: Duration) {
+            *self.now.lock() += duration;
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> SystemTime {
+            *self.now.lock()
+        }
+    }
+}
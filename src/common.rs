@@ -12,6 +12,17 @@ pub type ProtocolVersion = u32;
 /// [Protocol Versions](https://developer.bitcoin.org/reference/p2p_networking.html#protocol-versions)
 pub const MINIMUM_VERSION_NUMBER: ProtocolVersion = 70001;
 
+/// This constant represents the maximum size of `headers` messages.
+/// https://developer.bitcoin.org/reference/p2p_networking.html#headers
+pub const MAX_HEADERS_SIZE: usize = 2_000;
+
+/// This constant represents the minimum number of headers kept behind the active tip if the
+/// header chain length is limited. It covers a retarget interval, so the difficulty and the
+/// median time past of new headers can still be validated. It does not bound how far the
+/// canister may lag behind: headers at and above the anchor of the last `GetSuccessors`
+/// request are kept regardless of the window.
+pub const MIN_HEADER_CHAIN_LENGTH: u32 = 2_016;
+
 /// This const is used to provide a based buffer size for how many messages can be stashed into the
 /// channel. If there are more messages, the sender will end up waiting.
 pub const DEFAULT_CHANNEL_BUFFER_SIZE: usize = 64;
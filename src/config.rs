@@ -1,8 +1,15 @@
-use bitcoin::Network;
+use bitcoin::{
+    consensus::{deserialize, serialize},
+    hashes::{sha256d, Hash},
+    BlockHeader, Network, Script,
+};
 use logger::Config as LoggerConfig;
 use serde::{Deserialize, Serialize};
+use slog::Level;
+use std::collections::BTreeMap;
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::str::FromStr;
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
 /// The source of the unix domain socket to be used for inter-process
@@ -20,6 +27,27 @@ impl Default for IncomingSource {
     }
 }
 
+/// The SOCKS proxies the adapter may use to communicate with the BTC network.
+/// Either a single proxy url or a list of proxy urls can be configured.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum SocksProxy {
+    /// A single proxy url.
+    Single(String),
+    /// A list of proxy urls.
+    Multiple(Vec<String>),
+}
+
+impl SocksProxy {
+    /// This function returns the configured proxy urls.
+    pub fn urls(&self) -> &[String] {
+        match self {
+            SocksProxy::Single(url) => std::slice::from_ref(url),
+            SocksProxy::Multiple(urls) => urls,
+        }
+    }
+}
+
 /// This struct contains configuration options for the BTC Adapter.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Config {
@@ -34,7 +62,13 @@ pub struct Config {
     #[serde(default)]
     /// This field determines whether or not we will be using a SOCKS proxy to communicate with  the BTC network.
     /// Proxy url is validated and needs to have scheme, host and port specified. I.e socks5://socksproxy.com:1080.
-    pub socks_proxy: Option<String>,
+    /// A list of proxy urls may be provided, in which case a proxy is picked per connection.
+    pub socks_proxy: Option<SocksProxy>,
+    /// When this field is set to `true`, the adapter only connects to Bitcoin nodes through the
+    /// configured SOCKS proxy and never connects to them directly. Only onion addresses are
+    /// discovered in this mode, so the configured nodes should include onion services.
+    #[serde(default)]
+    pub proxy_only: bool,
     /// The number of seconds that need to pass for the adapter to enter the
     /// `Idle` state.
     #[serde(default = "default_idle_seconds")]
@@ -46,9 +80,180 @@ pub struct Config {
     /// Logger config.
     #[serde(default)]
     pub logger: LoggerConfig,
+    /// Per-module log level overrides, e.g., `{"blockchainmanager": "debug"}`. The keys are
+    /// module paths within the adapter. The most specific override matching a module takes
+    /// precedence over the reloadable `logger.level`, which only applies to modules without an
+    /// override. An override may therefore raise or lower the verbosity of a module.
+    #[serde(default)]
+    pub module_log_levels: BTreeMap<String, String>,
     /// Specifies which unix domain socket should be used for serving incoming requests.
     #[serde(default)]
     pub incoming_source: IncomingSource,
+    /// The 1-based index of the systemd socket serving the adapter's gRPC requests.
+    /// Only used if the incoming source is `Systemd`.
+    #[serde(default = "default_rpc_systemd_socket_index")]
+    pub rpc_systemd_socket_index: i32,
+    /// The 1-based index of the systemd socket serving the metrics requests.
+    /// Only used if the incoming source is `Systemd`.
+    #[serde(default = "default_metrics_systemd_socket_index")]
+    pub metrics_systemd_socket_index: i32,
+    /// The maximum number of transactions the adapter holds in its transaction cache.
+    /// When the limit is hit, the oldest transaction is evicted.
+    #[serde(default = "default_tx_cache_max_entries")]
+    pub tx_cache_max_entries: usize,
+    /// The number of seconds the adapter holds on to a transaction before it is
+    /// removed from the transaction cache.
+    #[serde(default = "default_tx_timeout_secs")]
+    pub tx_timeout_secs: u64,
+    /// The number of seconds after which a transaction is advertised again to all peers,
+    /// including the peers it has already been advertised to.
+    #[serde(default = "default_tx_rebroadcast_interval_secs")]
+    pub tx_rebroadcast_interval_secs: u64,
+    /// The maximum number of transaction IDs advertised to a single peer per tick.
+    /// Remaining transaction IDs are advertised on the following ticks.
+    #[serde(default = "default_tx_advertisements_per_peer_per_tick")]
+    pub tx_advertisements_per_peer_per_tick: usize,
+    /// The maximum size in bytes of a serialized transaction the adapter accepts.
+    /// Larger transactions are rejected.
+    #[serde(default = "default_max_tx_size_bytes")]
+    pub max_tx_size_bytes: usize,
+    /// The misbehavior score at which a peer is disconnected and banned.
+    #[serde(default = "default_peer_ban_threshold")]
+    pub peer_ban_threshold: u32,
+    /// The number of seconds a misbehaving peer stays banned.
+    #[serde(default = "default_peer_ban_duration_secs")]
+    pub peer_ban_duration_secs: u64,
+    /// The maximum number of connections the adapter maintains to Bitcoin peers.
+    /// No new peers are dialed while the limit is reached.
+    #[serde(default = "default_max_peer_connections")]
+    pub max_peer_connections: usize,
+    /// The number of seconds the adapter waits before dialing a peer again after a failed
+    /// connection. The delay doubles with each consecutive failure.
+    #[serde(default = "default_peer_backoff_base_secs")]
+    pub peer_backoff_base_secs: u64,
+    /// The maximum number of seconds the adapter waits before dialing a peer again after
+    /// consecutive failed connections.
+    #[serde(default = "default_peer_backoff_max_secs")]
+    pub peer_backoff_max_secs: u64,
+    /// The maximum number of bytes the blocks in a `get_successors` response can take.
+    /// The first block is always returned, even if it exceeds the limit.
+    #[serde(default = "default_max_get_successors_response_bytes")]
+    pub max_get_successors_response_bytes: usize,
+    /// The maximum number of blocks a fork branch's tip may be behind the active tip for its
+    /// blocks and headers to be included in a `get_successors` response.
+    #[serde(default = "default_max_successor_fork_depth")]
+    pub max_successor_fork_depth: u32,
+    /// The number of most recent `get_successors` responses whose block and header hashes are
+    /// recorded to debug consumers that do not advance. If it is not set, nothing is recorded.
+    #[serde(default)]
+    pub get_successors_debug_history: Option<usize>,
+    /// The file the discovered peer addresses are persisted to. The addresses are reloaded at
+    /// startup and tried before the DNS seeds.
+    #[serde(default)]
+    pub peers_file: Option<PathBuf>,
+    /// The maximum number of seconds since a persisted peer address was last seen for it to
+    /// be reloaded at startup.
+    #[serde(default = "default_peers_file_max_age_secs")]
+    pub peers_file_max_age_secs: u64,
+    /// The hex-encoded challenge script of a custom signet. Only valid if the network is
+    /// `signet`. If it is not set, the default signet is used.
+    /// The challenge only determines the network magic value (BIP-325). Verifying the signet
+    /// solutions of blocks against the challenge is not supported, and headers are validated
+    /// with the rules of the default signet.
+    #[serde(default)]
+    pub signet_challenge: Option<String>,
+    /// The maximum number of tips kept in the header cache. When exceeded, the tips with
+    /// the least work that have no cached block are dropped along with their headers.
+    #[serde(default = "default_max_tips")]
+    pub max_tips: usize,
+    /// The minimum feerate in sat/vB of submitted transactions. The filter only applies when
+    /// every input of a transaction spends an output of another cached transaction, since the
+    /// fee cannot be inferred otherwise; other transactions are always accepted. Transactions
+    /// submitted by the canister rarely spend only cached outputs, so the filter is mostly
+    /// relevant to chains of unconfirmed transactions. If it is not set, no feerate filter is
+    /// applied.
+    #[serde(default)]
+    pub min_relay_feerate_sat_vb: Option<u64>,
+    /// The hex-encoded serialized genesis header of a custom regtest network. Only valid if
+    /// the network is `regtest`. If it is not set, the default regtest genesis is used.
+    #[serde(default)]
+    pub custom_genesis: Option<String>,
+    /// The number of requests the channel from the `GetSuccessors` handler to the blockchain
+    /// manager can buffer. Requests are dropped while the channel is full.
+    #[serde(default = "default_blockchain_manager_channel_size")]
+    pub blockchain_manager_channel_size: usize,
+    /// The number of `SendTransaction` requests the channel to the transaction manager can
+    /// buffer. While the channel is full, the gRPC handler waits for buffer space, which applies
+    /// backpressure to the caller instead of dropping transactions.
+    #[serde(default = "default_transaction_manager_channel_size")]
+    pub transaction_manager_channel_size: usize,
+    /// The networks the adapter may be started against. Guards a deployment against a
+    /// misconfigured network. If it is not set, every network is allowed.
+    #[serde(default)]
+    pub allowed_networks: Option<Vec<Network>>,
+    /// The number of seconds a connection attempt through the SOCKS proxy may take before it
+    /// is aborted and the peer is backed off.
+    #[serde(default = "default_socks_connect_timeout_secs")]
+    pub socks_connect_timeout_secs: u64,
+    /// The number of blocks below the active tip that are kept in the block cache. Older blocks
+    /// are pruned whenever a block is added, e.g., 144 keeps about a day of blocks. It must be
+    /// large enough to cover how far the canister lags behind the tip, otherwise blocks it still
+    /// needs are pruned. If it is not set, blocks are only pruned on request.
+    #[serde(default)]
+    pub block_cache_depth: Option<u32>,
+    /// The maximum number of blocks in the block cache. Whenever a block is added and the cache
+    /// holds more blocks, the lowest blocks below the active tip are evicted. It composes with
+    /// `block_cache_depth`, and like it, it must leave room for the blocks the canister still
+    /// needs. If it is not set, the number of blocks is not limited.
+    #[serde(default)]
+    pub max_block_cache_entries: Option<usize>,
+    /// The number of headers kept behind the active tip. Older headers are pruned whenever
+    /// headers are added, except for the genesis header, which terminates the locators. Forks
+    /// below the window cannot be followed anymore. Headers at and above the anchor of the
+    /// last `GetSuccessors` request are never pruned, as the canister's next request starts
+    /// from there, and nothing is pruned until the first request is received. It must be at
+    /// least `MIN_HEADER_CHAIN_LENGTH`. If it is not set, all headers are kept.
+    #[serde(default)]
+    pub max_header_chain_length: Option<u32>,
+    /// When this field is set to `true`, the adapter requests the compact block filter headers
+    /// (BIP-157) of new headers from peers that serve compact block filters.
+    #[serde(default)]
+    pub compact_filters: bool,
+    /// The number of blocks of work by which a competing tip must exceed the work of the active
+    /// tip before it becomes the active tip. The margin is converted to work at the difficulty
+    /// of the active tip. It avoids flapping between tips of near-equal work during a contested
+    /// fork. If it is not set, the tip with the most work is always active.
+    #[serde(default)]
+    pub tip_switch_margin_blocks: Option<u32>,
+    /// If not empty, the adapter only connects to the Bitcoin nodes matching one of these
+    /// entries. An entry is either a socket address, e.g., `192.168.0.1:8333`, or a CIDR range
+    /// matching any port, e.g., `10.0.0.0/8`.
+    #[serde(default)]
+    pub allowed_peers: Vec<String>,
+    /// The adapter never connects to the Bitcoin nodes matching one of these entries, even if
+    /// they match an entry of `allowed_peers`. The entries have the same format.
+    #[serde(default)]
+    pub denied_peers: Vec<String>,
+    /// The number of `getdata` messages per second a peer may send once it used up its burst.
+    /// Messages exceeding the rate are dropped and the peer is penalized.
+    #[serde(default = "default_getdata_rate_per_sec")]
+    pub getdata_rate_per_sec: u32,
+    /// The number of `getdata` messages a peer may send at once before it is limited to
+    /// `getdata_rate_per_sec`.
+    #[serde(default = "default_getdata_burst")]
+    pub getdata_burst: u32,
+    /// The user agent the adapter advertises in its `version` messages. It may be at most 256
+    /// bytes long (BIP-14).
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+    /// The protocol version the adapter advertises in its `version` messages. Peers only use
+    /// the features the adapter relies on if the advertised version is recent enough:
+    /// `sendheaders` requires 70012, `feefilter` 70013 and `wtxidrelay` 70016.
+    #[serde(default = "default_protocol_version")]
+    pub protocol_version: u32,
+    /// The service flags the adapter advertises in its `version` messages.
+    #[serde(default = "default_services")]
+    pub services: u64,
 }
 
 /// Set the default idle seconds to one hour.
@@ -56,13 +261,245 @@ fn default_idle_seconds() -> u64 {
     3600
 }
 
+/// Set the default transaction cache size to 250 entries.
+fn default_tx_cache_max_entries() -> usize {
+    250
+}
+
+/// Set the default transaction timeout to 10 minutes.
+fn default_tx_timeout_secs() -> u64 {
+    10 * 60
+}
+
+/// Set the default transaction rebroadcast interval to 2 minutes.
+fn default_tx_rebroadcast_interval_secs() -> u64 {
+    2 * 60
+}
+
+/// Set the default number of transaction advertisements per peer and tick to 500.
+fn default_tx_advertisements_per_peer_per_tick() -> usize {
+    500
+}
+
+/// Set the default maximum transaction size to 100 kB, which corresponds to the
+/// maximum standard transaction weight relayed by Bitcoin Core.
+fn default_max_tx_size_bytes() -> usize {
+    100_000
+}
+
+/// Set the default misbehavior score at which a peer is banned to 100.
+fn default_peer_ban_threshold() -> u32 {
+    100
+}
+
+/// Set the default peer ban duration to 24 hours.
+fn default_peer_ban_duration_secs() -> u64 {
+    24 * 60 * 60
+}
+
+/// Set the default maximum number of peer connections to 8.
+fn default_max_peer_connections() -> usize {
+    8
+}
+
+/// Set the default reconnection backoff delay after the first failure to 1 second.
+fn default_peer_backoff_base_secs() -> u64 {
+    1
+}
+
+/// Set the default maximum reconnection backoff delay to 5 minutes.
+fn default_peer_backoff_max_secs() -> u64 {
+    5 * 60
+}
+
+/// Set the default maximum size of the blocks in a `get_successors` response to 2 MB,
+/// minus the space reserved for up to 100 next headers.
+fn default_max_get_successors_response_bytes() -> usize {
+    2_000_000 - 100 * 80
+}
+
+/// Set the default maximum fork depth for successors to 144 blocks, which is about one day.
+fn default_max_successor_fork_depth() -> u32 {
+    144
+}
+
+/// Set the default maximum age of persisted peer addresses to 7 days.
+fn default_peers_file_max_age_secs() -> u64 {
+    7 * 24 * 60 * 60
+}
+
+/// Set the default maximum number of tips to 100.
+fn default_max_tips() -> usize {
+    100
+}
+
+/// Set the default blockchain manager channel size to 10.
+fn default_blockchain_manager_channel_size() -> usize {
+    10
+}
+
+/// Set the default transaction manager channel size to 10.
+fn default_transaction_manager_channel_size() -> usize {
+    10
+}
+
+/// Set the default gRPC systemd socket to the first socket (FD 3).
+fn default_rpc_systemd_socket_index() -> i32 {
+    1
+}
+
+/// Set the default metrics systemd socket to the second socket (FD 4).
+fn default_metrics_systemd_socket_index() -> i32 {
+    2
+}
+
+/// Set the default SOCKS proxy connection timeout to 5 seconds.
+fn default_socks_connect_timeout_secs() -> u64 {
+    5
+}
+
+/// Set the default rate of `getdata` messages a peer may send to 10 per second.
+fn default_getdata_rate_per_sec() -> u32 {
+    10
+}
+
+/// Set the default burst of `getdata` messages a peer may send to 50.
+fn default_getdata_burst() -> u32 {
+    50
+}
+
+/// Set the default user agent to identify the adapter.
+fn default_user_agent() -> String {
+    String::from("bitbolt-btc-adapter")
+}
+
+/// Set the default advertised protocol version to 70016, the first version that supports
+/// `wtxidrelay` (BIP-339) and thus all features the adapter uses.
+fn default_protocol_version() -> u32 {
+    70016
+}
+
+/// Set the default advertised services to none, as the adapter does not serve any data.
+fn default_services() -> u64 {
+    0
+}
+
+/// The DNS seeds of Bitcoin Core for the mainnet.
+const BITCOIN_DNS_SEEDS: [&str; 6] = [
+    "seed.bitcoin.sipa.be",
+    "dnsseed.bluematt.me",
+    "dnsseed.bitcoin.dashjr.org",
+    "seed.bitcoinstats.com",
+    "seed.bitcoin.jonasschnelli.ch",
+    "seed.btc.petertodd.org",
+];
+
+/// The DNS seeds of Bitcoin Core for the testnet.
+const TESTNET_DNS_SEEDS: [&str; 4] = [
+    "testnet-seed.bitcoin.jonasschnelli.ch",
+    "seed.tbtc.petertodd.org",
+    "seed.testnet.bitcoin.sprovoost.nl",
+    "testnet-seed.bluematt.me",
+];
+
+/// The DNS seeds of Bitcoin Core for the signet.
+const SIGNET_DNS_SEEDS: [&str; 2] = [
+    "seed.signet.bitcoin.sprovoost.nl",
+    "seed.signet.achownodes.xyz",
+];
+
+/// The address of a local regtest node listening on the default regtest port.
+const REGTEST_LOCAL_NODE: &str = "127.0.0.1:18444";
+
 impl Config {
-    /// This function returns the port to use based on the Bitcoin network provided.
+    /// This function returns a config for the given network with its defaults: the DNS seeds
+    /// of Bitcoin Core on the mainnet, testnet and signet, and a local node on regtest. All
+    /// other fields have the same defaults on all networks.
+    pub fn default_for(network: Network) -> Self {
+        let dns_seeds: &[&str] = match network {
+            Network::Bitcoin => &BITCOIN_DNS_SEEDS,
+            Network::Testnet => &TESTNET_DNS_SEEDS,
+            Network::Signet => &SIGNET_DNS_SEEDS,
+            Network::Regtest => &[],
+        };
+        let nodes = match network {
+            Network::Regtest => vec![REGTEST_LOCAL_NODE
+                .parse()
+                .expect("the local regtest node address is valid")],
+            _ => vec![],
+        };
+        Self {
+            network,
+            dns_seeds: dns_seeds.iter().map(|seed| seed.to_string()).collect(),
+            nodes,
+            ..Self::default()
+        }
+    }
+
+    /// This function returns the default P2P port of the configured Bitcoin network, which is
+    /// used for DNS seeds that do not specify a port.
     pub fn network_port(&self) -> u16 {
         match self.network {
             Network::Bitcoin => 8333,
             Network::Testnet => 18333,
-            _ => 8333,
+            Network::Signet => 38333,
+            Network::Regtest => 18444,
+        }
+    }
+
+    /// This function returns the challenge script of a custom signet, if one is configured
+    /// and the network is signet.
+    pub fn signet_challenge_script(&self) -> Option<Script> {
+        if self.network != Network::Signet {
+            return None;
+        }
+        self.signet_challenge
+            .as_ref()
+            .and_then(|challenge| hex::decode(challenge).ok())
+            .map(Script::from)
+    }
+
+    /// This function returns the parsed per-module log level overrides. Overrides with an
+    /// unknown level are skipped.
+    pub fn module_log_levels(&self) -> BTreeMap<String, Level> {
+        self.module_log_levels
+            .iter()
+            .filter_map(|(module, level)| {
+                Level::from_str(level)
+                    .ok()
+                    .map(|level| (module.clone(), level))
+            })
+            .collect()
+    }
+
+    /// This function returns the genesis header of a custom regtest network, if one is
+    /// configured and the network is regtest.
+    pub fn custom_genesis_header(&self) -> Option<BlockHeader> {
+        if self.network != Network::Regtest {
+            return None;
+        }
+        self.custom_genesis
+            .as_ref()
+            .and_then(|genesis| hex::decode(genesis).ok())
+            .and_then(|bytes| deserialize(&bytes).ok())
+    }
+
+    /// This function checks if the configured network is one of the allowed networks.
+    pub fn is_network_allowed(&self) -> bool {
+        self.allowed_networks
+            .as_ref()
+            .map_or(true, |networks| networks.contains(&self.network))
+    }
+
+    /// This function returns the magic value identifying the Bitcoin network in messages.
+    /// A custom signet derives its magic value from the challenge script (BIP-325).
+    pub fn network_magic(&self) -> u32 {
+        match self.signet_challenge_script() {
+            Some(challenge) => {
+                let [b0, b1, b2, b3, ..] = sha256d::Hash::hash(&serialize(&challenge)).into_inner();
+                u32::from_le_bytes([b0, b1, b2, b3])
+            }
+            None => self.network.magic(),
         }
     }
 }
@@ -73,11 +510,50 @@ impl Default for Config {
             dns_seeds: Default::default(),
             network: Network::Bitcoin,
             socks_proxy: Default::default(),
+            proxy_only: false,
             nodes: vec![],
             idle_seconds: default_idle_seconds(),
             ipv6_only: false,
             logger: LoggerConfig::default(),
+            module_log_levels: BTreeMap::new(),
             incoming_source: Default::default(),
+            rpc_systemd_socket_index: default_rpc_systemd_socket_index(),
+            metrics_systemd_socket_index: default_metrics_systemd_socket_index(),
+            tx_cache_max_entries: default_tx_cache_max_entries(),
+            tx_timeout_secs: default_tx_timeout_secs(),
+            tx_rebroadcast_interval_secs: default_tx_rebroadcast_interval_secs(),
+            tx_advertisements_per_peer_per_tick: default_tx_advertisements_per_peer_per_tick(),
+            max_tx_size_bytes: default_max_tx_size_bytes(),
+            peer_ban_threshold: default_peer_ban_threshold(),
+            peer_ban_duration_secs: default_peer_ban_duration_secs(),
+            max_peer_connections: default_max_peer_connections(),
+            peer_backoff_base_secs: default_peer_backoff_base_secs(),
+            peer_backoff_max_secs: default_peer_backoff_max_secs(),
+            max_get_successors_response_bytes: default_max_get_successors_response_bytes(),
+            max_successor_fork_depth: default_max_successor_fork_depth(),
+            get_successors_debug_history: None,
+            peers_file: None,
+            peers_file_max_age_secs: default_peers_file_max_age_secs(),
+            signet_challenge: None,
+            max_tips: default_max_tips(),
+            min_relay_feerate_sat_vb: None,
+            custom_genesis: None,
+            blockchain_manager_channel_size: default_blockchain_manager_channel_size(),
+            transaction_manager_channel_size: default_transaction_manager_channel_size(),
+            allowed_networks: None,
+            block_cache_depth: None,
+            max_block_cache_entries: None,
+            max_header_chain_length: None,
+            compact_filters: false,
+            tip_switch_margin_blocks: None,
+            allowed_peers: vec![],
+            denied_peers: vec![],
+            socks_connect_timeout_secs: default_socks_connect_timeout_secs(),
+            getdata_rate_per_sec: default_getdata_rate_per_sec(),
+            getdata_burst: default_getdata_burst(),
+            user_agent: default_user_agent(),
+            protocol_version: default_protocol_version(),
+            services: default_services(),
         }
     }
 }
@@ -114,11 +590,247 @@ pub mod test {
             self
         }
 
+        pub fn with_socks_proxy(mut self, socks_proxy: Option<SocksProxy>) -> Self {
+            self.config.socks_proxy = socks_proxy;
+            self
+        }
+
+        pub fn with_proxy_only(mut self, proxy_only: bool) -> Self {
+            self.config.proxy_only = proxy_only;
+            self
+        }
+
         pub fn with_ipv6_only(mut self, ipv6_only: bool) -> Self {
             self.config.ipv6_only = ipv6_only;
             self
         }
 
+        pub fn with_tx_cache_max_entries(mut self, tx_cache_max_entries: usize) -> Self {
+            self.config.tx_cache_max_entries = tx_cache_max_entries;
+            self
+        }
+
+        pub fn with_tx_timeout_secs(mut self, tx_timeout_secs: u64) -> Self {
+            self.config.tx_timeout_secs = tx_timeout_secs;
+            self
+        }
+
+        pub fn with_tx_advertisements_per_peer_per_tick(
+            mut self,
+            tx_advertisements_per_peer_per_tick: usize,
+        ) -> Self {
+            self.config.tx_advertisements_per_peer_per_tick = tx_advertisements_per_peer_per_tick;
+            self
+        }
+
+        pub fn with_max_tx_size_bytes(mut self, max_tx_size_bytes: usize) -> Self {
+            self.config.max_tx_size_bytes = max_tx_size_bytes;
+            self
+        }
+
+        pub fn with_peer_ban_threshold(mut self, peer_ban_threshold: u32) -> Self {
+            self.config.peer_ban_threshold = peer_ban_threshold;
+            self
+        }
+
+        pub fn with_peer_ban_duration_secs(mut self, peer_ban_duration_secs: u64) -> Self {
+            self.config.peer_ban_duration_secs = peer_ban_duration_secs;
+            self
+        }
+
+        pub fn with_max_peer_connections(mut self, max_peer_connections: usize) -> Self {
+            self.config.max_peer_connections = max_peer_connections;
+            self
+        }
+
+        pub fn with_peer_backoff_base_secs(mut self, peer_backoff_base_secs: u64) -> Self {
+            self.config.peer_backoff_base_secs = peer_backoff_base_secs;
+            self
+        }
+
+        pub fn with_peer_backoff_max_secs(mut self, peer_backoff_max_secs: u64) -> Self {
+            self.config.peer_backoff_max_secs = peer_backoff_max_secs;
+            self
+        }
+
+        pub fn with_max_get_successors_response_bytes(
+            mut self,
+            max_get_successors_response_bytes: usize,
+        ) -> Self {
+            self.config.max_get_successors_response_bytes = max_get_successors_response_bytes;
+            self
+        }
+
+        pub fn with_max_successor_fork_depth(mut self, max_successor_fork_depth: u32) -> Self {
+            self.config.max_successor_fork_depth = max_successor_fork_depth;
+            self
+        }
+
+        pub fn with_get_successors_debug_history(
+            mut self,
+            get_successors_debug_history: Option<usize>,
+        ) -> Self {
+            self.config.get_successors_debug_history = get_successors_debug_history;
+            self
+        }
+
+        pub fn with_peers_file(mut self, peers_file: Option<PathBuf>) -> Self {
+            self.config.peers_file = peers_file;
+            self
+        }
+
+        pub fn with_peers_file_max_age_secs(mut self, peers_file_max_age_secs: u64) -> Self {
+            self.config.peers_file_max_age_secs = peers_file_max_age_secs;
+            self
+        }
+
+        pub fn with_signet_challenge(mut self, signet_challenge: Option<String>) -> Self {
+            self.config.signet_challenge = signet_challenge;
+            self
+        }
+
+        pub fn with_max_tips(mut self, max_tips: usize) -> Self {
+            self.config.max_tips = max_tips;
+            self
+        }
+
+        pub fn with_min_relay_feerate_sat_vb(
+            mut self,
+            min_relay_feerate_sat_vb: Option<u64>,
+        ) -> Self {
+            self.config.min_relay_feerate_sat_vb = min_relay_feerate_sat_vb;
+            self
+        }
+
+        pub fn with_custom_genesis(mut self, custom_genesis: Option<String>) -> Self {
+            self.config.custom_genesis = custom_genesis;
+            self
+        }
+
+        pub fn with_blockchain_manager_channel_size(
+            mut self,
+            blockchain_manager_channel_size: usize,
+        ) -> Self {
+            self.config.blockchain_manager_channel_size = blockchain_manager_channel_size;
+            self
+        }
+
+        pub fn with_transaction_manager_channel_size(
+            mut self,
+            transaction_manager_channel_size: usize,
+        ) -> Self {
+            self.config.transaction_manager_channel_size = transaction_manager_channel_size;
+            self
+        }
+
+        pub fn with_module_log_levels(
+            mut self,
+            module_log_levels: BTreeMap<String, String>,
+        ) -> Self {
+            self.config.module_log_levels = module_log_levels;
+            self
+        }
+
+        pub fn with_rpc_systemd_socket_index(mut self, rpc_systemd_socket_index: i32) -> Self {
+            self.config.rpc_systemd_socket_index = rpc_systemd_socket_index;
+            self
+        }
+
+        pub fn with_metrics_systemd_socket_index(
+            mut self,
+            metrics_systemd_socket_index: i32,
+        ) -> Self {
+            self.config.metrics_systemd_socket_index = metrics_systemd_socket_index;
+            self
+        }
+
+        pub fn with_tx_rebroadcast_interval_secs(
+            mut self,
+            tx_rebroadcast_interval_secs: u64,
+        ) -> Self {
+            self.config.tx_rebroadcast_interval_secs = tx_rebroadcast_interval_secs;
+            self
+        }
+
+        pub fn with_allowed_networks(mut self, allowed_networks: Option<Vec<Network>>) -> Self {
+            self.config.allowed_networks = allowed_networks;
+            self
+        }
+
+        pub fn with_socks_connect_timeout_secs(mut self, socks_connect_timeout_secs: u64) -> Self {
+            self.config.socks_connect_timeout_secs = socks_connect_timeout_secs;
+            self
+        }
+
+        pub fn with_block_cache_depth(mut self, block_cache_depth: Option<u32>) -> Self {
+            self.config.block_cache_depth = block_cache_depth;
+            self
+        }
+
+        pub fn with_max_block_cache_entries(
+            mut self,
+            max_block_cache_entries: Option<usize>,
+        ) -> Self {
+            self.config.max_block_cache_entries = max_block_cache_entries;
+            self
+        }
+
+        pub fn with_max_header_chain_length(
+            mut self,
+            max_header_chain_length: Option<u32>,
+        ) -> Self {
+            self.config.max_header_chain_length = max_header_chain_length;
+            self
+        }
+
+        pub fn with_compact_filters(mut self, compact_filters: bool) -> Self {
+            self.config.compact_filters = compact_filters;
+            self
+        }
+
+        pub fn with_tip_switch_margin_blocks(
+            mut self,
+            tip_switch_margin_blocks: Option<u32>,
+        ) -> Self {
+            self.config.tip_switch_margin_blocks = tip_switch_margin_blocks;
+            self
+        }
+
+        pub fn with_allowed_peers(mut self, allowed_peers: Vec<String>) -> Self {
+            self.config.allowed_peers = allowed_peers;
+            self
+        }
+
+        pub fn with_denied_peers(mut self, denied_peers: Vec<String>) -> Self {
+            self.config.denied_peers = denied_peers;
+            self
+        }
+
+        pub fn with_getdata_rate_per_sec(mut self, getdata_rate_per_sec: u32) -> Self {
+            self.config.getdata_rate_per_sec = getdata_rate_per_sec;
+            self
+        }
+
+        pub fn with_getdata_burst(mut self, getdata_burst: u32) -> Self {
+            self.config.getdata_burst = getdata_burst;
+            self
+        }
+
+        pub fn with_user_agent(mut self, user_agent: String) -> Self {
+            self.config.user_agent = user_agent;
+            self
+        }
+
+        pub fn with_protocol_version(mut self, protocol_version: u32) -> Self {
+            self.config.protocol_version = protocol_version;
+            self
+        }
+
+        pub fn with_services(mut self, services: u64) -> Self {
+            self.config.services = services;
+            self
+        }
+
         pub fn build(self) -> Config {
             self.config
         }
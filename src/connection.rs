@@ -105,6 +105,10 @@ pub struct Connection {
     writer: UnboundedSender<NetworkMessage>,
     /// This field is used to track the current ping status.
     ping_state: PingState,
+    /// This field contains the round-trip time of the last answered `ping`.
+    ping_latency: Option<Duration>,
+    /// This field is used to track whether a `getaddr` message has been answered.
+    getaddr_answered: bool,
 }
 
 impl Connection {
@@ -127,6 +131,8 @@ impl Connection {
             ping_state: PingState::Idle {
                 last_pong_at: timestamp,
             },
+            ping_latency: None,
+            getaddr_answered: false,
         }
     }
 
@@ -167,12 +173,26 @@ impl Connection {
         };
     }
 
+    /// This function is used to get the round-trip time of the last answered `ping`.
+    pub fn ping_latency(&self) -> Option<Duration> {
+        self.ping_latency
+    }
+
+    /// This function marks that a `getaddr` message is answered on this connection. It returns
+    /// `false` if one has already been answered, as a `getaddr` is only answered once per
+    /// connection.
+    pub fn answer_getaddr(&mut self) -> bool {
+        !std::mem::replace(&mut self.getaddr_answered, true)
+    }
+
     /// This function is used to update the ping state when a BTC node responds
     /// to the sent `ping` message with the `pong` message.
     pub fn idle(&mut self) {
-        self.ping_state = PingState::Idle {
-            last_pong_at: SystemTime::now(),
-        };
+        let last_pong_at = SystemTime::now();
+        if let PingState::ExpectingPong { ping_sent_at, .. } = self.ping_state {
+            self.ping_latency = last_pong_at.duration_since(ping_sent_at).ok();
+        }
+        self.ping_state = PingState::Idle { last_pong_at };
     }
 
     /// This function is used to set a connection to a connected state,
@@ -316,8 +336,18 @@ mod test {
                 state,
                 writer,
                 ping_state: PingState::Idle { last_pong_at },
+                ping_latency: None,
+                getaddr_answered: false,
             }
         }
+
+        /// This function is used to set the ping state to a `ping` sent at the given time.
+        pub fn expect_pong_sent_at(&mut self, nonce: u64, ping_sent_at: SystemTime) {
+            self.ping_state = PingState::ExpectingPong {
+                ping_sent_at,
+                nonce,
+            };
+        }
     }
 
     fn make_connection_and_receiver(
@@ -359,6 +389,27 @@ mod test {
         assert!(conn.is_available());
     }
 
+    /// This function tests that a `ping` without a `pong` times out and that the round-trip
+    /// time of an answered `ping` is recorded.
+    #[test]
+    fn test_connection_ping_timeout_and_latency() {
+        let runtime = tokio::runtime::Runtime::new().expect("runtime err");
+        let (mut conn, _) = make_connection_and_receiver(&runtime);
+        conn.state = ConnectionState::HandshakeComplete {
+            timestamp: SystemTime::now(),
+        };
+        assert!(!conn.has_ping_timed_out());
+        assert_eq!(conn.ping_latency(), None);
+
+        conn.expect_pong_sent_at(1, SystemTime::now() - Duration::from_secs(1));
+        assert!(!conn.has_ping_timed_out());
+        conn.idle();
+        assert!(conn.ping_latency().unwrap() >= Duration::from_secs(1));
+
+        conn.expect_pong_sent_at(2, SystemTime::now() - PING_TIMEOUT - Duration::from_secs(1));
+        assert!(conn.has_ping_timed_out());
+    }
+
     #[test]
     fn disconnect() {
         let runtime = tokio::runtime::Runtime::new().expect("runtime err");
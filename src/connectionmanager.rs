@@ -7,6 +7,7 @@ use std::sync::mpsc::{channel, Sender};
 use std::thread::JoinHandle;
 
 use bitcoin::network::{
+    address::AddrV2Message,
     constants::ServiceFlags,
     message::{CommandString, NetworkMessage},
     message_network::VersionMessage,
@@ -24,20 +25,21 @@ use tokio::{
 use crate::{
     addressbook::{
         validate_services, AddressBook, AddressBookError, AddressEntry, AddressTimestamp,
+        MAX_ADDR_MESSAGE_SIZE,
     },
     common::DEFAULT_CHANNEL_BUFFER_SIZE,
     common::*,
     config::Config,
     connection::{Connection, ConnectionConfig, ConnectionState, PingState},
     metrics::RouterMetrics,
+    peer_backoff::PeerBackoff,
+    peer_filter::PeerFilter,
+    peer_scores::{Misbehavior, PeerScores},
     stream::{StreamConfig, StreamEvent, StreamEventKind},
     Channel, ChannelError, Command, ProcessBitcoinNetworkMessage,
     ProcessBitcoinNetworkMessageError, ProcessEvent,
 };
 
-/// How the adapter identifies itself to other Bitcoin nodes.
-const USER_AGENT: &str = "bitbolt-btc-adapter";
-
 /// This constant represents the amount of time that is allowed for completing a version handshake
 /// in seconds. This is useful as some nodes do not respond in an orderly fashion with version
 /// information.
@@ -51,6 +53,10 @@ const SEED_ADDR_RETRIEVED_TIMEOUT_SECS: u64 = 5;
 /// This constant represents how many connections can be made during the address discovery process.
 const MAX_CONNECTIONS_DURING_ADDRESS_DISCOVERY: usize = 8;
 
+/// This constant represents the minimum protocol version of nodes that support wtxid-based
+/// transaction relay (BIP-339).
+const WTXID_RELAY_VERSION: u32 = 70016;
+
 /// This enum represents the possible errors that the connection manager may encounter.
 #[derive(Debug, Error)]
 pub enum ConnectionManagerError {
@@ -67,6 +73,15 @@ pub enum ConnectionManagerError {
     /// This can happen from recycling the DNS seed queue addresses.
     #[error("Address {0} is already connected")]
     AlreadyConnected(SocketAddr),
+    /// The address has been banned for misbehaving.
+    #[error("Address {0} is banned")]
+    Banned(SocketAddr),
+    /// The address recently failed to connect and may not be dialed yet.
+    #[error("Address {0} is backing off")]
+    BackingOff(SocketAddr),
+    /// The address is denied or not allowed by the configured peer lists.
+    #[error("Address {0} is not allowed")]
+    NotAllowed(SocketAddr),
 }
 
 /// This type is a simple wrapper for results created by a connection manager.
@@ -94,9 +109,21 @@ pub struct ConnectionManager {
     current_height: BlockHeight,
     /// This field contains connections that have connected are being managed.
     connections: HashMap<SocketAddr, Connection>,
-    /// This field determines whether or not we will be using a SOCKS proxy to communicate with
+    /// This field determines whether or not we will be using SOCKS proxies to communicate with
     /// the BTC network.
-    socks_proxy: Option<String>,
+    socks_proxies: Vec<String>,
+    /// This field contains the index of the SOCKS proxy to be used for the next connection.
+    next_socks_proxy_index: usize,
+    /// This field determines whether connections may only be made through the SOCKS proxies.
+    proxy_only: bool,
+    /// The time a connection attempt through the SOCKS proxy may take.
+    socks_connect_timeout: Duration,
+    /// How the adapter identifies itself to other Bitcoin nodes.
+    user_agent: String,
+    /// The protocol version advertised in `version` messages.
+    protocol_version: u32,
+    /// The services advertised in `version` messages.
+    services: ServiceFlags,
     /// This field is used to receive stream events from the active connection streams.
     stream_event_receiver: Receiver<StreamEvent>,
     /// This field is used to allow new streams to send events back to the connection manager.
@@ -104,6 +131,15 @@ pub struct ConnectionManager {
     network_message_sender: Sender<(SocketAddr, NetworkMessage)>,
     /// This field is used for the version nonce generation.
     rng: StdRng,
+    /// This field contains the misbehavior scores of the peers and the banned peers.
+    peer_scores: PeerScores,
+    /// This field contains the reconnection backoff state of peers that recently failed.
+    peer_backoff: PeerBackoff,
+    /// This field contains the allowlist and denylist of peers that are consulted before dialing.
+    peer_filter: PeerFilter,
+    /// This field contains the peers whose connections were reaped since the last call to
+    /// `take_disconnected_peers`, so other components can drop their state of these peers.
+    disconnected_peers: Vec<SocketAddr>,
     metrics: RouterMetrics,
 }
 
@@ -119,19 +155,34 @@ impl ConnectionManager {
         let (stream_event_sender, stream_event_receiver) =
             channel::<StreamEvent>(DEFAULT_CHANNEL_BUFFER_SIZE);
 
-        let (min_connections, max_connections) = connection_limits(&address_book);
+        let (min_connections, max_connections) =
+            connection_limits(&address_book, config.max_peer_connections);
 
         Self {
             initial_address_discovery: !address_book.has_enough_addresses(),
             address_book,
             logger,
-            magic: config.network.magic(),
+            magic: config.network_magic(),
             max_connections,
             min_connections,
             current_height: 0,
             connections: HashMap::with_capacity(max_connections),
             rng: StdRng::from_entropy(),
-            socks_proxy: config.socks_proxy.clone(),
+            socks_proxies: config
+                .socks_proxy
+                .as_ref()
+                .map(|socks_proxy| socks_proxy.urls().to_vec())
+                .unwrap_or_default(),
+            next_socks_proxy_index: 0,
+            proxy_only: config.proxy_only,
+            socks_connect_timeout: Duration::from_secs(config.socks_connect_timeout_secs),
+            user_agent: config.user_agent.clone(),
+            protocol_version: config.protocol_version,
+            services: ServiceFlags::from(config.services),
+            peer_scores: PeerScores::new(config),
+            peer_backoff: PeerBackoff::new(config),
+            peer_filter: PeerFilter::new(config),
+            disconnected_peers: vec![],
             stream_event_sender,
             network_message_sender,
             stream_event_receiver,
@@ -148,6 +199,31 @@ impl ConnectionManager {
         }
     }
 
+    /// This function is used to penalize a peer for misbehaving. If the peer's misbehavior
+    /// score reaches the ban threshold, the peer is banned and its connection is discarded.
+    pub fn record_misbehavior(&mut self, address: &SocketAddr, misbehavior: Misbehavior) {
+        if self.peer_scores.record(address, misbehavior) {
+            warn!(
+                self.logger,
+                "Banned {} after misbehaving: {:?}", address, misbehavior
+            );
+            self.internal_discard(address);
+        }
+    }
+
+    /// This function returns the current reconnection backoff delay of a peer, if it recently
+    /// failed to connect.
+    pub fn backoff_delay(&self, address: &SocketAddr) -> Option<Duration> {
+        self.peer_backoff.delay(address)
+    }
+
+    /// This function records a failed or dropped connection so the peer is not dialed again
+    /// before its backoff delay has passed.
+    fn record_connection_failure(&mut self, address: &SocketAddr) {
+        let delay = self.peer_backoff.record_failure(address, &mut self.rng);
+        trace!(self.logger, "Backing off from {} for {:?}", address, delay);
+    }
+
     /// This function pulls events off of the connection manager's stream event
     /// receiver and returns it to the caller.
     pub async fn receive_stream_event(&mut self) -> StreamEvent {
@@ -185,6 +261,9 @@ impl ConnectionManager {
         &mut self,
         handle: fn(StreamConfig) -> JoinHandle<()>,
     ) -> ConnectionManagerResult<()> {
+        self.peer_scores.remove_expired_bans();
+        self.peer_backoff.remove_expired();
+        self.address_book.save_if_due();
         self.manage_ping_states();
         self.flag_version_handshake_timeouts();
         self.flag_seed_addr_retrieval_timeouts();
@@ -195,6 +274,18 @@ impl ConnectionManager {
         self.metrics
             .known_peer_addresses
             .set(self.address_book.size() as i64);
+        let result = self.make_connections(handle);
+        self.metrics
+            .peer_connections
+            .set(self.connections.len() as i64);
+        result
+    }
+
+    /// This function establishes new connections until the max number of connections is reached.
+    fn make_connections(
+        &mut self,
+        handle: fn(StreamConfig) -> JoinHandle<()>,
+    ) -> ConnectionManagerResult<()> {
         while self.connections.len() < self.get_max_number_of_connections() {
             self.make_connection(handle)?;
         }
@@ -205,9 +296,10 @@ impl ConnectionManager {
     /// During initial address discovery, the adapter should only make one connection at
     /// a time while discovering addresses from the Bitcoin seed nodes. This is so the
     /// adapter does not overwhelm the network with connections.
+    /// The number never exceeds the configured maximum number of peer connections.
     fn get_max_number_of_connections(&self) -> usize {
         if self.initial_address_discovery {
-            MAX_CONNECTIONS_DURING_ADDRESS_DISCOVERY
+            MAX_CONNECTIONS_DURING_ADDRESS_DISCOVERY.min(self.max_connections)
         } else {
             self.max_connections
         }
@@ -284,16 +376,35 @@ impl ConnectionManager {
 
         for addr in disconnects {
             self.connections.remove(&addr);
+            self.metrics
+                .peer_ping_latency
+                .remove_label_values(&[&addr.to_string()])
+                .ok();
+            self.disconnected_peers.push(addr);
         }
     }
 
+    /// This function saves the addresses of the address book to the peers file, if one is
+    /// configured. It is called when the adapter shuts down.
+    pub fn save_addresses(&mut self) -> Option<JoinHandle<()>> {
+        self.address_book.save()
+    }
+
+    /// This function returns the peers whose connections were reaped since the last call.
+    pub fn take_disconnected_peers(&mut self) -> Vec<SocketAddr> {
+        std::mem::take(&mut self.disconnected_peers)
+    }
+
     /// This function creates a new connection with a stream to a BTC node.
     fn make_connection(
         &mut self,
         handle: fn(StreamConfig) -> JoinHandle<()>,
     ) -> ConnectionManagerResult<()> {
         self.metrics.connections.inc();
-        let address_entry_result = if !self.address_book.has_enough_addresses() {
+        // Addresses reloaded from the peers file are tried before falling back to the DNS seeds.
+        let address_entry_result = if !self.address_book.has_enough_addresses()
+            && !self.address_book.has_reloaded_addresses()
+        {
             self.address_book.pop_seed()
         } else {
             self.address_book.pop()
@@ -303,6 +414,18 @@ impl ConnectionManager {
         if self.connections.contains_key(&address) {
             return Err(ConnectionManagerError::AlreadyConnected(address));
         }
+        if !self.peer_filter.is_allowed(&address) {
+            self.address_book.discard(&address_entry);
+            return Err(ConnectionManagerError::NotAllowed(address));
+        }
+        if self.peer_scores.is_banned(&address) {
+            self.address_book.discard(&address_entry);
+            return Err(ConnectionManagerError::Banned(address));
+        }
+        if self.peer_backoff.is_backing_off(&address) {
+            self.address_book.remove_from_active(&address_entry);
+            return Err(ConnectionManagerError::BackingOff(address));
+        }
         let (writer, network_message_receiver) = unbounded_channel();
         let stream_event_sender = self.stream_event_sender.clone();
         let network_message_sender = self.network_message_sender.clone();
@@ -312,7 +435,9 @@ impl ConnectionManager {
             logger: self.logger.clone(),
             magic: self.magic,
             network_message_receiver,
-            socks_proxy: self.socks_proxy.clone(),
+            socks_proxy: self.next_socks_proxy(),
+            proxy_only: self.proxy_only,
+            socks_connect_timeout: self.socks_connect_timeout,
             stream_event_sender,
             network_message_sender,
         };
@@ -326,6 +451,14 @@ impl ConnectionManager {
         Ok(())
     }
 
+    /// This function selects the SOCKS proxy to be used for a new connection.
+    /// The configured proxies are used in a round-robin fashion.
+    fn next_socks_proxy(&mut self) -> Option<String> {
+        let socks_proxy = self.socks_proxies.get(self.next_socks_proxy_index)?.clone();
+        self.next_socks_proxy_index = (self.next_socks_proxy_index + 1) % self.socks_proxies.len();
+        Some(socks_proxy)
+    }
+
     /// This function retrieves a connection from the connections pool with a given socket address.
     fn get_connection(&mut self, addr: &SocketAddr) -> ConnectionManagerResult<&mut Connection> {
         match self.connections.get_mut(addr) {
@@ -340,8 +473,6 @@ impl ConnectionManager {
         // Setup the sender field. This field is now ignored and most simply filled with dummy data per the documentation.
         let adapter_address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0);
         let sender = Address::new(&adapter_address, ServiceFlags::NONE);
-        // The adapter will not provide any services to the network, so the flags are set to none.
-        let services = ServiceFlags::NONE;
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .map_err(|_| ConnectionManagerError::SystemTimeIsBehind)?
@@ -350,19 +481,24 @@ impl ConnectionManager {
         // The node address that will be receiving this message.
         let receiver = Address::new(addr, ServiceFlags::NETWORK | ServiceFlags::NETWORK_LIMITED);
         let nonce: u64 = self.rng.gen();
-        let user_agent = String::from(USER_AGENT);
-        let message = NetworkMessage::Version(VersionMessage::new(
-            services,
+        let mut version_message = VersionMessage::new(
+            self.services,
             timestamp as i64,
             receiver,
             sender,
             nonce,
-            user_agent,
+            self.user_agent.clone(),
             // The height the adapter believes is the active tip.
             self.current_height as i32,
-        ));
+        );
+        version_message.version = self.protocol_version;
+
+        self.send_to(addr, NetworkMessage::Version(version_message))
+    }
 
-        self.send_to(addr, message)
+    /// This function is used to send a `wtxidrelay` message to a specified connection.
+    fn send_wtxidrelay(&mut self, addr: &SocketAddr) -> ConnectionManagerResult<()> {
+        self.send_to(addr, NetworkMessage::WtxidRelay)
     }
 
     /// This function is used to send a `verack` message to a specified connection.
@@ -433,6 +569,13 @@ impl ConnectionManager {
             warn!(self.logger, "Received an invalid version from {}", address);
             return Err(ProcessBitcoinNetworkMessageError::InvalidMessage);
         }
+        // BIP-339: the `wtxidrelay` message has to be sent before the `verack` message.
+        // BIP-155: the same applies to the `sendaddrv2` message, which is understood by nodes
+        // of the same protocol version.
+        if message.version >= WTXID_RELAY_VERSION {
+            self.send_wtxidrelay(address).ok();
+            self.send_to(address, NetworkMessage::SendAddrV2).ok();
+        }
         self.send_verack(address).ok();
 
         if !self.address_book.has_max_address() {
@@ -453,6 +596,8 @@ impl ConnectionManager {
                 AddressEntry::Discovered(_) => conn.completed_handshake(),
             };
         }
+        self.peer_backoff.record_success(address);
+        self.address_book.mark_as_seen(address);
 
         trace!(
             self.logger,
@@ -484,6 +629,7 @@ impl ConnectionManager {
         // If we cannot find the connection, the connection has been cleaned up before the
         // message has been received. It can be skipped.
         trace!(self.logger, "Received pong from {}", address);
+        let mut ping_latency = None;
         if let Ok(conn) = self.get_connection(address) {
             let valid_pong = match conn.ping_state() {
                 PingState::ExpectingPong {
@@ -495,12 +641,19 @@ impl ConnectionManager {
 
             if valid_pong {
                 conn.idle();
+                ping_latency = conn.ping_latency();
             } else {
                 // Received an unexpected or invalid `pong` message.
                 // Disconnect from the BTC node.
                 conn.disconnect();
             };
         }
+        if let Some(latency) = ping_latency {
+            self.metrics
+                .peer_ping_latency
+                .with_label_values(&[&address.to_string()])
+                .set(latency.as_secs_f64());
+        }
         Ok(())
     }
 
@@ -544,6 +697,51 @@ impl ConnectionManager {
         Ok(())
     }
 
+    /// This function processes an `addrv2` message (BIP-155) received from a BTC node.
+    /// Only IPv4 and IPv6 addresses can be connected to, the other addresses are skipped.
+    fn process_addrv2_message(
+        &mut self,
+        address: &SocketAddr,
+        addresses: &[AddrV2Message],
+    ) -> Result<(), ProcessBitcoinNetworkMessageError> {
+        if addresses.len() > MAX_ADDR_MESSAGE_SIZE {
+            warn!(
+                self.logger,
+                "Received {} addresses from {} (max: {})",
+                addresses.len(),
+                address,
+                MAX_ADDR_MESSAGE_SIZE
+            );
+            return Err(ProcessBitcoinNetworkMessageError::InvalidMessage);
+        }
+
+        let addresses: Vec<(AddressTimestamp, Address)> = addresses
+            .iter()
+            .filter_map(|message| {
+                let addr = message.socket_addr().ok()?;
+                Some((message.time, Address::new(&addr, message.services)))
+            })
+            .collect();
+        self.process_addr_message(address, &addresses)
+    }
+
+    /// This function responds to a `getaddr` message with a sample of the recently seen
+    /// addresses. Only the first `getaddr` of a connection is answered, so a peer cannot
+    /// harvest the address book by asking repeatedly.
+    fn process_getaddr_message(
+        &mut self,
+        address: &SocketAddr,
+    ) -> Result<(), ProcessBitcoinNetworkMessageError> {
+        trace!(self.logger, "Received getaddr from {}", address);
+        match self.get_connection(address) {
+            Ok(conn) if conn.answer_getaddr() => {}
+            _ => return Ok(()),
+        }
+        let addresses = self.address_book.sample_recently_seen();
+        self.send_to(address, NetworkMessage::Addr(addresses)).ok();
+        Ok(())
+    }
+
     /// This function is used to handle an unknown command from a BTC node.
     fn process_unknown_message(
         &mut self,
@@ -601,7 +799,7 @@ impl Channel for ConnectionManager {
                     return None;
                 }
 
-                if conn.is_available() {
+                if conn.is_available() && !self.peer_scores.is_banned(addr) {
                     Some(*addr)
                 } else {
                     None
@@ -641,10 +839,12 @@ impl ProcessEvent for ConnectionManager {
                 if let Ok(conn) = self.get_connection(&event.address) {
                     conn.disconnect();
                 }
+                self.record_connection_failure(&event.address);
                 Ok(())
             }
             StreamEventKind::FailedToConnect => {
                 self.internal_discard(&event.address);
+                self.record_connection_failure(&event.address);
                 Ok(())
             }
         }
@@ -663,6 +863,8 @@ impl ProcessBitcoinNetworkMessage for ConnectionManager {
             }
             NetworkMessage::Verack => self.process_verack_message(&address),
             NetworkMessage::Addr(addresses) => self.process_addr_message(&address, addresses),
+            NetworkMessage::AddrV2(addresses) => self.process_addrv2_message(&address, addresses),
+            NetworkMessage::GetAddr => self.process_getaddr_message(&address),
             NetworkMessage::Ping(nonce) => self.process_ping_message(&address, *nonce),
             NetworkMessage::Pong(nonce) => self.process_pong_message(&address, *nonce),
             NetworkMessage::Unknown { command, payload } => {
@@ -673,23 +875,31 @@ impl ProcessBitcoinNetworkMessage for ConnectionManager {
     }
 }
 
-fn connection_limits(address_book: &AddressBook) -> (usize, usize) {
-    if address_book.has_seeds() {
+/// This function returns the minimum and maximum number of connections. The maximum never
+/// exceeds the configured maximum number of peer connections.
+fn connection_limits(address_book: &AddressBook, max_peer_connections: usize) -> (usize, usize) {
+    let (min_connections, max_connections) = if address_book.has_seeds() {
         // Seeds are available.
-        (2, 5)
+        (2, max_peer_connections)
     } else {
         // No seeds are available. Can only connect to nodes explicitly provided.
         (address_book.size(), address_book.size())
-    }
+    };
+    let max_connections = max_connections.min(max_peer_connections);
+    (min_connections.min(max_connections), max_connections)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::config::test::ConfigBuilder;
-    use bitcoin::{network::constants::ServiceFlags, Network};
+    use crate::config::{test::ConfigBuilder, SocksProxy};
+    use bitcoin::{
+        network::{address::AddrV2, constants::ServiceFlags},
+        Network,
+    };
     use logger::replica_logger::no_op_logger;
     use metrics::MetricsRegistry;
+    use std::net::Ipv6Addr;
     use std::str::FromStr;
     use std::sync::mpsc::channel;
     use std::thread::JoinHandle;
@@ -883,7 +1093,8 @@ mod test {
             network_message_sender,
             RouterMetrics::new(&MetricsRegistry::default()),
         );
-        let addr = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        // The seed is connected on the default signet port.
+        let addr = SocketAddr::from_str("127.0.0.1:38333").expect("invalid address");
         assert!(manager.initial_address_discovery);
         assert_eq!(manager.current_height, 0);
         assert_eq!(
@@ -1090,9 +1301,183 @@ mod test {
 
             manager.make_idle();
             assert!(manager.connections.is_empty());
+
+            // The reaped peers are reported once.
+            let mut disconnected_peers = manager.take_disconnected_peers();
+            disconnected_peers.sort();
+            assert_eq!(disconnected_peers, vec![addr, addr2]);
+            assert!(manager.take_disconnected_peers().is_empty());
         });
     }
 
+    /// This test ensures that a failed connection starts a backoff for the peer, which is
+    /// reset once a handshake with the peer completes.
+    #[test]
+    fn test_connection_failure_backoff() {
+        let addr = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let config = ConfigBuilder::new()
+            .with_dns_seeds(vec![String::from("127.0.0.1")])
+            .build();
+        let (network_message_sender, _network_message_receiver) =
+            channel::<(SocketAddr, NetworkMessage)>(DEFAULT_CHANNEL_BUFFER_SIZE);
+        let mut manager = ConnectionManager::new(
+            &config,
+            no_op_logger(),
+            network_message_sender,
+            RouterMetrics::new(&MetricsRegistry::default()),
+        );
+        assert_eq!(manager.backoff_delay(&addr), None);
+
+        manager
+            .process_event(&StreamEvent {
+                address: addr,
+                kind: StreamEventKind::FailedToConnect,
+            })
+            .unwrap();
+        let first_delay = manager.backoff_delay(&addr).unwrap();
+        manager
+            .process_event(&StreamEvent {
+                address: addr,
+                kind: StreamEventKind::FailedToConnect,
+            })
+            .unwrap();
+        assert!(manager.backoff_delay(&addr).unwrap() >= first_delay);
+
+        manager.process_verack_message(&addr).unwrap();
+        assert_eq!(manager.backoff_delay(&addr), None);
+    }
+
+    /// This test ensures that the number of connections never exceeds the configured maximum
+    /// number of peer connections.
+    #[tokio::test]
+    async fn test_max_peer_connections() {
+        let nodes: Vec<SocketAddr> = (1..=5)
+            .map(|i| SocketAddr::from_str(&format!("127.0.0.{}:8333", i)).expect("invalid address"))
+            .collect();
+        let config = ConfigBuilder::new()
+            .with_network(Network::Regtest)
+            .with_nodes(nodes)
+            .with_max_peer_connections(2)
+            .build();
+        let (network_message_sender, _network_message_receiver) =
+            channel::<(SocketAddr, NetworkMessage)>(DEFAULT_CHANNEL_BUFFER_SIZE);
+        let mut manager = ConnectionManager::new(
+            &config,
+            no_op_logger(),
+            network_message_sender,
+            RouterMetrics::new(&MetricsRegistry::default()),
+        );
+        assert_eq!(manager.max_connections, 2);
+        assert_eq!(manager.min_connections, 2);
+
+        manager.tick(BLOCK_HEIGHT_FOR_TESTS, simple_handle);
+        assert_eq!(manager.connections.len(), 2);
+        assert_eq!(manager.metrics.peer_connections.get(), 2);
+        manager.tick(BLOCK_HEIGHT_FOR_TESTS, simple_handle);
+        assert_eq!(manager.connections.len(), 2);
+
+        let config = ConfigBuilder::new()
+            .with_dns_seeds(vec![String::from("127.0.0.1")])
+            .with_max_peer_connections(3)
+            .build();
+        let (network_message_sender, _network_message_receiver) =
+            channel::<(SocketAddr, NetworkMessage)>(DEFAULT_CHANNEL_BUFFER_SIZE);
+        let manager = ConnectionManager::new(
+            &config,
+            no_op_logger(),
+            network_message_sender,
+            RouterMetrics::new(&MetricsRegistry::default()),
+        );
+        assert_eq!(manager.max_connections, 3);
+        assert_eq!(manager.get_max_number_of_connections(), 3);
+    }
+
+    /// This test ensures that a peer that repeatedly misbehaves is banned, discarded and
+    /// excluded from the available connections.
+    #[test]
+    fn test_record_misbehavior_bans_peer() {
+        let runtime = tokio::runtime::Runtime::new().expect("runtime err");
+        let addr = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let addr2 = SocketAddr::from_str("192.168.1.1:8333").expect("invalid address");
+        let config = ConfigBuilder::new()
+            .with_dns_seeds(vec![String::from("127.0.0.1")])
+            .with_peer_ban_threshold(100)
+            .build();
+        let (network_message_sender, _network_message_receiver) =
+            channel::<(SocketAddr, NetworkMessage)>(DEFAULT_CHANNEL_BUFFER_SIZE);
+
+        let mut manager = ConnectionManager::new(
+            &config,
+            no_op_logger(),
+            network_message_sender,
+            RouterMetrics::new(&MetricsRegistry::default()),
+        );
+        let (writer, _) = unbounded_channel();
+        runtime.block_on(async {
+            for address in [addr, addr2] {
+                let conn = Connection::new_with_state(
+                    ConnectionConfig {
+                        address_entry: AddressEntry::Discovered(address),
+                        handle: tokio::task::spawn(async {}),
+                        writer: writer.clone(),
+                    },
+                    ConnectionState::HandshakeComplete {
+                        timestamp: SystemTime::now(),
+                    },
+                );
+                manager.connections.insert(address, conn);
+            }
+            assert_eq!(manager.available_connections().len(), 2);
+
+            manager.record_misbehavior(&addr, Misbehavior::InvalidHeader);
+            assert!(manager.available_connections().contains(&addr));
+
+            manager.record_misbehavior(&addr, Misbehavior::InvalidHeader);
+            assert!(manager.peer_scores.is_banned(&addr));
+            assert_eq!(manager.available_connections(), vec![addr2]);
+            assert!(manager.connections.get(&addr).unwrap().is_disconnected());
+        });
+    }
+
+    /// This test ensures that the configured SOCKS proxies are selected in a round-robin fashion.
+    #[test]
+    fn test_next_socks_proxy_round_robin() {
+        let proxy1 = String::from("socks5://proxy1.com:1080");
+        let proxy2 = String::from("socks5://proxy2.com:1080");
+        let config = ConfigBuilder::new()
+            .with_dns_seeds(vec![String::from("127.0.0.1")])
+            .with_socks_proxy(Some(SocksProxy::Multiple(vec![
+                proxy1.clone(),
+                proxy2.clone(),
+            ])))
+            .build();
+        let (network_message_sender, _network_message_receiver) =
+            channel::<(SocketAddr, NetworkMessage)>(DEFAULT_CHANNEL_BUFFER_SIZE);
+        let mut manager = ConnectionManager::new(
+            &config,
+            no_op_logger(),
+            network_message_sender,
+            RouterMetrics::new(&MetricsRegistry::default()),
+        );
+
+        assert_eq!(manager.next_socks_proxy(), Some(proxy1.clone()));
+        assert_eq!(manager.next_socks_proxy(), Some(proxy2));
+        assert_eq!(manager.next_socks_proxy(), Some(proxy1));
+
+        let config = ConfigBuilder::new()
+            .with_dns_seeds(vec![String::from("127.0.0.1")])
+            .build();
+        let (network_message_sender, _network_message_receiver) =
+            channel::<(SocketAddr, NetworkMessage)>(DEFAULT_CHANNEL_BUFFER_SIZE);
+        let mut manager = ConnectionManager::new(
+            &config,
+            no_op_logger(),
+            network_message_sender,
+            RouterMetrics::new(&MetricsRegistry::default()),
+        );
+        assert_eq!(manager.next_socks_proxy(), None);
+    }
+
     #[tokio::test]
     async fn test_process_version_discovered_address_does_not_bypass_services_check() {
         let socket_1 = SocketAddr::from_str("127.0.0.1:8333").expect("bad address format");
@@ -1185,4 +1570,291 @@ mod test {
         let result = manager.process_version_message(&socket_2, &version_message);
         assert!(result.is_ok());
     }
+
+    /// This function tests that the adapter announces wtxid relay (BIP-339) support to nodes
+    /// that support it.
+    /// Test Steps:
+    /// 1. Process a version message from a node supporting wtxid relay.
+    /// 2. Check that `wtxidrelay` is sent before `verack`.
+    #[tokio::test]
+    async fn test_send_wtxidrelay_before_verack() {
+        let socket = SocketAddr::from_str("127.0.0.1:8333").expect("bad address format");
+        let services = ServiceFlags::NETWORK | ServiceFlags::WITNESS;
+        let mut version_message = VersionMessage::new(
+            services,
+            0,
+            Address::new(&socket, services),
+            Address::new(&socket, ServiceFlags::NONE),
+            1,
+            String::from("test"),
+            60_000,
+        );
+        version_message.version = WTXID_RELAY_VERSION;
+
+        let config = ConfigBuilder::new()
+            .with_dns_seeds(vec![String::from("127.0.0.1")])
+            .build();
+        let (network_message_sender, _network_message_receiver) =
+            channel::<(SocketAddr, NetworkMessage)>(DEFAULT_CHANNEL_BUFFER_SIZE);
+        let mut manager = ConnectionManager::new(
+            &config,
+            no_op_logger(),
+            network_message_sender,
+            RouterMetrics::new(&MetricsRegistry::default()),
+        );
+        let (writer, mut reader) = unbounded_channel();
+        let conn = Connection::new_with_state(
+            ConnectionConfig {
+                address_entry: AddressEntry::Discovered(socket),
+                handle: tokio::task::spawn(async {}),
+                writer,
+            },
+            ConnectionState::Connected {
+                timestamp: SystemTime::now(),
+            },
+        );
+        manager.connections.insert(socket, conn);
+
+        manager
+            .process_bitcoin_network_message(socket, &NetworkMessage::Version(version_message))
+            .unwrap();
+        assert_eq!(reader.try_recv().ok(), Some(NetworkMessage::WtxidRelay));
+        assert_eq!(reader.try_recv().ok(), Some(NetworkMessage::SendAddrV2));
+        assert_eq!(reader.try_recv().ok(), Some(NetworkMessage::Verack));
+    }
+
+    /// This function tests that the `version` message advertises the configured user agent,
+    /// protocol version and services.
+    #[tokio::test]
+    async fn test_send_version_uses_configured_values() {
+        let socket = SocketAddr::from_str("127.0.0.1:8333").expect("bad address format");
+        let config = ConfigBuilder::new()
+            .with_dns_seeds(vec![String::from("127.0.0.1")])
+            .with_user_agent(String::from("/operator:1.0/"))
+            .with_protocol_version(70015)
+            .with_services(ServiceFlags::WITNESS.as_u64())
+            .build();
+        let (network_message_sender, _network_message_receiver) =
+            channel::<(SocketAddr, NetworkMessage)>(DEFAULT_CHANNEL_BUFFER_SIZE);
+        let mut manager = ConnectionManager::new(
+            &config,
+            no_op_logger(),
+            network_message_sender,
+            RouterMetrics::new(&MetricsRegistry::default()),
+        );
+        let (writer, mut reader) = unbounded_channel();
+        let conn = Connection::new_with_state(
+            ConnectionConfig {
+                address_entry: AddressEntry::Discovered(socket),
+                handle: tokio::task::spawn(async {}),
+                writer,
+            },
+            ConnectionState::Connected {
+                timestamp: SystemTime::now(),
+            },
+        );
+        manager.connections.insert(socket, conn);
+
+        manager.send_version(&socket).unwrap();
+        match reader.try_recv() {
+            Ok(NetworkMessage::Version(version_message)) => {
+                assert_eq!(version_message.user_agent, "/operator:1.0/");
+                assert_eq!(version_message.version, 70015);
+                assert_eq!(version_message.services, ServiceFlags::WITNESS);
+            }
+            message => panic!("expected a version message, got {:?}", message),
+        }
+    }
+
+    /// This function tests that IPv6 addresses received in an `addrv2` message are added to
+    /// the address book and that addresses of other networks are skipped.
+    #[test]
+    fn test_process_addrv2_message_ipv6() {
+        let socket = SocketAddr::from_str("127.0.0.1:8333").expect("bad address format");
+        let config = ConfigBuilder::new()
+            .with_dns_seeds(vec![String::from("127.0.0.1")])
+            .build();
+        let (network_message_sender, _network_message_receiver) =
+            channel::<(SocketAddr, NetworkMessage)>(DEFAULT_CHANNEL_BUFFER_SIZE);
+        let mut manager = ConnectionManager::new(
+            &config,
+            no_op_logger(),
+            network_message_sender,
+            RouterMetrics::new(&MetricsRegistry::default()),
+        );
+        let ipv6 = Ipv6Addr::from_str("2001:db8::1").expect("bad address format");
+        let addresses = vec![
+            AddrV2Message {
+                time: 0,
+                services: ServiceFlags::NETWORK,
+                addr: AddrV2::Ipv6(ipv6),
+                port: 8333,
+            },
+            AddrV2Message {
+                time: 0,
+                services: ServiceFlags::NETWORK,
+                addr: AddrV2::TorV3([0; 32]),
+                port: 8333,
+            },
+        ];
+
+        manager
+            .process_bitcoin_network_message(socket, &NetworkMessage::AddrV2(addresses))
+            .unwrap();
+        assert_eq!(manager.address_book.size(), 1);
+        assert_eq!(
+            manager.address_book.pop().unwrap().addr(),
+            &SocketAddr::from((ipv6, 8333))
+        );
+
+        let too_many = vec![
+            AddrV2Message {
+                time: 0,
+                services: ServiceFlags::NETWORK,
+                addr: AddrV2::Ipv6(ipv6),
+                port: 8333,
+            };
+            MAX_ADDR_MESSAGE_SIZE + 1
+        ];
+        assert!(matches!(
+            manager.process_bitcoin_network_message(socket, &NetworkMessage::AddrV2(too_many)),
+            Err(ProcessBitcoinNetworkMessageError::InvalidMessage)
+        ));
+    }
+
+    /// This function tests that a `getaddr` message is only answered once per connection.
+    /// Test Steps:
+    /// 1. Send a `getaddr` message and check that it is answered with an `addr` message.
+    /// 2. Send another `getaddr` message and check that it is not answered.
+    #[tokio::test]
+    async fn test_process_getaddr_message_answered_once() {
+        let socket = SocketAddr::from_str("127.0.0.1:8333").expect("bad address format");
+        let config = ConfigBuilder::new()
+            .with_dns_seeds(vec![String::from("127.0.0.1")])
+            .build();
+        let (network_message_sender, _network_message_receiver) =
+            channel::<(SocketAddr, NetworkMessage)>(DEFAULT_CHANNEL_BUFFER_SIZE);
+        let mut manager = ConnectionManager::new(
+            &config,
+            no_op_logger(),
+            network_message_sender,
+            RouterMetrics::new(&MetricsRegistry::default()),
+        );
+        let (writer, mut reader) = unbounded_channel();
+        let conn = Connection::new_with_state(
+            ConnectionConfig {
+                address_entry: AddressEntry::Discovered(socket),
+                handle: tokio::task::spawn(async {}),
+                writer,
+            },
+            ConnectionState::HandshakeComplete {
+                timestamp: SystemTime::now(),
+            },
+        );
+        manager.connections.insert(socket, conn);
+
+        manager
+            .process_bitcoin_network_message(socket, &NetworkMessage::GetAddr)
+            .unwrap();
+        assert!(matches!(reader.try_recv(), Ok(NetworkMessage::Addr(_))));
+
+        manager
+            .process_bitcoin_network_message(socket, &NetworkMessage::GetAddr)
+            .unwrap();
+        assert!(reader.try_recv().is_err());
+    }
+
+    /// This function tests that a `pong` is only accepted if it matches the nonce of the sent
+    /// `ping` and that the round-trip time is recorded per peer.
+    /// Test Steps:
+    /// 1. Send a `ping` and answer it with the matching nonce.
+    /// 2. Check that the connection is idle and the latency metric is set.
+    /// 3. Send another `ping`, answer it with a different nonce and check that the peer is disconnected.
+    #[tokio::test]
+    async fn test_process_pong_message_nonce_matching() {
+        let socket = SocketAddr::from_str("127.0.0.1:8333").expect("bad address format");
+        let config = ConfigBuilder::new()
+            .with_dns_seeds(vec![String::from("127.0.0.1")])
+            .build();
+        let (network_message_sender, _network_message_receiver) =
+            channel::<(SocketAddr, NetworkMessage)>(DEFAULT_CHANNEL_BUFFER_SIZE);
+        let mut manager = ConnectionManager::new(
+            &config,
+            no_op_logger(),
+            network_message_sender,
+            RouterMetrics::new(&MetricsRegistry::default()),
+        );
+        let (writer, mut reader) = unbounded_channel();
+        let conn = Connection::new_with_state(
+            ConnectionConfig {
+                address_entry: AddressEntry::Discovered(socket),
+                handle: tokio::task::spawn(async {}),
+                writer,
+            },
+            ConnectionState::HandshakeComplete {
+                timestamp: SystemTime::now(),
+            },
+        );
+        manager.connections.insert(socket, conn);
+
+        manager.send_ping(&socket).unwrap();
+        let nonce = match reader.try_recv() {
+            Ok(NetworkMessage::Ping(nonce)) => nonce,
+            other => panic!("expected a ping, got {:?}", other),
+        };
+        manager
+            .process_bitcoin_network_message(socket, &NetworkMessage::Pong(nonce))
+            .unwrap();
+        let conn = manager.connections.get(&socket).unwrap();
+        assert!(matches!(conn.ping_state(), PingState::Idle { .. }));
+        let latency = conn.ping_latency().expect("latency should be recorded");
+        assert_eq!(
+            manager
+                .metrics
+                .peer_ping_latency
+                .with_label_values(&[&socket.to_string()])
+                .get(),
+            latency.as_secs_f64()
+        );
+
+        manager.send_ping(&socket).unwrap();
+        manager
+            .process_bitcoin_network_message(socket, &NetworkMessage::Pong(nonce.wrapping_add(1)))
+            .unwrap();
+        assert!(manager.connections.get(&socket).unwrap().is_disconnected());
+    }
+
+    /// This function tests that a peer that does not answer a `ping` within the timeout is
+    /// disconnected.
+    #[tokio::test]
+    async fn test_manage_ping_states_disconnects_unresponsive_peer() {
+        let socket = SocketAddr::from_str("127.0.0.1:8333").expect("bad address format");
+        let config = ConfigBuilder::new()
+            .with_dns_seeds(vec![String::from("127.0.0.1")])
+            .build();
+        let (network_message_sender, _network_message_receiver) =
+            channel::<(SocketAddr, NetworkMessage)>(DEFAULT_CHANNEL_BUFFER_SIZE);
+        let mut manager = ConnectionManager::new(
+            &config,
+            no_op_logger(),
+            network_message_sender,
+            RouterMetrics::new(&MetricsRegistry::default()),
+        );
+        let (writer, _reader) = unbounded_channel();
+        let mut conn = Connection::new_with_state(
+            ConnectionConfig {
+                address_entry: AddressEntry::Discovered(socket),
+                handle: tokio::task::spawn(async {}),
+                writer,
+            },
+            ConnectionState::HandshakeComplete {
+                timestamp: SystemTime::now(),
+            },
+        );
+        conn.expect_pong_sent_at(1, SystemTime::now() - Duration::from_secs(60 * 60));
+        manager.connections.insert(socket, conn);
+
+        manager.manage_ping_states();
+        assert!(manager.connections.get(&socket).unwrap().is_disconnected());
+    }
 }
@@ -7,27 +7,22 @@ use std::sync::Mutex;
 
 use bitcoin::{Block, BlockHash, BlockHeader, Network};
 use btc_validation::is_beyond_last_checkpoint;
-use tokio::sync::{mpsc::Sender, Mutex};
+use logger::{warn, ReplicaLogger};
+use metrics::MetricsRegistry;
+use tokio::sync::{
+    mpsc::{error::TrySendError, Sender},
+    Mutex,
+};
 use tonic::{Code, Status};
 
 use crate::{
-    blockchainstate::CachedHeader, common::BlockHeight, config::Config, BlockchainManagerRequest,
-    BlockchainState,
+    blockchainstate::CachedHeader, common::BlockHeight, config::Config,
+    metrics::GetSuccessorsMetrics, BlockchainManagerRequest, BlockchainState,
 };
 
 // Max number of next block headers that can be returned in the `GetSuccessorsResponse`.
 const MAX_NEXT_BLOCK_HEADERS_LENGTH: usize = 100;
 
-const BLOCK_HEADER_SIZE: usize = 80;
-
-// The maximum number of bytes the `next` field in a response can take.
-const MAX_NEXT_BYTES: usize = MAX_NEXT_BLOCK_HEADERS_LENGTH * BLOCK_HEADER_SIZE;
-
-// The maximum number of bytes the `blocks` in a response can take.
-// NOTE: This is a soft limit, and is only honored if there's > 1 blocks already in the response.
-// Having this as a soft limit as necessary to prevent large blocks from stalling consensus.
-const MAX_BLOCKS_BYTES: usize = 2_000_000 - MAX_NEXT_BYTES;
-
 // Max height for sending multiple blocks when connecting the Bitcoin mainnet.
 const MAINNET_MAX_MULTI_BLOCK_ANCHOR_HEIGHT: BlockHeight = 750_000;
 
@@ -37,6 +32,9 @@ pub struct GetSuccessorsRequest {
     pub anchor: BlockHash,
     /// Most recent block hashes that have been processed by the smart contract.
     pub processed_block_hashes: Vec<BlockHash>,
+    /// If set to a descendant of the anchor, only the blocks on the path from the anchor to
+    /// this block are returned, ending with this block.
+    pub stop_hash: Option<BlockHash>,
 }
 
 #[derive(Debug)]
@@ -45,13 +43,42 @@ pub struct GetSuccessorsResponse {
     pub blocks: Vec<Block>,
     /// Next set of headers to be sent to the smart contract.
     pub next: Vec<BlockHeader>,
+    /// Set when further cached blocks were left out of `blocks` because of the response size limit.
+    pub more_available: bool,
+}
+
+/// The hashes served in response to a `GetSuccessorsRequest`, recorded for debugging.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServedSuccessors {
+    /// The anchor of the request.
+    pub anchor: BlockHash,
+    /// The hashes of the blocks in the response.
+    pub blocks: Vec<BlockHash>,
+    /// The hashes of the next headers in the response.
+    pub next: Vec<BlockHash>,
 }
+
 /// Contains the functionality to respond to GetSuccessorsRequests via the RPC
 /// server.
 pub struct GetSuccessorsHandler {
     state: Arc<Mutex<BlockchainState>>,
     command_sender: Sender<BlockchainManagerRequest>,
+    /// The number of requests the channel to the blockchain manager can buffer.
+    command_channel_size: usize,
     network: Network,
+    /// The maximum number of bytes the `blocks` in a response can take.
+    /// NOTE: This is a soft limit, and is only honored if there's > 1 blocks already in the response.
+    /// Having this as a soft limit as necessary to prevent large blocks from stalling consensus.
+    max_blocks_bytes: usize,
+    /// The maximum number of blocks a fork branch's tip may be behind the active tip for the
+    /// branch to be followed.
+    max_fork_depth: BlockHeight,
+    /// The number of most recent responses that are recorded. Nothing is recorded if it is 0.
+    served_history_size: usize,
+    /// The most recent responses, oldest first.
+    served_history: parking_lot::Mutex<VecDeque<ServedSuccessors>>,
+    logger: ReplicaLogger,
+    metrics: GetSuccessorsMetrics,
 }
 
 impl GetSuccessorsHandler {
@@ -61,11 +88,20 @@ impl GetSuccessorsHandler {
         config: &Config,
         state: Arc<Mutex<BlockchainState>>,
         command_sender: Sender<BlockchainManagerRequest>,
+        logger: ReplicaLogger,
+        metrics_registry: &MetricsRegistry,
     ) -> Self {
         Self {
             state,
             command_sender,
+            command_channel_size: config.blockchain_manager_channel_size,
             network: config.network,
+            max_blocks_bytes: config.max_get_successors_response_bytes,
+            max_fork_depth: config.max_successor_fork_depth,
+            served_history_size: config.get_successors_debug_history.unwrap_or(0),
+            served_history: parking_lot::Mutex::new(VecDeque::new()),
+            logger,
+            metrics: GetSuccessorsMetrics::new(metrics_registry),
         }
     }
 
@@ -74,20 +110,25 @@ impl GetSuccessorsHandler {
     // TODO: ER-2479: Pruning blocks from the cache should also consider the height of the anchor hash.
     /// Handles a request for get successors. The response will contain the blocks that the adapter
     /// currently contains in its cache as well as the headers for the next blocks.
-    /// If the channels are full, PruneOldBlocks and EnqueueNewBlocksToDownload will not be executed.
+    /// If the channel is full, PruneBlocks and EnqueueNewBlocksToDownload will not be executed.
     pub async fn get_successors(
         &self,
         request: GetSuccessorsRequest,
     ) -> Result<GetSuccessorsResponse, Status> {
         let response = {
-            let state = self.state.lock().await;
+            let mut state = self.state.lock().await;
             let anchor_height = state
                 .get_cached_header(&request.anchor)
-                .map_or(0, |cached| cached.height);
+                .map(|cached| cached.height);
+            // The headers the canister's next request starts from must not be pruned.
+            if let Some(anchor_height) = anchor_height {
+                state.set_last_anchor_height(anchor_height);
+            }
+            let anchor_height = anchor_height.unwrap_or(0);
 
             // Wait with downloading blocks until we synced the header chain above the last checkpoint
             // to make sure we are following the correct chain.
-            if !is_beyond_last_checkpoint(&self.network, state.get_active_chain_tip().height) {
+            if !is_beyond_last_checkpoint(&self.network, state.active_tip_height()) {
                 return Err(Status::new(
                     Code::Unavailable,
                     "Header chain not yet synced past last checkpoint",
@@ -95,61 +136,127 @@ impl GetSuccessorsHandler {
             }
 
             let allow_multiple_blocks = are_multiple_blocks_allowed(self.network, anchor_height);
-            let blocks = get_successor_blocks(
+            let min_branch_tip_height = state
+                .active_tip_height()
+                .saturating_sub(self.max_fork_depth);
+            let (blocks, more_available) = get_successor_blocks(
                 &state,
                 &request.anchor,
                 &request.processed_block_hashes,
+                request.stop_hash.as_ref(),
                 allow_multiple_blocks,
+                self.max_blocks_bytes,
+                min_branch_tip_height,
             );
             let next = get_next_headers(
                 &state,
                 &request.anchor,
                 &request.processed_block_hashes,
                 &blocks,
+                min_branch_tip_height,
             );
-            GetSuccessorsResponse { blocks, next }
+            GetSuccessorsResponse {
+                blocks,
+                next,
+                more_available,
+            }
         };
+        self.record_served_successors(&request.anchor, &response);
 
         if !response.next.is_empty() {
-            // TODO: better handling of full channel as the receivers are never closed.
-            self.command_sender
-                .try_send(BlockchainManagerRequest::EnqueueNewBlocksToDownload(
-                    response.next.clone(),
-                ))
-                .ok();
+            self.send_command(BlockchainManagerRequest::EnqueueNewBlocksToDownload(
+                response.next.clone(),
+            ));
         }
 
-        // TODO: better handling of full channel as the receivers are never closed.
-        self.command_sender
-            .try_send(BlockchainManagerRequest::PruneBlocks(
-                request.anchor,
-                request.processed_block_hashes,
-            ))
-            .ok();
+        self.send_command(BlockchainManagerRequest::PruneBlocks(
+            request.anchor,
+            request.processed_block_hashes,
+        ));
 
         Ok(response)
     }
+
+    /// Returns the hashes served for the most recent requests, oldest first. It is empty unless
+    /// `get_successors_debug_history` is set.
+    pub fn served_successors(&self) -> Vec<ServedSuccessors> {
+        self.served_history.lock().iter().cloned().collect()
+    }
+
+    /// Records the hashes of the response if recording is enabled, dropping the oldest
+    /// recorded response once the history is full.
+    fn record_served_successors(&self, anchor: &BlockHash, response: &GetSuccessorsResponse) {
+        if self.served_history_size == 0 {
+            return;
+        }
+        let mut served_history = self.served_history.lock();
+        if served_history.len() >= self.served_history_size {
+            served_history.pop_front();
+        }
+        served_history.push_back(ServedSuccessors {
+            anchor: *anchor,
+            blocks: response
+                .blocks
+                .iter()
+                .map(|block| block.block_hash())
+                .collect(),
+            next: response
+                .next
+                .iter()
+                .map(|header| header.block_hash())
+                .collect(),
+        });
+    }
+
+    /// Sends a request to the blockchain manager without waiting for buffer space.
+    /// If the channel is saturated, the request is dropped, which is safe as the next
+    /// `GetSuccessorsRequest` sends an up-to-date request again.
+    fn send_command(&self, request: BlockchainManagerRequest) {
+        match self.command_sender.try_send(request) {
+            Ok(()) => {}
+            Err(TrySendError::Full(request)) => {
+                self.metrics.blockchain_manager_requests_dropped.inc();
+                warn!(
+                    self.logger,
+                    "The blockchain manager channel is full, dropping request";
+                    "request" => request.name(),
+                    "channel_size" => self.command_channel_size,
+                );
+            }
+            // The receiver is owned by the router and is never closed while the adapter runs.
+            Err(TrySendError::Closed(_)) => {}
+        }
+    }
 }
 
 // Performs a breadth-first search to retrieve blocks from the block cache.
 //
 // If blocks are available and `allow_multiple_blocks` is `true`, then as many blocks are returned
-// as possible that fit in the `MAX_BLOCKS_BYTES` limit, with a minimum of one block.  Otherwise, a
+// as possible that fit in the `max_blocks_bytes` limit, with a minimum of one block.  Otherwise, a
 // single block is returned.
+// The returned flag is set if a cached block had to be left out of the response.
+// Fork branches with a tip below `min_branch_tip_height` are not followed.
+// If `stop_hash` is a descendant of the anchor, only the path from the anchor to `stop_hash` is
+// followed and the search halts at `stop_hash`. Otherwise, it is ignored.
 fn get_successor_blocks(
     state: &BlockchainState,
     anchor: &BlockHash,
     processed_block_hashes: &[BlockHash],
+    stop_hash: Option<&BlockHash>,
     allow_multiple_blocks: bool,
-) -> Vec<Block> {
+    max_blocks_bytes: usize,
+    min_branch_tip_height: BlockHeight,
+) -> (Vec<Block>, bool) {
     let seen: HashSet<BlockHash> = processed_block_hashes.iter().copied().collect();
+    let path_to_stop = stop_hash.and_then(|stop_hash| path_from_anchor(state, anchor, stop_hash));
 
     let mut successor_blocks = vec![];
     // Block hashes that should be looked at in subsequent breadth-first searches.
     let mut response_block_size: usize = 0;
+    let mut more_available = false;
     let mut queue: VecDeque<CachedHeader> = state
         .get_cached_header(anchor)
-        .map(|c| c.children.lock().clone())
+        .map(|c| successor_children(c, min_branch_tip_height))
         .unwrap_or_default()
         .into_iter()
         .collect();
@@ -157,18 +264,24 @@ fn get_successor_blocks(
     // Compute the blocks by starting a breadth-first search.
     while let Some(cached_header) = queue.pop_front() {
         let block_hash = cached_header.header.block_hash();
+        if let Some(path_to_stop) = &path_to_stop {
+            if !path_to_stop.contains(&block_hash) {
+                continue;
+            }
+        }
         if !seen.contains(&block_hash) {
             // Retrieve the block from the cache.
             match state.get_block(&block_hash) {
                 Some(block) => {
                     let block_size = block.size();
                     if response_block_size == 0
-                        || (response_block_size + block_size <= MAX_BLOCKS_BYTES
+                        || (response_block_size + block_size <= max_blocks_bytes
                             && allow_multiple_blocks)
                     {
                         successor_blocks.push(block.clone());
                         response_block_size += block_size;
                     } else {
+                        more_available = true;
                         break;
                     }
                 }
@@ -180,18 +293,40 @@ fn get_successor_blocks(
             }
         }
 
-        queue.extend(cached_header.children.lock().clone());
+        if path_to_stop.is_some() && stop_hash == Some(&block_hash) {
+            break;
+        }
+        queue.extend(successor_children(&cached_header, min_branch_tip_height));
     }
 
-    successor_blocks
+    (successor_blocks, more_available)
+}
+
+/// Returns the hashes of the headers on the path from the anchor (exclusive) to the stop hash
+/// (inclusive), or `None` if the stop hash is not a cached descendant of the anchor.
+fn path_from_anchor(
+    state: &BlockchainState,
+    anchor: &BlockHash,
+    stop_hash: &BlockHash,
+) -> Option<HashSet<BlockHash>> {
+    let anchor_height = state.get_cached_header(anchor)?.height;
+    let mut path = HashSet::new();
+    let mut current = state.get_cached_header(stop_hash)?;
+    while current.height > anchor_height {
+        path.insert(current.header.block_hash());
+        current = state.get_cached_header(&current.header.prev_blockhash)?;
+    }
+    (current.header.block_hash() == *anchor && !path.is_empty()).then(|| path)
 }
 
 /// Get the next headers for blocks that may possibly be sent in upcoming GetSuccessor responses.
+/// Fork branches with a tip below `min_branch_tip_height` are not followed.
 fn get_next_headers(
     state: &BlockchainState,
     anchor: &BlockHash,
     processed_block_hashes: &[BlockHash],
     blocks: &[Block],
+    min_branch_tip_height: BlockHeight,
 ) -> Vec<BlockHeader> {
     let seen: HashSet<BlockHash> = processed_block_hashes
         .iter()
@@ -200,7 +335,7 @@ fn get_next_headers(
         .collect();
     let mut queue: VecDeque<CachedHeader> = state
         .get_cached_header(anchor)
-        .map(|c| c.children.lock().clone())
+        .map(|c| successor_children(c, min_branch_tip_height))
         .unwrap_or_default()
         .into_iter()
         .collect();
@@ -214,11 +349,38 @@ fn get_next_headers(
         if !seen.contains(&block_hash) {
             next_headers.push(cached_header.header);
         }
-        queue.extend(cached_header.children.lock().clone());
+        queue.extend(successor_children(&cached_header, min_branch_tip_height));
     }
     next_headers
 }
 
+/// Returns the children of the given header that should be followed when computing successors.
+/// Where the chain forks, branches whose tip is below `min_branch_tip_height` are excluded.
+fn successor_children(
+    cached_header: &CachedHeader,
+    min_branch_tip_height: BlockHeight,
+) -> Vec<CachedHeader> {
+    let children = cached_header.children.lock().clone();
+    if children.len() <= 1 {
+        return children;
+    }
+    children
+        .into_iter()
+        .filter(|child| branch_tip_height(child) >= min_branch_tip_height)
+        .collect()
+}
+
+/// Returns the height of the highest descendant of the given header.
+fn branch_tip_height(cached_header: &CachedHeader) -> BlockHeight {
+    let mut tip_height = cached_header.height;
+    let mut stack = vec![cached_header.clone()];
+    while let Some(cached_header) = stack.pop() {
+        tip_height = tip_height.max(cached_header.height);
+        stack.extend(cached_header.children.lock().clone());
+    }
+    tip_height
+}
+
 /// Helper used to determine if multiple blocks should be returned.
 fn are_multiple_blocks_allowed(network: Network, anchor_height: BlockHeight) -> bool {
     match network {
@@ -236,6 +398,7 @@ mod test {
 
     use bitcoin::Network;
     use ic_metrics::MetricsRegistry;
+    use logger::replica_logger::no_op_logger;
     use tokio::sync::{mpsc::channel, Mutex};
 
     use crate::{
@@ -258,6 +421,8 @@ mod test {
             &config,
             Arc::new(Mutex::new(blockchain_state)),
             blockchain_manager_tx,
+            no_op_logger(),
+            &MetricsRegistry::default(),
         );
         // Set up the following chain:
         // |--> 1'---> 2'
@@ -295,6 +460,7 @@ mod test {
         let request = GetSuccessorsRequest {
             anchor: genesis_hash,
             processed_block_hashes: vec![main_chain[0].block_hash(), main_chain[1].block_hash()],
+            stop_hash: None,
         };
 
         {
@@ -365,6 +531,8 @@ mod test {
             &config,
             Arc::new(Mutex::new(blockchain_state)),
             blockchain_manager_tx,
+            no_op_logger(),
+            &MetricsRegistry::default(),
         );
         // Set up the following chain:
         // 0 -> 1 ---> 2 ---> 3 -> 4
@@ -382,6 +550,7 @@ mod test {
         let request = GetSuccessorsRequest {
             anchor: genesis_hash,
             processed_block_hashes: vec![],
+            stop_hash: None,
         };
 
         {
@@ -407,6 +576,8 @@ mod test {
             &config,
             Arc::new(Mutex::new(blockchain_state)),
             blockchain_manager_tx,
+            no_op_logger(),
+            &MetricsRegistry::default(),
         );
 
         // Set up the following chain:
@@ -433,6 +604,7 @@ mod test {
         let request = GetSuccessorsRequest {
             anchor: genesis_hash,
             processed_block_hashes: vec![],
+            stop_hash: None,
         };
         let response = handler.get_successors(request).await.unwrap();
 
@@ -441,6 +613,72 @@ mod test {
         assert_eq!(response.next.len(), 3);
     }
 
+    /// This function tests that the hashes served for the most recent requests are recorded
+    /// when `get_successors_debug_history` is set.
+    /// Test Steps:
+    /// 1. Set up a chain of 5 headers with the first 2 blocks cached.
+    /// 2. Send 3 requests with a history of 2 responses.
+    /// 3. Check that the last 2 responses are recorded with the served hashes.
+    #[tokio::test]
+    async fn test_get_successors_records_served_successors() {
+        let config = ConfigBuilder::new()
+            .with_network(Network::Regtest)
+            .with_get_successors_debug_history(Some(2))
+            .build();
+        let blockchain_state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis = blockchain_state.genesis().clone();
+        let genesis_hash = genesis.header.block_hash();
+        let (blockchain_manager_tx, _) = channel::<BlockchainManagerRequest>(10);
+        let handler = GetSuccessorsHandler::new(
+            &config,
+            Arc::new(Mutex::new(blockchain_state)),
+            blockchain_manager_tx,
+            no_op_logger(),
+            &MetricsRegistry::default(),
+        );
+
+        let main_chain = generate_headers(genesis_hash, genesis.header.time, 5, &[]);
+        {
+            let mut blockchain = handler.state.lock().await;
+            blockchain.add_headers(&main_chain);
+            for header in &main_chain[..2] {
+                blockchain
+                    .add_block(Block {
+                        header: *header,
+                        txdata: vec![],
+                    })
+                    .expect("invalid block");
+            }
+        }
+        assert!(handler.served_successors().is_empty());
+
+        for anchor in [genesis_hash, genesis_hash, main_chain[0].block_hash()] {
+            let request = GetSuccessorsRequest {
+                anchor,
+                processed_block_hashes: vec![],
+                stop_hash: None,
+            };
+            handler.get_successors(request).await.unwrap();
+        }
+
+        let hashes = headers_to_hashes(&main_chain);
+        assert_eq!(
+            handler.served_successors(),
+            vec![
+                ServedSuccessors {
+                    anchor: genesis_hash,
+                    blocks: hashes[..2].to_vec(),
+                    next: hashes[2..].to_vec(),
+                },
+                ServedSuccessors {
+                    anchor: hashes[0],
+                    blocks: hashes[1..2].to_vec(),
+                    next: hashes[2..].to_vec(),
+                },
+            ]
+        );
+    }
+
     /// This tests ensures that `BlockchainManager::handle_client_request(...)` returns multiple
     /// blocks from the main chain and a fork. Order should be preserved.
     #[tokio::test]
@@ -454,6 +692,8 @@ mod test {
             &config,
             Arc::new(Mutex::new(blockchain_state)),
             blockchain_manager_tx,
+            no_op_logger(),
+            &MetricsRegistry::default(),
         );
         // Set up the following chain:
         // |-> 1'
@@ -498,6 +738,7 @@ mod test {
         let request = GetSuccessorsRequest {
             anchor: genesis_hash,
             processed_block_hashes: vec![],
+            stop_hash: None,
         };
         let response = handler.get_successors(request).await.unwrap();
         assert_eq!(response.blocks.len(), 3);
@@ -525,6 +766,8 @@ mod test {
             &config,
             Arc::new(Mutex::new(blockchain_state)),
             blockchain_manager_tx,
+            no_op_logger(),
+            &MetricsRegistry::default(),
         );
         // Set up the following chain:
         // |-> 1'
@@ -575,6 +818,7 @@ mod test {
         let request = GetSuccessorsRequest {
             anchor: genesis_hash,
             processed_block_hashes: vec![],
+            stop_hash: None,
         };
         let response = handler.get_successors(request).await.unwrap();
         assert_eq!(
@@ -616,6 +860,8 @@ mod test {
             &config,
             Arc::new(Mutex::new(blockchain_state)),
             blockchain_manager_tx,
+            no_op_logger(),
+            &MetricsRegistry::default(),
         );
         // Generate a blockchain with one large block.
         let large_blocks = generate_large_block_blockchain(genesis_hash, genesis.header.time, 1);
@@ -651,6 +897,7 @@ mod test {
         let request = GetSuccessorsRequest {
             anchor: genesis_hash,
             processed_block_hashes: vec![],
+            stop_hash: None,
         };
         let response = handler.get_successors(request).await.unwrap();
         // There are 2 blocks in the chain: {large, small}.
@@ -677,6 +924,8 @@ mod test {
             &config,
             Arc::new(Mutex::new(blockchain_state)),
             blockchain_manager_tx,
+            no_op_logger(),
+            &MetricsRegistry::default(),
         );
 
         let main_chain = generate_headers(genesis_hash, genesis.header.time, 5, &[]);
@@ -706,6 +955,7 @@ mod test {
         let request = GetSuccessorsRequest {
             anchor: genesis_hash,
             processed_block_hashes: vec![],
+            stop_hash: None,
         };
         let response = handler.get_successors(request).await.unwrap();
 
@@ -716,6 +966,8 @@ mod test {
             matches!(response.blocks.last(), Some(block) if block.block_hash() == main_chain.last().unwrap().block_hash())
         );
 
+        assert!(response.more_available);
+
         // The next field should contain the large block header as it is too large for the request.
         assert_eq!(response.next.len(), 1);
         assert!(
@@ -723,6 +975,256 @@ mod test {
         );
     }
 
+    /// This test ensures that `BlockchainManager::get_successors(...)` stops adding blocks once the
+    /// configured response size limit would be exceeded and signals that more blocks are available.
+    /// Test Steps:
+    /// 1. Create a handler with a limit that fits three of the five cached blocks.
+    /// 2. Check that only the first three blocks are returned and more blocks are signaled.
+    /// 3. Request the successors of the returned blocks and check that the rest are returned.
+    #[tokio::test]
+    async fn test_get_successors_truncated_at_configured_byte_limit() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let blockchain_state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis = blockchain_state.genesis().clone();
+        let genesis_hash = genesis.header.block_hash();
+        let main_chain = generate_headers(genesis_hash, genesis.header.time, 5, &[]);
+        let main_blocks = main_chain
+            .iter()
+            .map(|h| Block {
+                header: *h,
+                txdata: vec![],
+            })
+            .collect::<Vec<_>>();
+        let block_size = main_blocks[0].size();
+        let config = ConfigBuilder::new()
+            .with_network(Network::Regtest)
+            .with_max_get_successors_response_bytes(3 * block_size + block_size / 2)
+            .build();
+        let (blockchain_manager_tx, _) = channel::<BlockchainManagerRequest>(10);
+        let handler = GetSuccessorsHandler::new(
+            &config,
+            Arc::new(Mutex::new(blockchain_state)),
+            blockchain_manager_tx,
+            no_op_logger(),
+            &MetricsRegistry::default(),
+        );
+
+        {
+            let mut blockchain = handler.state.lock().await;
+            let (added_headers, _) = blockchain.add_headers(&main_chain);
+            assert_eq!(added_headers.len(), 5);
+            for block in &main_blocks {
+                blockchain.add_block(block.clone()).unwrap();
+            }
+        }
+
+        let request = GetSuccessorsRequest {
+            anchor: genesis_hash,
+            processed_block_hashes: vec![],
+            stop_hash: None,
+        };
+        let response = handler.get_successors(request).await.unwrap();
+        assert_eq!(response.blocks.len(), 3);
+        assert_eq!(
+            headers_to_hashes(&response.blocks.iter().map(|b| b.header).collect::<Vec<_>>()),
+            headers_to_hashes(&main_chain[..3])
+        );
+        assert!(response.more_available);
+
+        let request = GetSuccessorsRequest {
+            anchor: genesis_hash,
+            processed_block_hashes: headers_to_hashes(&main_chain[..3]),
+            stop_hash: None,
+        };
+        let response = handler.get_successors(request).await.unwrap();
+        assert_eq!(response.blocks.len(), 2);
+        assert!(!response.more_available);
+    }
+
+    /// This test ensures that `BlockchainManager::get_successors(...)` does not return blocks or
+    /// headers of a fork branch whose tip is too far behind the active tip.
+    /// Test Steps:
+    /// 1. Set up a main chain and a stale fork that is deeper than the configured fork depth.
+    /// 2. Add all blocks of both chains to the cache.
+    /// 3. Check that neither the blocks nor the headers of the stale fork are returned.
+    #[tokio::test]
+    async fn test_get_successors_excludes_deep_stale_fork() {
+        let config = ConfigBuilder::new()
+            .with_network(Network::Regtest)
+            .with_max_successor_fork_depth(5)
+            .build();
+        let blockchain_state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis = blockchain_state.genesis().clone();
+        let genesis_hash = genesis.header.block_hash();
+        let (blockchain_manager_tx, _) = channel::<BlockchainManagerRequest>(10);
+        let handler = GetSuccessorsHandler::new(
+            &config,
+            Arc::new(Mutex::new(blockchain_state)),
+            blockchain_manager_tx,
+            no_op_logger(),
+            &MetricsRegistry::default(),
+        );
+        // Set up the following chain:
+        // |-> 1' -> 2'
+        // 0 -> 1 -> 2 -> ... -> 10
+        let main_chain = generate_headers(genesis_hash, genesis.header.time, 10, &[]);
+        let stale_fork = generate_headers(
+            genesis_hash,
+            genesis.header.time,
+            2,
+            &headers_to_hashes(&main_chain),
+        );
+        {
+            let mut blockchain = handler.state.lock().await;
+            let (_, maybe_err) = blockchain.add_headers(&main_chain);
+            assert!(maybe_err.is_none());
+            let (_, maybe_err) = blockchain.add_headers(&stale_fork);
+            assert!(maybe_err.is_none());
+            for header in main_chain.iter().take(3).chain(stale_fork.iter()) {
+                blockchain
+                    .add_block(Block {
+                        header: *header,
+                        txdata: vec![],
+                    })
+                    .expect("invalid block");
+            }
+        }
+
+        let request = GetSuccessorsRequest {
+            anchor: genesis_hash,
+            processed_block_hashes: vec![],
+            stop_hash: None,
+        };
+        let response = handler.get_successors(request).await.unwrap();
+        assert_eq!(
+            headers_to_hashes(&response.blocks.iter().map(|b| b.header).collect::<Vec<_>>()),
+            headers_to_hashes(&main_chain[..3])
+        );
+        assert_eq!(
+            headers_to_hashes(&response.next),
+            headers_to_hashes(&main_chain[3..])
+        );
+    }
+
+    /// This test ensures that only the blocks on the path from the anchor to the stop hash are
+    /// returned when a stop hash is provided.
+    #[tokio::test]
+    async fn test_get_successors_with_stop_hash() {
+        let config = ConfigBuilder::new().with_network(Network::Regtest).build();
+        let blockchain_state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis = blockchain_state.genesis().clone();
+        let genesis_hash = genesis.header.block_hash();
+        let (blockchain_manager_tx, _) = channel::<BlockchainManagerRequest>(10);
+        let handler = GetSuccessorsHandler::new(
+            &config,
+            Arc::new(Mutex::new(blockchain_state)),
+            blockchain_manager_tx,
+            no_op_logger(),
+            &MetricsRegistry::default(),
+        );
+        // Set up the following chain:
+        // |--> 1'---> 2'
+        // 0 -> 1 ---> 2 ---> 3 -> 4
+        let main_chain = generate_headers(genesis_hash, genesis.header.time, 4, &[]);
+        let side_chain = generate_headers(
+            genesis_hash,
+            genesis.header.time,
+            2,
+            &headers_to_hashes(&main_chain),
+        );
+        {
+            let mut blockchain = handler.state.lock().await;
+            blockchain.add_headers(&main_chain);
+            blockchain.add_headers(&side_chain);
+            for header in main_chain.iter().chain(side_chain.iter()) {
+                blockchain
+                    .add_block(Block {
+                        header: *header,
+                        txdata: vec![],
+                    })
+                    .expect("invalid block");
+            }
+        }
+
+        let request = GetSuccessorsRequest {
+            anchor: genesis_hash,
+            processed_block_hashes: vec![],
+            stop_hash: Some(main_chain[2].block_hash()),
+        };
+        let response = handler.get_successors(request).await.unwrap();
+        assert_eq!(
+            headers_to_hashes(&response.blocks.iter().map(|b| b.header).collect::<Vec<_>>()),
+            headers_to_hashes(&main_chain[..3])
+        );
+
+        let request = GetSuccessorsRequest {
+            anchor: main_chain[0].block_hash(),
+            processed_block_hashes: vec![],
+            stop_hash: Some(main_chain[1].block_hash()),
+        };
+        let response = handler.get_successors(request).await.unwrap();
+        assert_eq!(
+            headers_to_hashes(&response.blocks.iter().map(|b| b.header).collect::<Vec<_>>()),
+            headers_to_hashes(&main_chain[1..2])
+        );
+
+        // A stop hash that is not a descendant of the anchor is ignored.
+        let request = GetSuccessorsRequest {
+            anchor: main_chain[0].block_hash(),
+            processed_block_hashes: vec![],
+            stop_hash: Some(side_chain[1].block_hash()),
+        };
+        let response = handler.get_successors(request).await.unwrap();
+        assert_eq!(
+            headers_to_hashes(&response.blocks.iter().map(|b| b.header).collect::<Vec<_>>()),
+            headers_to_hashes(&main_chain[1..])
+        );
+    }
+
+    /// This test ensures that requests to the blockchain manager are dropped and counted once the
+    /// channel is saturated.
+    #[tokio::test]
+    async fn test_get_successors_full_blockchain_manager_channel() {
+        let config = ConfigBuilder::new()
+            .with_network(Network::Regtest)
+            .with_blockchain_manager_channel_size(2)
+            .build();
+        let blockchain_state = BlockchainState::new(&config, &MetricsRegistry::default());
+        let genesis_hash = blockchain_state.genesis().header.block_hash();
+        let (blockchain_manager_tx, mut blockchain_manager_rx) =
+            channel::<BlockchainManagerRequest>(config.blockchain_manager_channel_size);
+        let handler = GetSuccessorsHandler::new(
+            &config,
+            Arc::new(Mutex::new(blockchain_state)),
+            blockchain_manager_tx,
+            no_op_logger(),
+            &MetricsRegistry::default(),
+        );
+
+        // Each request sends a `PruneBlocks` request as there are no headers to download.
+        for _ in 0..3 {
+            let request = GetSuccessorsRequest {
+                anchor: genesis_hash,
+                processed_block_hashes: vec![],
+                stop_hash: None,
+            };
+            handler.get_successors(request).await.unwrap();
+        }
+        assert_eq!(handler.metrics.blockchain_manager_requests_dropped.get(), 1);
+
+        assert!(matches!(
+            blockchain_manager_rx.recv().await,
+            Some(BlockchainManagerRequest::PruneBlocks(_, _))
+        ));
+        let request = GetSuccessorsRequest {
+            anchor: genesis_hash,
+            processed_block_hashes: vec![],
+            stop_hash: None,
+        };
+        handler.get_successors(request).await.unwrap();
+        assert_eq!(handler.metrics.blockchain_manager_requests_dropped.get(), 1);
+    }
+
     #[test]
     fn test_are_multiple_blocks_allowed() {
         // Mainnet
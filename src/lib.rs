@@ -4,10 +4,14 @@
 //! and publish transactions. Moreover, it interacts with the Bitcoin system
 //! component to provide blocks and collect outgoing transactions.
 
-use bitcoin::{network::message::NetworkMessage, BlockHash, BlockHeader};
+use bitcoin::{network::message::NetworkMessage, BlockHash, BlockHeader, Txid};
 use parking_lot::RwLock;
-use std::{net::SocketAddr, sync::Arc, time::Instant};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 use std::sync::RwLock;
+use tokio::{
+    sync::{oneshot, watch},
+    time::{interval, Instant},
+};
 
 /// This module contains the AddressManager struct. The struct stores addresses
 /// that will be used to create new connections. It also tracks addresses that
@@ -22,6 +26,8 @@ mod blockchainmanager;
 mod blockchainstate;
 /// This module contains command line arguments parser.
 pub mod cli;
+/// This module contains the clock that provides the current time to time-dependent components.
+mod clock;
 /// This module contains constants and types that are shared by many modules.
 mod common;
 /// This module contains the basic configuration struct used to start up an
@@ -33,7 +39,21 @@ mod connection;
 /// This module contains code that is used to manage multiple connections to
 /// BTC nodes.
 mod connectionmanager;
+/// This module contains the log drain that applies per-module log levels.
+mod log_filter;
 mod metrics;
+/// This module contains the helpers to handle Tor onion service addresses.
+mod onion;
+/// This module contains the reconnection backoff applied to Bitcoin nodes after failed connections.
+mod peer_backoff;
+/// This module contains the allowlist and denylist of the Bitcoin nodes the adapter may connect to.
+mod peer_filter;
+/// This module contains the per-peer rate limit of requests from Bitcoin nodes.
+mod peer_rate_limit;
+/// This module contains the misbehavior scores used to ban misbehaving Bitcoin nodes.
+mod peer_scores;
+/// This module contains the logic to reload the configuration while the adapter is running.
+mod reload;
 /// The module is responsible for awaiting messages from bitcoin peers and dispaching them
 /// to the correct component.
 mod router;
@@ -46,13 +66,17 @@ mod transaction_manager;
 mod get_successors_handler;
 
 pub use blockchainmanager::BlockchainManager;
-pub use blockchainstate::BlockchainState;
+pub use blockchainstate::{revalidate_headers, BlockchainState, HeaderRevalidation};
 use common::BlockHeight;
-pub use get_successors_handler::GetSuccessorsHandler;
+pub use get_successors_handler::{GetSuccessorsHandler, ServedSuccessors};
+pub use log_filter::with_module_log_levels;
+pub use metrics::{BlockchainStateMetricsSnapshot, TransactionMetricsSnapshot};
+use peer_scores::Misbehavior;
+pub use reload::{reload_config_on_sighup, ReloadableConfig, SharedReloadableConfig};
 pub use router::start_router;
 pub use rpc_server::spawn_grpc_server;
 use stream::StreamEvent;
-pub use transaction_manager::TransactionManager;
+pub use transaction_manager::{SendTransactionError, TransactionManager, TxStatus};
 
 /// This struct is used to represent commands given to the adapter in order to interact
 /// with BTC nodes.
@@ -72,6 +96,36 @@ pub enum ProcessBitcoinNetworkMessageError {
     /// This variant is used to represent when an invalid message has been
     /// received from a Bitcoin node.
     InvalidMessage,
+    /// This variant is used to represent when a header that failed validation has been
+    /// received from a Bitcoin node.
+    InvalidHeader,
+    /// This variant is used to represent when a Bitcoin node requested more items in a
+    /// `getdata` message than allowed.
+    TooManyGetDataItems,
+    /// This variant is used to represent when a Bitcoin node sent `getdata` messages faster
+    /// than allowed. The message is dropped, but the node is not disconnected.
+    GetDataRateExceeded,
+}
+
+impl ProcessBitcoinNetworkMessageError {
+    /// This function returns the misbehavior the Bitcoin node is penalized for, if any.
+    fn misbehavior(&self) -> Option<Misbehavior> {
+        match self {
+            ProcessBitcoinNetworkMessageError::InvalidMessage => None,
+            ProcessBitcoinNetworkMessageError::InvalidHeader => Some(Misbehavior::InvalidHeader),
+            ProcessBitcoinNetworkMessageError::TooManyGetDataItems => {
+                Some(Misbehavior::TooManyGetDataItems)
+            }
+            ProcessBitcoinNetworkMessageError::GetDataRateExceeded => {
+                Some(Misbehavior::GetDataRateExceeded)
+            }
+        }
+    }
+
+    /// This function returns whether the connection to the Bitcoin node should be discarded.
+    fn disconnects(&self) -> bool {
+        !matches!(self, ProcessBitcoinNetworkMessageError::GetDataRateExceeded)
+    }
 }
 
 /// This enum is used to represent errors that  
@@ -136,16 +190,32 @@ pub enum BlockchainManagerRequest {
     PruneBlocks(BlockHash, Vec<BlockHash>),
 }
 
+impl BlockchainManagerRequest {
+    /// Returns the name of the request used in logs.
+    pub fn name(&self) -> &'static str {
+        match self {
+            BlockchainManagerRequest::EnqueueNewBlocksToDownload(_) => {
+                "enqueue_new_blocks_to_download"
+            }
+            BlockchainManagerRequest::PruneBlocks(_, _) => "prune_blocks",
+        }
+    }
+}
+
 /// The transaction manager is owned by a single thread which listens on a channel
 /// for TransactionManagerRequest messages and executes the corresponding method.
 #[derive(Debug)]
 pub enum TransactionManagerRequest {
-    /// Command for executing send_transaction
-    SendTransaction(Vec<u8>),
+    /// Command for executing send_transaction. The result is sent back on the provided
+    /// channel.
+    SendTransaction(Vec<u8>, oneshot::Sender<Result<Txid, SendTransactionError>>),
 }
 
+/// The interval at which the adapter checks whether it became idle.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 /// The type tracks when then adapter should become idle. The type is
-/// thread-safe.
+/// thread-safe. Transitions between idle and active are published to subscribers.
 #[derive(Clone)]
 pub struct AdapterState {
     /// The field contains instant of the latest received request.
@@ -156,31 +226,107 @@ pub struct AdapterState {
     /// On MacOS this approach caused issues since on MacOS Instant::now() is time since boot and when substracting
     /// 'idle_seconds' we encountered an underflow and paniced.
     last_received_at: Arc<RwLock<Option<Instant>>>,
-    /// The field contains how long the adapter should wait to before becoming idle.
-    idle_seconds: u64,
+    /// The field contains the reloadable config, which determines how long the adapter
+    /// should wait to before becoming idle.
+    reloadable_config: SharedReloadableConfig,
+    /// The field is used to publish the idle state, `true` meaning idle.
+    idle_sender: Arc<watch::Sender<bool>>,
+    /// The field keeps the channel open, so the idle state can be published even if there
+    /// are no other subscribers.
+    idle_receiver: watch::Receiver<bool>,
 }
 
 impl AdapterState {
     /// Crates new instance of the AdapterState.
-    pub fn new(idle_seconds: u64) -> Self {
+    pub fn new(reloadable_config: SharedReloadableConfig) -> Self {
+        // The adapter is idle on startup.
+        let (idle_sender, idle_receiver) = watch::channel(true);
         Self {
             last_received_at: Arc::new(RwLock::new(None)),
-            idle_seconds,
+            reloadable_config,
+            idle_sender: Arc::new(idle_sender),
+            idle_receiver,
         }
     }
 
-    /// Returns if the adapter is idle.
+    /// Returns if the adapter is idle. A changed idle state is published to the subscribers.
     pub fn is_idle(&self) -> bool {
-        match *self.last_received_at.read() {
-            Some(last) => last.elapsed().as_secs() > self.idle_seconds,
+        let idle = match *self.last_received_at.read() {
+            Some(last) => last.elapsed().as_secs() > self.reloadable_config.read().idle_seconds,
             // Nothing received yet still in idle from startup.
             None => true,
-        }
+        };
+        self.publish_idle(idle);
+        idle
     }
 
     /// Updates the current state of the adapter given a request was received.
     pub fn received_now(&self) {
         // Instant::now() is monotonically nondecreasing clock.
         *self.last_received_at.write() = Some(Instant::now());
+        self.publish_idle(false);
+    }
+
+    /// Returns a receiver that observes the transitions between idle (`true`) and active (`false`).
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.idle_receiver.clone()
+    }
+
+    /// Periodically checks whether the adapter became idle, so the transition to idle is
+    /// published without a request being received.
+    pub async fn publish_idle_transitions(self) {
+        let mut check_interval = interval(IDLE_CHECK_INTERVAL);
+        loop {
+            check_interval.tick().await;
+            self.is_idle();
+        }
+    }
+
+    /// Publishes the idle state if it differs from the last published one.
+    fn publish_idle(&self, idle: bool) {
+        self.idle_sender.send_if_modified(|current| {
+            let modified = *current != idle;
+            *current = idle;
+            modified
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::test::ConfigBuilder;
+
+    /// This function tests that the transitions between idle and active are observed by subscribers.
+    /// Test Steps:
+    /// 1. Check that the adapter starts idle.
+    /// 2. Receive a request and check that the subscriber observes the transition to active.
+    /// 3. Let the idle period pass and check that the subscriber observes the transition to idle.
+    #[tokio::test(start_paused = true)]
+    async fn test_adapter_state_publishes_idle_transitions() {
+        let config = ConfigBuilder::new().build();
+        let reloadable_config = ReloadableConfig::new_shared(&config);
+        reloadable_config.write().idle_seconds = 0;
+        let adapter_state = AdapterState::new(reloadable_config);
+        let mut idle = adapter_state.subscribe();
+        assert!(*idle.borrow());
+
+        adapter_state.received_now();
+        idle.changed().await.unwrap();
+        assert!(!*idle.borrow());
+
+        // `is_idle` only reports idle once more than `idle_seconds` have passed.
+        tokio::time::advance(Duration::from_millis(1100)).await;
+        assert!(adapter_state.is_idle());
+        idle.changed().await.unwrap();
+        assert!(*idle.borrow());
+
+        // Unchanged states are not published again.
+        adapter_state.is_idle();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), idle.changed())
+                .await
+                .is_err()
+        );
     }
 }
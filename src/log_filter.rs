@@ -0,0 +1,178 @@
+use crate::reload::SharedReloadableConfig;
+use logger::ReplicaLogger;
+use slog::{Drain, Level, OwnedKVList, Record};
+use std::collections::BTreeMap;
+
+/// This struct is a log drain that only passes on records at or above the level configured
+/// for the module that emitted them. Records of modules without an override are checked against
+/// the reloadable log level.
+#[derive(Debug)]
+pub struct ModuleLevelFilter<D> {
+    drain: D,
+    /// The log level of each module, keyed by the module path within the adapter.
+    levels: BTreeMap<String, Level>,
+    /// The reloadable config providing the log level of modules without an override.
+    reloadable_config: SharedReloadableConfig,
+}
+
+impl<D> ModuleLevelFilter<D> {
+    /// This function creates a new filter in front of the provided drain.
+    pub fn new(
+        drain: D,
+        levels: BTreeMap<String, Level>,
+        reloadable_config: SharedReloadableConfig,
+    ) -> Self {
+        Self {
+            drain,
+            levels,
+            reloadable_config,
+        }
+    }
+
+    /// This function returns the level of the most specific override matching the module path,
+    /// if any. The crate name at the start of the path is ignored.
+    fn level_for(&self, module_path: &str) -> Option<Level> {
+        let module = module_path
+            .split_once("::")
+            .map_or("", |(_, module)| module);
+        self.levels
+            .iter()
+            .filter(|(prefix, _)| {
+                module == prefix.as_str()
+                    || module
+                        .strip_prefix(prefix.as_str())
+                        .map_or(false, |rest| rest.starts_with("::"))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, level)| *level)
+    }
+}
+
+impl<D: Drain> Drain for ModuleLevelFilter<D> {
+    type Ok = Option<D::Ok>;
+    type Err = D::Err;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<Self::Ok, Self::Err> {
+        let level = self
+            .level_for(record.module())
+            .unwrap_or_else(|| self.reloadable_config.read().log_level);
+        if record.level().is_at_least(level) {
+            self.drain.log(record, values).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// This function applies the per-module log levels and the reloadable log level to the
+/// provided logger. The provided logger should not filter any records itself, as records it
+/// drops cannot be enabled by a reload.
+pub fn with_module_log_levels(
+    logger: ReplicaLogger,
+    levels: BTreeMap<String, Level>,
+    reloadable_config: SharedReloadableConfig,
+) -> ReplicaLogger {
+    let filter =
+        ModuleLevelFilter::new(logger.inner_logger.root.clone(), levels, reloadable_config);
+    ReplicaLogger::from(slog::Logger::root(filter.ignore_res(), slog::o!()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{config::test::ConfigBuilder, reload::ReloadableConfig};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    /// A drain counting the records it receives.
+    #[derive(Clone, Default)]
+    struct CountingDrain(Arc<AtomicUsize>);
+
+    impl Drain for CountingDrain {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(&self, _: &Record, _: &OwnedKVList) -> Result<(), slog::Never> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    /// This function tests that the most specific override of a module is applied.
+    #[test]
+    fn test_level_for() {
+        let levels = BTreeMap::from([
+            ("transaction_manager".to_string(), Level::Debug),
+            ("router".to_string(), Level::Warning),
+            ("router::inner".to_string(), Level::Error),
+        ]);
+        let reloadable_config = ReloadableConfig::new_shared(&ConfigBuilder::new().build());
+        let filter = ModuleLevelFilter::new(slog::Discard, levels, reloadable_config);
+        assert_eq!(
+            filter.level_for("adapter::transaction_manager"),
+            Some(Level::Debug)
+        );
+        assert_eq!(
+            filter.level_for("adapter::transaction_manager::test"),
+            Some(Level::Debug)
+        );
+        assert_eq!(filter.level_for("adapter::router"), Some(Level::Warning));
+        assert_eq!(
+            filter.level_for("adapter::router::inner"),
+            Some(Level::Error)
+        );
+        assert_eq!(filter.level_for("adapter::routers"), None);
+        assert_eq!(filter.level_for("adapter::blockchainstate"), None);
+        assert_eq!(filter.level_for("adapter"), None);
+    }
+
+    /// This function tests that records below the level of their module are dropped.
+    /// Test Steps:
+    /// 1. Restrict this module to warnings.
+    /// 2. Log at info and warning level.
+    /// 3. Check that only the warning is passed on.
+    #[test]
+    fn test_module_level_filter_drops_records() {
+        let drain = CountingDrain::default();
+        let levels = BTreeMap::from([("log_filter".to_string(), Level::Warning)]);
+        let reloadable_config = ReloadableConfig::new_shared(&ConfigBuilder::new().build());
+        reloadable_config.write().log_level = Level::Trace;
+        let logger = slog::Logger::root(
+            ModuleLevelFilter::new(drain.clone(), levels, reloadable_config).ignore_res(),
+            slog::o!(),
+        );
+
+        slog::info!(logger, "dropped");
+        assert_eq!(drain.0.load(Ordering::SeqCst), 0);
+        slog::warn!(logger, "passed on");
+        assert_eq!(drain.0.load(Ordering::SeqCst), 1);
+    }
+
+    /// This function tests that records of modules without an override follow the reloadable
+    /// log level.
+    /// Test Steps:
+    /// 1. Set the reloadable log level to warning.
+    /// 2. Log at info level and check that the record is dropped.
+    /// 3. Lower the reloadable log level to info.
+    /// 4. Log at info level and check that the record is passed on.
+    #[test]
+    fn test_module_level_filter_follows_reloadable_level() {
+        let drain = CountingDrain::default();
+        let reloadable_config = ReloadableConfig::new_shared(&ConfigBuilder::new().build());
+        reloadable_config.write().log_level = Level::Warning;
+        let logger = slog::Logger::root(
+            ModuleLevelFilter::new(drain.clone(), BTreeMap::new(), reloadable_config.clone())
+                .ignore_res(),
+            slog::o!(),
+        );
+
+        slog::info!(logger, "dropped");
+        assert_eq!(drain.0.load(Ordering::SeqCst), 0);
+
+        reloadable_config.write().log_level = Level::Info;
+        slog::info!(logger, "passed on");
+        assert_eq!(drain.0.load(Ordering::SeqCst), 1);
+    }
+}
@@ -5,7 +5,7 @@ use logger::{info, new_replica_logger_from_config};
 use metrics::MetricsRegistry;
 use serde_json::to_string_pretty;
 use std::sync::Arc;
-use tokio::sync::{mpsc::channel, Mutex};
+use tokio::sync::{mpsc::channel, oneshot, Mutex};
 
 #[tokio::main]
 pub async fn main() {
@@ -15,13 +15,29 @@ pub async fn main() {
     abort_on_panic();
 
     let cli = Cli::parse();
+    if let Some(network) = cli.generate_config {
+        println!(
+            "{}",
+            to_string_pretty(&Config::default_for(network)).unwrap()
+        );
+        return;
+    }
     let config = match cli.get_config() {
         Ok(config) => config,
         Err(err) => {
             panic!("An error occurred while getting the config: {}", err);
         }
     };
-    let (logger, _async_log_guard) = new_replica_logger_from_config(&config.logger);
+    // The log level is applied by the module level filter, so it can be changed on reload.
+    let reloadable_config = ReloadableConfig::new_shared(&config);
+    let mut logger_config = config.logger.clone();
+    logger_config.level = slog::Level::Trace;
+    let (logger, _async_log_guard) = new_replica_logger_from_config(&logger_config);
+    let logger = with_module_log_levels(
+        logger,
+        config.module_log_levels(),
+        reloadable_config.clone(),
+    );
 
     info!(
         logger,
@@ -32,24 +48,30 @@ pub async fn main() {
     let metrics_registry = MetricsRegistry::global();
 
     // Metrics server should only be started if we are managed by systemd and receive the
-    // metrics socket as FD(4).
+    // metrics socket at the configured index.
     // SAFETY: The process is managed by systemd and is configured to start with at metrics socket.
     // Additionally this function is only called once here.
     if config.incoming_source == IncomingSource::Systemd {
-        let stream = unsafe { incoming_from_nth_systemd_socket(2) };
+        let stream =
+            unsafe { incoming_from_nth_systemd_socket(config.metrics_systemd_socket_index) };
         start_metrics_grpc(metrics_registry.clone(), logger.clone(), stream);
     }
 
-    // TODO: establish what the buffer size should be
-    let (blockchain_manager_tx, blockchain_manager_rx) = channel(10);
+    let (blockchain_manager_tx, blockchain_manager_rx) =
+        channel(config.blockchain_manager_channel_size);
 
-    let adapter_state = AdapterState::new(config.idle_seconds);
+    let adapter_state = AdapterState::new(reloadable_config.clone());
     let blockchain_state = Arc::new(Mutex::new(BlockchainState::new(&config, &metrics_registry)));
-    let get_successors_handler =
-        GetSuccessorsHandler::new(&config, blockchain_state.clone(), blockchain_manager_tx);
+    let get_successors_handler = GetSuccessorsHandler::new(
+        &config,
+        blockchain_state.clone(),
+        blockchain_manager_tx.clone(),
+        logger.clone(),
+        &metrics_registry,
+    );
 
-    // TODO: we should NOT have an unbounded channel for buffering TransactionManagerRequests.
-    let (transaction_manager_tx, transaction_manager_rx) = channel(10);
+    let (transaction_manager_tx, transaction_manager_rx) =
+        channel(config.transaction_manager_channel_size);
 
     spawn_grpc_server(
         config.clone(),
@@ -60,14 +82,30 @@ pub async fn main() {
         &metrics_registry,
     );
 
-    start_router(
+    let (router_shutdown_tx, router_shutdown_rx) = oneshot::channel();
+    let router_handle = start_router(
         &config,
         logger.clone(),
         blockchain_state,
         transaction_manager_rx,
         adapter_state,
+        blockchain_manager_tx,
         blockchain_manager_rx,
+        reloadable_config.clone(),
         &metrics_registry,
+        router_shutdown_rx,
     );
+
+    // Reload the config file on SIGHUP and apply the hot-reloadable fields.
+    tokio::task::spawn(reload_config_on_sighup(
+        cli,
+        config,
+        reloadable_config,
+        logger.clone(),
+    ));
     shutdown_signal(logger.inner_logger.root.clone()).await;
+
+    // Give peers a last chance to fetch the cached transactions before exiting.
+    router_shutdown_tx.send(()).ok();
+    router_handle.await.ok();
 }
@@ -1,13 +1,19 @@
-use metrics::{buckets::linear_buckets, MetricsRegistry};
-use prometheus::{Histogram, IntCounter, IntCounterVec, IntGauge};
+use metrics::{
+    buckets::{decimal_buckets, linear_buckets},
+    MetricsRegistry,
+};
+use prometheus::{Gauge, GaugeVec, Histogram, IntCounter, IntCounterVec, IntGauge};
+use serde::Serialize;
 
 pub(crate) const LABEL_GET_SUCCESSOR: &str = "get_successor";
+pub(crate) const LABEL_PEER: &str = "peer";
 pub(crate) const LABEL_REQUEST_TYPE: &str = "type";
 pub(crate) const LABEL_SEND_TRANSACTION: &str = "send_transaction";
 
 #[derive(Debug, Clone)]
 pub struct ServiceMetrics {
     pub requests: IntCounterVec,
+    pub tx_request_channel_full: IntCounter,
 }
 
 impl ServiceMetrics {
@@ -18,6 +24,10 @@ impl ServiceMetrics {
                 "Requests served by the adapter.",
                 &[LABEL_REQUEST_TYPE],
             ),
+            tx_request_channel_full: metrics_registry.int_counter(
+                "tx_request_channel_full_total",
+                "Number of transaction requests that had to wait because the transaction manager channel was full.",
+            ),
         }
     }
 }
@@ -28,8 +38,16 @@ pub struct RouterMetrics {
     pub bitcoin_messages_sent: IntCounterVec,
     pub bitcoin_messages_received: IntCounterVec,
     pub available_connections: IntGauge,
+    pub peer_connections: IntGauge,
     pub connections: IntCounter,
     pub known_peer_addresses: IntGauge,
+    /// The round-trip time of the last answered `ping` of each connected peer.
+    pub peer_ping_latency: GaugeVec,
+    /// The time between requesting a block from a peer and adding it to the block cache.
+    pub block_download_latency: Histogram,
+    /// The number of requests buffered in the channel to the blockchain manager, sampled on
+    /// every router tick.
+    pub blockchain_manager_channel_occupancy: IntGauge,
 }
 
 impl RouterMetrics {
@@ -51,10 +69,29 @@ impl RouterMetrics {
             ),
             available_connections: metrics_registry
                 .int_gauge("available_connections", "Active bitcoin peer connections."),
+            peer_connections: metrics_registry.int_gauge(
+                "peer_connections",
+                "Current bitcoin peer connections, including those not yet available.",
+            ),
             connections: metrics_registry
                 .int_counter("connection_total", "Connection setup attempts."),
             known_peer_addresses: metrics_registry
                 .int_gauge("known_peer_addresses", "Known peer addresses."),
+            peer_ping_latency: metrics_registry.gauge_vec(
+                "peer_ping_latency_seconds",
+                "Round-trip time of the last answered ping per peer.",
+                &[LABEL_PEER],
+            ),
+            // Buckets from 10ms to 50s.
+            block_download_latency: metrics_registry.histogram(
+                "block_download_latency_seconds",
+                "Time between requesting a block from a peer and adding it to the block cache.",
+                decimal_buckets(-2, 1),
+            ),
+            blockchain_manager_channel_occupancy: metrics_registry.int_gauge(
+                "blockchain_manager_channel_occupancy",
+                "Number of requests buffered in the channel to the blockchain manager.",
+            ),
         }
     }
 }
@@ -64,7 +101,24 @@ pub struct BlockchainStateMetrics {
     pub tip_height: IntGauge,
     pub block_cache_size: IntGauge,
     pub header_cache_size: IntGauge,
+    /// The estimated memory used by the header cache in bytes.
+    pub header_cache_bytes: IntGauge,
     pub tips: IntGauge,
+    /// The number of tips besides the active tip, i.e., the number of side forks.
+    pub active_forks: IntGauge,
+    /// The active tip's cumulative work. The work is a `Uint256` which does not fit into a
+    /// single `f64`, so the gauge only records an approximation as `log2(work)`.
+    pub tip_work_log2: Gauge,
+    /// The number of hashes in the most recently computed block locator of the active chain.
+    pub locator_hashes: IntGauge,
+    /// The number of seconds since a block was last added to the block cache.
+    pub seconds_since_last_block: IntGauge,
+    /// The number of block lookups that found the block in the block cache.
+    pub block_cache_hits: IntCounter,
+    /// The number of block lookups that did not find the block in the block cache.
+    pub block_cache_misses: IntCounter,
+    /// The number of headers rejected because their previous header was not cached.
+    pub headers_out_of_order: IntCounter,
 }
 
 impl BlockchainStateMetrics {
@@ -77,7 +131,93 @@ impl BlockchainStateMetrics {
                 "header_cache_size",
                 "Number of headers stored in the adapter.",
             ),
+            header_cache_bytes: metrics_registry.int_gauge(
+                "header_cache_size_bytes",
+                "Estimated memory used by the headers stored in the adapter.",
+            ),
             tips: metrics_registry.int_gauge("blockchain_tips", "Number of active tips."),
+            active_forks: metrics_registry.int_gauge(
+                "blockchain_active_forks",
+                "Number of tips besides the active tip.",
+            ),
+            tip_work_log2: metrics_registry.gauge(
+                "tip_work_log2",
+                "Approximate cumulative work of the active tip as log2(work).",
+            ),
+            locator_hashes: metrics_registry.int_gauge(
+                "locator_hashes",
+                "Number of hashes in the most recent block locator.",
+            ),
+            seconds_since_last_block: metrics_registry.int_gauge(
+                "seconds_since_last_block",
+                "Seconds since a block was last added to the block cache.",
+            ),
+            block_cache_hits: metrics_registry.int_counter(
+                "block_cache_hits_total",
+                "Number of block lookups served from the block cache.",
+            ),
+            block_cache_misses: metrics_registry.int_counter(
+                "block_cache_misses_total",
+                "Number of block lookups that missed the block cache.",
+            ),
+            headers_out_of_order: metrics_registry.int_counter(
+                "headers_out_of_order_total",
+                "Number of headers received before their previous header.",
+            ),
+        }
+    }
+}
+
+/// The current values of the blockchain state metrics, for embedders that do not run the
+/// metrics server.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BlockchainStateMetricsSnapshot {
+    pub tip_height: i64,
+    pub block_cache_size_bytes: i64,
+    pub header_cache_size: i64,
+    pub header_cache_size_bytes: i64,
+    pub tips: i64,
+    pub active_forks: i64,
+    pub tip_work_log2: f64,
+    pub locator_hashes: i64,
+    pub seconds_since_last_block: i64,
+    pub block_cache_hits: u64,
+    pub block_cache_misses: u64,
+    pub headers_out_of_order: u64,
+}
+
+impl BlockchainStateMetrics {
+    /// Returns the current values of the metrics.
+    pub fn snapshot(&self) -> BlockchainStateMetricsSnapshot {
+        BlockchainStateMetricsSnapshot {
+            tip_height: self.tip_height.get(),
+            block_cache_size_bytes: self.block_cache_size.get(),
+            header_cache_size: self.header_cache_size.get(),
+            header_cache_size_bytes: self.header_cache_bytes.get(),
+            tips: self.tips.get(),
+            active_forks: self.active_forks.get(),
+            tip_work_log2: self.tip_work_log2.get(),
+            locator_hashes: self.locator_hashes.get(),
+            seconds_since_last_block: self.seconds_since_last_block.get(),
+            block_cache_hits: self.block_cache_hits.get(),
+            block_cache_misses: self.block_cache_misses.get(),
+            headers_out_of_order: self.headers_out_of_order.get(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GetSuccessorsMetrics {
+    pub blockchain_manager_requests_dropped: IntCounter,
+}
+
+impl GetSuccessorsMetrics {
+    pub fn new(metrics_registry: &MetricsRegistry) -> Self {
+        Self {
+            blockchain_manager_requests_dropped: metrics_registry.int_counter(
+                "blockchain_manager_requests_dropped_total",
+                "Number of requests to the blockchain manager dropped because the channel was full.",
+            ),
         }
     }
 }
@@ -86,6 +226,11 @@ impl BlockchainStateMetrics {
 pub struct TransactionMetrics {
     pub tx_peer_requests: Histogram,
     pub tx_store_size: IntGauge,
+    pub tx_confirmed: IntCounter,
+    pub tx_reaped_timeout: IntCounter,
+    pub tx_evicted_full: IntCounter,
+    /// The number of transactions advertised to each connected peer.
+    pub tx_advertised: IntCounterVec,
 }
 
 impl TransactionMetrics {
@@ -100,6 +245,45 @@ impl TransactionMetrics {
                 "tx_store_size",
                 "Number of transactions that are stored in the adapter and are made available to peers.",
             ),
+            tx_confirmed: metrics_registry.int_counter(
+                "tx_confirmed_total",
+                "Number of transactions removed from the adapter because they appeared in a block.",
+            ),
+            tx_reaped_timeout: metrics_registry.int_counter(
+                "tx_reaped_timeout_total",
+                "Number of transactions removed from the adapter because they timed out.",
+            ),
+            tx_evicted_full: metrics_registry.int_counter(
+                "tx_evicted_full_total",
+                "Number of transactions evicted from the adapter because the cache was full.",
+            ),
+            tx_advertised: metrics_registry.int_counter_vec(
+                "tx_advertised_total",
+                "Number of transactions advertised to a peer.",
+                &[LABEL_PEER],
+            ),
+        }
+    }
+}
+
+/// The current values of the transaction metrics, for embedders that do not run the metrics
+/// server.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TransactionMetricsSnapshot {
+    pub tx_store_size: i64,
+    pub tx_confirmed: u64,
+    pub tx_reaped_timeout: u64,
+    pub tx_evicted_full: u64,
+}
+
+impl TransactionMetrics {
+    /// Returns the current values of the metrics.
+    pub fn snapshot(&self) -> TransactionMetricsSnapshot {
+        TransactionMetricsSnapshot {
+            tx_store_size: self.tx_store_size.get(),
+            tx_confirmed: self.tx_confirmed.get(),
+            tx_reaped_timeout: self.tx_reaped_timeout.get(),
+            tx_evicted_full: self.tx_evicted_full.get(),
         }
     }
 }
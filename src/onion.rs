@@ -0,0 +1,74 @@
+use std::net::{IpAddr, SocketAddr};
+
+/// The OnionCat prefix `fd87:d87e:eb43::/48` under which Tor onion services are encoded as
+/// IPv6 addresses in `addr` messages.
+const ONION_CAT_PREFIX: [u16; 3] = [0xfd87, 0xd87e, 0xeb43];
+
+/// The number of bytes of an IPv6 address covered by the OnionCat prefix.
+const ONION_CAT_PREFIX_BYTES: usize = 6;
+
+/// This function checks if the address is an onion service encoded as an OnionCat address.
+pub fn is_onion_address(addr: &SocketAddr) -> bool {
+    match addr.ip() {
+        IpAddr::V6(ip) => ip.segments().starts_with(&ONION_CAT_PREFIX),
+        IpAddr::V4(_) => false,
+    }
+}
+
+/// This function returns the `.onion` hostname of an OnionCat address, which is passed to the
+/// SOCKS proxy in place of the IPv6 address. The hostname is the base32 encoding of the
+/// address bits following the prefix.
+pub fn onion_hostname(addr: &SocketAddr) -> Option<String> {
+    let ip = match addr.ip() {
+        IpAddr::V6(ip) if is_onion_address(addr) => ip,
+        _ => return None,
+    };
+
+    let mut hostname = String::new();
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for byte in ip.octets().iter().skip(ONION_CAT_PREFIX_BYTES) {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            let value = ((buffer >> bits) & 0x1f) as u8;
+            hostname.push(char::from(if value < 26 {
+                b'a' + value
+            } else {
+                b'2' + value - 26
+            }));
+        }
+        buffer &= (1 << bits) - 1;
+    }
+    hostname.push_str(".onion");
+    Some(hostname)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    /// This function tests that only addresses within the OnionCat prefix are onion addresses.
+    #[test]
+    fn test_is_onion_address() {
+        let onion = SocketAddr::from_str("[fd87:d87e:eb43:25df:8a67:3cb4:2188:1d2d]:8333").unwrap();
+        assert!(is_onion_address(&onion));
+        let ipv6 = SocketAddr::from_str("[2001:db8::1]:8333").unwrap();
+        assert!(!is_onion_address(&ipv6));
+        let ipv4 = SocketAddr::from_str("127.0.0.1:8333").unwrap();
+        assert!(!is_onion_address(&ipv4));
+    }
+
+    /// This function tests that an OnionCat address is converted into its `.onion` hostname.
+    #[test]
+    fn test_onion_hostname() {
+        let onion = SocketAddr::from_str("[fd87:d87e:eb43:25df:8a67:3cb4:2188:1d2d]:8333").unwrap();
+        assert_eq!(
+            onion_hostname(&onion),
+            Some("expyuzz4wqqyqhjn.onion".to_string())
+        );
+        let ipv6 = SocketAddr::from_str("[2001:db8::1]:8333").unwrap();
+        assert_eq!(onion_hostname(&ipv6), None);
+    }
+}
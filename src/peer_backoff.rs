@@ -0,0 +1,173 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    time::{Duration, SystemTime},
+};
+
+use rand::Rng;
+
+use crate::config::Config;
+
+/// This struct contains the backoff state of a single peer.
+#[derive(Debug)]
+struct BackoffState {
+    /// The number of consecutive failed connections to the peer.
+    failures: u32,
+    /// The delay applied after the last failure, including jitter.
+    delay: Duration,
+    /// The time after which the peer may be dialed again.
+    retry_at: SystemTime,
+}
+
+/// This struct tracks consecutive connection failures per peer and applies an exponential
+/// backoff with jitter before a peer may be dialed again.
+#[derive(Debug)]
+pub struct PeerBackoff {
+    /// This field contains the backoff state of the peers that recently failed.
+    peers: HashMap<SocketAddr, BackoffState>,
+    /// The delay after the first failure.
+    base: Duration,
+    /// The maximum delay.
+    max: Duration,
+}
+
+impl PeerBackoff {
+    /// This function creates a new backoff tracker with the delays from the config.
+    pub fn new(config: &Config) -> Self {
+        Self {
+            peers: HashMap::new(),
+            base: Duration::from_secs(config.peer_backoff_base_secs),
+            max: Duration::from_secs(config.peer_backoff_max_secs),
+        }
+    }
+
+    /// This function records a failed connection to the peer and returns the delay before the
+    /// peer may be dialed again.
+    /// The delay doubles with each consecutive failure up to the maximum delay. A random jitter
+    /// picks the actual delay between half and all of it, so peers do not retry in lockstep.
+    pub fn record_failure(&mut self, addr: &SocketAddr, rng: &mut impl Rng) -> Duration {
+        let failures = self
+            .peers
+            .get(addr)
+            .map_or(0, |state| state.failures)
+            .saturating_add(1);
+        let exponent = failures.saturating_sub(1).min(31);
+        let delay = self.base.saturating_mul(1 << exponent).min(self.max);
+        let delay = delay / 2 + delay.mul_f64(rng.gen_range(0.0..=0.5));
+        self.peers.insert(
+            *addr,
+            BackoffState {
+                failures,
+                delay,
+                retry_at: SystemTime::now() + delay,
+            },
+        );
+        delay
+    }
+
+    /// This function resets the backoff state of the peer after a successful handshake.
+    pub fn record_success(&mut self, addr: &SocketAddr) {
+        self.peers.remove(addr);
+    }
+
+    /// This function returns the current backoff delay of the peer, if it recently failed.
+    pub fn delay(&self, addr: &SocketAddr) -> Option<Duration> {
+        self.peers.get(addr).map(|state| state.delay)
+    }
+
+    /// This function checks if the peer may not be dialed yet.
+    pub fn is_backing_off(&self, addr: &SocketAddr) -> bool {
+        self.peers
+            .get(addr)
+            .map_or(false, |state| SystemTime::now() < state.retry_at)
+    }
+
+    /// This function removes the peers that have not failed again for the maximum delay after
+    /// they could be dialed again, so the map does not grow with every peer that ever failed.
+    pub fn remove_expired(&mut self) {
+        let now = SystemTime::now();
+        let max = self.max;
+        self.peers
+            .retain(|_, state| now < state.retry_at.checked_add(max).unwrap_or(state.retry_at));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::test::ConfigBuilder;
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::str::FromStr;
+
+    /// This function tests that the backoff delay grows with each consecutive failure up to the
+    /// maximum delay.
+    /// Test Steps:
+    /// 1. Record consecutive failures for a peer.
+    /// 2. Check that each delay is at least as large as the previous one and within the jitter bounds.
+    /// 3. Check that the delay is capped at the maximum delay.
+    #[test]
+    fn test_record_failure_grows_delay() {
+        let addr = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let config = ConfigBuilder::new()
+            .with_peer_backoff_base_secs(1)
+            .with_peer_backoff_max_secs(60)
+            .build();
+        let mut backoff = PeerBackoff::new(&config);
+        let mut rng = StdRng::seed_from_u64(42);
+
+        let mut previous = Duration::ZERO;
+        for failures in 0..6 {
+            let delay = backoff.record_failure(&addr, &mut rng);
+            let expected = Duration::from_secs(1 << failures);
+            assert!(delay >= expected / 2 && delay <= expected);
+            assert!(delay >= previous);
+            assert_eq!(backoff.delay(&addr), Some(delay));
+            assert!(backoff.is_backing_off(&addr));
+            previous = delay;
+        }
+
+        for _ in 0..10 {
+            let delay = backoff.record_failure(&addr, &mut rng);
+            assert!(delay >= Duration::from_secs(30) && delay <= Duration::from_secs(60));
+        }
+    }
+
+    /// This function tests that the backoff state is reset after a successful handshake.
+    #[test]
+    fn test_record_success_resets_delay() {
+        let addr = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let config = ConfigBuilder::new()
+            .with_peer_backoff_base_secs(1)
+            .with_peer_backoff_max_secs(60)
+            .build();
+        let mut backoff = PeerBackoff::new(&config);
+        let mut rng = StdRng::seed_from_u64(42);
+
+        for _ in 0..4 {
+            backoff.record_failure(&addr, &mut rng);
+        }
+        backoff.record_success(&addr);
+        assert_eq!(backoff.delay(&addr), None);
+        assert!(!backoff.is_backing_off(&addr));
+
+        let delay = backoff.record_failure(&addr, &mut rng);
+        assert!(delay <= Duration::from_secs(1));
+    }
+
+    /// This function tests that the backoff state of a peer is removed once it expired.
+    #[test]
+    fn test_remove_expired() {
+        let addr = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let config = ConfigBuilder::new()
+            .with_peer_backoff_base_secs(0)
+            .with_peer_backoff_max_secs(0)
+            .build();
+        let mut backoff = PeerBackoff::new(&config);
+        let mut rng = StdRng::seed_from_u64(42);
+
+        backoff.record_failure(&addr, &mut rng);
+        assert_eq!(backoff.delay(&addr), Some(Duration::ZERO));
+        backoff.remove_expired();
+        assert!(backoff.peers.is_empty());
+    }
+}
@@ -0,0 +1,205 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use thiserror::Error;
+
+use crate::config::Config;
+
+/// This enum is used when an entry of the `allowed_peers` or `denied_peers` lists cannot be
+/// parsed.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ParsePeerMatcherError {
+    /// The entry is neither a socket address nor a CIDR range.
+    #[error("Expected a socket address or a CIDR range: {0}")]
+    InvalidAddress(String),
+    /// The prefix length of the CIDR range exceeds the length of the IP address.
+    #[error("Invalid prefix length of the CIDR range: {0}")]
+    InvalidPrefixLength(String),
+}
+
+/// This enum represents an entry of the `allowed_peers` or `denied_peers` lists.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PeerMatcher {
+    /// Matches a single peer, e.g., `192.168.0.1:8333`.
+    Address(SocketAddr),
+    /// Matches all peers within an IP range on any port, e.g., `10.0.0.0/8` or `2001:db8::/32`.
+    Range {
+        /// The IP address of the range.
+        ip: IpAddr,
+        /// The number of leading bits of the IP address that must match.
+        prefix_len: u8,
+    },
+}
+
+impl PeerMatcher {
+    /// This function checks if the peer's address matches the entry.
+    pub fn matches(&self, addr: &SocketAddr) -> bool {
+        match self {
+            PeerMatcher::Address(address) => address == addr,
+            PeerMatcher::Range { ip, prefix_len } => match (ip, addr.ip()) {
+                (IpAddr::V4(ip), IpAddr::V4(other)) => prefix_matches(
+                    u32::from(*ip).into(),
+                    u32::from(other).into(),
+                    32,
+                    *prefix_len,
+                ),
+                (IpAddr::V6(ip), IpAddr::V6(other)) => {
+                    prefix_matches(u128::from(*ip), u128::from(other), 128, *prefix_len)
+                }
+                _ => false,
+            },
+        }
+    }
+}
+
+impl FromStr for PeerMatcher {
+    type Err = ParsePeerMatcherError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = SocketAddr::from_str(s) {
+            return Ok(PeerMatcher::Address(addr));
+        }
+
+        let (ip, prefix_len) = s
+            .split_once('/')
+            .ok_or_else(|| ParsePeerMatcherError::InvalidAddress(s.to_string()))?;
+        let ip = IpAddr::from_str(ip)
+            .map_err(|_| ParsePeerMatcherError::InvalidAddress(s.to_string()))?;
+        let max_prefix_len = if ip.is_ipv4() { 32 } else { 128 };
+        let prefix_len = u8::from_str(prefix_len)
+            .ok()
+            .filter(|prefix_len| *prefix_len <= max_prefix_len)
+            .ok_or_else(|| ParsePeerMatcherError::InvalidPrefixLength(s.to_string()))?;
+        Ok(PeerMatcher::Range { ip, prefix_len })
+    }
+}
+
+/// This function checks if the leading `prefix_len` bits of two addresses of `bits` bits match.
+fn prefix_matches(a: u128, b: u128, bits: u8, prefix_len: u8) -> bool {
+    if prefix_len == 0 {
+        return true;
+    }
+    let shift = u32::from(bits - prefix_len);
+    a >> shift == b >> shift
+}
+
+/// This struct decides which peers the adapter may connect to based on the configured
+/// `allowed_peers` and `denied_peers` lists.
+#[derive(Clone, Debug, Default)]
+pub struct PeerFilter {
+    /// If not empty, only peers matching one of these entries are allowed.
+    allowed: Vec<PeerMatcher>,
+    /// Peers matching one of these entries are never allowed.
+    denied: Vec<PeerMatcher>,
+}
+
+impl PeerFilter {
+    /// This function creates a new peer filter from the config. Entries that cannot be parsed
+    /// are skipped as they are rejected when the config is loaded.
+    pub fn new(config: &Config) -> Self {
+        let parse = |entries: &[String]| {
+            entries
+                .iter()
+                .filter_map(|entry| PeerMatcher::from_str(entry).ok())
+                .collect()
+        };
+        Self {
+            allowed: parse(&config.allowed_peers),
+            denied: parse(&config.denied_peers),
+        }
+    }
+
+    /// This function checks if the adapter may connect to the peer. A peer matching both lists
+    /// is denied.
+    pub fn is_allowed(&self, addr: &SocketAddr) -> bool {
+        if self.denied.iter().any(|matcher| matcher.matches(addr)) {
+            return false;
+        }
+        self.allowed.is_empty() || self.allowed.iter().any(|matcher| matcher.matches(addr))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::test::ConfigBuilder;
+
+    fn addr(s: &str) -> SocketAddr {
+        SocketAddr::from_str(s).expect("invalid address")
+    }
+
+    /// This function tests that socket addresses and CIDR ranges are parsed.
+    #[test]
+    fn test_parse_peer_matcher() {
+        assert_eq!(
+            PeerMatcher::from_str("127.0.0.1:8333"),
+            Ok(PeerMatcher::Address(addr("127.0.0.1:8333")))
+        );
+        assert_eq!(
+            PeerMatcher::from_str("2001:db8::/32"),
+            Ok(PeerMatcher::Range {
+                ip: IpAddr::from_str("2001:db8::").unwrap(),
+                prefix_len: 32,
+            })
+        );
+        assert!(matches!(
+            PeerMatcher::from_str("127.0.0.1"),
+            Err(ParsePeerMatcherError::InvalidAddress(_))
+        ));
+        assert!(matches!(
+            PeerMatcher::from_str("10.0.0.0/33"),
+            Err(ParsePeerMatcherError::InvalidPrefixLength(_))
+        ));
+    }
+
+    /// This function tests that only the allowed peers are allowed if an allowlist is configured.
+    #[test]
+    fn test_allowlist_only() {
+        let config = ConfigBuilder::new()
+            .with_allowed_peers(vec![
+                "127.0.0.1:8333".to_string(),
+                "10.0.0.0/8".to_string(),
+                "2001:db8::/32".to_string(),
+            ])
+            .build();
+        let filter = PeerFilter::new(&config);
+
+        assert!(filter.is_allowed(&addr("127.0.0.1:8333")));
+        assert!(!filter.is_allowed(&addr("127.0.0.1:8334")));
+        assert!(filter.is_allowed(&addr("10.1.2.3:18333")));
+        assert!(!filter.is_allowed(&addr("11.0.0.1:8333")));
+        assert!(filter.is_allowed(&addr("[2001:db8::1]:8333")));
+        assert!(!filter.is_allowed(&addr("[2001:db9::1]:8333")));
+    }
+
+    /// This function tests that all peers but the denied peers are allowed if only a denylist
+    /// is configured.
+    #[test]
+    fn test_denylist_only() {
+        let config = ConfigBuilder::new()
+            .with_denied_peers(vec!["127.0.0.1:8333".to_string(), "10.0.0.0/8".to_string()])
+            .build();
+        let filter = PeerFilter::new(&config);
+
+        assert!(!filter.is_allowed(&addr("127.0.0.1:8333")));
+        assert!(filter.is_allowed(&addr("127.0.0.1:8334")));
+        assert!(!filter.is_allowed(&addr("10.255.0.1:8333")));
+        assert!(filter.is_allowed(&addr("[2001:db8::1]:8333")));
+    }
+
+    /// This function tests that a peer matching both lists is denied.
+    #[test]
+    fn test_peer_matching_both_lists_is_denied() {
+        let config = ConfigBuilder::new()
+            .with_allowed_peers(vec!["10.0.0.0/8".to_string()])
+            .with_denied_peers(vec!["10.0.0.1:8333".to_string()])
+            .build();
+        let filter = PeerFilter::new(&config);
+
+        assert!(!filter.is_allowed(&addr("10.0.0.1:8333")));
+        assert!(filter.is_allowed(&addr("10.0.0.2:8333")));
+        assert!(!filter.is_allowed(&addr("11.0.0.1:8333")));
+    }
+}
@@ -0,0 +1,114 @@
+use std::{collections::HashMap, net::SocketAddr, time::Instant};
+
+/// This struct contains the token bucket of a single peer.
+#[derive(Debug)]
+struct TokenBucket {
+    /// The number of requests the peer may currently make.
+    tokens: f64,
+    /// When the tokens were last refilled.
+    refilled_at: Instant,
+}
+
+/// This struct limits the rate of requests per peer with a token bucket. A peer may make a
+/// burst of requests, after which its requests are limited to the refill rate.
+#[derive(Debug)]
+pub struct PeerRateLimiter {
+    /// This field contains the token buckets of the peers that recently made requests.
+    buckets: HashMap<SocketAddr, TokenBucket>,
+    /// The number of tokens refilled per second.
+    rate_per_sec: f64,
+    /// The maximum number of tokens a bucket holds.
+    burst: f64,
+}
+
+impl PeerRateLimiter {
+    /// This function creates a new rate limiter refilling `rate_per_sec` tokens per second up
+    /// to `burst` tokens.
+    pub fn new(rate_per_sec: u32, burst: u32) -> Self {
+        Self {
+            buckets: HashMap::new(),
+            rate_per_sec: f64::from(rate_per_sec),
+            burst: f64::from(burst),
+        }
+    }
+
+    /// This function takes a token from the peer's bucket. Returns false if the bucket is empty,
+    /// i.e., the peer exceeded the rate.
+    pub fn try_acquire(&mut self, addr: &SocketAddr) -> bool {
+        let now = Instant::now();
+        let bucket = self.buckets.entry(*addr).or_insert(TokenBucket {
+            tokens: self.burst,
+            refilled_at: now,
+        });
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate_per_sec).min(self.burst);
+        bucket.refilled_at = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    /// This function removes the buckets that have been refilled completely, so the state of
+    /// peers that stopped making requests is not kept.
+    pub fn remove_full_buckets(&mut self) {
+        let now = Instant::now();
+        let (rate_per_sec, burst) = (self.rate_per_sec, self.burst);
+        self.buckets.retain(|_, bucket| {
+            let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+            bucket.tokens + elapsed * rate_per_sec < burst
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{str::FromStr, time::Duration};
+
+    /// This function tests that a peer is limited once it used up its burst and that its
+    /// tokens are refilled over time.
+    /// Test Steps:
+    /// 1. Take the burst of tokens and check that the next request is limited.
+    /// 2. Check that other peers are not affected.
+    /// 3. Let time pass and check that the peer may make requests again.
+    #[test]
+    fn test_try_acquire() {
+        let addr = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let other = SocketAddr::from_str("127.0.0.2:8333").expect("invalid address");
+        let mut limiter = PeerRateLimiter::new(1, 3);
+
+        for _ in 0..3 {
+            assert!(limiter.try_acquire(&addr));
+        }
+        assert!(!limiter.try_acquire(&addr));
+        assert!(limiter.try_acquire(&other));
+
+        let bucket = limiter.buckets.get_mut(&addr).expect("bucket should exist");
+        bucket.refilled_at = Instant::now() - Duration::from_secs(2);
+        assert!(limiter.try_acquire(&addr));
+        assert!(limiter.try_acquire(&addr));
+        assert!(!limiter.try_acquire(&addr));
+    }
+
+    /// This function tests that only the buckets of peers that stopped making requests are
+    /// removed.
+    #[test]
+    fn test_remove_full_buckets() {
+        let addr = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let other = SocketAddr::from_str("127.0.0.2:8333").expect("invalid address");
+        let mut limiter = PeerRateLimiter::new(1, 3);
+
+        assert!(limiter.try_acquire(&addr));
+        assert!(limiter.try_acquire(&other));
+        limiter
+            .buckets
+            .get_mut(&addr)
+            .expect("bucket should exist")
+            .refilled_at = Instant::now() - Duration::from_secs(60);
+        limiter.remove_full_buckets();
+        assert!(!limiter.buckets.contains_key(&addr));
+        assert!(limiter.buckets.contains_key(&other));
+    }
+}
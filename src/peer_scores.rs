@@ -0,0 +1,135 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    time::{Duration, SystemTime},
+};
+
+use crate::config::Config;
+
+/// This enum represents the kinds of misbehavior a peer may be penalized for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Misbehavior {
+    /// The peer sent a header that failed validation.
+    InvalidHeader,
+    /// The peer requested more items in a `getdata` message than allowed.
+    TooManyGetDataItems,
+    /// The peer sent `getdata` messages faster than allowed.
+    GetDataRateExceeded,
+}
+
+impl Misbehavior {
+    /// This function returns the score a peer accrues for the misbehavior.
+    pub fn score(&self) -> u32 {
+        match self {
+            Misbehavior::InvalidHeader => 50,
+            Misbehavior::TooManyGetDataItems => 20,
+            Misbehavior::GetDataRateExceeded => 5,
+        }
+    }
+}
+
+/// This struct tracks the misbehavior scores of peers and bans peers whose score
+/// reaches the configured threshold.
+#[derive(Debug)]
+pub struct PeerScores {
+    /// This field contains the accrued misbehavior score of each peer.
+    scores: HashMap<SocketAddr, u32>,
+    /// This field contains the banned peers and when their ban expires.
+    banned_until: HashMap<SocketAddr, SystemTime>,
+    /// The score at which a peer is banned.
+    ban_threshold: u32,
+    /// How long a peer stays banned.
+    ban_duration: Duration,
+}
+
+impl PeerScores {
+    /// This function creates a new peer scores tracker with the thresholds from the config.
+    pub fn new(config: &Config) -> Self {
+        Self {
+            scores: HashMap::new(),
+            banned_until: HashMap::new(),
+            ban_threshold: config.peer_ban_threshold,
+            ban_duration: Duration::from_secs(config.peer_ban_duration_secs),
+        }
+    }
+
+    /// This function adds the score of the misbehavior to the peer's score.
+    /// If the score reaches the ban threshold, the peer is banned and its score is reset.
+    /// Returns true if the peer has been banned.
+    pub fn record(&mut self, addr: &SocketAddr, misbehavior: Misbehavior) -> bool {
+        let score = self.scores.entry(*addr).or_insert(0);
+        *score = score.saturating_add(misbehavior.score());
+        if *score < self.ban_threshold {
+            return false;
+        }
+
+        self.scores.remove(addr);
+        self.banned_until
+            .insert(*addr, SystemTime::now() + self.ban_duration);
+        true
+    }
+
+    /// This function returns the current misbehavior score of the peer.
+    pub fn score(&self, addr: &SocketAddr) -> u32 {
+        self.scores.get(addr).copied().unwrap_or(0)
+    }
+
+    /// This function checks if the peer is currently banned.
+    pub fn is_banned(&self, addr: &SocketAddr) -> bool {
+        self.banned_until
+            .get(addr)
+            .map_or(false, |until| SystemTime::now() < *until)
+    }
+
+    /// This function removes the bans that have expired.
+    pub fn remove_expired_bans(&mut self) {
+        let now = SystemTime::now();
+        self.banned_until.retain(|_, until| now < *until);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::test::ConfigBuilder;
+    use std::str::FromStr;
+
+    /// This function tests that a peer is banned once repeated misbehavior pushes its score
+    /// over the threshold.
+    /// Test Steps:
+    /// 1. Record misbehavior below the threshold and check the peer is not banned.
+    /// 2. Record misbehavior reaching the threshold and check the peer is banned.
+    /// 3. Check that other peers are not affected.
+    #[test]
+    fn test_record_bans_peer_over_threshold() {
+        let addr = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let other = SocketAddr::from_str("127.0.0.2:8333").expect("invalid address");
+        let config = ConfigBuilder::new().with_peer_ban_threshold(100).build();
+        let mut peer_scores = PeerScores::new(&config);
+
+        assert!(!peer_scores.record(&addr, Misbehavior::InvalidHeader));
+        assert!(!peer_scores.record(&addr, Misbehavior::TooManyGetDataItems));
+        assert_eq!(peer_scores.score(&addr), 70);
+        assert!(!peer_scores.is_banned(&addr));
+
+        assert!(peer_scores.record(&addr, Misbehavior::InvalidHeader));
+        assert!(peer_scores.is_banned(&addr));
+        assert!(!peer_scores.is_banned(&other));
+    }
+
+    /// This function tests that bans expire after the configured ban duration.
+    #[test]
+    fn test_remove_expired_bans() {
+        let addr = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let config = ConfigBuilder::new()
+            .with_peer_ban_threshold(1)
+            .with_peer_ban_duration_secs(0)
+            .build();
+        let mut peer_scores = PeerScores::new(&config);
+
+        assert!(peer_scores.record(&addr, Misbehavior::InvalidHeader));
+        assert!(!peer_scores.is_banned(&addr));
+        peer_scores.remove_expired_bans();
+        assert!(peer_scores.banned_until.is_empty());
+    }
+}
@@ -0,0 +1,242 @@
+use crate::{cli::Cli, config::Config};
+use logger::{error, info, warn, ReplicaLogger};
+use parking_lot::RwLock;
+use serde_json::{Map, Value};
+use slog::Level;
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
+
+/// The config fields that can be changed while the adapter is running.
+const RELOADABLE_FIELDS: [&str; 2] = ["idle_seconds", "tx_cache_max_entries"];
+
+/// The config field containing the logger config, of which only the level can be reloaded.
+const LOGGER_FIELD: &str = "logger";
+
+/// The field of the logger config containing the log level.
+const LOG_LEVEL_FIELD: &str = "level";
+
+/// This type is used to share the reloadable config between the running components.
+pub type SharedReloadableConfig = Arc<RwLock<ReloadableConfig>>;
+
+/// This struct contains the subset of the config that can be changed while the adapter is running.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReloadableConfig {
+    /// The number of seconds that need to pass for the adapter to enter the
+    /// `Idle` state.
+    pub idle_seconds: u64,
+    /// The maximum number of transactions the adapter holds in its transaction cache.
+    pub tx_cache_max_entries: usize,
+    /// The log level of the modules without a module-specific log level.
+    pub log_level: Level,
+}
+
+impl ReloadableConfig {
+    /// This function creates a shared reloadable config from the provided config.
+    pub fn new_shared(config: &Config) -> SharedReloadableConfig {
+        Arc::new(RwLock::new(Self::from(config)))
+    }
+}
+
+impl From<&Config> for ReloadableConfig {
+    fn from(config: &Config) -> Self {
+        Self {
+            idle_seconds: config.idle_seconds,
+            tx_cache_max_entries: config.tx_cache_max_entries,
+            log_level: config.logger.level,
+        }
+    }
+}
+
+/// This struct describes the outcome of a config reload.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ConfigReload {
+    /// The fields that changed and have been applied.
+    pub applied: Vec<String>,
+    /// The fields that changed but require a restart to take effect.
+    pub ignored: Vec<String>,
+}
+
+/// This function applies the reloadable fields of `new_config` to `config` and to the
+/// shared reloadable config. Changes to any other field are reported as ignored.
+pub fn reload_config(
+    config: &mut Config,
+    new_config: &Config,
+    reloadable_config: &RwLock<ReloadableConfig>,
+) -> ConfigReload {
+    let current_value = serde_json::to_value(&*config).expect("Config should be serializable");
+    let new_value = serde_json::to_value(new_config).expect("Config should be serializable");
+
+    let mut reload = ConfigReload::default();
+    if let (Some(current_fields), Some(new_fields)) =
+        (current_value.as_object(), new_value.as_object())
+    {
+        for (field, new_field_value) in new_fields {
+            if current_fields.get(field) == Some(new_field_value) {
+                continue;
+            }
+            if field == LOGGER_FIELD {
+                diff_logger_config(current_fields.get(field), new_field_value, &mut reload);
+            } else if RELOADABLE_FIELDS.contains(&field.as_str()) {
+                reload.applied.push(field.clone());
+            } else {
+                reload.ignored.push(field.clone());
+            }
+        }
+    }
+    reload.applied.sort();
+    reload.ignored.sort();
+
+    config.idle_seconds = new_config.idle_seconds;
+    config.tx_cache_max_entries = new_config.tx_cache_max_entries;
+    config.logger.level = new_config.logger.level;
+    *reloadable_config.write() = ReloadableConfig::from(&*config);
+    reload
+}
+
+/// This function reports a changed log level as `logger.level` in the applied fields and
+/// changes to any other logger field as `logger` in the ignored fields.
+fn diff_logger_config(current: Option<&Value>, new: &Value, reload: &mut ConfigReload) {
+    let level = |value: Option<&Value>| value.and_then(|value| value.get(LOG_LEVEL_FIELD));
+    let other_fields = |value: Option<&Value>| {
+        value.and_then(Value::as_object).map(|fields| {
+            let mut fields: Map<String, Value> = fields.clone();
+            fields.remove(LOG_LEVEL_FIELD);
+            fields
+        })
+    };
+
+    if level(current) != level(Some(new)) {
+        reload
+            .applied
+            .push(format!("{}.{}", LOGGER_FIELD, LOG_LEVEL_FIELD));
+    }
+    if other_fields(current) != other_fields(Some(new)) {
+        reload.ignored.push(LOGGER_FIELD.to_string());
+    }
+}
+
+/// This function waits for SIGHUP signals and reloads the config file provided through the `Cli`
+/// each time one is received. Only the reloadable fields are applied to the running components.
+/// A config read from stdin cannot be read again, so reloads are rejected in that case.
+pub async fn reload_config_on_sighup(
+    cli: Cli,
+    mut config: Config,
+    reloadable_config: SharedReloadableConfig,
+    logger: ReplicaLogger,
+) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(err) => {
+            error!(logger, "Failed to install the SIGHUP handler: {}", err);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        if cli.reads_config_from_stdin() {
+            warn!(
+                logger,
+                "Received SIGHUP, but the config was read from stdin and cannot be reloaded"
+            );
+            continue;
+        }
+        info!(logger, "Received SIGHUP, reloading the config");
+        let new_config = match cli.get_config() {
+            Ok(new_config) => new_config,
+            Err(err) => {
+                warn!(logger, "Failed to reload the config: {}", err);
+                continue;
+            }
+        };
+
+        let reload = reload_config(&mut config, &new_config, &reloadable_config);
+        info!(
+            logger,
+            "Applied changed config fields: {:?}", reload.applied
+        );
+        if !reload.ignored.is_empty() {
+            warn!(
+                logger,
+                "Ignored changed config fields, a restart is required to apply them: {:?}",
+                reload.ignored
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::test::ConfigBuilder;
+    use bitcoin::Network;
+
+    /// This function tests that only the reloadable fields are applied when reloading the config.
+    /// Test Steps:
+    /// 1. Create a config and a shared reloadable config.
+    /// 2. Reload a config that changes reloadable and non-reloadable fields.
+    /// 3. Check that the reloadable fields are applied and the others are reported as ignored.
+    #[test]
+    fn test_reload_config() {
+        let mut config = ConfigBuilder::new().with_network(Network::Bitcoin).build();
+        let reloadable_config = ReloadableConfig::new_shared(&config);
+
+        let mut new_config = ConfigBuilder::new()
+            .with_network(Network::Testnet)
+            .with_tx_cache_max_entries(10)
+            .build();
+        new_config.idle_seconds = 10;
+
+        let reload = reload_config(&mut config, &new_config, &reloadable_config);
+        assert_eq!(
+            reload,
+            ConfigReload {
+                applied: vec![
+                    "idle_seconds".to_string(),
+                    "tx_cache_max_entries".to_string()
+                ],
+                ignored: vec!["network".to_string()],
+            }
+        );
+        assert_eq!(
+            *reloadable_config.read(),
+            ReloadableConfig {
+                idle_seconds: 10,
+                tx_cache_max_entries: 10,
+                log_level: config.logger.level,
+            }
+        );
+        assert_eq!(config.idle_seconds, 10);
+        assert_eq!(config.tx_cache_max_entries, 10);
+        assert_eq!(config.network, Network::Bitcoin);
+
+        // Reloading the same config again reports the ignored fields, since they were never applied.
+        let reload = reload_config(&mut config, &new_config, &reloadable_config);
+        assert!(reload.applied.is_empty());
+        assert_eq!(reload.ignored, vec!["network".to_string()]);
+    }
+
+    /// This function tests that the log level is the only reloadable field of the logger config.
+    /// Test Steps:
+    /// 1. Change the log level and reload the config.
+    /// 2. Check that the log level is applied.
+    /// 3. Change another logger field and reload the config.
+    /// 4. Check that the change is ignored.
+    #[test]
+    fn test_reload_config_log_level() {
+        let mut config = ConfigBuilder::new().build();
+        let reloadable_config = ReloadableConfig::new_shared(&config);
+        let mut new_config = config.clone();
+        new_config.logger.level = Level::Trace;
+
+        let reload = reload_config(&mut config, &new_config, &reloadable_config);
+        assert_eq!(reload.applied, vec!["logger.level".to_string()]);
+        assert!(reload.ignored.is_empty());
+        assert_eq!(reloadable_config.read().log_level, Level::Trace);
+        assert_eq!(config.logger.level, Level::Trace);
+
+        new_config.logger.node_id += 1;
+        let reload = reload_config(&mut config, &new_config, &reloadable_config);
+        assert!(reload.applied.is_empty());
+        assert_eq!(reload.ignored, vec!["logger".to_string()]);
+    }
+}
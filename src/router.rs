@@ -5,7 +5,7 @@ use crate::{
     connectionmanager::ConnectionManager, metrics::RouterMetrics, stream::handle_stream,
     transaction_manager::TransactionManager, AdapterState, BlockchainManagerRequest,
     BlockchainState, Channel, ProcessBitcoinNetworkMessage, ProcessBitcoinNetworkMessageError,
-    ProcessEvent, TransactionManagerRequest,
+    ProcessEvent, SharedReloadableConfig, TransactionManagerRequest,
 };
 use bitcoin::network::message::NetworkMessage;
 use logger::ReplicaLogger;
@@ -15,34 +15,45 @@ use std::sync::Arc;
 use std::time::Duration;
 use tokio::{
     sync::{
-        mpsc::{channel, Receiver},
-        Mutex,
+        mpsc::{channel, Receiver, Sender},
+        oneshot, Mutex,
     },
-    time::{interval, sleep},
+    task::JoinHandle,
+    time::interval,
 };
 
+/// The maximum time spent serving `getdata` requests for cached transactions on shutdown.
+const SHUTDOWN_DRAIN_DEADLINE: Duration = Duration::from_secs(5);
+
 /// The function starts a Tokio task that awaits messages from the ConnectionManager.
 /// After receiving a message, it is dispached to _all_ relevant components for processing.
 /// Having a design where we have a separate task that awaits on messages from the
 /// ConnectionManager, we keep the ConnectionManager free of dependencies like the
 /// TransactionManager or the BlockchainManager.
+/// When `shutdown` receives a signal, the cached transactions are drained and the task ends.
+/// `blockchain_manager_tx` is only used to sample the occupancy of the channel to the
+/// blockchain manager.
 pub fn start_router(
     config: &Config,
     logger: ReplicaLogger,
     blockchain_state: Arc<Mutex<BlockchainState>>,
     mut transaction_manager_rx: Receiver<TransactionManagerRequest>,
     adapter_state: AdapterState,
+    blockchain_manager_tx: Sender<BlockchainManagerRequest>,
     mut blockchain_manager_rx: Receiver<BlockchainManagerRequest>,
+    reloadable_config: SharedReloadableConfig,
     metrics_registry: &MetricsRegistry,
-) {
+    mut shutdown: oneshot::Receiver<()>,
+) -> JoinHandle<()> {
     let (network_message_sender, mut network_message_receiver) =
         channel::<(SocketAddr, NetworkMessage)>(DEFAULT_CHANNEL_BUFFER_SIZE);
 
     let router_metrics = RouterMetrics::new(metrics_registry);
+    let blockchain_manager_channel_size = config.blockchain_manager_channel_size;
 
     let mut blockchain_manager =
         BlockchainManager::new(blockchain_state, logger.clone(), router_metrics.clone());
-    let mut transaction_manager = TransactionManager::new(logger.clone(), metrics_registry);
+    let mut transaction_manager = TransactionManager::new(config, logger.clone(), metrics_registry);
     let mut connection_manager = ConnectionManager::new(
         config,
         logger,
@@ -50,17 +61,27 @@ pub fn start_router(
         router_metrics.clone(),
     );
 
+    // The idle state is watched, so the managers react as soon as the adapter becomes idle
+    // or active.
+    let mut idle = adapter_state.subscribe();
+    tokio::task::spawn(adapter_state.publish_idle_transitions());
+
     tokio::task::spawn(async move {
         let mut tick_interval = interval(Duration::from_millis(100));
         loop {
-            let sleep_idle_interval = Duration::from_millis(100);
-            if adapter_state.is_idle() {
-                connection_manager.make_idle();
-                blockchain_manager.make_idle().await;
-                transaction_manager.make_idle();
-                // TODO: instead of sleeping here add some async synchonization.
-                sleep(sleep_idle_interval).await;
-                continue;
+            if *idle.borrow() {
+                clear_idle_state(
+                    &mut connection_manager,
+                    &mut blockchain_manager,
+                    &mut transaction_manager,
+                )
+                .await;
+                tokio::select! {
+                    // The sender is owned by the adapter state, which lives as long as the task publishing the transitions.
+                    _ = idle.changed() => continue,
+                    // The transaction cache is already empty when idle, so there is nothing to drain.
+                    _ = &mut shutdown => break,
+                }
             }
 
             // We do a select over tokio::sync::mpsc::Receiver::recv, tokio::sync::mpsc::UnboundedReceiver::recv,
@@ -84,11 +105,29 @@ pub fn start_router(
                         connection_manager.discard(&address);
                     }
 
-                    if let Err(ProcessBitcoinNetworkMessageError::InvalidMessage) = blockchain_manager.process_bitcoin_network_message(&mut connection_manager, address, &message).await {
-                        connection_manager.discard(&address);
+                    match blockchain_manager.process_bitcoin_network_message(&mut connection_manager, address, &message).await {
+                        Err(err) => {
+                            if let Some(misbehavior) = err.misbehavior() {
+                                connection_manager.record_misbehavior(&address, misbehavior);
+                            }
+                            connection_manager.discard(&address);
+                        }
+                        Ok(()) => {
+                            // The block was successfully added to the blockchain state. Its transactions
+                            // no longer need to be advertised.
+                            if let NetworkMessage::Block(block) = &message {
+                                transaction_manager.notify_block_confirmed(block);
+                            }
+                        }
                     }
-                    if let Err(ProcessBitcoinNetworkMessageError::InvalidMessage) = transaction_manager.process_bitcoin_network_message(&mut connection_manager, address, &message) {
-                        connection_manager.discard(&address);
+                    if let Err(err) = transaction_manager.process_bitcoin_network_message(&mut connection_manager, address, &message) {
+                        if let Some(misbehavior) = err.misbehavior() {
+                            connection_manager.record_misbehavior(&address, misbehavior);
+                        }
+                        // A rate limited peer is only disconnected once its misbehavior gets it banned.
+                        if err.disconnects() {
+                            connection_manager.discard(&address);
+                        }
                     }
                 },
                 result = blockchain_manager_rx.recv() => {
@@ -104,18 +143,116 @@ pub fn start_router(
                 }
                 transaction_manager_request = transaction_manager_rx.recv() => {
                     match transaction_manager_request.unwrap() {
-                        TransactionManagerRequest::SendTransaction(transaction) => transaction_manager.send_transaction(&transaction),
+                        TransactionManagerRequest::SendTransaction(transaction, response_sender) => {
+                            // The requester may have gone away in the meantime, so the result is allowed to be dropped.
+                            response_sender.send(transaction_manager.send_transaction(&transaction)).ok();
+                        }
                     }
                 },
                 _ = tick_interval.tick() => {
                     // After an event is dispatched, the managers `tick` method is called to process possible
                     // outgoing messages.
                     connection_manager.tick(blockchain_manager.get_height().await, handle_stream);
+                    for address in connection_manager.take_disconnected_peers() {
+                        transaction_manager.remove_peer(&address);
+                    }
                     blockchain_manager
                         .tick(&mut connection_manager).await;
+                    transaction_manager
+                        .set_tx_cache_max_entries(reloadable_config.read().tx_cache_max_entries);
                     transaction_manager.tick(&mut connection_manager);
+                    router_metrics.blockchain_manager_channel_occupancy.set(
+                        blockchain_manager_channel_size
+                            .saturating_sub(blockchain_manager_tx.capacity()) as i64,
+                    );
+                }
+                _ = idle.changed() => {
+                    // The adapter became idle. The idle state is cleared at the top of the loop.
+                }
+                _ = &mut shutdown => {
+                    transaction_manager
+                        .drain_and_advertise(&mut connection_manager, &mut network_message_receiver, SHUTDOWN_DRAIN_DEADLINE)
+                        .await;
+                    if let Some(save) = connection_manager.save_addresses() {
+                        save.await.ok();
+                    }
+                    break;
                 }
             };
         }
-    });
+    })
+}
+
+/// Frees the state the managers hold while the adapter is idle: the connections, the block
+/// cache and the cached transactions. It is called once on every transition to idle, as the
+/// router only wakes up again once the idle state changes.
+async fn clear_idle_state(
+    connection_manager: &mut ConnectionManager,
+    blockchain_manager: &mut BlockchainManager,
+    transaction_manager: &mut TransactionManager,
+) {
+    connection_manager.make_idle();
+    blockchain_manager.make_idle().await;
+    transaction_manager.make_idle();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{common::test_common::block_1, config::test::ConfigBuilder};
+    use bitcoin::{blockdata::constants::genesis_block, consensus::serialize, Network};
+    use logger::replica_logger::no_op_logger;
+
+    /// This function tests that both the block cache and the transaction cache are cleared
+    /// when the adapter becomes idle.
+    /// Test Steps:
+    /// 1. Add a block to the blockchain state and a transaction to the transaction manager.
+    /// 2. Clear the idle state.
+    /// 3. Check that the block and the transaction are no longer cached.
+    #[tokio::test]
+    async fn test_clear_idle_state_clears_caches() {
+        let config = ConfigBuilder::new().build();
+        let metrics_registry = MetricsRegistry::default();
+        let router_metrics = RouterMetrics::new(&metrics_registry);
+        let blockchain_state =
+            Arc::new(Mutex::new(BlockchainState::new(&config, &metrics_registry)));
+        let mut blockchain_manager = BlockchainManager::new(
+            blockchain_state.clone(),
+            no_op_logger(),
+            router_metrics.clone(),
+        );
+        let mut transaction_manager =
+            TransactionManager::new(&config, no_op_logger(), &metrics_registry);
+        let (network_message_sender, _network_message_receiver) =
+            channel(DEFAULT_CHANNEL_BUFFER_SIZE);
+        let mut connection_manager = ConnectionManager::new(
+            &config,
+            no_op_logger(),
+            network_message_sender,
+            router_metrics,
+        );
+
+        let block = block_1();
+        let block_hash = block.block_hash();
+        blockchain_state
+            .lock()
+            .await
+            .add_block(block)
+            .expect("should be able to add block 1");
+        let transaction = genesis_block(Network::Regtest).txdata[0].clone();
+        let txid = transaction_manager
+            .send_transaction(&serialize(&transaction))
+            .expect("should be able to send the transaction");
+        assert!(transaction_manager.transaction_age(&txid).is_some());
+
+        clear_idle_state(
+            &mut connection_manager,
+            &mut blockchain_manager,
+            &mut transaction_manager,
+        )
+        .await;
+
+        assert!(!blockchain_state.lock().await.contains_block(&block_hash));
+        assert!(transaction_manager.transaction_age(&txid).is_none());
+    }
 }
@@ -5,7 +5,7 @@ use crate::{
     AdapterState, GetSuccessorsHandler, TransactionManagerRequest,
 };
 use bitcoin::{consensus::Encodable, hashes::Hash, BlockHash};
-use async_utils::{incoming_from_first_systemd_socket, incoming_from_path};
+use async_utils::{incoming_from_nth_systemd_socket, incoming_from_path};
 use btc_service::{
     btc_service_server::{BtcService, BtcServiceServer},
     BtcServiceGetSuccessorsRequest, BtcServiceGetSuccessorsResponse,
@@ -15,7 +15,10 @@ use logger::{debug, ReplicaLogger};
 use metrics::MetricsRegistry;
 use std::convert::{TryFrom, TryInto};
 use std::sync::mpsc::Sender;
-use tokio::sync::mpsc::Sender;
+use tokio::sync::{
+    mpsc::{error::TrySendError, Sender},
+    oneshot,
+};
 use tonic::{transport::Server, Request, Response, Status};
 
 struct BtcServiceImpl {
@@ -45,6 +48,7 @@ impl TryFrom<BtcServiceGetSuccessorsRequest> for GetSuccessorsRequest {
         Ok(GetSuccessorsRequest {
             anchor,
             processed_block_hashes,
+            stop_hash: None,
         })
     }
 }
@@ -73,6 +77,29 @@ impl TryFrom<GetSuccessorsResponse> for BtcServiceGetSuccessorsResponse {
     }
 }
 
+impl BtcServiceImpl {
+    /// Sends a request to the transaction manager. If the channel is full, the call waits
+    /// until the router frees buffer space, which applies backpressure to the gRPC caller
+    /// rather than dropping the transaction.
+    async fn send_to_transaction_manager(&self, request: TransactionManagerRequest) {
+        let request = match self.transaction_manager_tx.try_send(request) {
+            Ok(()) => return,
+            Err(TrySendError::Full(request)) => {
+                self.metrics.tx_request_channel_full.inc();
+                debug!(
+                    self.logger,
+                    "The transaction manager channel is full, waiting for buffer space"
+                );
+                request
+            }
+            Err(TrySendError::Closed(request)) => request,
+        };
+        self.transaction_manager_tx.send(request).await.expect(
+            "Sending should not fail because we never close the receiving part of the channel.",
+        );
+    }
+}
+
 #[tonic::async_trait]
 impl BtcService for BtcServiceImpl {
     async fn get_successors(
@@ -110,13 +137,22 @@ impl BtcService for BtcServiceImpl {
             .requests
             .with_label_values(&[LABEL_SEND_TRANSACTION])
             .inc();
-        self.transaction_manager_tx
-            .send(TransactionManagerRequest::SendTransaction(transaction))
-            .await
-            .expect(
-                "Sending should not fail because we never close the receiving part of the channel.",
-            );
-        Ok(Response::new(BtcServiceSendTransactionResponse {}))
+        let (response_tx, response_rx) = oneshot::channel();
+        self.send_to_transaction_manager(TransactionManagerRequest::SendTransaction(
+            transaction,
+            response_tx,
+        ))
+        .await;
+        match response_rx.await {
+            Ok(Ok(txid)) => {
+                debug!(self.logger, "Accepted transaction {}", txid);
+                Ok(Response::new(BtcServiceSendTransactionResponse {}))
+            }
+            Ok(Err(err)) => Err(Status::invalid_argument(err.to_string())),
+            Err(_) => Err(Status::internal(
+                "The transaction manager dropped the request.",
+            )),
+        }
     }
 }
 
@@ -136,6 +172,7 @@ pub fn spawn_grpc_server(
         logger,
         metrics: ServiceMetrics::new(metrics_registry),
     };
+    let rpc_systemd_socket_index = config.rpc_systemd_socket_index;
     tokio::spawn(async move {
         match config.incoming_source {
             IncomingSource::Path(uds_path) => {
@@ -148,12 +185,91 @@ pub fn spawn_grpc_server(
             IncomingSource::Systemd => {
                 Server::builder()
                     .add_service(BtcServiceServer::new(btc_adapter_impl))
-                    // SAFETY: The process is managed by systemd and is configured to start with at least one socket.
-                    // Additionally this function is only called once here.
-                    .serve_with_incoming(unsafe { incoming_from_first_systemd_socket() })
+                    // SAFETY: The process is managed by systemd and is configured to start with the
+                    // socket at the configured index. Additionally this function is only called once here.
+                    .serve_with_incoming(unsafe {
+                        incoming_from_nth_systemd_socket(rpc_systemd_socket_index)
+                    })
                     .await
                     .expect("gRPC server crashed");
             }
         };
     });
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{config::test::ConfigBuilder, BlockchainState, ReloadableConfig};
+    use bitcoin::{Network, Txid};
+    use logger::replica_logger::no_op_logger;
+    use std::{sync::Arc, time::Duration};
+    use tokio::{
+        sync::{mpsc::channel, Mutex},
+        time::timeout,
+    };
+
+    /// This function tests that transaction requests wait for buffer space while the
+    /// transaction manager channel is full instead of being dropped.
+    /// Test Steps:
+    /// 1. Fill the transaction manager channel.
+    /// 2. Check that a further request waits and is counted as having hit a full channel.
+    /// 3. Receive a request and check that the waiting request is delivered.
+    #[tokio::test]
+    async fn test_send_to_transaction_manager_applies_backpressure() {
+        let config = ConfigBuilder::new()
+            .with_network(Network::Regtest)
+            .with_transaction_manager_channel_size(1)
+            .build();
+        let metrics_registry = MetricsRegistry::default();
+        let blockchain_state = BlockchainState::new(&config, &metrics_registry);
+        let (blockchain_manager_tx, _blockchain_manager_rx) = channel(10);
+        let (transaction_manager_tx, mut transaction_manager_rx) =
+            channel(config.transaction_manager_channel_size);
+        let service = Arc::new(BtcServiceImpl {
+            adapter_state: AdapterState::new(ReloadableConfig::new_shared(&config)),
+            get_successors_handler: GetSuccessorsHandler::new(
+                &config,
+                Arc::new(Mutex::new(blockchain_state)),
+                blockchain_manager_tx,
+                no_op_logger(),
+                &metrics_registry,
+            ),
+            transaction_manager_tx,
+            logger: no_op_logger(),
+            metrics: ServiceMetrics::new(&metrics_registry),
+        });
+
+        let (response_tx, _response_rx) = oneshot::channel::<Result<Txid, _>>();
+        service
+            .send_to_transaction_manager(TransactionManagerRequest::SendTransaction(
+                vec![1],
+                response_tx,
+            ))
+            .await;
+        assert_eq!(service.metrics.tx_request_channel_full.get(), 0);
+
+        let waiting_service = service.clone();
+        let (response_tx, _response_rx) = oneshot::channel::<Result<Txid, _>>();
+        let mut waiting = tokio::spawn(async move {
+            waiting_service
+                .send_to_transaction_manager(TransactionManagerRequest::SendTransaction(
+                    vec![2],
+                    response_tx,
+                ))
+                .await;
+        });
+        assert!(timeout(Duration::from_millis(100), &mut waiting)
+            .await
+            .is_err());
+        assert_eq!(service.metrics.tx_request_channel_full.get(), 1);
+
+        let TransactionManagerRequest::SendTransaction(transaction, _) =
+            transaction_manager_rx.recv().await.unwrap();
+        assert_eq!(transaction, vec![1]);
+        waiting.await.unwrap();
+        let TransactionManagerRequest::SendTransaction(transaction, _) =
+            transaction_manager_rx.recv().await.unwrap();
+        assert_eq!(transaction, vec![2]);
+    }
+}
@@ -17,7 +17,9 @@ use tokio::{
     sync::mpsc::{Sender, UnboundedReceiver},
     time::{sleep, timeout},
 };
-use tokio_socks::{tcp::Socks5Stream, Error as SocksError};
+use tokio_socks::{tcp::Socks5Stream, Error as SocksError, TargetAddr};
+
+use crate::onion::onion_hostname;
 
 /// This provides a default amount of time to wait before a timeout occurs while
 /// attempting to connect to a BTC node.
@@ -56,6 +58,10 @@ pub enum StreamError {
     /// This .
     #[error("Received message exceeds maximum allowed size.")]
     TooLarge,
+    /// This variant is used to indicate that a direct connection was attempted while the
+    /// adapter may only connect through the SOCKS proxy.
+    #[error("Direct connections are not allowed in proxy-only mode.")]
+    DirectConnectionNotAllowed,
 }
 
 /// This type is a wrapper for results that contain StreamError.
@@ -76,6 +82,10 @@ pub struct StreamConfig {
     /// This field represents the address that the stream may use to proxy
     /// requests to the address field.
     pub socks_proxy: Option<String>,
+    /// This field determines whether the stream may only connect through the SOCKS proxy.
+    pub proxy_only: bool,
+    /// This field contains the time a connection attempt through the SOCKS proxy may take.
+    pub socks_connect_timeout: Duration,
     /// This field is used to send events from the stream back to the network and connection structs.
     pub stream_event_sender: Sender<StreamEvent>,
     pub network_message_sender: Sender<(SocketAddr, NetworkMessage)>,
@@ -133,6 +143,8 @@ impl Stream {
         let StreamConfig {
             address,
             socks_proxy,
+            proxy_only,
+            socks_connect_timeout,
             magic,
             network_message_receiver,
             network_message_sender,
@@ -143,18 +155,23 @@ impl Stream {
         let data = vec![0u8; STREAM_BUFFER_SIZE];
         let unparsed = vec![];
 
-        let tcp_stream_attempt = timeout(
-            timeout_duration,
-            TcpStream::connect(&address).map_err(StreamError::Io),
-        )
-        .await
-        .map_err(|_| StreamError::Timeout)?;
+        let tcp_stream_attempt = if proxy_only {
+            // In proxy-only mode the node must never be connected to directly.
+            Err(StreamError::DirectConnectionNotAllowed)
+        } else {
+            timeout(
+                timeout_duration,
+                TcpStream::connect(&address).map_err(StreamError::Io),
+            )
+            .await
+            .map_err(|_| StreamError::Timeout)?
+        };
 
         // If connecting through the node socket fails we may do a second attempt through the socks proxy if it's configured.
         let stream = match tcp_stream_attempt {
             Ok(stream) => stream,
             Err(err) => {
-                timeout(timeout_duration, async {
+                timeout(socks_connect_timeout, async {
                     match socks_proxy {
                         Some(socks_proxy_addr) => {
                             // The socks stream::connect takes a socks proxy address that implements 'tokio_socks::ToProxyAddrs'.
@@ -174,8 +191,16 @@ impl Stream {
                                     StreamError::Socks(SocksError::AddressTypeNotSupported),
                                 )?
                                 .to_owned();
+                            // Onion services are passed to the proxy by their hostname, as only
+                            // the proxy can resolve them.
+                            let target = match onion_hostname(&address) {
+                                Some(hostname) => {
+                                    TargetAddr::Domain(hostname.into(), address.port())
+                                }
+                                None => TargetAddr::Ip(address),
+                            };
                             Ok(
-                                Socks5Stream::connect(socks_addr_authority.as_str(), address)
+                                Socks5Stream::connect(socks_addr_authority.as_str(), target)
                                     .map_err(StreamError::Socks)
                                     .await?
                                     .into_inner(),
@@ -392,6 +417,8 @@ pub mod test {
             magic: network.magic(),
             network_message_receiver: adapter_rx,
             socks_proxy: None,
+            proxy_only: false,
+            socks_connect_timeout: Duration::from_secs(CONNECTION_TIMEOUT_SECS),
             stream_event_sender: stream_tx,
             network_message_sender: net_tx,
         };
@@ -445,13 +472,78 @@ pub mod test {
             magic: network.magic(),
             network_message_receiver: adapter_rx,
             socks_proxy: None,
+            proxy_only: false,
+            socks_connect_timeout: Duration::from_secs(CONNECTION_TIMEOUT_SECS),
+            stream_event_sender: stream_tx,
+            network_message_sender: net_tx,
+        };
+
+        let stream_result = Stream::connect(stream_config, &no_op_logger()).await;
+        let err = stream_result.unwrap_err();
+        assert!(matches!(err, StreamError::Timeout));
+    }
+
+    /// Test that in proxy-only mode the stream does not connect to a node directly, even if the
+    /// node is reachable.
+    #[tokio::test]
+    async fn proxy_only_rejects_direct_connections() {
+        let network = Network::Bitcoin;
+        let (net_tx, _) = tokio::sync::mpsc::channel(DEFAULT_CHANNEL_BUFFER_SIZE);
+        let (_adapter_tx, adapter_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (stream_tx, _) = tokio::sync::mpsc::channel(DEFAULT_CHANNEL_BUFFER_SIZE);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let stream_config = StreamConfig {
+            address,
+            logger: no_op_logger(),
+            magic: network.magic(),
+            network_message_receiver: adapter_rx,
+            socks_proxy: None,
+            proxy_only: true,
+            socks_connect_timeout: Duration::from_secs(CONNECTION_TIMEOUT_SECS),
+            stream_event_sender: stream_tx,
+            network_message_sender: net_tx,
+        };
+
+        let stream_result = Stream::connect(stream_config, &no_op_logger()).await;
+        let err = stream_result.unwrap_err();
+        assert!(matches!(err, StreamError::DirectConnectionNotAllowed));
+    }
+
+    /// Test that a connection attempt through an unresponsive SOCKS proxy is aborted after the
+    /// configured timeout.
+    #[tokio::test]
+    async fn socks_connection_times_out() {
+        let network = Network::Bitcoin;
+        let (net_tx, _) = tokio::sync::mpsc::channel(DEFAULT_CHANNEL_BUFFER_SIZE);
+        let (_adapter_tx, adapter_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (stream_tx, _) = tokio::sync::mpsc::channel(DEFAULT_CHANNEL_BUFFER_SIZE);
+        // The proxy accepts the TCP connection but never answers the SOCKS handshake.
+        let proxy = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_address = proxy.local_addr().unwrap();
+        let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 0)), 8333);
+
+        let stream_config = StreamConfig {
+            address,
+            logger: no_op_logger(),
+            magic: network.magic(),
+            network_message_receiver: adapter_rx,
+            socks_proxy: Some(format!("socks5://{}", proxy_address)),
+            proxy_only: true,
+            socks_connect_timeout: Duration::from_secs(1),
             stream_event_sender: stream_tx,
             network_message_sender: net_tx,
         };
 
+        let started_at = std::time::Instant::now();
         let stream_result = Stream::connect(stream_config, &no_op_logger()).await;
         let err = stream_result.unwrap_err();
         assert!(matches!(err, StreamError::Timeout));
+        let elapsed = started_at.elapsed();
+        assert!(elapsed >= Duration::from_secs(1));
+        assert!(elapsed < Duration::from_secs(CONNECTION_TIMEOUT_SECS));
+        drop(proxy);
     }
 
     /// Test that .
@@ -470,6 +562,8 @@ pub mod test {
             magic: network.magic(),
             network_message_receiver: adapter_rx,
             socks_proxy: None,
+            proxy_only: false,
+            socks_connect_timeout: Duration::from_secs(CONNECTION_TIMEOUT_SECS),
             stream_event_sender: stream_tx,
             network_message_sender: net_tx,
         };
@@ -1,34 +1,81 @@
-use std::collections::HashSet;
+use std::collections::{hash_map::Entry, HashMap, HashSet};
 use std::net::SocketAddr;
+use std::sync::Arc;
 use std::{time::Duration, time::SystemTime};
 
-use bitcoin::consensus::deserialize;
+use bitcoin::consensus::{deserialize, encode::VarInt, Decodable};
 use bitcoin::{
-    blockdata::transaction::Transaction, hash_types::Txid, network::message::NetworkMessage,
+    blockdata::transaction::Transaction,
+    hash_types::{Txid, Wtxid},
+    network::message::NetworkMessage,
     network::message_blockdata::Inventory,
+    Block,
 };
 use hashlink::LinkedHashMap;
-use logger::{debug, trace, warn, ReplicaLogger};
+use logger::{debug, info, trace, warn, ReplicaLogger};
 use metrics::MetricsRegistry;
+use rand::{prelude::SliceRandom, rngs::StdRng, SeedableRng};
+use thiserror::Error;
+use tokio::{
+    sync::mpsc::Receiver,
+    time::{timeout_at, Instant},
+};
 
-use crate::metrics::TransactionMetrics;
+use crate::clock::{Clock, SystemClock};
+use crate::config::Config;
+use crate::metrics::{TransactionMetrics, TransactionMetricsSnapshot};
+use crate::peer_rate_limit::PeerRateLimiter;
 use crate::ProcessBitcoinNetworkMessageError;
 use crate::{Channel, Command};
 
-/// How long should the transaction manager hold on to a transaction.
-const TX_CACHE_TIMEOUT_PERIOD_SECS: u64 = 10 * 60; // 10 minutes
-
 /// Maxmimum number of transaction to advertise.
 // https://developer.bitcoin.org/reference/p2p_networking.html#inv
 const MAXIMUM_TRANSACTION_PER_INV: usize = 50_000;
 
-/// Maximum number of transactions the adapter holds.
-/// A transaction gets removed from the cache in two cases:
-///     - Transaction times out
-///     - Cache size limit is hit and this transaction is the oldest.
-/// Note: This number should not be too large since it holds user generated
-/// transaction data, which can be a few Mb per transaction.
-const TX_CACHE_SIZE: usize = 250;
+/// The possible errors the `TransactionManager::send_transaction(...)` may produce.
+#[derive(Debug, Error)]
+pub enum SendTransactionError {
+    /// The serialized transaction exceeds the configured maximum size.
+    #[error("Transaction is too large: {size} bytes (max: {max_size} bytes)")]
+    TooLarge {
+        /// This field contains the size of the received transaction.
+        size: usize,
+        /// This field contains the maximum allowed transaction size.
+        max_size: usize,
+    },
+    /// The transaction does not spend any inputs.
+    #[error("Transaction has no inputs")]
+    NoInputs,
+    /// The transaction does not create any outputs.
+    #[error("Transaction has no outputs")]
+    NoOutputs,
+    /// The raw bytes could not be deserialized into a transaction.
+    #[error("Failed to deserialize transaction")]
+    Deserialize,
+    /// The feerate of the transaction is below the configured minimum relay feerate.
+    #[error(
+        "Transaction feerate is too low: {fee} sat for {vsize} vB (min: {min_feerate} sat/vB)"
+    )]
+    FeerateTooLow {
+        /// This field contains the inferred fee of the transaction.
+        fee: u64,
+        /// This field contains the virtual size of the transaction.
+        vsize: u64,
+        /// This field contains the minimum relay feerate.
+        min_feerate: u64,
+    },
+}
+
+/// This struct represents the advertisement status of a submitted transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TxStatus {
+    /// The number of peers the transaction has been advertised to.
+    pub advertised_to: usize,
+    /// How long ago the transaction was received.
+    pub age: Duration,
+    /// Whether the transaction is in the last tenth of its timeout period.
+    pub timed_out_soon: bool,
+}
 
 /// This struct represents the current information to track the
 /// broadcasting of a transaction.
@@ -36,38 +83,158 @@ const TX_CACHE_SIZE: usize = 250;
 struct TransactionInfo {
     /// The actual transaction to be sent to the BTC network.
     transaction: Transaction,
+    /// The ID of the transaction. Unlike the wtxid, it does not commit to the witness data.
+    txid: Txid,
     /// Set of peer to which we advertised this transaction.
     advertised: HashSet<SocketAddr>,
+    /// When the transaction was first advertised to a peer since `advertised` was last empty.
+    /// Once the rebroadcast interval has passed, `advertised` is cleared so that the
+    /// transaction is offered to all peers again.
+    last_advertised_at: Option<SystemTime>,
+    /// When the transaction was received.
+    received_at: SystemTime,
+    /// When a peer first requested the transaction with a `getdata` message.
+    first_fetched_at: Option<SystemTime>,
     /// How long the transaction should be held on to.
     timeout_at: SystemTime,
+    /// The feerate of the transaction in sat/kvB, if its fee can be inferred.
+    feerate_sat_kvb: Option<u64>,
 }
 
 impl TransactionInfo {
     /// This function is used to instantiate a [TransactionInfo](TransactionInfo) struct.
-    fn new(transaction: &Transaction) -> Self {
+    /// The transaction is held on to for the provided timeout period, starting at `now`.
+    fn new(
+        transaction: &Transaction,
+        now: SystemTime,
+        timeout: Duration,
+        feerate_sat_kvb: Option<u64>,
+    ) -> Self {
         Self {
             transaction: transaction.clone(),
+            txid: transaction.txid(),
             advertised: HashSet::new(),
-            timeout_at: SystemTime::now() + Duration::from_secs(TX_CACHE_TIMEOUT_PERIOD_SECS),
+            last_advertised_at: None,
+            received_at: now,
+            first_fetched_at: None,
+            timeout_at: now + timeout,
+            feerate_sat_kvb,
         }
     }
+
+    /// This function returns how long ago the transaction was received.
+    fn age(&self, now: SystemTime) -> Duration {
+        now.duration_since(self.received_at)
+            .unwrap_or(Duration::ZERO)
+    }
 }
 
 /// This struct stores the list of transactions submitted by the system component.
 pub struct TransactionManager {
     /// This field contains a logger for the transaction manager to
     logger: ReplicaLogger,
-    /// This field contains the transactions being tracked by the manager.
-    transactions: LinkedHashMap<Txid, TransactionInfo>,
+    /// This field contains the transactions being tracked by the manager, keyed by their wtxid.
+    /// Segwit transactions that only differ in their witness data share a txid but are tracked
+    /// separately. For transactions without witness data, the wtxid equals the txid.
+    transactions: LinkedHashMap<Wtxid, TransactionInfo>,
+    /// This field maps the txid of each cached transaction to the wtxids of the cached
+    /// transactions sharing it, in the order they were added, so lookups by txid do not need
+    /// to scan the cache.
+    wtxids_by_txid: HashMap<Txid, Vec<Wtxid>>,
+    /// Maximum number of transactions the adapter holds.
+    /// A transaction gets removed from the cache in two cases:
+    ///     - Transaction times out
+    ///     - Cache size limit is hit and this transaction is the oldest.
+    /// Note: This number should not be too large since it holds user generated
+    /// transaction data, which can be a few Mb per transaction.
+    tx_cache_max_entries: usize,
+    /// How long should the transaction manager hold on to a transaction.
+    tx_timeout: Duration,
+    /// How long after its last advertisement round a transaction is advertised to all peers again.
+    tx_rebroadcast_interval: Duration,
+    /// The maximum number of transaction IDs advertised to a single peer per tick.
+    tx_advertisements_per_peer_per_tick: usize,
+    /// The maximum size in bytes of a serialized transaction the manager accepts.
+    max_tx_size_bytes: usize,
+    /// The minimum feerate in sat/vB of transactions whose fee can be inferred.
+    min_relay_feerate_sat_vb: Option<u64>,
+    /// This field contains the random number generator used to shuffle advertised transaction IDs.
+    rng: StdRng,
+    /// This field contains the peers that negotiated wtxid-based transaction relay (BIP-339).
+    wtxid_relay_peers: HashSet<SocketAddr>,
+    /// This field contains the minimum feerate in sat/kvB each peer announced with a
+    /// `feefilter` message (BIP-133).
+    peer_fee_filters: HashMap<SocketAddr, u64>,
+    /// This field contains the peers with a `tx_advertised_total` series. The series of
+    /// disconnected peers are removed to keep the label cardinality bounded by the peer count.
+    advertised_peers: HashSet<SocketAddr>,
+    /// This field limits the rate of `getdata` messages each peer may send.
+    getdata_rate_limiter: PeerRateLimiter,
+    /// This field contains the clock that drives the timeouts and ages of the transactions.
+    clock: Arc<dyn Clock>,
     metrics: TransactionMetrics,
 }
 
 impl TransactionManager {
     /// This function creates a new transaction manager.
-    pub fn new(logger: ReplicaLogger, metrics_registry: &MetricsRegistry) -> Self {
+    pub fn new(config: &Config, logger: ReplicaLogger, metrics_registry: &MetricsRegistry) -> Self {
+        Self::new_with_rng(config, logger, metrics_registry, StdRng::from_entropy())
+    }
+
+    /// This function creates a new transaction manager that uses the provided random number
+    /// generator to shuffle advertised transaction IDs.
+    pub fn new_with_rng(
+        config: &Config,
+        logger: ReplicaLogger,
+        metrics_registry: &MetricsRegistry,
+        rng: StdRng,
+    ) -> Self {
+        Self::new_with_rng_and_clock(config, logger, metrics_registry, rng, Arc::new(SystemClock))
+    }
+
+    /// This function creates a new transaction manager that uses the provided clock for the
+    /// timeouts and ages of the transactions.
+    pub fn new_with_clock(
+        config: &Config,
+        logger: ReplicaLogger,
+        metrics_registry: &MetricsRegistry,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Self::new_with_rng_and_clock(
+            config,
+            logger,
+            metrics_registry,
+            StdRng::from_entropy(),
+            clock,
+        )
+    }
+
+    fn new_with_rng_and_clock(
+        config: &Config,
+        logger: ReplicaLogger,
+        metrics_registry: &MetricsRegistry,
+        rng: StdRng,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
         TransactionManager {
             logger,
             transactions: LinkedHashMap::new(),
+            wtxids_by_txid: HashMap::new(),
+            tx_cache_max_entries: config.tx_cache_max_entries,
+            tx_timeout: Duration::from_secs(config.tx_timeout_secs),
+            tx_rebroadcast_interval: Duration::from_secs(config.tx_rebroadcast_interval_secs),
+            tx_advertisements_per_peer_per_tick: config.tx_advertisements_per_peer_per_tick,
+            max_tx_size_bytes: config.max_tx_size_bytes,
+            min_relay_feerate_sat_vb: config.min_relay_feerate_sat_vb,
+            rng,
+            wtxid_relay_peers: HashSet::new(),
+            peer_fee_filters: HashMap::new(),
+            advertised_peers: HashSet::new(),
+            getdata_rate_limiter: PeerRateLimiter::new(
+                config.getdata_rate_per_sec,
+                config.getdata_burst,
+            ),
+            clock,
             metrics: TransactionMetrics::new(metrics_registry),
         }
     }
@@ -77,6 +244,7 @@ impl TransactionManager {
     pub fn tick(&mut self, channel: &mut impl Channel) {
         self.advertise_txids(channel);
         self.reap();
+        self.getdata_rate_limiter.remove_full_buckets();
         self.metrics
             .tx_store_size
             .set(self.transactions.len() as i64);
@@ -84,17 +252,241 @@ impl TransactionManager {
 
     /// This method is used to send a single transaction.
     /// If the transaction is not known, the transaction is added the the transactions map.
-    pub fn send_transaction(&mut self, raw_tx: &[u8]) {
-        if let Ok(transaction) = deserialize::<Transaction>(raw_tx) {
-            let txid = transaction.txid();
-            trace!(self.logger, "Received {} from the system component", txid);
-            // If hashmap has `TX_CACHE_SIZE` values we remove the oldest transaction in the cache.
-            if self.transactions.len() == TX_CACHE_SIZE {
-                self.transactions.pop_front();
+    /// Transactions larger than the configured maximum size, without inputs or outputs, or that
+    /// cannot be deserialized are rejected. On success, the transaction's ID is returned.
+    pub fn send_transaction(&mut self, raw_tx: &[u8]) -> Result<Txid, SendTransactionError> {
+        // A successfully deserialized transaction consumes all bytes, so the raw length is
+        // the serialized size of the transaction.
+        if raw_tx.len() > self.max_tx_size_bytes {
+            warn!(
+                self.logger,
+                "Rejected oversized transaction";
+                "size" => raw_tx.len(),
+                "max_size" => self.max_tx_size_bytes,
+            );
+            return Err(SendTransactionError::TooLarge {
+                size: raw_tx.len(),
+                max_size: self.max_tx_size_bytes,
+            });
+        }
+
+        // The input count precedes the inputs, so it is checked before the full parse.
+        if peek_input_count(raw_tx) == Some(0) {
+            warn!(self.logger, "Rejected transaction without inputs");
+            return Err(SendTransactionError::NoInputs);
+        }
+
+        let transaction = deserialize::<Transaction>(raw_tx).map_err(|err| {
+            warn!(self.logger, "Failed to deserialize transaction"; "error" => %err);
+            SendTransactionError::Deserialize
+        })?;
+        if transaction.output.is_empty() {
+            warn!(self.logger, "Rejected transaction without outputs"; "txid" => %transaction.txid());
+            return Err(SendTransactionError::NoOutputs);
+        }
+        let txid = transaction.txid();
+        let wtxid = transaction.wtxid();
+        trace!(
+            self.logger,
+            "Received transaction from the system component";
+            "txid" => %txid,
+            "wtxid" => %wtxid,
+        );
+        let fee = self.inferred_fee(&transaction);
+        self.check_feerate(&transaction, fee)?;
+        // If hashmap has `tx_cache_max_entries` values we remove the oldest transaction in the cache.
+        if self.transactions.len() >= self.tx_cache_max_entries {
+            self.evict_oldest_transaction();
+        }
+        if !self.transactions.contains_key(&wtxid) {
+            let feerate_sat_kvb = fee.map(|fee| fee.saturating_mul(1_000) / vsize(&transaction));
+            let info = TransactionInfo::new(
+                &transaction,
+                self.clock.now(),
+                self.tx_timeout,
+                feerate_sat_kvb,
+            );
+            self.transactions.insert(wtxid, info);
+            self.wtxids_by_txid.entry(txid).or_default().push(wtxid);
+        }
+        Ok(txid)
+    }
+
+    /// This method removes the oldest transaction from the cache because the cache is full.
+    fn evict_oldest_transaction(&mut self) {
+        if let Some((wtxid, info)) = self.transactions.pop_front() {
+            remove_from_txid_index(&mut self.wtxids_by_txid, &info.txid, &wtxid);
+            self.metrics.tx_evicted_full.inc();
+        }
+    }
+
+    /// This method returns the oldest cached transaction with the given txid.
+    fn get_transaction_info(&self, txid: &Txid) -> Option<&TransactionInfo> {
+        self.wtxids_by_txid
+            .get(txid)?
+            .first()
+            .and_then(|wtxid| self.transactions.get(wtxid))
+    }
+
+    /// This method checks the feerate of the transaction against the minimum relay feerate.
+    /// If the fee could not be inferred, the transaction is accepted.
+    fn check_feerate(
+        &self,
+        transaction: &Transaction,
+        fee: Option<u64>,
+    ) -> Result<(), SendTransactionError> {
+        let min_feerate = match self.min_relay_feerate_sat_vb {
+            Some(min_feerate) => min_feerate,
+            None => return Ok(()),
+        };
+        let fee = match fee {
+            Some(fee) => fee,
+            None => return Ok(()),
+        };
+
+        let vsize = vsize(transaction);
+        if fee < min_feerate.saturating_mul(vsize) {
+            warn!(
+                self.logger,
+                "Rejected transaction with a low feerate";
+                "txid" => %transaction.txid(),
+                "fee" => fee,
+                "vsize" => vsize,
+            );
+            return Err(SendTransactionError::FeerateTooLow {
+                fee,
+                vsize,
+                min_feerate,
+            });
+        }
+        Ok(())
+    }
+
+    /// This method infers the fee of the transaction. The fee can only be inferred if all
+    /// inputs spend outputs of cached transactions.
+    fn inferred_fee(&self, transaction: &Transaction) -> Option<u64> {
+        let input_value = self.inferred_input_value(transaction)?;
+        let output_value = transaction
+            .output
+            .iter()
+            .fold(0u64, |sum, output| sum.saturating_add(output.value));
+        // Spending more than the inputs is invalid, so it is treated as paying no fee.
+        Some(input_value.saturating_sub(output_value))
+    }
+
+    /// This method sums up the values of the outputs spent by the transaction.
+    /// Returns `None` if an input spends an output that is not known to the manager.
+    fn inferred_input_value(&self, transaction: &Transaction) -> Option<u64> {
+        transaction.input.iter().try_fold(0u64, |sum, input| {
+            let previous_output = &input.previous_output;
+            let value = self
+                .get_transaction_info(&previous_output.txid)?
+                .transaction
+                .output
+                .get(previous_output.vout as usize)?
+                .value;
+            Some(sum.saturating_add(value))
+        })
+    }
+
+    /// This method is used to send a batch of transactions.
+    /// Each transaction is handled as if it was sent with `send_transaction`, in order,
+    /// so the oldest transactions are evicted one at a time once the cache is full.
+    /// A result is returned for each entry of the batch.
+    pub fn send_transactions(
+        &mut self,
+        raw_txs: &[Vec<u8>],
+    ) -> Vec<Result<Txid, SendTransactionError>> {
+        raw_txs
+            .iter()
+            .map(|raw_tx| self.send_transaction(raw_tx))
+            .collect()
+    }
+
+    /// This method is used to query the advertisement status of a transaction.
+    /// Returns `None` if the transaction is not known to the manager.
+    pub fn transaction_status(&self, txid: &Txid) -> Option<TxStatus> {
+        let info = self.get_transaction_info(txid)?;
+        let now = self.clock.now();
+        let age = info.age(now);
+        let remaining = info
+            .timeout_at
+            .duration_since(now)
+            .unwrap_or(Duration::ZERO);
+        Some(TxStatus {
+            advertised_to: info.advertised.len(),
+            age,
+            timed_out_soon: remaining <= self.tx_timeout / 10,
+        })
+    }
+
+    /// This method returns how long the transaction has been held in the cache.
+    /// Returns `None` if the transaction is not known to the manager.
+    pub fn transaction_age(&self, txid: &Txid) -> Option<Duration> {
+        self.get_transaction_info(txid)
+            .map(|info| info.age(self.clock.now()))
+    }
+
+    /// This method is used to check if a peer has requested the transaction, which signals
+    /// that the transaction was picked up by at least one peer.
+    pub fn transaction_was_fetched(&self, txid: &Txid) -> bool {
+        self.wtxids_by_txid.get(txid).map_or(false, |wtxids| {
+            wtxids.iter().any(|wtxid| {
+                self.transactions
+                    .get(wtxid)
+                    .map_or(false, |info| info.first_fetched_at.is_some())
+            })
+        })
+    }
+
+    /// This method returns the current values of the transaction metrics.
+    pub fn metrics_snapshot(&self) -> TransactionMetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    /// This method is used when a block has been added to the blockchain state.
+    /// Transactions contained in the block have been picked up by the network and no longer
+    /// need to be advertised, so they are removed from the cache. Cached transactions that only
+    /// differ in their witness data from a confirmed transaction are removed as well.
+    pub fn notify_block_confirmed(&mut self, block: &Block) {
+        let confirmed: HashSet<Txid> = block.txdata.iter().map(|tx| tx.txid()).collect();
+        let block_hash = block.block_hash();
+        let wtxids_by_txid = &mut self.wtxids_by_txid;
+        self.transactions.retain(|wtxid, info| {
+            if !confirmed.contains(&info.txid) {
+                return true;
             }
-            self.transactions
-                .entry(txid)
-                .or_insert_with(|| TransactionInfo::new(&transaction));
+            remove_from_txid_index(wtxids_by_txid, &info.txid, wtxid);
+            trace!(
+                self.logger,
+                "Transaction included in block";
+                "txid" => %info.txid,
+                "block_hash" => %block_hash,
+            );
+            self.metrics.tx_confirmed.inc();
+            false
+        });
+    }
+
+    /// This method is used to change the maximum number of transactions held in the cache.
+    /// If the cache holds more transactions than the new maximum, the oldest transactions are evicted.
+    pub fn set_tx_cache_max_entries(&mut self, tx_cache_max_entries: usize) {
+        self.tx_cache_max_entries = tx_cache_max_entries;
+        while self.transactions.len() > self.tx_cache_max_entries {
+            self.evict_oldest_transaction();
+        }
+    }
+
+    /// This method is used when the connection to a peer was closed. The state negotiated with
+    /// the peer is dropped, so it does not outlive the connection.
+    pub fn remove_peer(&mut self, address: &SocketAddr) {
+        self.wtxid_relay_peers.remove(address);
+        self.peer_fee_filters.remove(address);
+        if self.advertised_peers.remove(address) {
+            self.metrics
+                .tx_advertised
+                .remove_label_values(&[&address.to_string()])
+                .ok();
         }
     }
 
@@ -102,15 +494,70 @@ impl TransactionManager {
     /// Clears all transactions the adapter is currently caching.
     pub fn make_idle(&mut self) {
         self.transactions.clear();
+        self.wtxids_by_txid.clear();
+        self.wtxid_relay_peers.clear();
+        self.peer_fee_filters.clear();
+    }
+
+    /// This method is used when the adapter shuts down. It advertises the transactions to all
+    /// peers and then serves the `getdata` requests received through `network_messages` until
+    /// every transaction has been fetched or the deadline has passed. Transactions that become
+    /// available for advertisement again, e.g., after a `notfound` message, are re-advertised.
+    /// Afterwards, the transactions are cleared.
+    pub async fn drain_and_advertise(
+        &mut self,
+        channel: &mut impl Channel,
+        network_messages: &mut Receiver<(SocketAddr, NetworkMessage)>,
+        deadline: Duration,
+    ) {
+        let deadline = Instant::now() + deadline;
+        self.advertise_all_txids(channel, deadline);
+        while self
+            .transactions
+            .values()
+            .any(|info| info.first_fetched_at.is_none())
+        {
+            match timeout_at(deadline, network_messages.recv()).await {
+                Ok(Some((addr, message))) => {
+                    // The adapter is shutting down, so misbehaving peers are not penalized anymore.
+                    self.process_bitcoin_network_message(channel, addr, &message)
+                        .ok();
+                    self.advertise_all_txids(channel, deadline);
+                }
+                Ok(None) | Err(_) => break,
+            }
+        }
+
+        let unfetched = self
+            .transactions
+            .values()
+            .filter(|info| info.first_fetched_at.is_none())
+            .count();
+        info!(
+            self.logger,
+            "Drained the transaction cache";
+            "transactions" => self.transactions.len(),
+            "unfetched" => unfetched,
+        );
+        self.make_idle();
+    }
+
+    /// This method advertises transaction IDs until there is nothing left to advertise or the
+    /// deadline has passed. Each pass is limited by `tx_advertisements_per_peer_per_tick`.
+    fn advertise_all_txids(&mut self, channel: &mut impl Channel, deadline: Instant) {
+        while Instant::now() < deadline && self.advertise_txids(channel) > 0 {}
     }
 
     /// Clear out transactions that have been held on to for more than the transaction timeout period.
     fn reap(&mut self) {
-        let now = SystemTime::now();
+        let now = self.clock.now();
+        let wtxids_by_txid = &mut self.wtxids_by_txid;
         self.transactions
             .retain(|tx, info| {
                 if info.timeout_at < now {
-                    warn!(self.logger, "Advertising bitcoin transaction {} timed out, meaning it was not picked up by any bitcoin peer.", tx);
+                    warn!(self.logger, "Transaction advertisement timed out, no peer picked it up"; "txid" => %info.txid, "wtxid" => %tx);
+                    self.metrics.tx_reaped_timeout.inc();
+                    remove_from_txid_index(wtxids_by_txid, &info.txid, tx);
                     false
                 }
                 else {
@@ -120,70 +567,179 @@ impl TransactionManager {
     }
 
     /// This method is used to broadcast known transaction IDs to connected peers.
-    /// If the timeout period has passed for a transaction ID, it is broadcasted again.
+    /// If the rebroadcast interval has passed for a transaction ID, it is broadcasted again.
     /// If the transaction has not been broadcasted, the transaction ID is broadcasted.
-    fn advertise_txids(&mut self, channel: &mut impl Channel) {
+    /// At most `tx_advertisements_per_peer_per_tick` transaction IDs are advertised to a peer
+    /// per call. The remaining transaction IDs are advertised on subsequent calls.
+    /// The order of the transaction IDs within an inventory is shuffled so that it does not
+    /// reveal the order in which the transactions were received.
+    /// Peers that support wtxid-based relay (BIP-339) are advertised the wtxid instead of the txid.
+    /// Transactions below the feerate a peer announced with `feefilter` (BIP-133) are not
+    /// advertised to it. Transactions whose feerate is unknown are advertised to all peers.
+    /// Returns the number of transaction IDs advertised.
+    fn advertise_txids(&mut self, channel: &mut impl Channel) -> usize {
+        let available_connections: HashSet<_> =
+            channel.available_connections().into_iter().collect();
+        let metrics = &self.metrics;
+        self.advertised_peers.retain(|address| {
+            let connected = available_connections.contains(address);
+            if !connected {
+                metrics
+                    .tx_advertised
+                    .remove_label_values(&[&address.to_string()])
+                    .ok();
+            }
+            connected
+        });
+        self.peer_fee_filters
+            .retain(|address, _| available_connections.contains(address));
+
+        let now = self.clock.now();
+        for info in self.transactions.values_mut() {
+            let rebroadcast_due = info.last_advertised_at.map_or(false, |last_advertised_at| {
+                last_advertised_at + self.tx_rebroadcast_interval <= now
+            });
+            if rebroadcast_due {
+                info.advertised.clear();
+                info.last_advertised_at = None;
+            }
+        }
+
+        let mut total_advertised_count: usize = 0;
         for address in channel.available_connections() {
+            let wtxid_relay = self.wtxid_relay_peers.contains(&address);
+            let min_feerate = self.peer_fee_filters.get(&address).copied();
             let mut inventory = vec![];
-            for (txid, info) in self.transactions.iter_mut() {
-                if !info.advertised.contains(&address) {
-                    inventory.push(Inventory::Transaction(*txid));
+            let mut advertised_count: usize = 0;
+            for (wtxid, info) in self.transactions.iter_mut() {
+                if advertised_count >= self.tx_advertisements_per_peer_per_tick {
+                    break;
+                }
+                let below_fee_filter = matches!(
+                    (min_feerate, info.feerate_sat_kvb),
+                    (Some(min_feerate), Some(feerate)) if feerate < min_feerate
+                );
+                if !info.advertised.contains(&address) && !below_fee_filter {
+                    if wtxid_relay {
+                        inventory.push(Inventory::WTx(*wtxid));
+                    } else {
+                        inventory.push(Inventory::Transaction(info.txid));
+                    }
+                    if info.advertised.is_empty() {
+                        info.last_advertised_at = Some(now);
+                    }
                     info.advertised.insert(address);
+                    advertised_count += 1;
                 }
                 // If the inventory contains the maximum allowed number of transactions, we will send it
-                // and start building a new one.
+                // and start building a new one. The inventory was built for this peer only.
                 if inventory.len() == MAXIMUM_TRANSACTION_PER_INV {
-                    debug!(self.logger, "Broadcasting Txids ({:?}) to peers", inventory);
-                    for address in channel.available_connections() {
-                        channel
-                            .send(Command {
-                                address: Some(address),
-                                message: NetworkMessage::Inv(inventory.clone()),
-                            })
-                            .ok();
-                    }
-                    inventory = vec![];
+                    self.send_inventory(channel, address, std::mem::take(&mut inventory));
                 }
             }
 
+            total_advertised_count += advertised_count;
             if inventory.is_empty() {
                 continue;
             }
+            self.send_inventory(channel, address, inventory);
+        }
+        total_advertised_count
+    }
 
-            debug!(
-                self.logger,
-                "Broadcasting Txids ({:?}) to peer {:?}", inventory, address
-            );
+    /// This method sends the shuffled inventory to the given peer and records it in the
+    /// peer's `tx_advertised` series.
+    fn send_inventory(
+        &mut self,
+        channel: &mut impl Channel,
+        address: SocketAddr,
+        mut inventory: Vec<Inventory>,
+    ) {
+        inventory.shuffle(&mut self.rng);
 
-            channel
-                .send(Command {
-                    address: Some(address),
-                    message: NetworkMessage::Inv(inventory.clone()),
-                })
-                .ok();
-        }
+        debug!(
+            self.logger,
+            "Broadcasting transactions to peer";
+            "peer" => %address,
+            "inventory" => ?inventory,
+        );
+
+        self.metrics
+            .tx_advertised
+            .with_label_values(&[&address.to_string()])
+            .inc_by(inventory.len() as u64);
+        self.advertised_peers.insert(address);
+        channel
+            .send(Command {
+                address: Some(address),
+                message: NetworkMessage::Inv(inventory),
+            })
+            .ok();
     }
 
     /// This method is used to process an event from the connected BTC nodes.
-    /// This function processes a `getdata` message from a BTC node.
-    /// If there are messages for transactions, the transaction is sent to the
-    /// requesting node. Transactions sent are then removed from the cache.
+    /// This function processes `getdata` and `notfound` messages from a BTC node.
+    /// If there are `getdata` messages for transactions, the transaction is sent to the
+    /// requesting node and the time of the first request is recorded.
+    /// If a node sends a `notfound` message for transactions, the node is removed from the
+    /// transactions' advertised sets so they are advertised again on the next tick.
+    /// If a node sends a `wtxidrelay` message during the version handshake, transactions are
+    /// advertised to it by wtxid. The negotiation is reset when a new handshake starts.
+    /// If a node sends a `feefilter` message, transactions below its feerate are no longer
+    /// advertised to it. A feerate of 0 removes the filter.
     pub fn process_bitcoin_network_message(
         &mut self,
         channel: &mut impl Channel,
         addr: SocketAddr,
         message: &NetworkMessage,
     ) -> Result<(), ProcessBitcoinNetworkMessageError> {
-        if let NetworkMessage::GetData(inventory) = message {
-            if inventory.len() > MAXIMUM_TRANSACTION_PER_INV {
-                return Err(ProcessBitcoinNetworkMessageError::InvalidMessage);
+        match message {
+            NetworkMessage::Version(_) => {
+                self.wtxid_relay_peers.remove(&addr);
+                self.peer_fee_filters.remove(&addr);
+            }
+            NetworkMessage::FeeFilter(feerate) => {
+                trace!(self.logger, "Peer set a fee filter"; "peer" => %addr, "feerate" => feerate);
+                match u64::try_from(*feerate) {
+                    Ok(feerate) if feerate > 0 => {
+                        self.peer_fee_filters.insert(addr, feerate);
+                    }
+                    _ => {
+                        self.peer_fee_filters.remove(&addr);
+                    }
+                }
             }
+            NetworkMessage::WtxidRelay => {
+                trace!(self.logger, "Peer negotiated wtxid relay"; "peer" => %addr);
+                self.wtxid_relay_peers.insert(addr);
+            }
+            NetworkMessage::GetData(inventory) => {
+                if inventory.len() > MAXIMUM_TRANSACTION_PER_INV {
+                    return Err(ProcessBitcoinNetworkMessageError::TooManyGetDataItems);
+                }
+                if !self.getdata_rate_limiter.try_acquire(&addr) {
+                    debug!(self.logger, "Dropped rate limited getdata message"; "peer" => %addr);
+                    return Err(ProcessBitcoinNetworkMessageError::GetDataRateExceeded);
+                }
 
-            for inv in inventory {
-                if let Inventory::Transaction(txid) = inv {
-                    if let Some(TransactionInfo { transaction, .. }) =
-                        self.transactions.get_mut(txid)
+                for inv in inventory {
+                    if let Some(TransactionInfo {
+                        transaction,
+                        txid,
+                        first_fetched_at,
+                        ..
+                    }) =
+                        get_transaction_info_mut(&mut self.transactions, &self.wtxids_by_txid, inv)
                     {
+                        if first_fetched_at.is_none() {
+                            debug!(
+                                self.logger,
+                                "Transaction fetched for the first time";
+                                "txid" => %txid,
+                                "peer" => %addr,
+                            );
+                            *first_fetched_at = Some(self.clock.now());
+                        }
                         channel
                             .send(Command {
                                 address: Some(addr),
@@ -193,24 +749,108 @@ impl TransactionManager {
                     }
                 }
             }
+            NetworkMessage::NotFound(inventory) => {
+                if inventory.len() > MAXIMUM_TRANSACTION_PER_INV {
+                    return Err(ProcessBitcoinNetworkMessageError::InvalidMessage);
+                }
+
+                for inv in inventory {
+                    if let Some(info) =
+                        get_transaction_info_mut(&mut self.transactions, &self.wtxids_by_txid, inv)
+                    {
+                        trace!(
+                            self.logger,
+                            "Peer did not find transaction, re-advertising";
+                            "txid" => %info.txid,
+                            "peer" => %addr,
+                        );
+                        info.advertised.remove(&addr);
+                    }
+                }
+            }
+            _ => {}
         }
         Ok(())
     }
 }
 
+/// This function returns the virtual size of the transaction in vbytes.
+fn vsize(transaction: &Transaction) -> u64 {
+    (transaction.weight() as u64 + 3) / 4
+}
+
+/// This function reads the input count of a serialized transaction without parsing the
+/// transaction. Returns `None` if the bytes are too short to contain the count.
+fn peek_input_count(raw_tx: &[u8]) -> Option<u64> {
+    // Skip the 4-byte version.
+    let mut reader = raw_tx.get(4..)?;
+    let count = VarInt::consensus_decode(&mut reader).ok()?.0;
+    // A zero count followed by the flag byte marks the extended serialization of BIP-144,
+    // in which the actual input count follows the flag.
+    if count == 0 && reader.first() == Some(&1) {
+        let mut reader = reader.get(1..)?;
+        return VarInt::consensus_decode(&mut reader)
+            .ok()
+            .map(|count| count.0);
+    }
+    Some(count)
+}
+
+/// This function looks up the transaction an inventory entry refers to.
+/// Entries may refer to a transaction by its txid or, for peers supporting BIP-339, by its wtxid.
+/// A txid refers to the oldest cached transaction with that txid.
+fn get_transaction_info_mut<'a>(
+    transactions: &'a mut LinkedHashMap<Wtxid, TransactionInfo>,
+    wtxids_by_txid: &HashMap<Txid, Vec<Wtxid>>,
+    inv: &Inventory,
+) -> Option<&'a mut TransactionInfo> {
+    match inv {
+        Inventory::WTx(wtxid) => transactions.get_mut(wtxid),
+        Inventory::Transaction(txid) | Inventory::WitnessTransaction(txid) => {
+            let wtxid = wtxids_by_txid.get(txid)?.first()?;
+            transactions.get_mut(wtxid)
+        }
+        _ => None,
+    }
+}
+
+/// This function removes the wtxid of a transaction that left the cache from the txid index.
+fn remove_from_txid_index(
+    wtxids_by_txid: &mut HashMap<Txid, Vec<Wtxid>>,
+    txid: &Txid,
+    wtxid: &Wtxid,
+) {
+    if let Entry::Occupied(mut entry) = wtxids_by_txid.entry(*txid) {
+        entry.get_mut().retain(|indexed| indexed != wtxid);
+        if entry.get().is_empty() {
+            entry.remove();
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::clock::test::MockClock;
     use crate::common::test_common::TestChannel;
+    use crate::config::test::ConfigBuilder;
     use bitcoin::{
-        blockdata::constants::genesis_block, consensus::serialize, Network, Transaction,
+        blockdata::constants::genesis_block,
+        consensus::serialize,
+        network::{constants::ServiceFlags, message_network::VersionMessage, Address},
+        Network, OutPoint, Script, Transaction, TxIn, TxOut, Witness,
     };
     use logger::replica_logger::no_op_logger;
     use std::str::FromStr;
 
     /// This function creates a new transaction manager with a test logger.
     fn make_transaction_manager() -> TransactionManager {
-        TransactionManager::new(no_op_logger(), &MetricsRegistry::default())
+        make_transaction_manager_with_config(&ConfigBuilder::new().build())
+    }
+
+    /// This function creates a new transaction manager with a test logger and the given config.
+    fn make_transaction_manager_with_config(config: &Config) -> TransactionManager {
+        TransactionManager::new(config, no_op_logger(), &MetricsRegistry::default())
     }
 
     /// This function pulls a transaction out of the `regtest` genesis block.
@@ -223,6 +863,24 @@ mod test {
             .expect("There should be a transaction here.")
     }
 
+    /// This function creates a segwit transaction with the given witness data.
+    fn get_segwit_transaction(witness: Vec<u8>) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(get_transaction().txid(), 0),
+                script_sig: Script::new(),
+                sequence: 0xFFFFFFFF,
+                witness: Witness::from_vec(vec![witness]),
+            }],
+            output: vec![TxOut {
+                value: 1_000,
+                script_pubkey: Script::new(),
+            }],
+        }
+    }
+
     /// This function tests the `TransactionManager::reap(...)` method.
     /// Test Steps:
     /// 1. Receive a transaction
@@ -235,18 +893,51 @@ mod test {
         let mut manager = make_transaction_manager();
         let transaction = get_transaction();
         let raw_tx = serialize(&transaction);
-        manager.send_transaction(&raw_tx);
+        manager.send_transaction(&raw_tx).unwrap();
         assert_eq!(manager.transactions.len(), 1);
         manager.reap();
         assert_eq!(manager.transactions.len(), 1);
 
         let info = manager
             .transactions
-            .get_mut(&transaction.txid())
+            .get_mut(&transaction.wtxid())
             .expect("transaction should be map");
-        info.timeout_at = SystemTime::now() - Duration::from_secs(TX_CACHE_TIMEOUT_PERIOD_SECS);
+        info.timeout_at = SystemTime::now() - manager.tx_timeout;
+        manager.reap();
+        assert_eq!(manager.transactions.len(), 0);
+        assert_eq!(manager.metrics.tx_reaped_timeout.get(), 1);
+        assert_eq!(manager.metrics.tx_evicted_full.get(), 0);
+    }
+
+    /// This function tests that the configured timeout is used when reaping transactions.
+    /// Test Steps:
+    /// 1. Create a manager with a mock clock and receive a transaction.
+    /// 2. Advance the clock up to the timeout and ensure the transaction is not reaped.
+    /// 3. Advance the clock past the timeout and ensure the transaction is reaped.
+    #[test]
+    fn test_reap_with_configured_timeout() {
+        let config = ConfigBuilder::new().with_tx_timeout_secs(60).build();
+        let clock = Arc::new(MockClock::new(SystemTime::now()));
+        let mut manager = TransactionManager::new_with_clock(
+            &config,
+            no_op_logger(),
+            &MetricsRegistry::default(),
+            clock.clone(),
+        );
+        let transaction = get_transaction();
+        let raw_tx = serialize(&transaction);
+        manager.send_transaction(&raw_tx).unwrap();
+        manager.reap();
+        assert_eq!(manager.transactions.len(), 1);
+
+        clock.advance(Duration::from_secs(60));
+        manager.reap();
+        assert_eq!(manager.transactions.len(), 1);
+
+        clock.advance(Duration::from_secs(1));
         manager.reap();
         assert_eq!(manager.transactions.len(), 0);
+        assert_eq!(manager.metrics.tx_reaped_timeout.get(), 1);
     }
 
     /// This function tests the `TransactionManager::broadcast_txids(...)` method.
@@ -262,18 +953,18 @@ mod test {
         let transaction = get_transaction();
         let raw_tx = serialize(&transaction);
         let txid = transaction.txid();
-        manager.send_transaction(&raw_tx);
+        manager.send_transaction(&raw_tx).unwrap();
         assert_eq!(manager.transactions.len(), 1);
         let info = manager
             .transactions
-            .get(&transaction.txid())
+            .get(&transaction.wtxid())
             .expect("transaction should be map");
         assert!(info.advertised.is_empty());
         // Initial broadcast
         manager.advertise_txids(&mut channel);
         let info = manager
             .transactions
-            .get_mut(&txid)
+            .get_mut(&transaction.wtxid())
             .expect("transaction should be map");
         assert!(info.advertised.len() == 1);
         assert_eq!(channel.command_count(), 1);
@@ -290,115 +981,484 @@ mod test {
         );
     }
 
-    /// This function tests that the oldest transaction gets removed in case of a full transaction cache.
+    /// This function tests that transactions are advertised to IPv6 peers.
     /// Test Steps:
-    /// 1. Add transaction that should be removed to manager.
-    /// 2. Add n transaction such that the first one gets evicted.
-    /// 3. Make sure the first transaction is actually removed from the cache.
+    /// 1. Receive a transaction and advertise it to an IPv6 peer.
+    /// 2. Check that the `inv` message is addressed to the peer and the peer is marked as advertised.
+    /// 3. Check that the transaction is not advertised to the peer again.
     #[test]
-    fn test_adapter_transaction_cache_full() {
+    fn test_advertise_txids_ipv6_peer() {
+        let address = SocketAddr::from_str("[2001:db8::1]:8333").expect("invalid address");
+        let mut channel = TestChannel::new(vec![address]);
         let mut manager = make_transaction_manager();
+        let transaction = get_transaction();
+        manager.send_transaction(&serialize(&transaction)).unwrap();
 
-        // Send one transaction. This transaction should be removed first if we are at capacity.
-        let mut first_tx = get_transaction();
-        first_tx.lock_time = u32::MAX;
-        let raw_tx = serialize(&first_tx);
-        manager.send_transaction(&raw_tx);
+        manager.advertise_txids(&mut channel);
+        assert_eq!(channel.command_count(), 1);
+        let command = channel.pop_front().expect("There should be one.");
+        assert_eq!(command.address, Some(address));
+        assert!(matches!(command.message, NetworkMessage::Inv(_)));
+        let info = manager
+            .transactions
+            .get(&transaction.wtxid())
+            .expect("transaction should be map");
+        assert!(info.advertised.contains(&address));
 
-        for i in 0..TX_CACHE_SIZE {
-            // First regtest genesis transaction.
-            let mut transaction = get_transaction();
-            // Alter transaction such that we get a different `txid`
-            transaction.lock_time = i.try_into().unwrap();
-            let raw_tx = serialize(&transaction);
-            manager.send_transaction(&raw_tx);
-        }
-        assert_eq!(manager.transactions.len(), TX_CACHE_SIZE);
-        assert!(manager.transactions.get(&first_tx.txid()).is_none());
+        manager.advertise_txids(&mut channel);
+        assert_eq!(channel.command_count(), 0);
     }
 
-    /// This function tests that we don't readvertise transactions that were already advertised.
+    /// This function tests that the transactions advertised to each peer are counted.
     /// Test Steps:
-    /// 1. Add transaction to manager.
-    /// 2. Advertise that transaction and create requests from peer.
-    /// 3. Check that this transaction does not get advertised again during manager tick.
-    /// 3. Check transaction advertisment is correctly tracked.
+    /// 1. Advertise a transaction to two peers and check that each peer's counter is incremented.
+    /// 2. Advertise a second transaction to the peers and check that the counters are incremented again.
+    /// 3. Disconnect a peer and check that its counter is removed.
     #[test]
-    fn test_adapter_dont_readvertise() {
-        let address = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
-        let mut channel = TestChannel::new(vec![address]);
+    fn test_advertise_txids_counts_advertisements_per_peer() {
+        let address_1 = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let address_2 = SocketAddr::from_str("127.0.0.2:8333").expect("invalid address");
+        let mut channel = TestChannel::new(vec![address_1, address_2]);
         let mut manager = make_transaction_manager();
+        let advertised = |manager: &TransactionManager, address: &SocketAddr| {
+            manager
+                .metrics
+                .tx_advertised
+                .with_label_values(&[&address.to_string()])
+                .get()
+        };
 
         let mut transaction = get_transaction();
-        transaction.lock_time = 0;
-        let raw_tx = serialize(&transaction);
-        manager.send_transaction(&raw_tx);
-        manager.tick(&mut channel);
-        channel.pop_front().unwrap();
+        manager.send_transaction(&serialize(&transaction)).unwrap();
+        manager.advertise_txids(&mut channel);
+        assert_eq!(advertised(&manager, &address_1), 1);
+        assert_eq!(advertised(&manager, &address_2), 1);
 
-        // Request transaction
-        manager
-            .process_bitcoin_network_message(
-                &mut channel,
-                address,
-                &NetworkMessage::GetData(vec![Inventory::Transaction(transaction.txid())]),
-            )
-            .unwrap();
-        // Send transaction
-        channel.pop_front().unwrap();
+        transaction.lock_time = 1;
+        manager.send_transaction(&serialize(&transaction)).unwrap();
+        manager.advertise_txids(&mut channel);
+        assert_eq!(advertised(&manager, &address_1), 2);
+        assert_eq!(advertised(&manager, &address_2), 2);
 
-        manager.tick(&mut channel);
-        // Transaction should not be readvertised.
-        assert_eq!(channel.command_count(), 0);
-        // Transaction should be marked as advertised
-        assert_eq!(
-            manager
-                .transactions
-                .get(&transaction.txid())
-                .unwrap()
-                .advertised
-                .len(),
-            1
-        );
-        assert_eq!(
-            manager
-                .transactions
-                .get(&transaction.txid())
-                .unwrap()
-                .advertised
-                .get(&address),
-            Some(&address)
-        );
+        let mut channel = TestChannel::new(vec![address_1]);
+        manager.advertise_txids(&mut channel);
+        assert_eq!(manager.advertised_peers, HashSet::from([address_1]));
+        assert!(manager
+            .metrics
+            .tx_advertised
+            .remove_label_values(&[&address_2.to_string()])
+            .is_err());
     }
 
-    /// This function tests that we advertise to muliple peers and don't readvertise after
-    /// first adverisment.
+    /// This function tests that a transaction is advertised again after the rebroadcast interval.
     /// Test Steps:
-    /// 1. Add transaction to manager.
-    /// 2. Advertise that transaction and request it from peer 1.
-    /// 3. Check that this transaction does not get readvertised.
+    /// 1. Advertise a transaction and check it is not advertised again before the interval passes.
+    /// 2. Let the rebroadcast interval pass.
+    /// 3. Check that the transaction is advertised to the peer again.
     #[test]
-    fn test_adapter_dont_readvertise_multiple_peers() {
-        let address1 = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
-        let address2 = SocketAddr::from_str("127.0.0.1:8334").expect("invalid address");
-        let mut channel = TestChannel::new(vec![address1, address2]);
-        let mut manager = make_transaction_manager();
+    fn test_advertise_txids_after_rebroadcast_interval() {
+        let address = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let mut channel = TestChannel::new(vec![address]);
+        let config = ConfigBuilder::new()
+            .with_tx_rebroadcast_interval_secs(60)
+            .build();
+        let mut manager = make_transaction_manager_with_config(&config);
+        let transaction = get_transaction();
+        manager.send_transaction(&serialize(&transaction)).unwrap();
 
-        let mut transaction = get_transaction();
-        transaction.lock_time = 0;
-        let raw_tx = serialize(&transaction);
-        manager.send_transaction(&raw_tx);
-        manager.tick(&mut channel);
-        // Transaction advertisment to both peers.
-        assert_eq!(channel.command_count(), 2);
-        channel.pop_front().unwrap();
-        channel.pop_front().unwrap();
+        manager.advertise_txids(&mut channel);
+        assert_eq!(channel.command_count(), 1);
+        channel.pop_front();
+        let last_advertised_at = manager
+            .transactions
+            .get(&transaction.wtxid())
+            .and_then(|info| info.last_advertised_at)
+            .expect("transaction should have been advertised");
 
-        // Request transaction from peer 1
-        manager
-            .process_bitcoin_network_message(
-                &mut channel,
-                address1,
+        manager.advertise_txids(&mut channel);
+        assert_eq!(channel.command_count(), 0);
+
+        let info = manager
+            .transactions
+            .get_mut(&transaction.wtxid())
+            .expect("transaction should be map");
+        info.last_advertised_at = Some(last_advertised_at - Duration::from_secs(60));
+        manager.advertise_txids(&mut channel);
+        assert_eq!(channel.command_count(), 1);
+        let command = channel.pop_front().expect("There should be one.");
+        assert_eq!(command.address, Some(address));
+        assert!(matches!(command.message, NetworkMessage::Inv(_)));
+        let info = manager
+            .transactions
+            .get(&transaction.wtxid())
+            .expect("transaction should be map");
+        assert!(info.last_advertised_at > Some(last_advertised_at - Duration::from_secs(60)));
+    }
+
+    /// This function tests that the number of transaction IDs advertised to a peer per tick is capped.
+    /// Test Steps:
+    /// 1. Add more transactions than the per-tick advertisement cap.
+    /// 2. Check that the first tick only advertises up to the cap.
+    /// 3. Check that the remaining transactions are advertised on the next tick.
+    #[test]
+    fn test_advertise_txids_per_peer_cap() {
+        let address = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let mut channel = TestChannel::new(vec![address]);
+        let config = ConfigBuilder::new()
+            .with_tx_advertisements_per_peer_per_tick(3)
+            .build();
+        let mut manager = make_transaction_manager_with_config(&config);
+        for i in 0..5 {
+            let mut transaction = get_transaction();
+            transaction.lock_time = i;
+            manager.send_transaction(&serialize(&transaction)).unwrap();
+        }
+
+        manager.advertise_txids(&mut channel);
+        assert_eq!(channel.command_count(), 1);
+        let command = channel.pop_front().unwrap();
+        assert!(matches!(command.message, NetworkMessage::Inv(inv) if inv.len() == 3));
+
+        manager.advertise_txids(&mut channel);
+        assert_eq!(channel.command_count(), 1);
+        let command = channel.pop_front().unwrap();
+        assert!(matches!(command.message, NetworkMessage::Inv(inv) if inv.len() == 2));
+
+        manager.advertise_txids(&mut channel);
+        assert_eq!(channel.command_count(), 0);
+        assert!(manager
+            .transactions
+            .values()
+            .all(|info| info.advertised.contains(&address)));
+    }
+
+    /// This function tests that shuffling the advertisement order does not change which
+    /// transaction IDs are advertised.
+    /// Test Steps:
+    /// 1. Create a manager with a seeded random number generator and add transactions.
+    /// 2. Advertise the transactions.
+    /// 3. Check that the advertised transaction IDs are exactly the received ones.
+    #[test]
+    fn test_advertise_txids_shuffled() {
+        let address = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let mut channel = TestChannel::new(vec![address]);
+        let mut manager = TransactionManager::new_with_rng(
+            &ConfigBuilder::new().build(),
+            no_op_logger(),
+            &MetricsRegistry::default(),
+            StdRng::seed_from_u64(42),
+        );
+        let mut txids = HashSet::new();
+        for i in 0..20 {
+            let mut transaction = get_transaction();
+            transaction.lock_time = i;
+            txids.insert(manager.send_transaction(&serialize(&transaction)).unwrap());
+        }
+
+        manager.advertise_txids(&mut channel);
+        assert_eq!(channel.command_count(), 1);
+        let command = channel.pop_front().unwrap();
+        let inventory = if let NetworkMessage::Inv(inv) = command.message {
+            inv
+        } else {
+            vec![]
+        };
+        assert_eq!(inventory.len(), txids.len());
+        let advertised: HashSet<Txid> = inventory
+            .iter()
+            .filter_map(|inv| match inv {
+                Inventory::Transaction(txid) => Some(*txid),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(advertised, txids);
+    }
+
+    /// This function tests that transactions exceeding the configured maximum size are rejected.
+    #[test]
+    fn test_send_transaction_too_large() {
+        let transaction = get_transaction();
+        let raw_tx = serialize(&transaction);
+        let config = ConfigBuilder::new()
+            .with_max_tx_size_bytes(raw_tx.len() - 1)
+            .build();
+        let mut manager = make_transaction_manager_with_config(&config);
+
+        let result = manager.send_transaction(&raw_tx);
+        assert!(matches!(
+            result,
+            Err(SendTransactionError::TooLarge { size, max_size }) if size == raw_tx.len() && max_size == raw_tx.len() - 1
+        ));
+        assert!(manager.transactions.is_empty());
+    }
+
+    /// This function tests that transactions without inputs are rejected before they are parsed.
+    #[test]
+    fn test_send_transaction_no_inputs() {
+        let mut manager = make_transaction_manager();
+        let mut transaction = get_transaction();
+        transaction.input.clear();
+        let raw_tx = serialize(&transaction);
+        assert_eq!(peek_input_count(&raw_tx), Some(0));
+
+        let result = manager.send_transaction(&raw_tx);
+        assert!(matches!(result, Err(SendTransactionError::NoInputs)));
+        assert!(manager.transactions.is_empty());
+
+        let transaction = get_segwit_transaction(vec![1]);
+        assert_eq!(peek_input_count(&serialize(&transaction)), Some(1));
+        assert_eq!(peek_input_count(&[1, 0, 0]), None);
+    }
+
+    /// This function tests that transactions without outputs are rejected.
+    #[test]
+    fn test_send_transaction_no_outputs() {
+        let mut manager = make_transaction_manager();
+        let mut transaction = get_transaction();
+        transaction.output.clear();
+
+        let result = manager.send_transaction(&serialize(&transaction));
+        assert!(matches!(result, Err(SendTransactionError::NoOutputs)));
+        assert!(manager.transactions.is_empty());
+    }
+
+    /// This function tests that transactions below the minimum relay feerate are rejected if
+    /// their fee can be inferred.
+    /// Test Steps:
+    /// 1. Send a transaction whose inputs are unknown and check that it is accepted.
+    /// 2. Send a child transaction that pays no fee and check that it is rejected.
+    /// 3. Send a child transaction that pays a fee and check that it is accepted.
+    #[test]
+    fn test_send_transaction_feerate_too_low() {
+        let config = ConfigBuilder::new()
+            .with_min_relay_feerate_sat_vb(Some(1))
+            .build();
+        let mut manager = make_transaction_manager_with_config(&config);
+        let parent = get_transaction();
+        manager.send_transaction(&serialize(&parent)).unwrap();
+
+        let mut no_fee_child = get_segwit_transaction(vec![1]);
+        no_fee_child.output[0].value = parent.output[0].value;
+        let result = manager.send_transaction(&serialize(&no_fee_child));
+        assert!(matches!(
+            result,
+            Err(SendTransactionError::FeerateTooLow {
+                fee: 0,
+                min_feerate: 1,
+                ..
+            })
+        ));
+        assert_eq!(manager.transactions.len(), 1);
+
+        let child = get_segwit_transaction(vec![1]);
+        manager.send_transaction(&serialize(&child)).unwrap();
+        assert_eq!(manager.transactions.len(), 2);
+
+        // Without a minimum feerate, the transaction is accepted.
+        let mut manager = make_transaction_manager();
+        manager.send_transaction(&serialize(&parent)).unwrap();
+        manager.send_transaction(&serialize(&no_fee_child)).unwrap();
+    }
+
+    /// This function tests that bytes that cannot be deserialized into a transaction are rejected.
+    #[test]
+    fn test_send_transaction_deserialize_error() {
+        let mut manager = make_transaction_manager();
+        let result = manager.send_transaction(&[0xde, 0xad, 0xbe, 0xef]);
+        assert!(matches!(result, Err(SendTransactionError::Deserialize)));
+        assert!(manager.transactions.is_empty());
+
+        let transaction = get_transaction();
+        let result = manager.send_transaction(&serialize(&transaction));
+        assert!(matches!(result, Ok(txid) if txid == transaction.txid()));
+    }
+
+    /// This function tests the `TransactionManager::send_transactions(...)` method with a batch
+    /// larger than the remaining cache capacity.
+    /// Test Steps:
+    /// 1. Fill part of the cache with transactions.
+    /// 2. Send a batch containing an invalid entry that exceeds the remaining capacity.
+    /// 3. Check the per-entry results and that only the oldest transactions were evicted.
+    #[test]
+    fn test_send_transactions_exceeds_capacity() {
+        let tx_cache_max_entries = 4;
+        let config = ConfigBuilder::new()
+            .with_tx_cache_max_entries(tx_cache_max_entries)
+            .build();
+        let mut manager = make_transaction_manager_with_config(&config);
+        let transactions: Vec<Transaction> = (0..6)
+            .map(|i| {
+                let mut transaction = get_transaction();
+                transaction.lock_time = i;
+                transaction
+            })
+            .collect();
+        manager
+            .send_transaction(&serialize(&transactions[0]))
+            .unwrap();
+        manager
+            .send_transaction(&serialize(&transactions[1]))
+            .unwrap();
+
+        let mut batch: Vec<Vec<u8>> = transactions[2..].iter().map(serialize).collect();
+        batch.insert(1, vec![0xde, 0xad, 0xbe, 0xef]);
+        let results = manager.send_transactions(&batch);
+        assert_eq!(results.len(), 5);
+        assert!(matches!(results[0], Ok(txid) if txid == transactions[2].txid()));
+        assert!(matches!(results[1], Err(SendTransactionError::Deserialize)));
+        assert!(matches!(results[2], Ok(txid) if txid == transactions[3].txid()));
+        assert!(matches!(results[4], Ok(txid) if txid == transactions[5].txid()));
+
+        assert_eq!(manager.transactions.len(), tx_cache_max_entries);
+        assert!(!manager.transactions.contains_key(&transactions[0].wtxid()));
+        assert!(!manager.transactions.contains_key(&transactions[1].wtxid()));
+        for transaction in &transactions[2..] {
+            assert!(manager.transactions.contains_key(&transaction.wtxid()));
+        }
+        assert_eq!(manager.metrics.tx_evicted_full.get(), 2);
+    }
+
+    /// This function tests that the oldest transaction gets removed in case of a full transaction cache.
+    /// Test Steps:
+    /// 1. Add transaction that should be removed to manager.
+    /// 2. Add n transaction such that the first one gets evicted.
+    /// 3. Make sure the first transaction is actually removed from the cache.
+    #[test]
+    fn test_adapter_transaction_cache_full() {
+        let tx_cache_max_entries = 10;
+        let config = ConfigBuilder::new()
+            .with_tx_cache_max_entries(tx_cache_max_entries)
+            .build();
+        let mut manager = make_transaction_manager_with_config(&config);
+
+        // Send one transaction. This transaction should be removed first if we are at capacity.
+        let mut first_tx = get_transaction();
+        first_tx.lock_time = u32::MAX;
+        let raw_tx = serialize(&first_tx);
+        manager.send_transaction(&raw_tx).unwrap();
+
+        for i in 0..tx_cache_max_entries {
+            // First regtest genesis transaction.
+            let mut transaction = get_transaction();
+            // Alter transaction such that we get a different `txid`
+            transaction.lock_time = i.try_into().unwrap();
+            let raw_tx = serialize(&transaction);
+            manager.send_transaction(&raw_tx).unwrap();
+        }
+        assert_eq!(manager.transactions.len(), tx_cache_max_entries);
+        assert!(manager.transactions.get(&first_tx.wtxid()).is_none());
+        assert_eq!(manager.metrics.tx_evicted_full.get(), 1);
+        assert_eq!(manager.metrics.tx_reaped_timeout.get(), 0);
+    }
+
+    /// This function tests that lowering the maximum cache size evicts the oldest transactions.
+    /// Test Steps:
+    /// 1. Add transactions to the manager.
+    /// 2. Lower the maximum cache size below the number of cached transactions.
+    /// 3. Check that the oldest transactions were evicted.
+    #[test]
+    fn test_set_tx_cache_max_entries() {
+        let mut manager = make_transaction_manager();
+        let transactions: Vec<Transaction> = (0..4)
+            .map(|i| {
+                let mut transaction = get_transaction();
+                transaction.lock_time = i;
+                transaction
+            })
+            .collect();
+        for transaction in &transactions {
+            manager.send_transaction(&serialize(transaction)).unwrap();
+        }
+
+        manager.set_tx_cache_max_entries(2);
+        assert_eq!(manager.transactions.len(), 2);
+        assert!(!manager.transactions.contains_key(&transactions[0].wtxid()));
+        assert!(!manager.transactions.contains_key(&transactions[1].wtxid()));
+        assert_eq!(manager.metrics.tx_evicted_full.get(), 2);
+    }
+
+    /// This function tests that we don't readvertise transactions that were already advertised.
+    /// Test Steps:
+    /// 1. Add transaction to manager.
+    /// 2. Advertise that transaction and create requests from peer.
+    /// 3. Check that this transaction does not get advertised again during manager tick.
+    /// 3. Check transaction advertisment is correctly tracked.
+    #[test]
+    fn test_adapter_dont_readvertise() {
+        let address = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let mut channel = TestChannel::new(vec![address]);
+        let mut manager = make_transaction_manager();
+
+        let mut transaction = get_transaction();
+        transaction.lock_time = 0;
+        let raw_tx = serialize(&transaction);
+        manager.send_transaction(&raw_tx).unwrap();
+        manager.tick(&mut channel);
+        channel.pop_front().unwrap();
+
+        // Request transaction
+        manager
+            .process_bitcoin_network_message(
+                &mut channel,
+                address,
+                &NetworkMessage::GetData(vec![Inventory::Transaction(transaction.txid())]),
+            )
+            .unwrap();
+        // Send transaction
+        channel.pop_front().unwrap();
+
+        manager.tick(&mut channel);
+        // Transaction should not be readvertised.
+        assert_eq!(channel.command_count(), 0);
+        // Transaction should be marked as advertised
+        assert_eq!(
+            manager
+                .transactions
+                .get(&transaction.wtxid())
+                .unwrap()
+                .advertised
+                .len(),
+            1
+        );
+        assert_eq!(
+            manager
+                .transactions
+                .get(&transaction.wtxid())
+                .unwrap()
+                .advertised
+                .get(&address),
+            Some(&address)
+        );
+    }
+
+    /// This function tests that we advertise to muliple peers and don't readvertise after
+    /// first adverisment.
+    /// Test Steps:
+    /// 1. Add transaction to manager.
+    /// 2. Advertise that transaction and request it from peer 1.
+    /// 3. Check that this transaction does not get readvertised.
+    #[test]
+    fn test_adapter_dont_readvertise_multiple_peers() {
+        let address1 = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let address2 = SocketAddr::from_str("127.0.0.1:8334").expect("invalid address");
+        let mut channel = TestChannel::new(vec![address1, address2]);
+        let mut manager = make_transaction_manager();
+
+        let mut transaction = get_transaction();
+        transaction.lock_time = 0;
+        let raw_tx = serialize(&transaction);
+        manager.send_transaction(&raw_tx).unwrap();
+        manager.tick(&mut channel);
+        // Transaction advertisment to both peers.
+        assert_eq!(channel.command_count(), 2);
+        channel.pop_front().unwrap();
+        channel.pop_front().unwrap();
+
+        // Request transaction from peer 1
+        manager
+            .process_bitcoin_network_message(
+                &mut channel,
+                address1,
                 &NetworkMessage::GetData(vec![Inventory::Transaction(transaction.txid())]),
             )
             .unwrap();
@@ -428,7 +1488,7 @@ mod test {
         let mut transaction = get_transaction();
         transaction.lock_time = 0;
         let raw_tx = serialize(&transaction);
-        manager.send_transaction(&raw_tx);
+        manager.send_transaction(&raw_tx).unwrap();
         manager.tick(&mut channel);
         assert_eq!(channel.command_count(), 1);
         channel.pop_front().unwrap();
@@ -477,7 +1537,7 @@ mod test {
         let transaction = get_transaction();
         let raw_tx = serialize(&transaction);
         let txid = transaction.txid();
-        manager.send_transaction(&raw_tx);
+        manager.send_transaction(&raw_tx).unwrap();
         assert_eq!(manager.transactions.len(), 1);
         manager
             .process_bitcoin_network_message(
@@ -491,6 +1551,163 @@ mod test {
         assert!(matches!(command.message, NetworkMessage::Tx(t) if t.txid() == txid));
     }
 
+    /// This function tests that a transaction is flagged as fetched once a peer requests it.
+    /// Test Steps:
+    /// 1. Add a transaction to the manager and check that it has not been fetched.
+    /// 2. Process a `getdata` message for the transaction from a peer.
+    /// 3. Check that the transaction has been fetched and the first fetch time is kept on further requests.
+    #[test]
+    fn test_transaction_was_fetched() {
+        let address = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let mut channel = TestChannel::new(vec![address]);
+        let mut manager = make_transaction_manager();
+        let transaction = get_transaction();
+        let txid = manager.send_transaction(&serialize(&transaction)).unwrap();
+        assert!(!manager.transaction_was_fetched(&txid));
+
+        let get_data = NetworkMessage::GetData(vec![Inventory::Transaction(txid)]);
+        manager
+            .process_bitcoin_network_message(&mut channel, address, &get_data)
+            .unwrap();
+        assert!(manager.transaction_was_fetched(&txid));
+        let first_fetched_at = manager
+            .transactions
+            .get(&transaction.wtxid())
+            .and_then(|info| info.first_fetched_at)
+            .expect("transaction should have been fetched");
+
+        manager
+            .process_bitcoin_network_message(&mut channel, address, &get_data)
+            .unwrap();
+        assert_eq!(
+            manager
+                .transactions
+                .get(&transaction.wtxid())
+                .and_then(|info| info.first_fetched_at),
+            Some(first_fetched_at)
+        );
+        assert_eq!(channel.command_count(), 2);
+    }
+
+    /// This function tests that the transactions are advertised and served before the cache is
+    /// cleared on shutdown.
+    /// Test Steps:
+    /// 1. Add a transaction and queue a `getdata` message for it from a peer.
+    /// 2. Drain the manager and check that the transaction was advertised and sent to the peer.
+    /// 3. Check that the cache is cleared and that draining stops at the deadline if nothing is fetched.
+    #[tokio::test]
+    async fn test_drain_and_advertise() {
+        let address = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let mut channel = TestChannel::new(vec![address]);
+        let mut manager = make_transaction_manager();
+        let transaction = get_transaction();
+        let txid = manager.send_transaction(&serialize(&transaction)).unwrap();
+
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(10);
+        sender
+            .send((
+                address,
+                NetworkMessage::GetData(vec![Inventory::Transaction(txid)]),
+            ))
+            .await
+            .unwrap();
+        manager
+            .drain_and_advertise(&mut channel, &mut receiver, Duration::from_secs(60))
+            .await;
+
+        assert_eq!(channel.command_count(), 2);
+        let command = channel.pop_front().expect("There should be an inv.");
+        assert!(matches!(command.message, NetworkMessage::Inv(_)));
+        let command = channel.pop_front().expect("There should be a tx.");
+        assert_eq!(command.address, Some(address));
+        assert!(matches!(command.message, NetworkMessage::Tx(tx) if tx.txid() == txid));
+        assert!(manager.transactions.is_empty());
+
+        // Nobody fetches the transaction, so draining stops at the deadline.
+        manager.send_transaction(&serialize(&transaction)).unwrap();
+        manager
+            .drain_and_advertise(&mut channel, &mut receiver, Duration::from_millis(10))
+            .await;
+        assert!(manager.transactions.is_empty());
+    }
+
+    /// This function tests that draining advertises all transactions, even if they exceed the
+    /// per-tick advertisement cap.
+    /// Test Steps:
+    /// 1. Add more transactions than the per-tick advertisement cap.
+    /// 2. Drain the manager without any peer fetching the transactions.
+    /// 3. Check that every transaction was advertised.
+    #[tokio::test]
+    async fn test_drain_and_advertise_exceeds_per_tick_cap() {
+        let address = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let mut channel = TestChannel::new(vec![address]);
+        let config = ConfigBuilder::new()
+            .with_tx_advertisements_per_peer_per_tick(2)
+            .build();
+        let mut manager = make_transaction_manager_with_config(&config);
+        for i in 0..5 {
+            let mut transaction = get_transaction();
+            transaction.lock_time = i;
+            manager.send_transaction(&serialize(&transaction)).unwrap();
+        }
+
+        let (_sender, mut receiver) = tokio::sync::mpsc::channel(10);
+        manager
+            .drain_and_advertise(&mut channel, &mut receiver, Duration::from_millis(10))
+            .await;
+
+        let mut advertised = 0;
+        while let Some(command) = channel.pop_front() {
+            match command.message {
+                NetworkMessage::Inv(inventory) => advertised += inventory.len(),
+                message => panic!("unexpected message: {:?}", message),
+            }
+        }
+        assert_eq!(advertised, 5);
+        assert!(manager.transactions.is_empty());
+    }
+
+    /// This function tests that a transaction is re-advertised to a peer after it sent a `notfound` message.
+    /// Test Steps:
+    /// 1. Add transaction to manager and advertise it.
+    /// 2. Process a `notfound` network message for the transaction from the peer.
+    /// 3. Check that the transaction is re-advertised to the peer on the next tick.
+    #[test]
+    fn test_process_bitcoin_network_message_not_found() {
+        let address = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let mut channel = TestChannel::new(vec![address]);
+        let mut manager = make_transaction_manager();
+        let transaction = get_transaction();
+        let txid = transaction.txid();
+        manager.send_transaction(&serialize(&transaction)).unwrap();
+        manager.tick(&mut channel);
+        assert_eq!(channel.command_count(), 1);
+        channel.pop_front().unwrap();
+
+        manager
+            .process_bitcoin_network_message(
+                &mut channel,
+                address,
+                &NetworkMessage::NotFound(vec![Inventory::Transaction(txid)]),
+            )
+            .unwrap();
+        assert!(manager
+            .transactions
+            .get(&transaction.wtxid())
+            .unwrap()
+            .advertised
+            .is_empty());
+
+        manager.tick(&mut channel);
+        assert_eq!(
+            channel.pop_front().unwrap(),
+            Command {
+                address: Some(address),
+                message: NetworkMessage::Inv(vec![Inventory::Transaction(txid)])
+            }
+        );
+    }
+
     /// This function tests the `TransactionManager::process_bitcoin_network_message(...)` method.
     /// Test Steps:
     /// 1. Receive a more than `MAXIMUM_TRANSACTION_PER_INV` transaction.
@@ -511,13 +1728,52 @@ mod test {
             let txid = transaction.txid();
             inventory.push(Inventory::Transaction(txid));
         }
+        let result = manager.process_bitcoin_network_message(
+            &mut channel,
+            address,
+            &NetworkMessage::GetData(inventory),
+        );
+        assert!(matches!(
+            result,
+            Err(ProcessBitcoinNetworkMessageError::TooManyGetDataItems)
+        ));
+    }
+
+    /// This function tests that `getdata` messages are dropped once a peer exceeds the rate.
+    /// Test Steps:
+    /// 1. Receive a transaction.
+    /// 2. Flood the manager with `getdata` messages for the transaction from one peer.
+    /// 3. Check that only the burst is served and the remaining messages are rate limited.
+    /// 4. Check that another peer is still served.
+    #[test]
+    fn test_getdata_rate_limit() {
+        let address = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let other = SocketAddr::from_str("127.0.0.2:8333").expect("invalid address");
+        let mut channel = TestChannel::new(vec![address, other]);
+        let config = ConfigBuilder::new()
+            .with_getdata_rate_per_sec(1)
+            .with_getdata_burst(5)
+            .build();
+        let mut manager = make_transaction_manager_with_config(&config);
+        let transaction = get_transaction();
+        let txid = transaction.txid();
+        manager.send_transaction(&serialize(&transaction)).unwrap();
+        let getdata = NetworkMessage::GetData(vec![Inventory::Transaction(txid)]);
+
+        let results: Vec<_> = (0..20)
+            .map(|_| manager.process_bitcoin_network_message(&mut channel, address, &getdata))
+            .collect();
+        assert!(results[..5].iter().all(|result| result.is_ok()));
+        assert!(results[5..].iter().all(|result| matches!(
+            result,
+            Err(ProcessBitcoinNetworkMessageError::GetDataRateExceeded)
+        )));
+        assert_eq!(channel.command_count(), 5);
+
         manager
-            .process_bitcoin_network_message(
-                &mut channel,
-                address,
-                &NetworkMessage::GetData(inventory),
-            )
-            .unwrap_err();
+            .process_bitcoin_network_message(&mut channel, other, &getdata)
+            .unwrap();
+        assert_eq!(channel.command_count(), 6);
     }
 
     /// This function tests the `TransactionManager::tick(...)` method.
@@ -534,7 +1790,7 @@ mod test {
         let transaction = get_transaction();
         let raw_tx = serialize(&transaction);
         let txid = transaction.txid();
-        manager.send_transaction(&raw_tx);
+        manager.send_transaction(&raw_tx).unwrap();
         manager.tick(&mut channel);
         manager
             .process_bitcoin_network_message(
@@ -560,16 +1816,448 @@ mod test {
         let command = channel.pop_front().unwrap();
         assert!(matches!(command.message, NetworkMessage::Tx(t) if t.txid() == txid));
 
-        manager.send_transaction(&raw_tx);
+        manager.send_transaction(&raw_tx).unwrap();
         let info = manager
             .transactions
-            .get_mut(&transaction.txid())
+            .get_mut(&transaction.wtxid())
             .expect("transaction should be in the map");
-        info.timeout_at = SystemTime::now() - Duration::from_secs(TX_CACHE_TIMEOUT_PERIOD_SECS);
+        info.timeout_at = SystemTime::now() - manager.tx_timeout;
         manager.tick(&mut channel);
         assert_eq!(manager.transactions.len(), 0);
     }
 
+    /// This function tests the `TransactionManager::notify_block_confirmed(...)` method.
+    /// Test Steps:
+    /// 1. Receive two transactions.
+    /// 2. Confirm a block that contains only the first transaction.
+    /// 3. Check that only the first transaction has been removed from the cache.
+    #[test]
+    fn test_notify_block_confirmed() {
+        let mut manager = make_transaction_manager();
+        let block = genesis_block(Network::Regtest);
+        let transaction = get_transaction();
+        let mut other_transaction = get_transaction();
+        other_transaction.lock_time = 1;
+        manager.send_transaction(&serialize(&transaction)).unwrap();
+        manager
+            .send_transaction(&serialize(&other_transaction))
+            .unwrap();
+        assert_eq!(manager.transactions.len(), 2);
+
+        manager.notify_block_confirmed(&block);
+        assert_eq!(manager.transactions.len(), 1);
+        assert!(!manager.transactions.contains_key(&transaction.wtxid()));
+        assert!(manager
+            .transactions
+            .contains_key(&other_transaction.wtxid()));
+        assert_eq!(manager.metrics.tx_confirmed.get(), 1);
+    }
+
+    /// This function tests that the metrics snapshot reflects the transaction cache.
+    /// Test Steps:
+    /// 1. Receive two transactions into a cache holding a single transaction.
+    /// 2. Check that the snapshot counts the eviction and the cached transaction.
+    /// 3. Confirm the cached transaction and check that the snapshot reflects it.
+    #[test]
+    fn test_metrics_snapshot() {
+        let mut channel = TestChannel::new(vec![]);
+        let config = ConfigBuilder::new().with_tx_cache_max_entries(1).build();
+        let mut manager = make_transaction_manager_with_config(&config);
+        let mut evicted_transaction = get_transaction();
+        evicted_transaction.lock_time = 1;
+        manager
+            .send_transaction(&serialize(&evicted_transaction))
+            .unwrap();
+        manager
+            .send_transaction(&serialize(&get_transaction()))
+            .unwrap();
+        manager.tick(&mut channel);
+        assert_eq!(
+            manager.metrics_snapshot(),
+            TransactionMetricsSnapshot {
+                tx_store_size: 1,
+                tx_confirmed: 0,
+                tx_reaped_timeout: 0,
+                tx_evicted_full: 1,
+            }
+        );
+
+        manager.notify_block_confirmed(&genesis_block(Network::Regtest));
+        manager.tick(&mut channel);
+        let snapshot = manager.metrics_snapshot();
+        assert_eq!(snapshot.tx_store_size, 0);
+        assert_eq!(snapshot.tx_confirmed, 1);
+        let json = serde_json::to_value(&snapshot).unwrap();
+        assert_eq!(json["tx_confirmed"], 1);
+    }
+
+    /// This function tests the `TransactionManager::transaction_age(...)` method.
+    /// Test Steps:
+    /// 1. Check that an unknown transaction has no age.
+    /// 2. Receive a transaction and check that its age follows the clock.
+    #[test]
+    fn test_transaction_age() {
+        let clock = Arc::new(MockClock::new(SystemTime::now()));
+        let mut manager = TransactionManager::new_with_clock(
+            &ConfigBuilder::new().build(),
+            no_op_logger(),
+            &MetricsRegistry::default(),
+            clock.clone(),
+        );
+        let transaction = get_transaction();
+        let txid = transaction.txid();
+        assert_eq!(manager.transaction_age(&txid), None);
+
+        manager.send_transaction(&serialize(&transaction)).unwrap();
+        assert_eq!(manager.transaction_age(&txid), Some(Duration::ZERO));
+
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(
+            manager.transaction_age(&txid),
+            Some(Duration::from_secs(30))
+        );
+    }
+
+    /// This function tests the `TransactionManager::transaction_status(...)` method.
+    /// Test Steps:
+    /// 1. Check that an unknown transaction has no status.
+    /// 2. Receive a transaction and advertise it to two peers.
+    /// 3. Check that the status reflects the advertisement.
+    #[test]
+    fn test_transaction_status() {
+        let address1 = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let address2 = SocketAddr::from_str("127.0.0.1:8334").expect("invalid address");
+        let mut channel = TestChannel::new(vec![address1, address2]);
+        let mut manager = make_transaction_manager();
+        let transaction = get_transaction();
+        let txid = transaction.txid();
+        assert_eq!(manager.transaction_status(&txid), None);
+
+        manager.send_transaction(&serialize(&transaction)).unwrap();
+        let status = manager.transaction_status(&txid).unwrap();
+        assert_eq!(status.advertised_to, 0);
+
+        manager.tick(&mut channel);
+        let status = manager.transaction_status(&txid).unwrap();
+        assert_eq!(status.advertised_to, 2);
+        assert!(status.age < manager.tx_timeout);
+        assert!(!status.timed_out_soon);
+    }
+
+    /// This function tests that segwit transactions are deduplicated by their wtxid.
+    /// Test Steps:
+    /// 1. Send the same segwit transaction twice and check that it is cached once.
+    /// 2. Send the transaction with a different witness and check that both are cached under the same txid.
+    /// 3. Confirm one of them in a block and check that both are removed.
+    #[test]
+    fn test_send_transaction_deduplicates_by_wtxid() {
+        let mut manager = make_transaction_manager();
+        let transaction = get_segwit_transaction(vec![1; 32]);
+        let other_witness_transaction = get_segwit_transaction(vec![2; 32]);
+        assert_eq!(transaction.txid(), other_witness_transaction.txid());
+        assert_ne!(transaction.wtxid(), other_witness_transaction.wtxid());
+
+        let txid = manager.send_transaction(&serialize(&transaction)).unwrap();
+        assert_eq!(txid, transaction.txid());
+        manager.send_transaction(&serialize(&transaction)).unwrap();
+        assert_eq!(manager.transactions.len(), 1);
+
+        let other_txid = manager
+            .send_transaction(&serialize(&other_witness_transaction))
+            .unwrap();
+        assert_eq!(other_txid, txid);
+        assert_eq!(manager.transactions.len(), 2);
+        assert!(manager.transactions.contains_key(&transaction.wtxid()));
+        assert!(manager
+            .transactions
+            .contains_key(&other_witness_transaction.wtxid()));
+
+        let mut block = genesis_block(Network::Regtest);
+        block.txdata.push(transaction);
+        assert_eq!(
+            manager.wtxids_by_txid.get(&txid),
+            Some(&vec![
+                transaction.wtxid(),
+                other_witness_transaction.wtxid()
+            ])
+        );
+        manager.notify_block_confirmed(&block);
+        assert!(manager.transactions.is_empty());
+        assert!(manager.wtxids_by_txid.is_empty());
+        assert_eq!(manager.metrics.tx_confirmed.get(), 2);
+    }
+
+    /// This function tests that transactions below the feerate a peer announced with
+    /// `feefilter` are not advertised to it.
+    /// Test Steps:
+    /// 1. Let the first peer send a `feefilter` message of 1 sat/vB.
+    /// 2. Send a transaction with an unknown fee, a child paying a high fee and a child paying
+    ///    a low fee.
+    /// 3. Check that the low fee child is only advertised to the second peer.
+    /// 4. Remove the filter and check that the low fee child is advertised to the first peer.
+    #[test]
+    fn test_advertise_txids_respects_fee_filter() {
+        let address1 = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let address2 = SocketAddr::from_str("127.0.0.2:8333").expect("invalid address");
+        let mut channel = TestChannel::new(vec![address1, address2]);
+        let mut manager = make_transaction_manager();
+        manager
+            .process_bitcoin_network_message(
+                &mut channel,
+                address1,
+                &NetworkMessage::FeeFilter(1_000),
+            )
+            .unwrap();
+
+        let parent = get_transaction();
+        let high_fee_child = get_segwit_transaction(vec![1]);
+        let mut low_fee_child = get_segwit_transaction(vec![2]);
+        low_fee_child.output[0].value = parent.output[0].value - 1;
+        for transaction in [&parent, &high_fee_child, &low_fee_child] {
+            manager.send_transaction(&serialize(transaction)).unwrap();
+        }
+
+        let inventory_txids = |command: Command| -> HashSet<Txid> {
+            match command.message {
+                NetworkMessage::Inv(inventory) => inventory
+                    .into_iter()
+                    .filter_map(|inv| match inv {
+                        Inventory::Transaction(txid) => Some(txid),
+                        _ => None,
+                    })
+                    .collect(),
+                _ => panic!("expected an inv message"),
+            }
+        };
+        manager.advertise_txids(&mut channel);
+        assert_eq!(channel.command_count(), 2);
+        let command = channel.pop_front().unwrap();
+        assert_eq!(command.address, Some(address1));
+        assert_eq!(
+            inventory_txids(command),
+            HashSet::from([parent.txid(), high_fee_child.txid()])
+        );
+        let command = channel.pop_front().unwrap();
+        assert_eq!(command.address, Some(address2));
+        assert_eq!(
+            inventory_txids(command),
+            HashSet::from([parent.txid(), high_fee_child.txid(), low_fee_child.txid()])
+        );
+
+        manager
+            .process_bitcoin_network_message(&mut channel, address1, &NetworkMessage::FeeFilter(0))
+            .unwrap();
+        manager.advertise_txids(&mut channel);
+        assert_eq!(channel.command_count(), 1);
+        let command = channel.pop_front().unwrap();
+        assert_eq!(command.address, Some(address1));
+        assert_eq!(
+            inventory_txids(command),
+            HashSet::from([low_fee_child.txid()])
+        );
+    }
+
+    /// This function tests that segwit transactions are advertised by wtxid to peers that
+    /// negotiated BIP-339 and by txid to all other peers.
+    /// Test Steps:
+    /// 1. Send a segwit transaction with one regular peer and one wtxid relay peer connected.
+    /// 2. Check that each peer receives the inventory type it supports.
+    /// 3. Request the transaction by wtxid and by txid and check that it is sent for both.
+    #[test]
+    fn test_advertise_txids_by_wtxid() {
+        let address1 = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let address2 = SocketAddr::from_str("127.0.0.2:8333").expect("invalid address");
+        let mut channel = TestChannel::new(vec![address1, address2]);
+        let mut manager = make_transaction_manager();
+        manager
+            .process_bitcoin_network_message(&mut channel, address2, &NetworkMessage::WtxidRelay)
+            .unwrap();
+        let transaction = get_segwit_transaction(vec![1; 32]);
+        let txid = transaction.txid();
+        let wtxid = transaction.wtxid();
+        assert_ne!(txid.as_hash(), wtxid.as_hash());
+        manager.send_transaction(&serialize(&transaction)).unwrap();
+
+        manager.advertise_txids(&mut channel);
+        assert_eq!(channel.command_count(), 2);
+        assert_eq!(
+            channel.pop_front().unwrap(),
+            Command {
+                address: Some(address1),
+                message: NetworkMessage::Inv(vec![Inventory::Transaction(txid)])
+            }
+        );
+        assert_eq!(
+            channel.pop_front().unwrap(),
+            Command {
+                address: Some(address2),
+                message: NetworkMessage::Inv(vec![Inventory::WTx(wtxid)])
+            }
+        );
+
+        for (address, inv) in [
+            (address1, Inventory::WitnessTransaction(txid)),
+            (address2, Inventory::WTx(wtxid)),
+        ] {
+            manager
+                .process_bitcoin_network_message(
+                    &mut channel,
+                    address,
+                    &NetworkMessage::GetData(vec![inv]),
+                )
+                .unwrap();
+            let command = channel.pop_front().expect("There should be one.");
+            assert_eq!(command.address, Some(address));
+            assert!(matches!(command.message, NetworkMessage::Tx(t) if t.wtxid() == wtxid));
+        }
+    }
+
+    /// This function tests that an inventory that reaches `MAXIMUM_TRANSACTION_PER_INV` entries
+    /// is only sent to the peer it was built for.
+    /// Test Steps:
+    /// 1. Send more than `MAXIMUM_TRANSACTION_PER_INV` transactions with one regular peer and
+    ///    one wtxid relay peer connected.
+    /// 2. Advertise the transactions.
+    /// 3. Check that each peer receives two inventories of the type it supports, covering
+    ///    every transaction once.
+    #[test]
+    fn test_advertise_txids_full_inventory_sent_to_peer_only() {
+        let num_transaction = MAXIMUM_TRANSACTION_PER_INV + 1;
+        let address1 = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let address2 = SocketAddr::from_str("127.0.0.2:8333").expect("invalid address");
+        let mut channel = TestChannel::new(vec![address1, address2]);
+        let config = ConfigBuilder::new()
+            .with_tx_cache_max_entries(num_transaction)
+            .with_tx_advertisements_per_peer_per_tick(num_transaction)
+            .build();
+        let mut manager = make_transaction_manager_with_config(&config);
+        manager
+            .process_bitcoin_network_message(&mut channel, address2, &NetworkMessage::WtxidRelay)
+            .unwrap();
+        for i in 0..num_transaction {
+            let mut transaction = get_transaction();
+            transaction.lock_time = i.try_into().unwrap();
+            manager.send_transaction(&serialize(&transaction)).unwrap();
+        }
+
+        manager.advertise_txids(&mut channel);
+        assert_eq!(channel.command_count(), 4);
+        let mut advertised: HashMap<SocketAddr, usize> = HashMap::new();
+        while let Some(command) = channel.pop_front() {
+            let address = command
+                .address
+                .expect("inventories are sent to a single peer");
+            let inventory = match command.message {
+                NetworkMessage::Inv(inventory) => inventory,
+                message => panic!("unexpected message: {:?}", message),
+            };
+            assert!(inventory.iter().all(|inv| match inv {
+                Inventory::Transaction(_) => address == address1,
+                Inventory::WTx(_) => address == address2,
+                _ => false,
+            }));
+            *advertised.entry(address).or_default() += inventory.len();
+        }
+        assert_eq!(advertised.get(&address1), Some(&num_transaction));
+        assert_eq!(advertised.get(&address2), Some(&num_transaction));
+        for address in [address1, address2] {
+            assert_eq!(
+                manager
+                    .metrics
+                    .tx_advertised
+                    .with_label_values(&[&address.to_string()])
+                    .get(),
+                num_transaction as u64
+            );
+        }
+    }
+
+    /// This function tests that the state negotiated with a peer is dropped once the peer
+    /// is removed.
+    /// Test Steps:
+    /// 1. Negotiate wtxid relay and a fee filter with a peer and advertise a transaction to it.
+    /// 2. Remove the peer.
+    /// 3. Check that no state of the peer is left.
+    #[test]
+    fn test_remove_peer() {
+        let address = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let mut channel = TestChannel::new(vec![address]);
+        let mut manager = make_transaction_manager();
+        manager
+            .process_bitcoin_network_message(&mut channel, address, &NetworkMessage::WtxidRelay)
+            .unwrap();
+        manager
+            .process_bitcoin_network_message(
+                &mut channel,
+                address,
+                &NetworkMessage::FeeFilter(1_000),
+            )
+            .unwrap();
+        manager
+            .send_transaction(&serialize(&get_transaction()))
+            .unwrap();
+        manager.advertise_txids(&mut channel);
+        assert!(manager.advertised_peers.contains(&address));
+
+        manager.remove_peer(&address);
+        assert!(manager.wtxid_relay_peers.is_empty());
+        assert!(manager.peer_fee_filters.is_empty());
+        assert!(manager.advertised_peers.is_empty());
+    }
+
+    /// This function tests that the wtxid relay negotiation is reset when a peer starts a new
+    /// version handshake.
+    /// Test Steps:
+    /// 1. Negotiate wtxid relay with a peer and check that the wtxid is advertised.
+    /// 2. Process a new `version` message from the peer.
+    /// 3. Check that the txid is advertised after the transaction is re-advertised.
+    #[test]
+    fn test_wtxid_relay_reset_on_version() {
+        let address = SocketAddr::from_str("127.0.0.1:8333").expect("invalid address");
+        let mut channel = TestChannel::new(vec![address]);
+        let mut manager = make_transaction_manager();
+        let transaction = get_segwit_transaction(vec![1; 32]);
+        manager.send_transaction(&serialize(&transaction)).unwrap();
+
+        manager
+            .process_bitcoin_network_message(&mut channel, address, &NetworkMessage::WtxidRelay)
+            .unwrap();
+        manager.advertise_txids(&mut channel);
+        assert_eq!(
+            channel.pop_front().unwrap().message,
+            NetworkMessage::Inv(vec![Inventory::WTx(transaction.wtxid())])
+        );
+
+        let version = VersionMessage::new(
+            ServiceFlags::NETWORK,
+            0,
+            Address::new(&address, ServiceFlags::NETWORK),
+            Address::new(&address, ServiceFlags::NONE),
+            1,
+            String::from("test"),
+            0,
+        );
+        manager
+            .process_bitcoin_network_message(
+                &mut channel,
+                address,
+                &NetworkMessage::Version(version),
+            )
+            .unwrap();
+        manager
+            .process_bitcoin_network_message(
+                &mut channel,
+                address,
+                &NetworkMessage::NotFound(vec![Inventory::WTx(transaction.wtxid())]),
+            )
+            .unwrap();
+        manager.advertise_txids(&mut channel);
+        assert_eq!(
+            channel.pop_front().unwrap().message,
+            NetworkMessage::Inv(vec![Inventory::Transaction(transaction.txid())])
+        );
+    }
+
     /// Test to ensure that when `TransactionManager.idle(...)` is called that the `transactions`
     /// field is cleared.
     #[test]
@@ -577,15 +2265,15 @@ mod test {
         let mut manager = make_transaction_manager();
         let transaction = get_transaction();
         let raw_tx = serialize(&transaction);
-        let txid = transaction.txid();
+        let wtxid = transaction.wtxid();
 
-        manager.send_transaction(&raw_tx);
+        manager.send_transaction(&raw_tx).unwrap();
 
         assert_eq!(manager.transactions.len(), 1);
-        assert!(manager.transactions.contains_key(&txid));
+        assert!(manager.transactions.contains_key(&wtxid));
 
         manager.make_idle();
         assert_eq!(manager.transactions.len(), 0);
-        assert!(!manager.transactions.contains_key(&txid));
+        assert!(!manager.transactions.contains_key(&wtxid));
     }
 }